@@ -0,0 +1,33 @@
+#![no_main]
+
+use bytes::BytesMut;
+use h11::{Event, HttpConn, Server};
+use libfuzzer_sys::fuzz_target;
+
+const HEAD: &[u8] = b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n";
+
+fuzz_target!(|data: &[u8]| {
+    let mut in_buf = BytesMut::from(HEAD);
+    in_buf.extend_from_slice(data);
+    let mut conn =
+        HttpConn::<Server>::from_bufs(8192, in_buf, BytesMut::new());
+
+    match conn.next_event() {
+        Ok(Some(Event::Request(_))) => {}
+        r => panic!("fixed head must parse: {:?}", r),
+    }
+
+    // The decoded body can never be larger than the encoded input.
+    let mut decoded = 0;
+    loop {
+        match conn.next_event() {
+            Ok(Some(Event::Data(d))) => {
+                assert!(!d.is_empty());
+                decoded += d.len();
+                assert!(decoded <= data.len());
+            }
+            Ok(Some(Event::EndOfMessage(_))) | Ok(None) | Err(_) => break,
+            Ok(Some(e)) => panic!("unexpected event in body: {:?}", e),
+        }
+    }
+});
@@ -0,0 +1,42 @@
+#![no_main]
+
+use bytes::BytesMut;
+use h11::testing::parse_req_head;
+use httparse::{Request, Status, EMPTY_HEADER};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = BytesMut::from(data);
+    let ours = parse_req_head(&mut buf);
+    let consumed = data.len() - buf.len();
+
+    let mut hdrs = [EMPTY_HEADER; 50];
+    let mut theirs = Request::new(&mut hdrs);
+    let res = theirs.parse(&data[..consumed]);
+
+    match ours {
+        Ok(Some(head)) => {
+            // Anything we accept must also be a complete, valid head
+            // according to httparse, and must agree field by field.
+            match res {
+                Ok(Status::Complete(n)) => assert_eq!(consumed, n),
+                r => panic!("we accepted a head httparse rejects: {:?}", r),
+            }
+            assert_eq!(theirs.method.unwrap(), head.method.as_str());
+            let path = theirs.path.unwrap();
+            if path.starts_with('/') {
+                assert_eq!(path, head.uri.to_string());
+            }
+            assert_eq!(theirs.headers.len(), head.headers.len());
+            for hdr in theirs.headers.iter() {
+                assert!(head
+                    .headers
+                    .get_all(hdr.name)
+                    .iter()
+                    .any(|v| v.as_bytes() == hdr.value));
+            }
+        }
+        Ok(None) => assert_eq!(0, consumed),
+        Err(_) => {}
+    }
+});
@@ -0,0 +1,36 @@
+#![no_main]
+
+use bytes::BytesMut;
+use h11::testing::parse_resp_head;
+use httparse::{Response, Status, EMPTY_HEADER};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = BytesMut::from(data);
+    let ours = parse_resp_head(&mut buf);
+    let consumed = data.len() - buf.len();
+
+    let mut hdrs = [EMPTY_HEADER; 50];
+    let mut theirs = Response::new(&mut hdrs);
+    let res = theirs.parse(&data[..consumed]);
+
+    match ours {
+        Ok(Some(head)) => {
+            match res {
+                Ok(Status::Complete(n)) => assert_eq!(consumed, n),
+                r => panic!("we accepted a head httparse rejects: {:?}", r),
+            }
+            assert_eq!(theirs.code.unwrap(), head.status.as_u16());
+            assert_eq!(theirs.headers.len(), head.headers.len());
+            for hdr in theirs.headers.iter() {
+                assert!(head
+                    .headers
+                    .get_all(hdr.name)
+                    .iter()
+                    .any(|v| v.as_bytes() == hdr.value));
+            }
+        }
+        Ok(None) => assert_eq!(0, consumed),
+        Err(_) => {}
+    }
+});
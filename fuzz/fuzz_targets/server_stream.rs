@@ -0,0 +1,51 @@
+#![no_main]
+
+use bytes::Bytes;
+use h11::{Event, HttpConn, Server};
+use http::{HeaderMap, StatusCode};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte picks the read size so that libFuzzer also explores
+// arbitrary split points in the stream.
+fuzz_target!(|data: &[u8]| {
+    let (split, mut data) = match data.split_first() {
+        Some((&split, rest)) => (usize::from(split).max(1), rest),
+        None => return,
+    };
+    let mut conn = HttpConn::<Server>::new();
+
+    loop {
+        let n = split.min(data.len());
+        let mut chunk = &data[..n];
+        if conn.read_from(&mut chunk).is_err() {
+            return;
+        }
+        data = &data[n..];
+
+        loop {
+            match conn.next_event() {
+                Ok(Some(Event::EndOfMessage(_))) => {
+                    // Answer with an empty response, as `h11-check` does,
+                    // so that pipelined and kept-alive requests after the
+                    // first are decoded too.
+                    let reused = conn
+                        .respond(
+                            StatusCode::NO_CONTENT,
+                            HeaderMap::new(),
+                            Bytes::new(),
+                        )
+                        .and_then(|_| conn.start_next_cycle());
+                    if reused.is_err() {
+                        return;
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+        if n == 0 {
+            return;
+        }
+    }
+});
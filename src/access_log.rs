@@ -0,0 +1,175 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use http::header::{REFERER, USER_AGENT};
+
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+use crate::util::format_clf_date;
+
+#[derive(Debug)]
+pub struct LogEntry<'a> {
+    pub remote_addr: Option<IpAddr>,
+    pub user: Option<&'a str>,
+    pub time: SystemTime,
+    pub request: &'a ReqHead,
+    pub response: &'a RespHead,
+    pub body_bytes_sent: u64,
+}
+
+impl LogEntry<'_> {
+    #[must_use]
+    pub fn common(&self) -> String {
+        let mut line = String::new();
+        let _ = match self.remote_addr {
+            Some(addr) => write!(line, "{addr}"),
+            None => write!(line, "-"),
+        };
+        line.push_str(" - ");
+        match self.user {
+            Some(user) => push_escaped(&mut line, user.as_bytes()),
+            None => line.push('-'),
+        }
+        let _ = write!(line, " [{}] \"", format_clf_date(self.time));
+        push_escaped(&mut line, self.request.method.as_str().as_bytes());
+        line.push(' ');
+        push_escaped(&mut line, self.request.uri.to_string().as_bytes());
+        let _ = write!(
+            line,
+            " {:?}\" {} ",
+            self.request.version,
+            self.response.status.as_str()
+        );
+        if self.body_bytes_sent == 0 {
+            line.push('-');
+        } else {
+            let _ = write!(line, "{}", self.body_bytes_sent);
+        }
+        line
+    }
+
+    #[must_use]
+    pub fn combined(&self) -> String {
+        let mut line = self.common();
+        for name in &[REFERER, USER_AGENT] {
+            line.push_str(" \"");
+            match self.request.headers.get(name) {
+                Some(value) => push_escaped(&mut line, value.as_bytes()),
+                None => line.push('-'),
+            }
+            line.push('"');
+        }
+        line
+    }
+}
+
+// Follows Apache's escaping: quotes and backslashes are backslash-escaped
+// and anything outside printable ASCII is written as `\xHH`, so a field
+// can never break out of its quotes or inject a line break.
+fn push_escaped(line: &mut String, bytes: &[u8]) {
+    for &b in bytes {
+        match b {
+            b'"' => line.push_str("\\\""),
+            b'\\' => line.push_str("\\\\"),
+            b' '..=b'~' => line.push(b as char),
+            _ => {
+                let _ = write!(line, "\\x{b:02x}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use http::{HeaderMap, HeaderValue, Method, StatusCode, Version};
+
+    fn entry<'a>(req: &'a ReqHead, resp: &'a RespHead) -> LogEntry<'a> {
+        LogEntry {
+            remote_addr: Some("127.0.0.1".parse().unwrap()),
+            user: Some("frank"),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            request: req,
+            response: resp,
+            body_bytes_sent: 2326,
+        }
+    }
+
+    fn req(headers: HeaderMap) -> ReqHead {
+        ReqHead {
+            method: Method::GET,
+            uri: "/apache_pb.gif".parse().unwrap(),
+            version: Version::HTTP_10,
+            headers,
+        }
+    }
+
+    fn resp() -> RespHead {
+        RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_10,
+            headers: HeaderMap::new(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn common() {
+        let (req, resp) = (req(HeaderMap::new()), resp());
+        assert_eq!(
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] \
+             \"GET /apache_pb.gif HTTP/1.0\" 200 2326",
+            entry(&req, &resp).common()
+        );
+    }
+
+    #[test]
+    fn common_missing_values() {
+        let (req, resp) = (req(HeaderMap::new()), resp());
+        let entry = LogEntry {
+            remote_addr: None,
+            user: None,
+            body_bytes_sent: 0,
+            ..entry(&req, &resp)
+        };
+        assert_eq!(
+            "- - - [10/Oct/2000:13:55:36 +0000] \
+             \"GET /apache_pb.gif HTTP/1.0\" 200 -",
+            entry.common()
+        );
+    }
+
+    #[test]
+    fn combined_escapes_headers() {
+        let req = req(vec![
+            (
+                REFERER,
+                HeaderValue::from_static("http://www.example.com/start.html"),
+            ),
+            (
+                USER_AGENT,
+                HeaderValue::from_bytes(b"Mozilla \"4.08\" \\ \xff").unwrap(),
+            ),
+        ]
+        .into_iter()
+        .collect());
+        let resp = resp();
+        assert_eq!(
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] \
+             \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \
+             \"http://www.example.com/start.html\" \
+             \"Mozilla \\\"4.08\\\" \\\\ \\xff\"",
+            entry(&req, &resp).combined()
+        );
+    }
+
+    #[test]
+    fn combined_missing_headers() {
+        let (req, resp) = (req(HeaderMap::new()), resp());
+        assert!(entry(&req, &resp).combined().ends_with(" \"-\" \"-\""));
+    }
+}
@@ -0,0 +1,273 @@
+use http::header::{AsHeaderName, HeaderValue, InvalidHeaderValue};
+use http::HeaderMap;
+
+use crate::headers::{lowercase, take_quoted, take_token};
+use crate::util::trim;
+
+const BASE64: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// An `Authorization: Basic` value. RFC 7617 does not allow a colon in the
+// user name, since the server could not tell where the password starts.
+#[must_use]
+pub fn basic(user: &str, password: &str) -> HeaderValue {
+    let mut value = b"Basic ".to_vec();
+    base64_encode(format!("{user}:{password}").as_bytes(), &mut value);
+    let mut value = HeaderValue::from_shared(value.into())
+        .unwrap_or_else(|_| unreachable!("base64 is a valid header value"));
+    value.set_sensitive(true);
+    value
+}
+
+/// An `Authorization: Bearer` value.
+///
+/// # Errors
+///
+/// Fails if the token has characters a header value cannot carry.
+pub fn bearer(token: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {token}"))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+fn base64_encode(input: &[u8], out: &mut Vec<u8>) {
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+}
+
+// One challenge from a `WWW-Authenticate` or `Proxy-Authenticate` field.
+// The scheme and parameter names are lowercased since they are
+// case-insensitive; a challenge carries either a token68 or parameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Challenge {
+    pub scheme: String,
+    pub token68: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    #[must_use]
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[must_use]
+    pub fn realm(&self) -> Option<&str> {
+        self.param("realm")
+    }
+}
+
+// The challenges in every field with the given name, in order. A field
+// that does not parse is skipped as a whole, since there is no telling
+// which of its commas separate challenges.
+pub fn challenges<K: AsHeaderName>(
+    headers: &HeaderMap,
+    name: K,
+) -> Vec<Challenge> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(parse_challenges)
+        .flatten()
+        .collect()
+}
+
+// Parses one field value, which may hold several challenges.
+pub fn parse_challenges(value: &HeaderValue) -> Option<Vec<Challenge>> {
+    let mut rest = value.as_bytes();
+    let mut challenges = Vec::new();
+    loop {
+        rest = skip_separators(rest);
+        if rest.is_empty() {
+            return Some(challenges);
+        }
+        let scheme = take_token(&mut rest)?;
+        if !rest.is_empty() && !matches!(rest[0], b' ' | b',') {
+            return None;
+        }
+        rest = trim(rest);
+        let mut challenge = Challenge {
+            scheme: lowercase(scheme),
+            token68: take_token68(&mut rest),
+            params: Vec::new(),
+        };
+        if challenge.token68.is_none() {
+            while starts_with_param(rest) {
+                let name = take_token(&mut rest)?;
+                rest = trim(rest).strip_prefix(b"=")?;
+                rest = trim(rest);
+                let value = if rest.first() == Some(&b'"') {
+                    take_quoted(&mut rest)?
+                } else {
+                    String::from_utf8(take_token(&mut rest)?.to_vec()).ok()?
+                };
+                challenge.params.push((lowercase(name), value));
+                rest = trim(rest);
+                if !rest.is_empty() && rest[0] != b',' {
+                    return None;
+                }
+                rest = skip_separators(rest);
+            }
+        }
+        challenges.push(challenge);
+    }
+}
+
+fn skip_separators(mut bytes: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t' | b',', rest @ ..] = bytes {
+        bytes = rest;
+    }
+    bytes
+}
+
+// Whether `bytes` starts with `name=`, as opposed to the scheme of the
+// next challenge.
+fn starts_with_param(mut bytes: &[u8]) -> bool {
+    take_token(&mut bytes).is_some() && trim(bytes).starts_with(b"=")
+}
+
+// Takes a token68 only if nothing but the end of the challenge follows, so
+// that `realm=x` is still read as a parameter.
+fn take_token68(bytes: &mut &[u8]) -> Option<String> {
+    let len = bytes
+        .iter()
+        .take_while(|&&b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b))
+        .count();
+    let padded = len + bytes[len..].iter().take_while(|&&b| b == b'=').count();
+    let rest = trim(&bytes[padded..]);
+    if len == 0 || !(rest.is_empty() || rest[0] == b',') {
+        return None;
+    }
+    let token = String::from_utf8_lossy(&bytes[..padded]).into_owned();
+    *bytes = rest;
+    Some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::{PROXY_AUTHENTICATE, WWW_AUTHENTICATE};
+
+    fn parse(value: &'static str) -> Option<Vec<Challenge>> {
+        parse_challenges(&HeaderValue::from_static(value))
+    }
+
+    fn challenge(
+        scheme: &str,
+        token68: Option<&str>,
+        params: &[(&str, &str)],
+    ) -> Challenge {
+        Challenge {
+            scheme: scheme.to_owned(),
+            token68: token68.map(str::to_owned),
+            params: params
+                .iter()
+                .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn basic_credentials() {
+        // The example from RFC 7617.
+        let value = basic("Aladdin", "open sesame");
+        assert_eq!("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==", value);
+        assert!(value.is_sensitive());
+        assert_eq!("Basic YTpi", basic("a", "b"));
+        assert_eq!("Basic YTpiYw==", basic("a", "bc"));
+        assert_eq!("Basic OnDDpHNz", basic("", "päss"));
+    }
+
+    #[test]
+    fn bearer_token() {
+        let value = bearer("mF_9.B5f-4.1JqM").unwrap();
+        assert_eq!("Bearer mF_9.B5f-4.1JqM", value);
+        assert!(value.is_sensitive());
+        assert!(bearer("bad\ntoken").is_err());
+    }
+
+    #[test]
+    fn several_challenges() {
+        assert_eq!(
+            Some(vec![
+                challenge("newauth", None, &[]),
+                challenge(
+                    "basic",
+                    None,
+                    &[("realm", "simple, \"really\""), ("charset", "UTF-8")],
+                ),
+                challenge("negotiate", Some("YII="), &[]),
+                challenge("bearer", None, &[("realm", "api")]),
+            ]),
+            parse(
+                "Newauth, Basic realm=\"simple, \\\"really\\\"\", \
+                 Charset = UTF-8,, Negotiate YII=, Bearer realm=api",
+            )
+        );
+    }
+
+    #[test]
+    fn challenge_params() {
+        let challenges = parse(
+            "Digest realm=\"http-auth@example.org\", qop=\"auth\", \
+             algorithm=SHA-256, nonce=\"7ypf\"",
+        )
+        .unwrap();
+        assert_eq!(1, challenges.len());
+        assert_eq!(Some("http-auth@example.org"), challenges[0].realm());
+        assert_eq!(Some("SHA-256"), challenges[0].param("Algorithm"));
+        assert_eq!(None, challenges[0].param("opaque"));
+    }
+
+    #[test]
+    fn malformed_challenges() {
+        for value in &[
+            "Basic realm=a, =b",
+            "Basic a=\"b\"c",
+            "Basic realm=\"open",
+            "Basic realm=a b",
+            "Basic/1 realm=a",
+            "=abc",
+        ] {
+            let value = HeaderValue::from_static(value);
+            assert_eq!(None, parse_challenges(&value), "{value:?}");
+        }
+        assert_eq!(Some(vec![]), parse(" , "));
+    }
+
+    #[test]
+    fn challenges_across_fields() {
+        let mut headers = HeaderMap::new();
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic ="));
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Bearer error=\"invalid_token\""),
+        );
+        headers.append(PROXY_AUTHENTICATE, HeaderValue::from_static("Other"));
+        assert_eq!(
+            vec![
+                challenge("basic", None, &[]),
+                challenge("bearer", None, &[("error", "invalid_token")]),
+            ],
+            challenges(&headers, WWW_AUTHENTICATE)
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+use std::time::SystemTime;
+
+use bytes::BytesMut;
+use h11::{Event, HttpConn, RespHead, Server};
+use http::{HeaderMap, StatusCode, Version};
+
+fn usage() -> ! {
+    eprintln!("usage: h11-check [FILE]");
+    eprintln!();
+    eprintln!("Decodes a client-to-server HTTP/1.1 byte stream from FILE (or");
+    eprintln!("stdin when FILE is omitted or `-`) and prints its events.");
+    process::exit(2);
+}
+
+fn read_input() -> io::Result<Vec<u8>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next();
+    if args.next().is_some() {
+        usage();
+    }
+    let mut input = Vec::new();
+    match path.as_deref() {
+        None | Some("-") => io::stdin().read_to_end(&mut input)?,
+        Some("-h") | Some("--help") => usage(),
+        Some(path) => File::open(path)?.read_to_end(&mut input)?,
+    };
+    Ok(input)
+}
+
+fn main() {
+    let input = match read_input() {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("h11-check: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let mut conn = HttpConn::<Server>::from_bufs(
+        8192,
+        BytesMut::from(&input[..]),
+        BytesMut::new(),
+    );
+    // The wire log tells us exactly how many bytes each event consumed,
+    // which is what lets us report offsets.
+    conn.enable_wire_log(1, SystemTime::now);
+    let mut offset = 0;
+    let mut eof_sent = false;
+
+    loop {
+        let res = conn.next_event();
+        let consumed: usize =
+            conn.take_wire_log().iter().map(|r| r.bytes.len()).sum();
+        match res {
+            Ok(Some(event)) => {
+                println!("{:>8}: {}", offset, event);
+                offset += consumed;
+                if let Event::EndOfMessage(_) = event {
+                    // Play the server's part with an empty response so
+                    // the next pipelined request can be decoded.
+                    let finished = conn
+                        .send_resp(RespHead {
+                            status: StatusCode::NO_CONTENT,
+                            version: Version::HTTP_11,
+                            headers: HeaderMap::new(),
+                            reason: None,
+                        })
+                        .and_then(|_| conn.finish_and_reuse(None));
+                    match finished {
+                        Ok((_, true)) => {}
+                        Ok((_, false)) | Err(_) => break,
+                    }
+                }
+            }
+            Ok(None) if !eof_sent => {
+                eof_sent = true;
+                if let Err(e) = conn.read_from(&mut &b""[..]) {
+                    eprintln!("error at byte {}: {}", offset, e);
+                    process::exit(1);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("error at byte {}: {}", offset, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let (in_buf, _) = conn.into_bufs();
+    if !in_buf.is_empty() {
+        eprintln!(
+            "{} trailing bytes at byte {} were not decoded",
+            in_buf.len(),
+            offset
+        );
+        process::exit(1);
+    }
+}
@@ -5,6 +5,10 @@ use http::header::{HeaderName, HeaderValue};
 use http::HeaderMap;
 use httparse::{parse_chunk_size, parse_headers, Status, EMPTY_HEADER};
 
+#[cfg(feature = "gzip")]
+use crate::coding::{transfer_codings, DecodingLimit, TransferDecoder};
+use crate::coding::{unsupported_transfer_coding, UnsupportedCoding};
+use crate::config::Config;
 use crate::event::Event;
 
 pub use self::writer::BodyWriter;
@@ -13,8 +17,11 @@ pub mod writer {
     use std::io::{Cursor, Write};
     use std::mem::size_of;
 
-    use crate::body::{BodyError, BodyResult};
+    use crate::body::{BodyError, BodyResult, FramingMethod};
+    use crate::util::maybe_content_length;
     use bytes::{BufMut, Bytes, BytesMut};
+    use http::header::{HeaderValue, CONTENT_LENGTH};
+    use http::HeaderMap;
 
     #[derive(Clone, Copy, Debug)]
     pub enum BodyWriter {
@@ -23,12 +30,80 @@ pub mod writer {
         Http10,
     }
 
+    impl BodyWriter {
+        // For bodies that are complete before the head is sent. A missing
+        // Content-Length is filled in from the body; one that disagrees
+        // with it is an error.
+        pub fn buffered(
+            headers: &mut HeaderMap,
+            len: usize,
+        ) -> BodyResult<Self> {
+            if !headers.contains_key(CONTENT_LENGTH) {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+            } else if maybe_content_length(headers) != Some(len) {
+                return Err(BodyError::ContentLengthMismatch);
+            }
+            Ok(Self::ContentLength(ContentLength(len)))
+        }
+
+        pub(crate) fn write_data(
+            &mut self,
+            buf: &mut BytesMut,
+            data: &Bytes,
+        ) -> BodyResult<Bytes> {
+            match *self {
+                Self::ContentLength(ref mut w) => w.write_chunk(data.clone()),
+                // An empty chunk would end the body early.
+                Self::Chunked if data.is_empty() => Ok(Bytes::new()),
+                Self::Chunked => write_chunked_chunk(buf, data),
+                Self::Http10 => Ok(data.clone()),
+            }
+        }
+
+        pub(crate) fn write_end(
+            &mut self,
+            buf: &mut BytesMut,
+            trailers: Option<&HeaderMap>,
+        ) -> BodyResult<Bytes> {
+            match *self {
+                Self::Chunked => {
+                    buf.extend_from_slice(b"0\r\n");
+                    for (name, value) in trailers.into_iter().flatten() {
+                        buf.extend_from_slice(name.as_str().as_bytes());
+                        buf.extend_from_slice(b": ");
+                        buf.extend_from_slice(value.as_bytes());
+                        buf.extend_from_slice(b"\r\n");
+                    }
+                    buf.extend_from_slice(b"\r\n");
+                    Ok(buf.take().freeze())
+                }
+                _ if trailers.is_some() => Err(BodyError::TrailersNotChunked),
+                Self::ContentLength(ContentLength(0)) | Self::Http10 => {
+                    Ok(Bytes::new())
+                }
+                Self::ContentLength(_) => Err(BodyError::TooLittleData),
+            }
+        }
+    }
+
+    impl From<FramingMethod> for BodyWriter {
+        fn from(m: FramingMethod) -> Self {
+            match m {
+                FramingMethod::ContentLength(n) => {
+                    Self::ContentLength(ContentLength(n))
+                }
+                FramingMethod::Chunked => Self::Chunked,
+                FramingMethod::Http10 => Self::Http10,
+            }
+        }
+    }
+
     #[derive(Clone, Copy, Debug)]
     pub struct ContentLength(usize);
 
     impl ContentLength {
         fn write_chunk(&mut self, data: Bytes) -> BodyResult<Bytes> {
-            if data.len() < self.0 {
+            if data.len() > self.0 {
                 return Err(BodyError::TooMuchData);
             }
             self.0 -= data.len();
@@ -59,28 +134,108 @@ pub mod writer {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum FramingMethod {
     ContentLength(usize),
     Chunked,
     Http10,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum BodyReader {
     ContentLength(ContentLength),
     Chunked(Chunked),
+    // A chunked body with further transfer codings, such as
+    // `Transfer-Encoding: gzip, chunked`, undone after de-chunking.
+    // The event that ends the body is held back while the decoder's last
+    // output goes out.
+    #[cfg(feature = "gzip")]
+    Decoded(Chunked, TransferDecoder, Option<Box<Event>>),
+    // A body whose transfer codings cannot be undone, when the config
+    // asks for those to be refused.
+    Unsupported(UnsupportedCoding),
     Http10,
 }
 
 impl BodyReader {
+    // Applies the transfer codings listed before `chunked` in the head's
+    // `Transfer-Encoding`. Codings that cannot be decoded are left for the
+    // caller to deal with, unless the config says to refuse them.
+    pub(crate) fn with_transfer_codings(
+        self,
+        headers: &HeaderMap,
+        config: &Config,
+    ) -> Self {
+        if !matches!(self, Self::Chunked(_)) {
+            return self;
+        }
+        if config.reject_unsupported_codings {
+            if let Some(unsupported) =
+                unsupported_transfer_coding(headers, config)
+            {
+                return Self::Unsupported(unsupported);
+            }
+        }
+        #[cfg(feature = "gzip")]
+        if !config.decode_transfer_codings {
+            return self;
+        }
+        #[cfg(feature = "gzip")]
+        if let Self::Chunked(chunked) = self {
+            let limit = DecodingLimit::new(config);
+            let decoder = transfer_codings(headers)
+                .and_then(|codings| TransferDecoder::new(&codings))
+                .map(|decoder| decoder.with_limit(limit));
+            if let Some(decoder) = decoder {
+                return Self::Decoded(chunked, decoder, None);
+            }
+        }
+        self
+    }
+
+    // Whether `Data` events come out with the transfer codings undone.
+    pub(crate) fn decodes_transfer_codings(&self) -> bool {
+        match *self {
+            #[cfg(feature = "gzip")]
+            Self::Decoded(..) => true,
+            _ => false,
+        }
+    }
+
     pub(crate) fn next_event(
         &mut self,
         buf: &mut BytesMut,
+        config: &Config,
     ) -> BodyResult<Option<Event>> {
         match *self {
             Self::ContentLength(ref mut r) => r.next_event(buf),
-            Self::Chunked(ref mut r) => r.next_event(buf),
+            Self::Chunked(ref mut r) => r.next_event(buf, config),
+            #[cfg(feature = "gzip")]
+            Self::Decoded(ref mut r, ref mut decoder, ref mut end) => loop {
+                if let Some(event) = end.take() {
+                    return Ok(Some(*event));
+                }
+                match r.next_event(buf, config)? {
+                    Some(Event::Data(data)) => {
+                        let data = decoder.decode(&data)?;
+                        if !data.is_empty() {
+                            return Ok(Some(Event::Data(data)));
+                        }
+                    }
+                    Some(event) => {
+                        let rest = decoder.finish()?;
+                        if rest.is_empty() {
+                            return Ok(Some(event));
+                        }
+                        *end = Some(Box::new(event));
+                        return Ok(Some(Event::Data(rest)));
+                    }
+                    None => return Ok(None),
+                }
+            },
+            Self::Unsupported(ref unsupported) => {
+                Err(BodyError::UnsupportedCoding(unsupported.clone()))
+            }
             Self::Http10 => Http10::next_event(buf),
         }
     }
@@ -90,6 +245,11 @@ impl BodyReader {
             Self::ContentLength(_) | Self::Chunked(_) => {
                 Err(BodyError::ConnectionClosedPrematurely)
             }
+            #[cfg(feature = "gzip")]
+            Self::Decoded(..) => Err(BodyError::ConnectionClosedPrematurely),
+            Self::Unsupported(ref unsupported) => {
+                Err(BodyError::UnsupportedCoding(unsupported.clone()))
+            }
             Self::Http10 => Ok(Event::EndOfMessage(None)),
         }
     }
@@ -101,7 +261,7 @@ impl From<FramingMethod> for BodyReader {
             FramingMethod::ContentLength(n) => {
                 Self::ContentLength(ContentLength(n))
             }
-            FramingMethod::Chunked => Self::Chunked(Chunked::Start),
+            FramingMethod::Chunked => Self::Chunked(Chunked::new()),
             FramingMethod::Http10 => Self::Http10,
         }
     }
@@ -125,7 +285,14 @@ impl ContentLength {
 }
 
 #[derive(Clone, Copy, Debug)]
-pub enum Chunked {
+pub struct Chunked {
+    state: ChunkState,
+    // Bytes of chunk extensions read so far in this message.
+    extension_bytes: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ChunkState {
     Start,
     Data(usize),
     End,
@@ -148,11 +315,37 @@ impl HeaderPos {
 }
 
 impl Chunked {
-    fn next_event(&mut self, buf: &mut BytesMut) -> BodyResult<Option<Event>> {
-        use self::Chunked::*;
+    pub(crate) fn new() -> Self {
+        Self {
+            state: ChunkState::Start,
+            extension_bytes: 0,
+        }
+    }
+
+    // `line` is a whole chunk-size line, CRLF included.
+    fn count_extensions(
+        &mut self,
+        line: &[u8],
+        config: &Config,
+    ) -> BodyResult<()> {
+        if let Some(i) = line.iter().position(|&b| b == b';') {
+            self.extension_bytes += line.len() - 2 - i;
+            if self.extension_bytes > config.max_chunk_extensions {
+                return Err(BodyError::ChunkExtensionsTooLong);
+            }
+        }
+        Ok(())
+    }
+
+    fn next_event(
+        &mut self,
+        buf: &mut BytesMut,
+        config: &Config,
+    ) -> BodyResult<Option<Event>> {
+        use self::ChunkState::{Data, End, Start, Trailers};
 
         loop {
-            match *self {
+            match self.state {
                 Start => {
                     let r = parse_chunk_size(buf);
                     if r.is_err() {
@@ -160,9 +353,20 @@ impl Chunked {
                     }
                     let st = r.unwrap();
                     match st {
+                        Status::Complete((consume, _))
+                            if consume > config.max_chunk_size_line =>
+                        {
+                            return Err(BodyError::ChunkSizeLineTooLong);
+                        }
+                        Status::Partial
+                            if buf.len() >= config.max_chunk_size_line =>
+                        {
+                            return Err(BodyError::ChunkSizeLineTooLong);
+                        }
                         Status::Complete((consume, chunk_size)) => {
-                            buf.split_to(consume);
-                            *self = if chunk_size == 0 {
+                            let line = buf.split_to(consume);
+                            self.count_extensions(&line, config)?;
+                            self.state = if chunk_size == 0 {
                                 Trailers
                             } else {
                                 Data(chunk_size as usize)
@@ -178,7 +382,7 @@ impl Chunked {
                         return Ok(None);
                     }
                     if *rem == data_buf.len() {
-                        *self = End;
+                        self.state = End;
                     } else {
                         *rem -= data_buf.len();
                     }
@@ -188,67 +392,68 @@ impl Chunked {
                     if buf.len() < 2 {
                         return Ok(None);
                     }
+                    if buf[..2] != b"\r\n"[..] {
+                        return Err(BodyError::InvalidChunkEnd);
+                    }
                     buf.split_to(2);
-                    *self = Start;
+                    self.state = Start;
                     continue;
                 }
-                Trailers => {
-                    // XXX: this is in serious need of cleanup. It would be
-                    //      incredibly nice if httparse returned offsets
-                    //      instead of slices
-                    let mut hdr_pos = [HeaderPos::new(); 20];
-                    let (consume, hdr_pos) = {
-                        let mut hdrs = [EMPTY_HEADER; 20];
-                        match parse_headers(&buf, &mut hdrs)? {
-                            Status::Complete((n, hdrs)) => {
-                                debug_assert!(hdrs.len() <= hdr_pos.len());
-                                let buf_start = buf.as_ref().as_ptr() as usize;
-                                let hdr_pos = &mut hdr_pos[..hdrs.len()];
-                                for (hdr, ref mut hdr_pos) in
-                                    hdrs.iter().zip(hdr_pos.iter_mut())
-                                {
-                                    let name_start =
-                                        hdr.name.as_bytes().as_ptr() as usize
-                                            - buf_start;
-                                    let name_end = name_start + hdr.name.len();
-                                    let value_start = hdr.value.as_ptr()
-                                        as usize
-                                        - buf_start;
-                                    let value_end =
-                                        value_start + hdr.value.len();
-                                    hdr_pos.name = (name_start, name_end);
-                                    hdr_pos.value = (value_start, value_end);
-                                }
-                                (n, hdr_pos)
-                            }
-                            Status::Partial => return Ok(None),
-                        }
-                    };
-                    let hdr_buf = buf.split_to(consume).freeze();
-
-                    if hdr_pos.is_empty() {
-                        return Ok(Some(Event::EndOfMessage(None)));
-                    }
+                Trailers => return Self::trailers(buf),
+            }
+        }
+    }
 
-                    let mut headers = HeaderMap::with_capacity(hdr_pos.len());
-                    for hdr_pos in hdr_pos.iter() {
-                        let (name_start, name_end) = hdr_pos.name;
-                        let name = HeaderName::from_bytes(
-                            &hdr_buf.slice(name_start, name_end),
-                        )
-                        .expect("header name already valid");
-                        let (value_start, value_end) = hdr_pos.value;
-                        let value = unsafe {
-                            HeaderValue::from_shared_unchecked(
-                                hdr_buf.slice(value_start, value_end),
-                            )
-                        };
-                        headers.append(name, value);
+    fn trailers(buf: &mut BytesMut) -> BodyResult<Option<Event>> {
+        // XXX: this is in serious need of cleanup. It would be
+        //      incredibly nice if httparse returned offsets
+        //      instead of slices
+        let mut hdr_pos = [HeaderPos::new(); 20];
+        let (consume, hdr_pos) = {
+            let mut hdrs = [EMPTY_HEADER; 20];
+            match parse_headers(buf, &mut hdrs)? {
+                Status::Complete((n, hdrs)) => {
+                    debug_assert!(hdrs.len() <= hdr_pos.len());
+                    let buf_start = buf.as_ref().as_ptr() as usize;
+                    let hdr_pos = &mut hdr_pos[..hdrs.len()];
+                    for (hdr, ref mut hdr_pos) in
+                        hdrs.iter().zip(hdr_pos.iter_mut())
+                    {
+                        let name_start =
+                            hdr.name.as_bytes().as_ptr() as usize - buf_start;
+                        let name_end = name_start + hdr.name.len();
+                        let value_start =
+                            hdr.value.as_ptr() as usize - buf_start;
+                        let value_end = value_start + hdr.value.len();
+                        hdr_pos.name = (name_start, name_end);
+                        hdr_pos.value = (value_start, value_end);
                     }
-                    return Ok(Some(Event::EndOfMessage(Some(headers))));
+                    (n, hdr_pos)
                 }
+                Status::Partial => return Ok(None),
             }
+        };
+        let hdr_buf = buf.split_to(consume).freeze();
+
+        if hdr_pos.is_empty() {
+            return Ok(Some(Event::EndOfMessage(None)));
         }
+
+        let mut headers = HeaderMap::with_capacity(hdr_pos.len());
+        for hdr_pos in hdr_pos.iter() {
+            let (name_start, name_end) = hdr_pos.name;
+            let name =
+                HeaderName::from_bytes(&hdr_buf.slice(name_start, name_end))
+                    .expect("header name already valid");
+            let (value_start, value_end) = hdr_pos.value;
+            let value = unsafe {
+                HeaderValue::from_shared_unchecked(
+                    hdr_buf.slice(value_start, value_end),
+                )
+            };
+            headers.append(name, value);
+        }
+        Ok(Some(Event::EndOfMessage(Some(headers))))
     }
 }
 
@@ -267,8 +472,20 @@ impl Http10 {
 #[derive(Debug)]
 pub enum BodyError {
     TooMuchData,
+    TooLittleData,
+    TrailersNotChunked,
+    ContentLengthMismatch,
     ConnectionClosedPrematurely,
     InvalidChunkSize,
+    // Chunk data was not followed by CRLF.
+    InvalidChunkEnd,
+    ChunkSizeLineTooLong,
+    // The chunk extensions of one message add up to more than the
+    // configured limit.
+    ChunkExtensionsTooLong,
+    Decoding(std::io::Error),
+    DecodedTooLarge,
+    UnsupportedCoding(UnsupportedCoding),
     IO(std::io::Error),
     HttpParse(httparse::Error),
 }
@@ -277,10 +494,35 @@ impl fmt::Display for BodyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::TooMuchData => write!(f, "Too much data to write"),
+            Self::TooLittleData => {
+                write!(f, "body ended before the declared Content-Length")
+            }
+            Self::TrailersNotChunked => {
+                write!(f, "trailers can only be sent with chunked encoding")
+            }
+            Self::ContentLengthMismatch => {
+                write!(f, "Content-Length does not match the body")
+            }
             Self::ConnectionClosedPrematurely => {
                 write!(f, "connection closed before finishing body")
             }
             Self::InvalidChunkSize => write!(f, "invalid chunk size"),
+            Self::InvalidChunkEnd => {
+                write!(f, "chunk data not followed by CRLF")
+            }
+            Self::ChunkSizeLineTooLong => {
+                write!(f, "chunk size line exceeds the configured limit")
+            }
+            Self::ChunkExtensionsTooLong => {
+                write!(f, "chunk extensions exceed the configured limit")
+            }
+            Self::Decoding(e) => {
+                write!(f, "could not decode the content coding: {e}")
+            }
+            Self::DecodedTooLarge => {
+                write!(f, "decoded body exceeds the decompression limits")
+            }
+            Self::UnsupportedCoding(e) => write!(f, "{e}"),
             Self::IO(e) => write!(f, "An IO error occurred: {}", e),
             Self::HttpParse(e) => {
                 write!(f, "An error occurred when parsing HTTP: {}", e)
@@ -292,8 +534,9 @@ impl fmt::Display for BodyError {
 impl std::error::Error for BodyError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Self::IO(e) => Some(e),
+            Self::Decoding(e) | Self::IO(e) => Some(e),
             Self::HttpParse(e) => Some(e),
+            Self::UnsupportedCoding(e) => Some(e),
             _ => None,
         }
     }
@@ -346,17 +589,19 @@ mod tests {
 
         #[test]
         fn empty_no_trailers() {
-            let mut r = Chunked::Start;
+            let mut r = Chunked::new();
             let buf = &b"0\r\n\r\n"[..];
             assert_eq!(
                 Event::EndOfMessage(None),
-                r.next_event(&mut buf.into()).unwrap().unwrap(),
+                r.next_event(&mut buf.into(), &Config::default())
+                    .unwrap()
+                    .unwrap(),
             );
         }
 
         #[test]
         fn empty_single_trailer() {
-            let mut r = Chunked::Start;
+            let mut r = Chunked::new();
             let buf = &b"0\r\nSome: header\r\n\r\n"[..];
             assert_eq!(
                 Event::EndOfMessage(Some(
@@ -368,13 +613,16 @@ mod tests {
                     .into_iter()
                     .collect()
                 )),
-                r.next_event(&mut buf.into()).unwrap().unwrap(),
+                r.next_event(&mut buf.into(), &Config::default())
+                    .unwrap()
+                    .unwrap(),
             );
         }
 
         #[test]
         fn two_chunks() {
-            let mut r = Chunked::Start;
+            let mut r = Chunked::new();
+            let config = Config::default();
             let mut buf = b"5\r\n\
                           01234\r\n\
                           10\r\n\
@@ -384,16 +632,124 @@ mod tests {
                 .into();
             assert_eq!(
                 Event::Data(b"01234"[..].into()),
-                r.next_event(&mut buf).expect("read 5 bytes").unwrap(),
+                r.next_event(&mut buf, &config)
+                    .expect("read 5 bytes")
+                    .unwrap(),
             );
             assert_eq!(
                 Event::Data(b"0123456789abcdef"[..].into()),
-                r.next_event(&mut buf).expect("read 5 bytes").unwrap(),
+                r.next_event(&mut buf, &config)
+                    .expect("read 5 bytes")
+                    .unwrap(),
             );
             assert_eq!(
                 Event::EndOfMessage(None),
-                r.next_event(&mut buf).unwrap().unwrap(),
+                r.next_event(&mut buf, &config).unwrap().unwrap(),
             );
         }
+
+        #[test]
+        fn chunk_size_line_too_long() {
+            let mut r = Chunked::new();
+            let config = Config {
+                max_chunk_size_line: 16,
+                ..Config::default()
+            };
+            let buf = &b"5;ext=aaaaaaaaaaaaaaaa\r\n01234\r\n"[..];
+            match r.next_event(&mut buf.into(), &config) {
+                Err(BodyError::ChunkSizeLineTooLong) => {}
+                r => panic!("expected line too long, got {:?}", r),
+            }
+        }
+
+        #[test]
+        fn partial_chunk_size_line_too_long() {
+            let mut r = Chunked::new();
+            let config = Config {
+                max_chunk_size_line: 16,
+                ..Config::default()
+            };
+            let buf = &b"5;ext=aaaaaaaaaaaaaaaa"[..];
+            match r.next_event(&mut buf.into(), &config) {
+                Err(BodyError::ChunkSizeLineTooLong) => {}
+                r => panic!("expected line too long, got {:?}", r),
+            }
+        }
+
+        #[test]
+        fn chunk_extensions_add_up() {
+            let mut r = Chunked::new();
+            let config = Config {
+                max_chunk_extensions: 16,
+                ..Config::default()
+            };
+            let mut buf = b"1;a=bbbb\r\nx\r\n1;c=dddd\r\ny\r\n\
+                            1;e=ffff\r\nz\r\n0\r\n\r\n"[..]
+                .into();
+            for _ in 0..2 {
+                assert!(r.next_event(&mut buf, &config).unwrap().is_some());
+            }
+            match r.next_event(&mut buf, &config) {
+                Err(BodyError::ChunkExtensionsTooLong) => {}
+                r => panic!("expected extensions too long, got {:?}", r),
+            }
+        }
+
+        #[test]
+        fn chunk_extensions_within_limit() {
+            let mut r = Chunked::new();
+            let buf = &b"5;ext=value\r\n01234\r\n"[..];
+            assert_eq!(
+                Event::Data(b"01234"[..].into()),
+                r.next_event(&mut buf.into(), &Config::default())
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+    }
+
+    mod writer {
+        use super::*;
+
+        use bytes::Bytes;
+
+        #[test]
+        fn content_length_counts_bytes() {
+            let mut w = BodyWriter::from(FramingMethod::ContentLength(5));
+            let mut buf = BytesMut::new();
+            let data = Bytes::from_static(b"hello");
+            assert_eq!(data, w.write_data(&mut buf, &data).unwrap());
+            assert!(w.write_data(&mut buf, &data).is_err());
+            assert!(w.write_end(&mut buf, None).unwrap().is_empty());
+
+            let mut w = BodyWriter::from(FramingMethod::ContentLength(5));
+            assert!(w.write_end(&mut buf, None).is_err());
+        }
+
+        #[test]
+        fn chunked_frames_data_and_trailers() {
+            let mut w = BodyWriter::Chunked;
+            let mut buf = BytesMut::new();
+            assert_eq!(
+                &b"a\r\n1234567890\r\n"[..],
+                &w.write_data(&mut buf, &Bytes::from_static(b"1234567890"))
+                    .unwrap()[..]
+            );
+            assert!(w.write_data(&mut buf, &Bytes::new()).unwrap().is_empty());
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+            assert_eq!(
+                &b"0\r\nx-checksum: abc\r\n\r\n"[..],
+                &w.write_end(&mut buf, Some(&trailers)).unwrap()[..]
+            );
+        }
+
+        #[test]
+        fn trailers_need_chunked() {
+            let mut w = BodyWriter::Http10;
+            assert!(w
+                .write_end(&mut BytesMut::new(), Some(&HeaderMap::new()))
+                .is_err());
+        }
     }
 }
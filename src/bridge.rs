@@ -0,0 +1,189 @@
+use std::io::Read;
+
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response};
+
+use crate::conn::{Error, HttpConn, Server};
+use crate::event::Event;
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+
+// The body of a request, read from the connection it arrived on. Being
+// sans-IO, `data` returning `None` before `is_done` means the bytes read
+// from the client have to be given to `feed` or `read_from` first.
+#[derive(Debug)]
+pub struct Body<'a> {
+    conn: &'a mut HttpConn<Server>,
+    trailers: Option<HeaderMap>,
+    done: bool,
+}
+
+impl<'a> Body<'a> {
+    pub fn new(conn: &'a mut HttpConn<Server>) -> Self {
+        Self {
+            conn,
+            trailers: None,
+            done: false,
+        }
+    }
+
+    #[must_use]
+    pub fn get_ref(&self) -> &HttpConn<Server> {
+        self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut HttpConn<Server> {
+        self.conn
+    }
+
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::feed` would.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.conn.feed(bytes)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::read_from` would.
+    pub fn read_from<I: Read>(&mut self, r: &mut I) -> Result<usize, Error> {
+        self.conn.read_from(r)
+    }
+
+    /// The next chunk of body data. The body also ends, with no trailers,
+    /// if the client goes away mid-body; the connection reports that as
+    /// an error on the next call.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::next_event` would.
+    pub fn data(&mut self) -> Result<Option<Bytes>, Error> {
+        while !self.done {
+            match self.conn.next_event()? {
+                Some(Event::Data(data)) if data.is_empty() => {}
+                Some(Event::Data(data)) => return Ok(Some(data)),
+                Some(Event::Trailers(trailers)) => {
+                    self.trailers = Some(trailers);
+                }
+                Some(Event::EndOfMessage(trailers)) => {
+                    self.trailers = trailers.or_else(|| self.trailers.take());
+                    self.done = true;
+                }
+                Some(_) => self.done = true,
+                None => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+
+    #[must_use]
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    #[must_use]
+    pub fn into_trailers(self) -> Option<HeaderMap> {
+        self.trailers
+    }
+}
+
+// The `http::Request` for a request head the server just received, with
+// a body backed by the connection, for handing to a framework's service.
+pub fn request(
+    conn: &mut HttpConn<Server>,
+    head: ReqHead,
+) -> Request<Body<'_>> {
+    into_request(head, Body::new(conn))
+}
+
+pub fn into_request<B>(head: ReqHead, body: B) -> Request<B> {
+    let mut req = Request::new(body);
+    *req.method_mut() = head.method;
+    *req.uri_mut() = head.uri;
+    *req.version_mut() = head.version;
+    *req.headers_mut() = head.headers;
+    req
+}
+
+// Splits a service's response into the head to send and its body.
+// Extensions are dropped, and the reason phrase is the canonical one.
+pub fn into_response_head<B>(resp: Response<B>) -> (RespHead, B) {
+    let (parts, body) = resp.into_parts();
+    let head = RespHead {
+        status: parts.status,
+        version: parts.version,
+        headers: parts.headers,
+        reason: None,
+    };
+    (head, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::{HeaderValue, CONTENT_LENGTH};
+    use http::{Method, StatusCode, Version};
+
+    fn next_request(conn: &mut HttpConn<Server>) -> ReqHead {
+        match conn.next_event() {
+            Ok(Some(Event::Request(head))) => head,
+            r => panic!("expected a request, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn request_with_body() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(
+            b"POST /upload?x=1 HTTP/1.1\r\nhost: example.com\r\n\
+              transfer-encoding: chunked\r\n\r\n3\r\nabc\r\n",
+        )
+        .unwrap();
+        let head = next_request(&mut conn);
+        let mut req = request(&mut conn, head);
+        assert_eq!(Method::POST, *req.method());
+        assert_eq!("/upload?x=1", req.uri());
+        assert_eq!(Version::HTTP_11, req.version());
+        assert_eq!("example.com", req.headers()["host"]);
+
+        let body = req.body_mut();
+        assert_eq!(Some(Bytes::from("abc")), body.data().unwrap());
+        assert_eq!(None, body.data().unwrap());
+        assert!(!body.is_done());
+        body.feed(b"0\r\nx-sum: 3\r\n\r\n").unwrap();
+        assert_eq!(None, body.data().unwrap());
+        assert!(body.is_done());
+        assert_eq!("3", body.trailers().unwrap()["x-sum"]);
+    }
+
+    #[test]
+    fn request_without_body() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n").unwrap();
+        let head = next_request(&mut conn);
+        let (_, mut body) = request(&mut conn, head).into_parts();
+        assert_eq!(None, body.data().unwrap());
+        assert!(body.is_done());
+        assert!(body.into_trailers().is_none());
+    }
+
+    #[test]
+    fn response_head() {
+        let resp = Response::builder()
+            .status(StatusCode::CREATED)
+            .header(CONTENT_LENGTH, HeaderValue::from(2))
+            .body("ok")
+            .unwrap();
+        let (head, body) = into_response_head(resp);
+        assert_eq!(StatusCode::CREATED, head.status);
+        assert_eq!(Version::HTTP_11, head.version);
+        assert_eq!("2", head.headers[CONTENT_LENGTH]);
+        assert_eq!("ok", body);
+    }
+}
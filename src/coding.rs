@@ -0,0 +1,2008 @@
+#[cfg(feature = "zstd")]
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::mem;
+
+use bytes::Bytes;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use bytes::BytesMut;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use http::header::CONTENT_TYPE;
+use http::header::{
+    HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH,
+    TRANSFER_ENCODING, VARY,
+};
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use http::Version;
+use http::{HeaderMap, Method, StatusCode};
+
+use crate::body::{BodyError, BodyReader, BodyResult, FramingMethod};
+use crate::config::Config;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use crate::conn::Server;
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+use crate::headers::split_all;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use crate::headers::{parse_media_type, MediaType};
+use crate::resp::RespHead;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use crate::resp::StatusClass;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+use crate::util::maybe_content_length;
+use crate::util::{has_token, token_eq};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentCoding {
+    // `x-gzip` is an alias kept for old clients. `identity` is not a
+    // coding at all and gives `None`, as does anything unknown.
+    #[must_use]
+    pub fn from_token(token: &[u8]) -> Option<Self> {
+        if token_eq(token, "gzip") || token_eq(token, "x-gzip") {
+            Some(Self::Gzip)
+        } else if token_eq(token, "deflate") {
+            Some(Self::Deflate)
+        } else if token_eq(token, "br") {
+            Some(Self::Brotli)
+        } else if token_eq(token, "zstd") {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    // Whether this build can decode the coding.
+    #[must_use]
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Gzip | Self::Deflate => cfg!(feature = "gzip"),
+            Self::Brotli => cfg!(feature = "brotli"),
+            Self::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+}
+
+// The codings listed in `Content-Encoding`, in the order they were
+// applied, skipping `identity`. `None` if any is unknown.
+pub fn content_codings(headers: &HeaderMap) -> Option<Vec<ContentCoding>> {
+    split_all(headers, CONTENT_ENCODING)
+        .into_iter()
+        .filter(|token| !token_eq(token, "identity"))
+        .map(ContentCoding::from_token)
+        .collect()
+}
+
+// Whether `Content-Encoding` lists any coding other than `identity`.
+pub fn is_compressed(headers: &HeaderMap) -> bool {
+    split_all(headers, CONTENT_ENCODING)
+        .into_iter()
+        .any(|token| !token_eq(token, "identity"))
+}
+
+// A coding this build cannot undo. Content codings are answered with 415
+// Unsupported Media Type and transfer codings with 501 Not Implemented.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsupportedCoding {
+    pub coding: String,
+    pub transfer: bool,
+}
+
+impl UnsupportedCoding {
+    // The status a server should answer with.
+    #[must_use]
+    pub fn status_hint(&self) -> StatusCode {
+        if self.transfer {
+            StatusCode::NOT_IMPLEMENTED
+        } else {
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        }
+    }
+}
+
+impl fmt::Display for UnsupportedCoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.transfer { "transfer" } else { "content" };
+        write!(f, "unsupported {} coding: {}", kind, self.coding)
+    }
+}
+
+impl std::error::Error for UnsupportedCoding {}
+
+fn first_unsupported(
+    tokens: Vec<&[u8]>,
+    supported: impl Fn(ContentCoding) -> bool,
+    transfer: bool,
+) -> Option<UnsupportedCoding> {
+    coding_tokens(tokens)
+        .into_iter()
+        .find(|token| {
+            !ContentCoding::from_token(token.as_bytes())
+                .is_some_and(&supported)
+        })
+        .map(|coding| UnsupportedCoding { coding, transfer })
+}
+
+fn unsupported_content_coding(
+    headers: &HeaderMap,
+) -> Option<UnsupportedCoding> {
+    first_unsupported(
+        split_all(headers, CONTENT_ENCODING),
+        ContentCoding::is_supported,
+        false,
+    )
+}
+
+// The first coding before the final `chunked` that `BodyReader` cannot
+// undo, which is all of them unless the config asks for them decoded.
+pub(crate) fn unsupported_transfer_coding(
+    headers: &HeaderMap,
+    config: &Config,
+) -> Option<UnsupportedCoding> {
+    let mut tokens = split_all(headers, TRANSFER_ENCODING);
+    tokens.pop();
+    first_unsupported(
+        tokens,
+        |coding| {
+            cfg!(feature = "gzip")
+                && config.decode_transfer_codings
+                && matches!(
+                    coding,
+                    ContentCoding::Gzip | ContentCoding::Deflate
+                )
+        },
+        true,
+    )
+}
+
+// What the `Data` bytes of a message are still encoded with, as tokens in
+// the order the codings were applied. Unknown codings are included, so a
+// caller that cannot decode the body can still tell what it holds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataEncoding {
+    // From `Content-Encoding`.
+    pub content: Vec<String>,
+    // From `Transfer-Encoding`, less the final `chunked` and any codings
+    // the connection undid itself.
+    pub transfer: Vec<String>,
+}
+
+impl DataEncoding {
+    pub(crate) fn for_body(headers: &HeaderMap, reader: &BodyReader) -> Self {
+        let mut transfer = split_all(headers, TRANSFER_ENCODING);
+        if transfer.last().is_some_and(|t| token_eq(t, "chunked")) {
+            transfer.pop();
+        }
+        if reader.decodes_transfer_codings() {
+            transfer.clear();
+        }
+        Self {
+            content: coding_tokens(split_all(headers, CONTENT_ENCODING)),
+            transfer: coding_tokens(transfer),
+        }
+    }
+
+    // True when the bytes are the representation itself.
+    #[must_use]
+    pub fn is_identity(&self) -> bool {
+        self.content.is_empty() && self.transfer.is_empty()
+    }
+}
+
+fn coding_tokens(tokens: Vec<&[u8]>) -> Vec<String> {
+    tokens
+        .into_iter()
+        .filter(|token| !token_eq(token, "identity"))
+        // Tokens are always ASCII.
+        .map(|token| String::from_utf8_lossy(token).to_ascii_lowercase())
+        .collect()
+}
+
+// Input is decoded this much at a time, so the expansion ratio is judged
+// against what has actually been taken in.
+const DECODE_STEP: usize = 1024;
+
+// Output below this is never held to the expansion ratio, since short
+// bodies of repetitive text legitimately compress very well.
+const RATIO_GRACE: usize = 64 * 1024;
+
+// Counts what a decoder has taken in and given out against the config's
+// decompression limits.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DecodingLimit {
+    max_size: Option<usize>,
+    max_ratio: Option<usize>,
+    input: usize,
+    output: usize,
+}
+
+impl DecodingLimit {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            max_size: config.max_decoded_size,
+            max_ratio: config.max_decoding_ratio,
+            ..Self::default()
+        }
+    }
+
+    fn record(&mut self, input: usize, output: usize) -> BodyResult<()> {
+        self.input = self.input.saturating_add(input);
+        self.output = self.output.saturating_add(output);
+        let too_large = self.max_size.is_some_and(|max| self.output > max);
+        let too_expanded = self.output > RATIO_GRACE
+            && self
+                .max_ratio
+                .is_some_and(|max| self.output / self.input.max(1) > max);
+        if too_large || too_expanded {
+            return Err(BodyError::DecodedTooLarge);
+        }
+        Ok(())
+    }
+}
+
+// Where a chain of decoders ends. Each piece written to it is held to the
+// limits before it is kept, so decoding stops as soon as one is crossed.
+#[derive(Debug, Default)]
+struct Sink {
+    out: Vec<u8>,
+    limit: DecodingLimit,
+    exceeded: bool,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.limit.record(0, buf.len()).is_err() {
+            self.exceeded = true;
+            return Err(io::Error::other("decoded body too large"));
+        }
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decoders chained so that each writes what it decodes, a buffer at a
+// time, straight into the next, ending at the `Sink`.
+enum Pipe {
+    Sink(Sink),
+    #[cfg(feature = "gzip")]
+    Gzip(Box<flate2::write::GzDecoder<Pipe>>),
+    #[cfg(feature = "gzip")]
+    Deflate(Box<flate2::write::ZlibDecoder<Pipe>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::DecompressorWriter<Pipe>>),
+    #[cfg(feature = "zstd")]
+    Zstd(Box<zstd::stream::write::Decoder<'static, Pipe>>),
+}
+
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+impl fmt::Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Sink(ref s) => s.fmt(f),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(ref d) => {
+                f.debug_tuple("Gzip").field(d.get_ref()).finish()
+            }
+            #[cfg(feature = "gzip")]
+            Self::Deflate(ref d) => {
+                f.debug_tuple("Deflate").field(d.get_ref()).finish()
+            }
+            #[cfg(feature = "brotli")]
+            Self::Brotli(ref d) => {
+                f.debug_tuple("Brotli").field(d.get_ref()).finish()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd(ref d) => {
+                f.debug_tuple("Zstd").field(d.get_ref()).finish()
+            }
+        }
+    }
+}
+
+impl Pipe {
+    // Undoes `codings`, given in the order they were applied. `None` when
+    // this build cannot decode one of them.
+    fn for_codings(
+        codings: &[ContentCoding],
+        limit: DecodingLimit,
+    ) -> Option<Self> {
+        let sink = Self::Sink(Sink {
+            limit,
+            ..Sink::default()
+        });
+        codings
+            .iter()
+            .try_fold(sink, |next, &coding| Self::new(coding, next))
+    }
+
+    #[allow(unused_variables, clippy::needless_pass_by_value)]
+    fn new(coding: ContentCoding, next: Self) -> Option<Self> {
+        match coding {
+            #[cfg(feature = "gzip")]
+            ContentCoding::Gzip => {
+                Some(Self::Gzip(Box::new(flate2::write::GzDecoder::new(next))))
+            }
+            #[cfg(feature = "gzip")]
+            ContentCoding::Deflate => Some(Self::Deflate(Box::new(
+                flate2::write::ZlibDecoder::new(next),
+            ))),
+            #[cfg(feature = "brotli")]
+            ContentCoding::Brotli => Some(Self::Brotli(Box::new(
+                brotli::DecompressorWriter::new(next, BROTLI_BUFFER_SIZE),
+            ))),
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => zstd::stream::write::Decoder::new(next)
+                .ok()
+                .map(|d| Self::Zstd(Box::new(d))),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn sink(&mut self) -> &mut Sink {
+        match *self {
+            Self::Sink(ref mut s) => s,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(ref mut d) => d.get_mut().sink(),
+            #[cfg(feature = "gzip")]
+            Self::Deflate(ref mut d) => d.get_mut().sink(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(ref mut d) => d.get_mut().sink(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(ref mut d) => d.get_mut().sink(),
+        }
+    }
+
+    // Flushes each stage into the next once the input is over, failing
+    // if the encoded data was cut short.
+    #[allow(clippy::unnecessary_wraps)]
+    fn finish(&mut self) -> io::Result<()> {
+        match *self {
+            Self::Sink(_) => Ok(()),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(ref mut d) => {
+                d.try_finish()?;
+                d.get_mut().finish()
+            }
+            #[cfg(feature = "gzip")]
+            Self::Deflate(ref mut d) => {
+                d.try_finish()?;
+                d.get_mut().finish()
+            }
+            #[cfg(feature = "brotli")]
+            Self::Brotli(ref mut d) => {
+                d.close()?;
+                d.get_mut().finish()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd(ref mut d) => {
+                d.flush()?;
+                d.get_mut().finish()
+            }
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Self::Sink(ref mut s) => s.write(buf),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(ref mut d) => d.write(buf),
+            #[cfg(feature = "gzip")]
+            Self::Deflate(ref mut d) => d.write(buf),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(ref mut d) => d.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(ref mut d) => d.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Self::Sink(ref mut s) => s.flush(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(ref mut d) => d.flush(),
+            #[cfg(feature = "gzip")]
+            Self::Deflate(ref mut d) => d.flush(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(ref mut d) => d.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(ref mut d) => d.flush(),
+        }
+    }
+}
+
+// Undoes a message's content codings as its body arrives.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    pipe: Pipe,
+}
+
+impl Decoder {
+    // `None` when there is nothing to decode, or when some coding cannot
+    // be decoded by this build, in which case the body is left as sent.
+    pub(crate) fn for_headers(headers: &HeaderMap) -> Option<Self> {
+        let codings = content_codings(headers)?;
+        if codings.is_empty() {
+            return None;
+        }
+        let pipe = Pipe::for_codings(&codings, DecodingLimit::default())?;
+        Some(Self { pipe })
+    }
+
+    pub(crate) fn with_limit(mut self, limit: DecodingLimit) -> Self {
+        self.pipe.sink().limit = limit;
+        self
+    }
+
+    pub(crate) fn decode(&mut self, data: &[u8]) -> BodyResult<Bytes> {
+        for step in data.chunks(DECODE_STEP) {
+            self.pipe.sink().limit.record(step.len(), 0)?;
+            let res = self.pipe.write_all(step);
+            self.check(res)?;
+        }
+        Ok(self.take_output())
+    }
+
+    // Flushes out whatever the stages still hold once the body is over,
+    // failing if the encoded data was cut short.
+    pub(crate) fn finish(&mut self) -> BodyResult<Bytes> {
+        let res = self.pipe.finish();
+        self.check(res)?;
+        Ok(self.take_output())
+    }
+
+    fn check(&mut self, res: io::Result<()>) -> BodyResult<()> {
+        match res {
+            Ok(()) => Ok(()),
+            Err(_) if self.pipe.sink().exceeded => {
+                Err(BodyError::DecodedTooLarge)
+            }
+            Err(e) => Err(BodyError::Decoding(e)),
+        }
+    }
+
+    fn take_output(&mut self) -> Bytes {
+        Bytes::from(mem::take(&mut self.pipe.sink().out))
+    }
+}
+
+// Wraps a connection so received bodies come out with their content
+// codings undone. Heads are passed on untouched, so the original
+// `Content-Encoding` and `Content-Length` stay visible. Bodies using a
+// coding this build cannot decode are passed on as sent.
+#[derive(Debug)]
+pub struct DecodingConn<R> {
+    conn: HttpConn<R>,
+    decoder: Option<Decoder>,
+    unsupported: Option<UnsupportedCoding>,
+    pending: Option<Event>,
+}
+
+impl<R: Role> DecodingConn<R> {
+    pub fn new(conn: HttpConn<R>) -> Self {
+        Self {
+            conn,
+            decoder: None,
+            unsupported: None,
+            pending: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &HttpConn<R> {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut HttpConn<R> {
+        &mut self.conn
+    }
+
+    pub fn into_inner(self) -> HttpConn<R> {
+        self.conn
+    }
+
+    pub fn is_decoding(&self) -> bool {
+        self.decoder.is_some()
+    }
+
+    // As `HttpConn::data_encoding`, less the content codings this wrapper
+    // undoes.
+    pub fn data_encoding(&self) -> Option<DataEncoding> {
+        let mut encoding = self.conn.data_encoding()?.clone();
+        if self.decoder.is_some() {
+            encoding.content.clear();
+        }
+        Some(encoding)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::next_event` would, or if the body uses a coding
+    /// this build cannot decode or fails to decode.
+    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        if let Some(event) = self.pending.take() {
+            return Ok(Some(event));
+        }
+        loop {
+            let Some(event) = self.conn.next_event()? else {
+                return Ok(None);
+            };
+            if matches!(
+                event,
+                Event::Data(_) | Event::Trailers(_) | Event::EndOfMessage(_)
+            ) {
+                if let Some(unsupported) = self.unsupported.take() {
+                    return Err(
+                        BodyError::UnsupportedCoding(unsupported).into()
+                    );
+                }
+            }
+            let decoder = match (&event, self.decoder.as_mut()) {
+                (Event::Request(req), _) => {
+                    let has_body = req.framing_method()
+                        != FramingMethod::ContentLength(0);
+                    self.start_decoding(&req.headers, has_body);
+                    return Ok(Some(event));
+                }
+                (Event::Response(resp), _) => {
+                    let method =
+                        self.conn.request_method().unwrap_or(&Method::GET);
+                    let has_body = resp.framing_method(method)
+                        != FramingMethod::ContentLength(0);
+                    self.start_decoding(&resp.headers, has_body);
+                    return Ok(Some(event));
+                }
+                (_, Some(decoder)) => decoder,
+                (_, None) => return Ok(Some(event)),
+            };
+            match event {
+                Event::Data(data) => {
+                    let data = decoder.decode(&data)?;
+                    if !data.is_empty() {
+                        return Ok(Some(Event::Data(data)));
+                    }
+                }
+                end @ (Event::Trailers(_) | Event::EndOfMessage(_)) => {
+                    let rest = decoder.finish()?;
+                    self.decoder = None;
+                    if rest.is_empty() {
+                        return Ok(Some(end));
+                    }
+                    self.pending = Some(end);
+                    return Ok(Some(Event::Data(rest)));
+                }
+                event => return Ok(Some(event)),
+            }
+        }
+    }
+
+    // A message without a body, such as a 304 or the response to a HEAD,
+    // still names the coding its body would have had, but there is
+    // nothing to decode.
+    fn start_decoding(&mut self, headers: &HeaderMap, has_body: bool) {
+        self.decoder = None;
+        self.unsupported = None;
+        if !has_body {
+            return;
+        }
+        let config = self.conn.config();
+        let limit = DecodingLimit::new(config);
+        self.decoder =
+            Decoder::for_headers(headers).map(|d| d.with_limit(limit));
+        if self.decoder.is_none() && config.reject_unsupported_codings {
+            self.unsupported = unsupported_content_coding(headers);
+        }
+    }
+}
+
+// The transfer codings applied beneath `chunked`, such as the `gzip` in
+// `Transfer-Encoding: gzip, chunked`, in the order they were applied.
+// `identity` is skipped, and `None` means some coding is unknown.
+pub(crate) fn transfer_codings(
+    headers: &HeaderMap,
+) -> Option<Vec<ContentCoding>> {
+    let mut tokens = split_all(headers, TRANSFER_ENCODING);
+    tokens.pop();
+    tokens
+        .into_iter()
+        .filter(|token| !token_eq(token, "identity"))
+        .map(|token| match ContentCoding::from_token(token) {
+            Some(ContentCoding::Brotli | ContentCoding::Zstd) | None => None,
+            coding => coding,
+        })
+        .collect()
+}
+
+// Undoes transfer codings as a chunked body arrives, through the same
+// decoders as `Decoder`. Their state cannot be copied, so a clone of a
+// connection taken part way through such a body fails it with a decoding
+// error rather than sharing the original's state.
+#[cfg(feature = "gzip")]
+#[derive(Debug)]
+pub(crate) struct TransferDecoder(Option<Decoder>);
+
+#[cfg(feature = "gzip")]
+impl Clone for TransferDecoder {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl TransferDecoder {
+    pub(crate) fn new(codings: &[ContentCoding]) -> Option<Self> {
+        if codings.is_empty() {
+            return None;
+        }
+        let pipe = Pipe::for_codings(codings, DecodingLimit::default())?;
+        Some(Self(Some(Decoder { pipe })))
+    }
+
+    pub(crate) fn with_limit(mut self, limit: DecodingLimit) -> Self {
+        self.0 = self.0.map(|decoder| decoder.with_limit(limit));
+        self
+    }
+
+    pub(crate) fn decode(&mut self, data: &[u8]) -> BodyResult<Bytes> {
+        self.decoder()?.decode(data)
+    }
+
+    pub(crate) fn finish(&mut self) -> BodyResult<Bytes> {
+        self.decoder()?.finish()
+    }
+
+    fn decoder(&mut self) -> BodyResult<&mut Decoder> {
+        self.0.as_mut().ok_or_else(|| {
+            BodyError::Decoding(io::Error::other(
+                "transfer decoding state was lost to a clone",
+            ))
+        })
+    }
+}
+
+// Picks the coding to compress a response with from the request's
+// `Accept-Encoding`: the supported coding with the highest quality, with
+// ties going to the one that compresses best. Codings the client did not
+// mention are acceptable only through `*`. `None` means sending the body
+// as is.
+pub fn negotiate(request_headers: &HeaderMap) -> Option<ContentCoding> {
+    let mut wildcard = None;
+    let mut listed = Vec::new();
+    for element in split_all(request_headers, ACCEPT_ENCODING) {
+        let mut params = element.split(|&b| b == b';');
+        let token = params.next().unwrap_or_default();
+        let q = params
+            .find_map(|param| {
+                let param = std::str::from_utf8(param).ok()?.trim();
+                let value = param
+                    .strip_prefix("q=")
+                    .or_else(|| param.strip_prefix("Q="))?;
+                value.parse::<f32>().ok()
+            })
+            .unwrap_or(1.0);
+        if token_eq(token, "*") {
+            wildcard = Some(q);
+        } else if let Some(coding) = ContentCoding::from_token(token) {
+            listed.push((coding, q));
+        }
+    }
+    let mut best: Option<(ContentCoding, f32)> = None;
+    for &coding in &[
+        ContentCoding::Brotli,
+        ContentCoding::Zstd,
+        ContentCoding::Gzip,
+        ContentCoding::Deflate,
+    ] {
+        if !coding.is_supported() {
+            continue;
+        }
+        let q = listed
+            .iter()
+            .find(|&&(c, _)| c == coding)
+            .map(|&(_, q)| q)
+            .or(wildcard)
+            .unwrap_or(0.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+// Media types that are already compressed, so compressing them again
+// costs CPU for next to nothing.
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+const PRECOMPRESSED_TYPES: &[&str] = &[
+    "audio/*",
+    "video/*",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "application/gzip",
+    "application/zip",
+    "application/zstd",
+    "font/woff",
+    "font/woff2",
+];
+
+// Decides which responses `EncodingConn` compresses, and how hard.
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+#[derive(Clone, Debug)]
+pub struct CompressionConfig {
+    // 0-9 for gzip and deflate, 0-11 for brotli and 1-22 for zstd; larger
+    // values are clamped. `None` uses each coding's default.
+    pub level: Option<u32>,
+    // Responses whose `Content-Length` is below this are sent as they
+    // are. Bodies of unknown length are always compressed.
+    pub min_size: usize,
+    // If set, only these media types are compressed. Entries are either
+    // a full type such as `text/html` or a wildcard such as `text/*`.
+    pub content_types: Option<Vec<String>>,
+    // Media types that are never compressed, in the same form.
+    pub skip_content_types: Vec<String>,
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+impl CompressionConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            level: None,
+            min_size: 256,
+            content_types: None,
+            skip_content_types: PRECOMPRESSED_TYPES
+                .iter()
+                .map(|&t| t.to_owned())
+                .collect(),
+        }
+    }
+
+    // Whether a response with these headers is worth compressing.
+    pub fn should_compress(&self, resp: &RespHead) -> bool {
+        let len = maybe_content_length(&resp.headers);
+        if len.is_some_and(|len| len < self.min_size) {
+            return false;
+        }
+        let media_type =
+            resp.headers.get(CONTENT_TYPE).and_then(parse_media_type);
+        let matches = |types: &[String]| {
+            media_type
+                .as_ref()
+                .is_some_and(|mt| types.iter().any(|t| type_matches(t, mt)))
+        };
+        if let Some(ref allowed) = self.content_types {
+            if !matches(allowed) {
+                return false;
+            }
+        }
+        !matches(&self.skip_content_types)
+    }
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+fn type_matches(pattern: &str, media_type: &MediaType) -> bool {
+    match pattern.split_once('/') {
+        Some((type_, "*")) => type_.eq_ignore_ascii_case(&media_type.type_),
+        _ => pattern.eq_ignore_ascii_case(&media_type.essence()),
+    }
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+enum EncodeStage {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "gzip")]
+    Deflate(flate2::write::ZlibEncoder<Vec<u8>>),
+    // Only taken when finishing, since that consumes the writer.
+    #[cfg(feature = "brotli")]
+    Brotli(Option<Box<brotli::CompressorWriter<Vec<u8>>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 5;
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW: u32 = 22;
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+// Compresses a body with one content coding as it is sent.
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+pub struct Encoder {
+    coding: ContentCoding,
+    stage: EncodeStage,
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+impl Encoder {
+    // `None` if this build cannot produce the coding.
+    #[must_use]
+    pub fn new(coding: ContentCoding) -> Option<Self> {
+        Self::with_level(coding, None)
+    }
+
+    // As `new`, at the given level as described on `CompressionConfig`.
+    #[allow(unused_variables)]
+    pub fn with_level(
+        coding: ContentCoding,
+        level: Option<u32>,
+    ) -> Option<Self> {
+        #[cfg(feature = "gzip")]
+        let flate_level = level
+            .map_or_else(flate2::Compression::default, |l| {
+                flate2::Compression::new(l.min(9))
+            });
+        let stage = match coding {
+            #[cfg(feature = "gzip")]
+            ContentCoding::Gzip => EncodeStage::Gzip(
+                flate2::write::GzEncoder::new(Vec::new(), flate_level),
+            ),
+            #[cfg(feature = "gzip")]
+            ContentCoding::Deflate => EncodeStage::Deflate(
+                flate2::write::ZlibEncoder::new(Vec::new(), flate_level),
+            ),
+            #[cfg(feature = "brotli")]
+            ContentCoding::Brotli => EncodeStage::Brotli(Some(Box::new(
+                brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BROTLI_BUFFER_SIZE,
+                    level.map_or(BROTLI_QUALITY, |l| l.min(11)),
+                    BROTLI_WINDOW,
+                ),
+            ))),
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => {
+                let level = level.map_or(ZSTD_LEVEL, |l| {
+                    i32::try_from(l.clamp(1, 22)).unwrap_or(ZSTD_LEVEL)
+                });
+                EncodeStage::Zstd(
+                    zstd::stream::write::Encoder::new(Vec::new(), level)
+                        .ok()?,
+                )
+            }
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        };
+        Some(Self { coding, stage })
+    }
+
+    #[must_use]
+    pub fn coding(&self) -> ContentCoding {
+        self.coding
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the compressor fails to write its output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after `finish`.
+    #[allow(unused_variables)]
+    pub fn encode(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let out = match self.stage {
+            #[cfg(feature = "gzip")]
+            EncodeStage::Gzip(ref mut e) => {
+                e.write_all(data)?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "gzip")]
+            EncodeStage::Deflate(ref mut e) => {
+                e.write_all(data)?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "brotli")]
+            EncodeStage::Brotli(ref mut e) => {
+                let e = e.as_mut().expect("encoder already finished");
+                e.write_all(data)?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "zstd")]
+            EncodeStage::Zstd(ref mut e) => {
+                e.write_all(data)?;
+                mem::take(e.get_mut())
+            }
+        };
+        Ok(Bytes::from(out))
+    }
+
+    /// Pushes out everything compressed so far, at some cost in ratio, for
+    /// bodies that are streamed to the client as they are produced.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the compressor fails to write its output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called after `finish`.
+    pub fn flush(&mut self) -> io::Result<Bytes> {
+        let out = match self.stage {
+            #[cfg(feature = "gzip")]
+            EncodeStage::Gzip(ref mut e) => {
+                e.flush()?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "gzip")]
+            EncodeStage::Deflate(ref mut e) => {
+                e.flush()?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "brotli")]
+            EncodeStage::Brotli(ref mut e) => {
+                let e = e.as_mut().expect("encoder already finished");
+                e.flush()?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "zstd")]
+            EncodeStage::Zstd(ref mut e) => {
+                e.flush()?;
+                mem::take(e.get_mut())
+            }
+        };
+        Ok(Bytes::from(out))
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the compressor fails to write its output.
+    pub fn finish(&mut self) -> io::Result<Bytes> {
+        let out = match self.stage {
+            #[cfg(feature = "gzip")]
+            EncodeStage::Gzip(ref mut e) => {
+                e.try_finish()?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "gzip")]
+            EncodeStage::Deflate(ref mut e) => {
+                e.try_finish()?;
+                mem::take(e.get_mut())
+            }
+            #[cfg(feature = "brotli")]
+            EncodeStage::Brotli(ref mut e) => {
+                e.take().map(|e| e.into_inner()).unwrap_or_default()
+            }
+            #[cfg(feature = "zstd")]
+            EncodeStage::Zstd(ref mut e) => {
+                e.do_finish()?;
+                mem::take(e.get_mut())
+            }
+        };
+        Ok(Bytes::from(out))
+    }
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+impl fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Encoder")
+            .field("coding", &self.coding)
+            .finish_non_exhaustive()
+    }
+}
+
+// Marks a response as compressed with `coding`. The length of the body
+// is no longer known up front, so any `Content-Length` is dropped and the
+// body is sent chunked, and caches are told the response depends on
+// `Accept-Encoding`.
+pub fn encode_response_headers(coding: ContentCoding, resp: &mut RespHead) {
+    resp.headers
+        .append(CONTENT_ENCODING, HeaderValue::from_static(coding.as_str()));
+    resp.headers.remove(CONTENT_LENGTH);
+    resp.headers
+        .insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+    if !has_token(&resp.headers, VARY, "accept-encoding") {
+        resp.headers
+            .append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+// Wraps a server connection so response bodies are compressed with
+// whatever coding the client accepts best.
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+#[derive(Debug)]
+pub struct EncodingConn {
+    conn: HttpConn<Server>,
+    config: CompressionConfig,
+    encoder: Option<Encoder>,
+}
+
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+impl EncodingConn {
+    pub fn new(conn: HttpConn<Server>) -> Self {
+        Self {
+            conn,
+            config: CompressionConfig::new(),
+            encoder: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_config(mut self, config: CompressionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &CompressionConfig {
+        &self.config
+    }
+
+    pub fn get_ref(&self) -> &HttpConn<Server> {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut HttpConn<Server> {
+        &mut self.conn
+    }
+
+    pub fn into_inner(self) -> HttpConn<Server> {
+        self.conn
+    }
+
+    pub fn coding(&self) -> Option<ContentCoding> {
+        self.encoder.as_ref().map(Encoder::coding)
+    }
+
+    /// Sends the response head, compressed if the request allows it.
+    /// Responses that already carry a `Content-Encoding`, those that never
+    /// have a body, and those the config rules out are sent as they are.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::send_resp` would.
+    pub fn send_resp(
+        &mut self,
+        request_headers: &HeaderMap,
+        mut resp: RespHead,
+    ) -> Result<Bytes, Error> {
+        self.encoder = None;
+        let has_body = resp.class() != StatusClass::Informational
+            && !matches!(resp.status.as_u16(), 204 | 304)
+            && self.conn.request_method() != Some(&Method::HEAD);
+        if has_body
+            && !resp.headers.contains_key(CONTENT_ENCODING)
+            && self.config.should_compress(&resp)
+        {
+            let level = self.config.level;
+            if let Some(encoder) = negotiate(request_headers)
+                .and_then(|coding| Encoder::with_level(coding, level))
+            {
+                encode_response_headers(encoder.coding(), &mut resp);
+                // HTTP/1.0 clients cannot read chunked bodies, so leave
+                // the connection to delimit the body by closing.
+                if self.conn.peer_http_version() == Some(Version::HTTP_10) {
+                    resp.headers.remove(TRANSFER_ENCODING);
+                }
+                self.encoder = Some(encoder);
+            }
+        }
+        self.conn.send_resp(resp)
+    }
+
+    /// Compressed data is only sent once the encoder has produced some, so
+    /// this often returns nothing.
+    ///
+    /// # Errors
+    ///
+    /// Fails if compressing the data fails, or as `HttpConn::send_data`
+    /// would.
+    pub fn send_data(&mut self, data: &[u8]) -> Result<Bytes, Error> {
+        match self.encoder {
+            Some(ref mut encoder) => {
+                let data = encoder.encode(data)?;
+                self.send_encoded(data)
+            }
+            None => self.conn.send_data(Bytes::from(data)),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Fails if compressing the data fails, or as `HttpConn::send_data`
+    /// would.
+    pub fn flush(&mut self) -> Result<Bytes, Error> {
+        match self.encoder {
+            Some(ref mut encoder) => {
+                let data = encoder.flush()?;
+                self.send_encoded(data)
+            }
+            None => Ok(Bytes::new()),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Fails if compressing the rest of the body fails, or as
+    /// `HttpConn::send_end_of_message` would.
+    pub fn send_end_of_message(
+        &mut self,
+        trailers: Option<HeaderMap>,
+    ) -> Result<Bytes, Error> {
+        let rest = match self.encoder.take() {
+            Some(mut encoder) => {
+                let rest = encoder.finish()?;
+                self.send_encoded(rest)?
+            }
+            None => Bytes::new(),
+        };
+        let end = self.conn.send_end_of_message(trailers)?;
+        let mut out = BytesMut::with_capacity(rest.len() + end.len());
+        out.extend_from_slice(&rest);
+        out.extend_from_slice(&end);
+        Ok(out.freeze())
+    }
+
+    fn send_encoded(&mut self, data: Bytes) -> Result<Bytes, Error> {
+        if data.is_empty() {
+            return Ok(Bytes::new());
+        }
+        self.conn.send_data(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BytesMut;
+    use http::header::HeaderValue;
+
+    fn headers(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn parse_codings() {
+        assert_eq!(Some(vec![]), content_codings(&HeaderMap::new()));
+        assert_eq!(
+            Some(vec![ContentCoding::Deflate, ContentCoding::Gzip]),
+            content_codings(&headers("deflate, identity, X-GZIP"))
+        );
+        assert_eq!(
+            Some(vec![ContentCoding::Zstd]),
+            content_codings(&headers("zstd"))
+        );
+        assert_eq!(None, content_codings(&headers("gzip, rot13")));
+        assert!(Decoder::for_headers(&headers("identity")).is_none());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decode_stacked_codings() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::fast());
+        zlib.write_all(b"hello, hello, hello").unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::fast());
+        gzip.write_all(&zlib.finish().unwrap()).unwrap();
+        let encoded = gzip.finish().unwrap();
+
+        let mut decoder =
+            Decoder::for_headers(&headers("deflate, gzip")).unwrap();
+        let mut body = Vec::new();
+        for piece in encoded.chunks(3) {
+            body.extend_from_slice(&decoder.decode(piece).unwrap());
+        }
+        body.extend_from_slice(&decoder.finish().unwrap());
+        assert_eq!(&b"hello, hello, hello"[..], &body[..]);
+
+        let mut decoder = Decoder::for_headers(&headers("gzip")).unwrap();
+        decoder.decode(&encoded[..encoded.len() - 4]).unwrap();
+        assert!(decoder.finish().is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decoding_conn() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        use crate::conn::Client;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::fast());
+        gzip.write_all(b"hello world").unwrap();
+        let encoded = gzip.finish().unwrap();
+        let mut msg = format!(
+            "HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\n\
+             content-length: {}\r\n\r\n",
+            encoded.len()
+        )
+        .into_bytes();
+        msg.extend_from_slice(&encoded);
+
+        let mut client = HttpConn::<Client>::new();
+        client
+            .send(Event::request(
+                http::Method::GET,
+                "/".parse().unwrap(),
+                HeaderMap::new(),
+            ))
+            .unwrap();
+        client.send(Event::EndOfMessage(None)).unwrap();
+        let mut conn = DecodingConn::new(client);
+        conn.get_mut().feed(&msg).unwrap();
+        match conn.next_event().unwrap() {
+            Some(Event::Response(resp)) => {
+                assert_eq!("gzip", resp.headers[CONTENT_ENCODING]);
+            }
+            e => panic!("expected response, got {:?}", e),
+        }
+        assert!(conn.is_decoding());
+        let mut body = Vec::new();
+        loop {
+            match conn.next_event().unwrap() {
+                Some(Event::Data(data)) => body.extend_from_slice(&data),
+                Some(Event::EndOfMessage(None)) => break,
+                e => panic!("expected body, got {:?}", e),
+            }
+        }
+        assert_eq!(&b"hello world"[..], &body[..]);
+        assert!(!conn.is_decoding());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decoding_conn_skips_bodiless_responses() {
+        use crate::conn::Client;
+
+        let cases: &[(http::Method, &[u8])] = &[
+            (
+                http::Method::GET,
+                b"HTTP/1.1 304 Not Modified\r\ncontent-encoding: gzip\r\n\
+                  content-length: 31\r\n\r\n",
+            ),
+            (
+                http::Method::GET,
+                b"HTTP/1.1 204 No Content\r\ncontent-encoding: gzip\r\n\r\n",
+            ),
+            (
+                http::Method::HEAD,
+                b"HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\n\
+                  content-length: 31\r\n\r\n",
+            ),
+        ];
+        for (method, msg) in cases {
+            let mut client = HttpConn::<Client>::new();
+            client
+                .send(Event::request(
+                    method.clone(),
+                    "/".parse().unwrap(),
+                    HeaderMap::new(),
+                ))
+                .unwrap();
+            client.send(Event::EndOfMessage(None)).unwrap();
+            let mut conn = DecodingConn::new(client);
+            conn.get_mut().feed(msg).unwrap();
+            match conn.next_event().unwrap() {
+                Some(Event::Response(resp)) => {
+                    assert_eq!("gzip", resp.headers[CONTENT_ENCODING]);
+                }
+                e => panic!("expected response, got {:?}", e),
+            }
+            assert!(!conn.is_decoding());
+            match conn.next_event().unwrap() {
+                Some(Event::EndOfMessage(None)) => {}
+                e => panic!("expected end of message, got {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_transfer_codings() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRANSFER_ENCODING,
+            HeaderValue::from_static("gzip, identity, deflate, chunked"),
+        );
+        assert_eq!(
+            Some(vec![ContentCoding::Gzip, ContentCoding::Deflate]),
+            transfer_codings(&headers)
+        );
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        assert_eq!(Some(vec![]), transfer_codings(&headers));
+        headers.insert(
+            TRANSFER_ENCODING,
+            HeaderValue::from_static("br, chunked"),
+        );
+        assert_eq!(None, transfer_codings(&headers));
+    }
+
+    #[test]
+    fn data_encoding_reports_codings() {
+        use crate::conn::Server;
+
+        let mut conn = HttpConn::<Server>::new();
+        assert_eq!(None, conn.data_encoding());
+        conn.feed(
+            b"POST / HTTP/1.1\r\nhost: a\r\n\
+              content-encoding: BR, identity, x-custom\r\n\
+              transfer-encoding: rot13, chunked\r\n\r\n",
+        )
+        .unwrap();
+        conn.next_event().unwrap();
+        let encoding = conn.data_encoding().unwrap();
+        assert_eq!(vec!["br", "x-custom"], encoding.content);
+        assert_eq!(vec!["rot13"], encoding.transfer);
+        assert!(!encoding.is_identity());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n").unwrap();
+        conn.next_event().unwrap();
+        assert!(conn.data_encoding().unwrap().is_identity());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn data_encoding_after_decoding() {
+        use crate::conn::Server;
+
+        let mut conn =
+            HttpConn::<Server>::new().with_config(transfer_decoding());
+        conn.feed(
+            b"POST / HTTP/1.1\r\nhost: a\r\ncontent-encoding: gzip\r\n\
+              transfer-encoding: gzip, chunked\r\n\r\n",
+        )
+        .unwrap();
+        let mut conn = DecodingConn::new(conn);
+        conn.next_event().unwrap();
+        let encoding = conn.get_ref().data_encoding().unwrap();
+        assert_eq!(vec!["gzip"], encoding.content);
+        assert!(encoding.transfer.is_empty());
+        assert!(conn.data_encoding().unwrap().is_identity());
+    }
+
+    #[test]
+    fn head_inspection() {
+        let mut req = crate::testing::parse_req_head(&mut BytesMut::from(
+            &b"POST / HTTP/1.1\r\ncontent-encoding: identity\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert!(!req.is_compressed());
+        assert_eq!(Some(vec![]), req.content_codings());
+        req.headers
+            .append(CONTENT_ENCODING, HeaderValue::from_static("rot13"));
+        assert!(req.is_compressed());
+        assert_eq!(None, req.content_codings());
+    }
+
+    #[test]
+    fn reject_unsupported_codings() {
+        use crate::conn::Server;
+
+        fn body_error(head: &str, decoding: bool) -> Option<BodyError> {
+            let config = Config {
+                reject_unsupported_codings: true,
+                ..Config::default()
+            };
+            let mut conn = HttpConn::<Server>::new().with_config(config);
+            conn.feed(
+                format!("POST / HTTP/1.1\r\nhost: a\r\n{head}\r\n").as_bytes(),
+            )
+            .unwrap();
+            conn.feed(b"5\r\nhello\r\n0\r\n\r\n").unwrap();
+            let mut conn = DecodingConn::new(conn);
+            loop {
+                let event = if decoding {
+                    conn.next_event()
+                } else {
+                    conn.get_mut().next_event()
+                };
+                match event {
+                    Ok(Some(Event::EndOfMessage(_))) => return None,
+                    Ok(_) => {}
+                    Err(Error::HttpBody(e)) => return Some(e),
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+        }
+
+        let te = "transfer-encoding: rot13, chunked\r\n";
+        match body_error(te, false) {
+            Some(BodyError::UnsupportedCoding(e)) => {
+                assert_eq!("rot13", e.coding);
+                assert_eq!(StatusCode::NOT_IMPLEMENTED, e.status_hint());
+            }
+            e => panic!("expected unsupported coding, got {:?}", e),
+        }
+
+        let ce = "content-encoding: Rot13\r\ntransfer-encoding: chunked\r\n";
+        assert!(body_error(ce, false).is_none());
+        match body_error(ce, true) {
+            Some(BodyError::UnsupportedCoding(e)) => {
+                assert_eq!("rot13", e.coding);
+                assert_eq!(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    e.status_hint()
+                );
+            }
+            e => panic!("expected unsupported coding, got {:?}", e),
+        }
+        assert!(body_error("transfer-encoding: identity, chunked\r\n", true)
+            .is_none());
+    }
+
+    #[cfg(feature = "gzip")]
+    fn transfer_decoding() -> Config {
+        Config {
+            decode_transfer_codings: true,
+            ..Config::default()
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    fn transfer_coded_request(body: &[u8]) -> Vec<u8> {
+        let mut msg = b"POST / HTTP/1.1\r\nhost: example.com\r\n\
+            transfer-encoding: deflate, gzip, chunked\r\n\r\n"
+            .to_vec();
+        for chunk in body.chunks(7) {
+            msg.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            msg.extend_from_slice(chunk);
+            msg.extend_from_slice(b"\r\n");
+        }
+        msg.extend_from_slice(b"0\r\n\r\n");
+        msg
+    }
+
+    #[cfg(feature = "gzip")]
+    fn transfer_encode(data: &[u8]) -> Vec<u8> {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::{Compression, GzBuilder};
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::fast());
+        zlib.write_all(data).unwrap();
+        let mut gzip: GzEncoder<Vec<u8>> = GzBuilder::new()
+            .filename("body.txt")
+            .comment("a comment")
+            .extra(vec![1, 2, 3])
+            .write(Vec::new(), Compression::fast());
+        gzip.write_all(&zlib.finish().unwrap()).unwrap();
+        gzip.finish().unwrap()
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decode_transfer_codings() {
+        use crate::conn::Server;
+
+        let plain = b"hello, hello, hello, hello, hello".repeat(20);
+        let msg = transfer_coded_request(&transfer_encode(&plain));
+        let mut conn =
+            HttpConn::<Server>::new().with_config(transfer_decoding());
+        conn.feed(&msg).unwrap();
+        match conn.next_event().unwrap() {
+            Some(Event::Request(_)) => {}
+            e => panic!("expected request, got {:?}", e),
+        }
+        let mut body = Vec::new();
+        loop {
+            match conn.next_event().unwrap() {
+                Some(Event::Data(data)) => {
+                    assert!(!data.is_empty());
+                    body.extend_from_slice(&data);
+                }
+                Some(Event::EndOfMessage(None)) => break,
+                e => panic!("expected body, got {:?}", e),
+            }
+        }
+        assert_eq!(plain, body);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn transfer_codings_left_unless_asked() {
+        use crate::conn::Server;
+
+        let encoded = transfer_encode(b"hello");
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(&transfer_coded_request(&encoded)).unwrap();
+        conn.next_event().unwrap();
+        let encoding = conn.data_encoding().unwrap();
+        assert_eq!(vec!["deflate", "gzip"], encoding.transfer);
+        let mut body = Vec::new();
+        while let Some(Event::Data(data)) = conn.next_event().unwrap() {
+            body.extend_from_slice(&data);
+        }
+        assert_eq!(encoded, body);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn transfer_decoding_in_a_clone() {
+        use crate::conn::Server;
+
+        let msg = transfer_coded_request(&transfer_encode(b"hello"));
+        let mut conn =
+            HttpConn::<Server>::new().with_config(transfer_decoding());
+        conn.feed(&msg).unwrap();
+        conn.next_event().unwrap();
+        let mut clone = conn.clone();
+        match clone.next_event() {
+            Err(Error::HttpBody(BodyError::Decoding(_))) => {}
+            r => panic!("expected decoding error, got {:?}", r),
+        }
+        let mut body = Vec::new();
+        while let Some(Event::Data(data)) = conn.next_event().unwrap() {
+            body.extend_from_slice(&data);
+        }
+        assert_eq!(&b"hello"[..], &body[..]);
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gzip_bomb(len: usize) -> Vec<u8> {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&vec![0; len]).unwrap();
+        gzip.finish().unwrap()
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decoding_limits() {
+        use crate::conn::Server;
+
+        fn decode(config: Config, encoded: &[u8]) -> Result<usize, Error> {
+            let mut msg = format!(
+                "POST / HTTP/1.1\r\nhost: a\r\ncontent-encoding: gzip\r\n\
+                 content-length: {}\r\n\r\n",
+                encoded.len()
+            )
+            .into_bytes();
+            msg.extend_from_slice(encoded);
+            let mut conn = HttpConn::<Server>::new().with_config(config);
+            conn.feed(&msg).unwrap();
+            let mut conn = DecodingConn::new(conn);
+            let mut len = 0;
+            loop {
+                match conn.next_event()? {
+                    Some(Event::Data(data)) => len += data.len(),
+                    Some(Event::EndOfMessage(_)) => return Ok(len),
+                    _ => {}
+                }
+            }
+        }
+
+        let bomb = gzip_bomb(8 << 20);
+        assert_eq!(8 << 20, decode(Config::default(), &bomb).unwrap());
+        for config in &[
+            Config {
+                max_decoded_size: Some(1 << 20),
+                ..Config::default()
+            },
+            Config {
+                max_decoding_ratio: Some(100),
+                ..Config::default()
+            },
+        ] {
+            match decode(*config, &bomb) {
+                Err(Error::HttpBody(BodyError::DecodedTooLarge)) => {}
+                r => panic!("expected limit error, got {:?}", r),
+            }
+        }
+
+        // Small bodies may expand a lot without tripping the ratio.
+        let config = Config {
+            max_decoding_ratio: Some(10),
+            ..Config::default()
+        };
+        assert_eq!(4096, decode(config, &gzip_bomb(4096)).unwrap());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decoding_stops_at_the_limit() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        // Each layer multiplies the size a thousandfold, so one step of
+        // input would be a gigabyte of output.
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&gzip_bomb(64 << 20)).unwrap();
+        let bomb = gzip.finish().unwrap();
+        let limit = DecodingLimit::new(&Config {
+            max_decoded_size: Some(64 * 1024),
+            ..Config::default()
+        });
+        let mut decoder = Decoder::for_headers(&headers("gzip, gzip"))
+            .unwrap()
+            .with_limit(limit);
+        match decoder.decode(&bomb).and_then(|_| decoder.finish()) {
+            Err(BodyError::DecodedTooLarge) => {}
+            r => panic!("expected limit error, got {:?}", r),
+        }
+        assert!(decoder.pipe.sink().out.len() <= 64 * 1024);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn transfer_decoding_limits() {
+        use crate::conn::Server;
+
+        let config = Config {
+            max_decoded_size: Some(1 << 20),
+            ..transfer_decoding()
+        };
+        let msg = transfer_coded_request(&transfer_encode(&vec![0; 2 << 20]));
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        conn.feed(&msg).unwrap();
+        let err = loop {
+            match conn.next_event() {
+                Ok(Some(Event::EndOfMessage(_)) | None) => {
+                    panic!("expected limit error")
+                }
+                Ok(_) => {}
+                Err(e) => break e,
+            }
+        };
+        match err {
+            Error::HttpBody(BodyError::DecodedTooLarge) => {}
+            e => panic!("expected limit error, got {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn transfer_coding_checksum_mismatch() {
+        use crate::conn::{Error, Server};
+
+        let mut encoded = transfer_encode(b"hello world");
+        let len = encoded.len();
+        encoded[len - 8] ^= 0xff;
+        let mut conn =
+            HttpConn::<Server>::new().with_config(transfer_decoding());
+        conn.feed(&transfer_coded_request(&encoded)).unwrap();
+        conn.next_event().unwrap();
+        let err = loop {
+            match conn.next_event() {
+                Ok(Some(Event::Data(_))) => {}
+                Ok(e) => panic!("expected error, got {:?}", e),
+                Err(e) => break e,
+            }
+        };
+        match err {
+            Error::HttpBody(BodyError::Decoding(_)) => {}
+            e => panic!("expected decoding error, got {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn decode_brotli() {
+        let mut compressor =
+            brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        compressor.write_all(b"hello, hello, hello").unwrap();
+        let encoded = compressor.into_inner();
+
+        let mut decoder = Decoder::for_headers(&headers("br")).unwrap();
+        let mut body = Vec::new();
+        for piece in encoded.chunks(3) {
+            body.extend_from_slice(&decoder.decode(piece).unwrap());
+        }
+        body.extend_from_slice(&decoder.finish().unwrap());
+        assert_eq!(&b"hello, hello, hello"[..], &body[..]);
+
+        let mut decoder = Decoder::for_headers(&headers("br")).unwrap();
+        decoder.decode(&encoded[..encoded.len() / 2]).unwrap();
+        assert!(decoder.finish().is_err());
+    }
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiate_codings() {
+        use ContentCoding::*;
+
+        let supported =
+            |coding: ContentCoding| Some(coding).filter(|c| c.is_supported());
+        assert_eq!(None, negotiate(&HeaderMap::new()));
+        assert_eq!(None, negotiate(&accept("identity, rot13")));
+        assert_eq!(None, negotiate(&accept("gzip;q=0, deflate;q=0")));
+        assert_eq!(
+            supported(Deflate),
+            negotiate(&accept("gzip;q=0.5, deflate"))
+        );
+        assert_eq!(
+            supported(Gzip).or(supported(Deflate)),
+            negotiate(&accept("deflate;q=0.5, gzip ; q=0.8"))
+        );
+        assert_eq!(
+            supported(Brotli).or(supported(Zstd)).or(supported(Gzip)),
+            negotiate(&accept("*, deflate;q=0.1"))
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn encoding_conn_round_trip() {
+        use http::{Method, StatusCode, Version};
+
+        use crate::testing::Duplex;
+
+        let Duplex {
+            mut client,
+            mut server,
+        } = HttpConn::pair();
+        let mut req = crate::req::ReqHead {
+            method: Method::GET,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: accept("gzip"),
+        };
+        req.headers
+            .insert(http::header::HOST, HeaderValue::from_static("a"));
+        let mut out = client.send_req(req).unwrap().to_vec();
+        out.extend_from_slice(&client.send_end_of_message(None).unwrap());
+        server.feed(&out).unwrap();
+        let request_headers = match server.next_event().unwrap() {
+            Some(Event::Request(req)) => req.headers,
+            e => panic!("expected request, got {:?}", e),
+        };
+        server.next_event().unwrap();
+
+        let mut server = EncodingConn::new(server);
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1000"));
+        let resp = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        };
+        let mut wire =
+            server.send_resp(&request_headers, resp).unwrap().to_vec();
+        assert_eq!(Some(ContentCoding::Gzip), server.coding());
+        for _ in 0..100 {
+            wire.extend_from_slice(&server.send_data(b"0123456789").unwrap());
+        }
+        wire.extend_from_slice(&server.send_end_of_message(None).unwrap());
+
+        let mut client = DecodingConn::new(client);
+        client.get_mut().feed(&wire).unwrap();
+        match client.next_event().unwrap() {
+            Some(Event::Response(resp)) => {
+                assert_eq!("gzip", resp.headers[CONTENT_ENCODING]);
+                assert_eq!("accept-encoding", resp.headers[VARY]);
+                assert_eq!("chunked", resp.headers[TRANSFER_ENCODING]);
+                assert!(resp.headers.get(CONTENT_LENGTH).is_none());
+            }
+            e => panic!("expected response, got {:?}", e),
+        }
+        let mut body = Vec::new();
+        while let Some(Event::Data(data)) = client.next_event().unwrap() {
+            body.extend_from_slice(&data);
+        }
+        assert_eq!(1000, body.len());
+        assert!(wire.len() < 500);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn encoding_fixes_up_headers() {
+        use http::header::CONNECTION;
+        use http::StatusCode;
+
+        use crate::testing::parse_resp_head;
+
+        fn send(request: &[u8]) -> RespHead {
+            let mut server = EncodingConn::new(HttpConn::<Server>::new());
+            server.get_mut().feed(request).unwrap();
+            server.get_mut().next_event().unwrap();
+            server.get_mut().next_event().unwrap();
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1000"));
+            headers.insert(
+                TRANSFER_ENCODING,
+                HeaderValue::from_static("chunked"),
+            );
+            let resp = RespHead {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers,
+                reason: None,
+            };
+            let wire = server.send_resp(&accept("gzip"), resp).unwrap();
+            parse_resp_head(&mut BytesMut::from(&wire[..]))
+                .unwrap()
+                .unwrap()
+        }
+
+        let resp = send(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n");
+        assert_eq!("gzip", resp.headers[CONTENT_ENCODING]);
+        assert_eq!("accept-encoding", resp.headers[VARY]);
+        assert_eq!(
+            vec!["chunked"],
+            resp.headers
+                .get_all(TRANSFER_ENCODING)
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert!(resp.headers.get(CONTENT_LENGTH).is_none());
+
+        let resp = send(b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!("gzip", resp.headers[CONTENT_ENCODING]);
+        assert!(resp.headers.get(TRANSFER_ENCODING).is_none());
+        assert!(resp.headers.get(CONTENT_LENGTH).is_none());
+        assert_eq!("close", resp.headers[CONNECTION]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn encoding_conn_skips_head_responses() {
+        use http::StatusCode;
+
+        let mut server = EncodingConn::new(HttpConn::<Server>::new());
+        server
+            .get_mut()
+            .feed(b"HEAD / HTTP/1.1\r\nhost: a\r\n\r\n")
+            .unwrap();
+        server.get_mut().next_event().unwrap();
+        server.get_mut().next_event().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1000"));
+        let resp = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        };
+        let wire = server.send_resp(&accept("gzip"), resp).unwrap();
+        assert_eq!(None, server.coding());
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 1000\r\n\r\n"[..],
+            &wire[..]
+        );
+        assert!(server.send_end_of_message(None).unwrap().is_empty());
+    }
+
+    #[cfg(any(feature = "brotli", feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn compression_config_filters() {
+        use http::{StatusCode, Version};
+
+        fn resp(len: Option<&'static str>, type_: &'static str) -> RespHead {
+            let mut headers = HeaderMap::new();
+            if let Some(len) = len {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from_static(len));
+            }
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(type_));
+            RespHead {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers,
+                reason: None,
+            }
+        }
+
+        let config = CompressionConfig::new();
+        assert!(config.should_compress(&resp(Some("1000"), "text/html")));
+        assert!(config.should_compress(&resp(None, "text/html")));
+        assert!(!config.should_compress(&resp(Some("100"), "text/html")));
+        assert!(!config.should_compress(&resp(None, "image/PNG")));
+        assert!(!config.should_compress(&resp(None, "video/mp4")));
+        assert!(config.should_compress(&resp(None, "image/svg+xml")));
+
+        let config = CompressionConfig {
+            min_size: 0,
+            content_types: Some(vec![
+                "text/*".into(),
+                "application/json".into(),
+            ]),
+            skip_content_types: vec!["text/event-stream".into()],
+            ..CompressionConfig::new()
+        };
+        assert!(config.should_compress(&resp(Some("1"), "text/plain")));
+        assert!(config.should_compress(&resp(None, "application/json")));
+        assert!(!config.should_compress(&resp(None, "text/event-stream")));
+        assert!(!config.should_compress(&resp(None, "application/xml")));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn encoding_conn_skips_small_bodies() {
+        use http::{StatusCode, Version};
+
+        let mut server = EncodingConn::new(HttpConn::<Server>::new())
+            .with_config(CompressionConfig {
+                level: Some(1),
+                ..CompressionConfig::new()
+            });
+        server
+            .get_mut()
+            .feed(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n")
+            .unwrap();
+        server.get_mut().next_event().unwrap();
+        server.get_mut().next_event().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("5"));
+        let resp = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        };
+        server.send_resp(&accept("gzip"), resp).unwrap();
+        assert_eq!(None, server.coding());
+        assert_eq!(&b"hello"[..], &server.send_data(b"hello").unwrap()[..]);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        let mut encoder =
+            Encoder::with_level(ContentCoding::Zstd, Some(30)).unwrap();
+        let mut compressed = encoder.encode(b"hello, ").unwrap().to_vec();
+        compressed.extend_from_slice(&encoder.flush().unwrap());
+        compressed.extend_from_slice(&encoder.encode(b"hello").unwrap());
+        compressed.extend_from_slice(&encoder.finish().unwrap());
+
+        let mut decoder = Decoder::for_headers(&headers("zstd")).unwrap();
+        let mut body = Vec::new();
+        for piece in compressed.chunks(3) {
+            body.extend_from_slice(&decoder.decode(piece).unwrap());
+        }
+        body.extend_from_slice(&decoder.finish().unwrap());
+        assert_eq!(&b"hello, hello"[..], &body[..]);
+        assert_eq!(
+            Some(ContentCoding::Zstd),
+            negotiate(&accept("zstd, gzip"))
+        );
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_encoder_round_trip() {
+        let mut encoder = Encoder::new(ContentCoding::Brotli).unwrap();
+        let mut compressed = encoder.encode(b"hello, ").unwrap().to_vec();
+        compressed.extend_from_slice(&encoder.flush().unwrap());
+        compressed.extend_from_slice(&encoder.encode(b"hello").unwrap());
+        compressed.extend_from_slice(&encoder.finish().unwrap());
+
+        let mut decoder = Decoder::for_headers(&headers("br")).unwrap();
+        let mut body = decoder.decode(&compressed).unwrap().to_vec();
+        body.extend_from_slice(&decoder.finish().unwrap());
+        assert_eq!(&b"hello, hello"[..], &body[..]);
+    }
+}
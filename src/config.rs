@@ -0,0 +1,115 @@
+// What a server does with trailers when the client's request did not
+// include `TE: trailers`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrailerPolicy {
+    Strip,
+    Reject,
+    Send,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    pub max_chunk_size_line: usize,
+    // Total bytes of chunk extensions allowed in one message, since each
+    // line alone staying under `max_chunk_size_line` does not bound them.
+    pub max_chunk_extensions: usize,
+    pub max_body_size: Option<usize>,
+    pub in_buf_capacity: usize,
+    pub out_buf_capacity: usize,
+    // How much room `read_from` makes in the input buffer before reading.
+    // `None` uses the connection's maximum event size.
+    pub read_growth: Option<usize>,
+    // After a cycle completes, a buffer whose capacity has grown past
+    // this is reallocated at its configured initial capacity.
+    pub shrink_threshold: Option<usize>,
+    pub unrequested_trailers: TrailerPolicy,
+    // Treat trailer fields that were not announced in the `Trailer` header,
+    // or that may never appear in trailers, as protocol errors.
+    pub strict_trailers: bool,
+    // Fail HTTP/1.1 requests that lack a `Host` header with
+    // `ReqHeadError::MissingHost`, as RFC 9112 requires of servers.
+    pub require_host: bool,
+    // Send would-be chunked responses to HTTP/1.0 clients delimited by
+    // closing the connection, rather than refusing them.
+    pub downgrade_chunked_for_http10: bool,
+    // Honour `Connection: keep-alive` on HTTP/1.0 messages.
+    pub http10_keep_alive: bool,
+    // Accept `name : value` header lines, dropping the whitespace, instead
+    // of rejecting the message.
+    pub allow_space_before_colon: bool,
+    // Accept runs of spaces and tabs between the parts of the request or
+    // status line.
+    pub allow_extra_line_whitespace: bool,
+    // Read a Shoutcast `ICY 200 OK` status line as an HTTP/1.0 response.
+    // Only used when receiving responses.
+    pub allow_icy_status_line: bool,
+    // Largest piece of body data `send_data_frames` frames at a time, so a
+    // large body goes out as several chunks rather than one.
+    pub max_send_frame_size: Option<usize>,
+    // Limits on decoding compressed bodies, as a total size and as a
+    // multiple of the encoded size, against decompression bombs.
+    pub max_decoded_size: Option<usize>,
+    pub max_decoding_ratio: Option<usize>,
+    // Fail a body whose codings cannot be decoded with
+    // `BodyError::UnsupportedCoding`, rather than passing it on as sent.
+    // Content codings are only checked by `DecodingConn`.
+    pub reject_unsupported_codings: bool,
+    // Undo `gzip` and `deflate` transfer codings applied beneath
+    // `chunked`, held to the decoding limits above. Needs the `gzip`
+    // feature; otherwise such bodies are passed on as sent.
+    pub decode_transfer_codings: bool,
+    // Hold back the body of a request sent with `Expect: 100-continue`
+    // until the server answers with 100; see `take_released_body`. A final
+    // error response that arrives first discards it.
+    pub hold_body_for_continue: bool,
+    // How many requests a connection serves before it is closed, so that
+    // load is spread as clients reconnect. The response to the last one
+    // carries `Connection: close`. Only used when sending responses.
+    pub max_requests_per_connection: Option<u64>,
+    // Drop body data sent in response to a HEAD request, so handlers
+    // written for GET can be reused as they are, instead of failing with
+    // `Error::BodyForHead`. Only used when sending responses.
+    pub discard_head_response_body: bool,
+    // Have `next_event` report received trailers as an `Event::Trailers`
+    // of their own, followed by an `EndOfMessage` without any.
+    pub separate_trailers: bool,
+}
+
+impl Config {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            max_chunk_size_line: 1024,
+            max_chunk_extensions: 16 * 1024,
+            max_body_size: None,
+            in_buf_capacity: 0,
+            out_buf_capacity: 0,
+            read_growth: None,
+            shrink_threshold: None,
+            unrequested_trailers: TrailerPolicy::Strip,
+            strict_trailers: false,
+            require_host: false,
+            downgrade_chunked_for_http10: false,
+            http10_keep_alive: false,
+            allow_space_before_colon: false,
+            allow_extra_line_whitespace: false,
+            allow_icy_status_line: false,
+            max_send_frame_size: None,
+            max_decoded_size: None,
+            max_decoding_ratio: None,
+            reject_unsupported_codings: false,
+            decode_transfer_codings: false,
+            hold_body_for_continue: false,
+            max_requests_per_connection: None,
+            discard_head_response_body: false,
+            separate_trailers: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
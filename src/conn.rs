@@ -1,15 +1,30 @@
-use std::io::Read;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
-use std::{fmt, str};
+use std::mem;
+use std::time::SystemTime;
 
 use bytes::{BufMut, Bytes, BytesMut};
+use http::header::{HeaderName, HeaderValue, HOST};
 use http::{HeaderMap, Method, StatusCode, Version};
 
-use crate::body::{BodyError, BodyReader};
-use crate::event::Event;
+use crate::body::{BodyError, BodyReader, BodyWriter, FramingMethod};
+use crate::coding::DataEncoding;
+use crate::config::{Config, TrailerPolicy};
+use crate::event::{ClientEvent, Event, ServerEvent};
+use crate::metrics::Metrics;
 use crate::req::{ReqHead, ReqHeadError};
-use crate::resp::RespHead;
+use crate::resp::{InfoStatus, RespHead, RespHeadError};
+use crate::security::{declared_trailers, trailer_findings, Finding};
+use crate::sniff::{sniff, Sniffed};
 use crate::state::{self, State, StateError, SwitchEvent};
+use crate::util::{
+    append_fields, format_http_date, has_token, is_chunked, last_token_is,
+};
+use crate::warning::{Warning, Warnings};
+use crate::wire::{Direction, WireLog, WireRecord};
 
 #[allow(clippy::empty_enum)]
 pub enum Client {}
@@ -17,12 +32,148 @@ pub enum Client {}
 #[allow(clippy::empty_enum)]
 pub enum Server {}
 
-pub struct HttpConn<Role> {
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Client {}
+    impl Sealed for super::Server {}
+}
+
+pub trait Role: sealed::Sealed + Sized {
+    // The events this side sends, and those its peer sends.
+    type Sends: Into<Event>;
+    type Receives: TryFrom<Event, Error = Event>;
+
+    #[doc(hidden)]
+    const SIDE: Side;
+
+    #[doc(hidden)]
+    fn send_head(
+        conn: &mut HttpConn<Self>,
+        event: Event,
+    ) -> Result<Bytes, Error>;
+}
+
+impl Role for Client {
+    type Sends = ClientEvent;
+    type Receives = ServerEvent;
+
+    const SIDE: Side = Side::Client;
+
+    fn send_head(
+        conn: &mut HttpConn<Self>,
+        event: Event,
+    ) -> Result<Bytes, Error> {
+        match event {
+            Event::Request(req) => conn.send_req(req),
+            _ => Err(Error::NotSendable),
+        }
+    }
+}
+
+impl Role for Server {
+    type Sends = ServerEvent;
+    type Receives = ClientEvent;
+
+    const SIDE: Side = Side::Server;
+
+    fn send_head(
+        conn: &mut HttpConn<Self>,
+        event: Event,
+    ) -> Result<Bytes, Error> {
+        match event {
+            Event::InfoResponse(resp) => conn.send_info_resp(resp),
+            Event::Response(resp) => conn.send_resp(resp),
+            _ => Err(Error::NotSendable),
+        }
+    }
+}
+
+// The direction says whether the deciding message was received from the
+// peer or sent by us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CloseReason {
+    Http10(Direction),
+    ConnectionClose(Direction),
+    CloseDelimited,
+    // A request body held back for `100 Continue` was never sent, since
+    // the server refused it first.
+    BodyWithheld,
+    // The connection served `Config::max_requests_per_connection`.
+    RequestLimit,
+    Shutdown,
+    Error,
+}
+
+// What a client needs to judge whether the request in flight can be
+// retried on a fresh connection after this one failed.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RetryInfo {
+    pub idempotent: bool,
+    pub head_sent: bool,
+    pub body_sent: bool,
+    pub response_started: bool,
+}
+
+impl RetryInfo {
+    // Nothing came back, and either the method may be repeated or the
+    // server never saw the request at all.
+    #[must_use]
+    pub fn is_safe(&self) -> bool {
+        !self.response_started && (self.idempotent || !self.head_sent)
+    }
+}
+
+// What the connection is waiting on, for picking a deadline. A request
+// head trickling in slowly deserves a much shorter one than a large body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimeoutPhase {
+    Idle,
+    ReadingHead,
+    ReadingBody,
+    // The client sent `Expect: 100-continue` and is holding back the body
+    // until the server answers or its own wait expires.
+    ContinueWait,
+}
+
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    fn peer(self) -> Self {
+        match self {
+            Self::Client => Self::Server,
+            Self::Server => Self::Client,
+        }
+    }
+
+    pub(crate) fn sends(self, event: &Event) -> bool {
+        matches!(
+            (self, event),
+            (Self::Client, Event::Request(_))
+                | (Self::Server, Event::InfoResponse(_) | Event::Response(_))
+                | (
+                    _,
+                    Event::Data(_)
+                        | Event::Trailers(_)
+                        | Event::EndOfMessage(_)
+                        | Event::ConnectionClosed
+                )
+        )
+    }
+}
+
+pub struct HttpConn<R> {
     inner: Inner,
-    pd: PhantomData<Role>,
+    pd: PhantomData<R>,
 }
 
-impl<Role> HttpConn<Role> {
+impl<R> HttpConn<R> {
     pub fn new() -> Self {
         Self::from_bufs(8192, BytesMut::new(), BytesMut::new())
     }
@@ -38,319 +189,4096 @@ impl<Role> HttpConn<Role> {
         }
     }
 
+    #[must_use]
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.inner.set_config(config);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
     pub fn into_bufs(self) -> (BytesMut, BytesMut) {
         self.inner.into_bufs()
     }
 
-    pub fn read_from<R: Read>(&mut self, r: &mut R) -> Result<usize, Error> {
-        self.inner.read_from(r)
+    pub fn into_parts(mut self) -> Parts<R> {
+        let in_buf = mem::replace(&mut self.inner.in_buf, BytesMut::new());
+        self.inner.in_buf_end -= in_buf.len() as u64;
+        let out_buf = mem::replace(&mut self.inner.out_buf, BytesMut::new());
+        Parts {
+            in_buf,
+            out_buf,
+            state: ConnState {
+                inner: self.inner,
+                pd: PhantomData,
+            },
+        }
     }
-}
 
-impl<Role> Default for HttpConn<Role> {
-    fn default() -> Self {
-        Self::new()
+    pub fn from_parts(parts: Parts<R>) -> Self {
+        let mut inner = parts.state.inner;
+        inner.in_buf_end += parts.in_buf.len() as u64;
+        inner.in_buf = parts.in_buf;
+        inner.out_buf = parts.out_buf;
+        Self {
+            inner,
+            pd: PhantomData,
+        }
     }
-}
 
-impl HttpConn<Client> {
-    pub fn send_req(&mut self, req: ReqHead) -> Result<Bytes, Error> {
-        let event = Event::Request(req);
-        self.inner.client_event(&event)?;
-        Ok(self.inner.write_event(event))
+    pub fn read_from<I: Read>(&mut self, r: &mut I) -> Result<usize, Error> {
+        self.inner.read_from(r)
     }
 
-    pub fn send_data(&mut self, data: Bytes) -> Result<Bytes, Error> {
-        let event = Event::Data(data);
-        self.inner.client_event(&event)?;
-        Ok(self.inner.write_event(event))
+    /// # Errors
+    ///
+    /// Fails if bytes arrive after the peer's end of input was fed.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.inner.feed(bytes)
     }
 
-    pub fn send_end_of_message(
+    // For completion-based IO, which needs to own the buffer being read
+    // into: the returned buffer is zeroed spare room at the end of the
+    // input buffer, to be handed back with `return_read_buf` along with
+    // how many bytes were read into its start. Zero bytes means EOF.
+    pub fn take_read_buf(&mut self) -> BytesMut {
+        self.inner.take_read_buf()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the buffer.
+    ///
+    /// # Errors
+    ///
+    /// Fails if bytes arrive after the peer's end of input.
+    pub fn return_read_buf(
         &mut self,
-        headers: Option<HeaderMap>,
-    ) -> Result<Bytes, Error> {
-        let event = Event::EndOfMessage(headers);
-        self.inner.client_event(&event)?;
-        Ok(self.inner.write_event(event))
+        buf: BytesMut,
+        n: usize,
+    ) -> Result<(), Error> {
+        self.inner.return_read_buf(buf, n)
     }
 
-    pub fn send_connection_closed(&mut self) -> Result<Bytes, Error> {
-        self.inner.client_event(&Event::ConnectionClosed)?;
-        Ok(Bytes::new())
+    /// # Errors
+    ///
+    /// Fails if either side has not finished its message of the current cycle,
+    /// or if the connection is not to be reused.
+    pub fn start_next_cycle(&mut self) -> Result<(), Error> {
+        self.inner.start_next_cycle()
     }
-}
 
-impl HttpConn<Server> {
-    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
-        self.inner.next_client_event()
+    pub fn peek_buffered(&self) -> &[u8] {
+        &self.inner.in_buf
     }
 
-    pub fn send_info_resp(&mut self, resp: RespHead) -> Result<Bytes, Error> {
-        let event = Event::InfoResponse(resp);
-        self.inner.server_event(&event)?;
-        Ok(self.inner.write_event(event))
+    pub fn sniff_buffered(&self) -> Sniffed {
+        sniff(&self.inner.in_buf)
     }
 
-    pub fn send_resp(&mut self, resp: RespHead) -> Result<Bytes, Error> {
-        let event = Event::Response(resp);
-        self.inner.server_event(&event)?;
-        Ok(self.inner.write_event(event))
+    pub fn metrics(&self) -> &Metrics {
+        &self.inner.metrics
     }
 
-    pub fn send_data(&mut self, data: Bytes) -> Result<Bytes, Error> {
-        let event = Event::Data(data);
-        self.inner.server_event(&event)?;
-        Ok(self.inner.write_event(event))
+    pub fn current_framing(&self) -> Option<FramingMethod> {
+        self.inner.framing
     }
 
-    pub fn send_end_of_message(
+    // How much of the body being received has been delivered as `Data`,
+    // so the data of the last event starts at this offset less its
+    // length. Restarts at zero with each received head.
+    pub fn received_body_offset(&self) -> u64 {
+        self.inner.body_received as u64
+    }
+
+    // The codings the current message's `Data` bytes arrive in, once its
+    // head has been received.
+    pub fn data_encoding(&self) -> Option<&DataEncoding> {
+        self.inner.data_encoding.as_ref()
+    }
+
+    // The version of the last head received from the peer.
+    pub fn peer_http_version(&self) -> Option<Version> {
+        self.inner.peer_http_version
+    }
+
+    // The method of the current cycle's request, once it has been sent or
+    // received.
+    pub fn request_method(&self) -> Option<&Method> {
+        self.inner.request_method.as_ref()
+    }
+
+    // False when the last head received from the peer only parsed thanks
+    // to one of the lenient parsing options in the config.
+    pub fn peer_head_was_strict(&self) -> bool {
+        self.inner.peer_head_strict
+    }
+
+    // Everything the lenient parsing options let through since the last
+    // call, oldest first.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.inner.warnings.take()
+    }
+
+    // Why the connection cannot be reused once the current cycle ends, or
+    // `None` while keep-alive is still possible.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        match self.inner.state.states() {
+            (state::Client::Error, _) | (_, state::Server::Error) => {
+                Some(CloseReason::Error)
+            }
+            _ => self.inner.close_reason,
+        }
+    }
+
+    // Puts both sides into the error state, for when what the connection
+    // holds can no longer be trusted.
+    pub(crate) fn fail(&mut self) {
+        self.inner.state = self.inner.state.client_error().server_error();
+    }
+
+    // Lets the exchange under way finish, but no further one: the response
+    // to it carries `Connection: close`, and the next cycle cannot start.
+    // True if no exchange is under way, so the connection can be closed
+    // at once.
+    pub fn graceful_shutdown(&mut self) -> bool {
+        self.inner.disable_keep_alive(CloseReason::Shutdown);
+        self.inner.state.states() == (state::Client::Idle, state::Server::Idle)
+            && self.inner.in_buf.is_empty()
+    }
+
+    // Records are stamped with the time `clock` gives, such as
+    // `SystemTime::now`.
+    pub fn enable_wire_log(
         &mut self,
-        headers: Option<HeaderMap>,
-    ) -> Result<Bytes, Error> {
-        let event = Event::EndOfMessage(headers);
-        self.inner.server_event(&event)?;
-        Ok(self.inner.write_event(event))
+        capacity: usize,
+        clock: fn() -> SystemTime,
+    ) {
+        let mut log = WireLog::new(capacity, clock, self.inner.in_offset());
+        log.received(&self.inner.in_buf);
+        self.inner.wire_log = Some(log);
     }
 
-    pub fn send_connection_closed(&mut self) -> Result<Bytes, Error> {
-        self.inner.server_event(&Event::ConnectionClosed)?;
-        Ok(Bytes::new())
+    pub fn disable_wire_log(&mut self) {
+        self.inner.wire_log = None;
+    }
+
+    pub fn take_wire_log(&mut self) -> Vec<WireRecord> {
+        self.inner
+            .wire_log
+            .as_mut()
+            .map_or_else(Vec::new, WireLog::take)
     }
 }
 
-struct Inner {
-    state: State,
-    max_event_size: usize,
-    in_buf: BytesMut,
-    in_buf_closed: bool,
-    out_buf: BytesMut,
-    client_wants_continue: bool,
-    body_reader: Option<BodyReader>,
-    peer_http_version: Option<Version>,
+pub struct Parts<R> {
+    pub in_buf: BytesMut,
+    pub out_buf: BytesMut,
+    pub state: ConnState<R>,
 }
 
-impl Inner {
-    fn from_bufs(
-        max_event_size: usize,
-        in_buf: BytesMut,
-        out_buf: BytesMut,
-    ) -> Self {
+// Everything about a connection except its buffers. It can only be turned
+// back into a connection of the same role.
+pub struct ConnState<R> {
+    inner: Inner,
+    pd: PhantomData<R>,
+}
+
+// Cloning a connection snapshots its parser state and buffered bytes, so
+// that input can be interpreted speculatively and the copy discarded if
+// that goes wrong.
+impl<R> Clone for HttpConn<R> {
+    fn clone(&self) -> Self {
         Self {
-            state: State::new(),
-            max_event_size,
-            in_buf,
-            in_buf_closed: false,
-            out_buf,
-            client_wants_continue: false,
-            body_reader: None,
-            peer_http_version: None,
+            inner: self.inner.clone(),
+            pd: PhantomData,
         }
     }
+}
 
-    fn into_bufs(self) -> (BytesMut, BytesMut) {
-        (self.in_buf, self.out_buf)
+impl<R> Clone for ConnState<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            pd: PhantomData,
+        }
     }
+}
 
-    // XXX: this should be able to indicate that it will *never* return
-    //      an event again, because the connection has been hijacked via
-    //      UPGRADE or CONNECT
-    fn next_client_event(&mut self) -> Result<Option<Event>, Error> {
-        use state::Client::*;
+impl<R> fmt::Debug for ConnState<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
 
-        match self.state.states().0 {
-            Idle => match ReqHead::from_buf(&mut self.in_buf) {
-                Ok(Some(r)) => {
-                    let br = BodyReader::from(r.framing_method());
-                    let event = Event::Request(r);
-                    self.client_event(&event)?;
-                    self.body_reader = Some(br);
-                    Ok(Some(event))
+impl<R> fmt::Debug for HttpConn<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<R> Default for HttpConn<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Role> HttpConn<R> {
+    /// # Errors
+    ///
+    /// Fails if the peer breaks the protocol or exceeds a configured limit,
+    /// after which the connection stays in an error state.
+    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        if self.inner.held_end_of_message {
+            self.inner.held_end_of_message = false;
+            return Ok(Some(Event::EndOfMessage(None)));
+        }
+        match self.inner.next_event(R::SIDE)? {
+            Some(Event::EndOfMessage(Some(trailers)))
+                if self.inner.config.separate_trailers =>
+            {
+                self.inner.held_end_of_message = true;
+                Ok(Some(Event::Trailers(trailers)))
+            }
+            event => Ok(event),
+        }
+    }
+
+    /// Like `next_event`, but typed as what the peer sends.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `next_event` would.
+    pub fn next_typed_event(&mut self) -> Result<Option<R::Receives>, Error> {
+        Ok(self.next_event()?.map(|event| {
+            R::Receives::try_from(event)
+                .unwrap_or_else(|_| unreachable!("only peer events arrive"))
+        }))
+    }
+
+    /// Reads the rest of the body currently being received from `r` and
+    /// writes its data to `w`, returning how many bytes were written and
+    /// the trailers. Must be called after the message head's event.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::NoBodyInProgress` if no body is being received,
+    /// or if reading, writing or decoding the body fails.
+    pub fn copy_body<I: Read, O: Write>(
+        &mut self,
+        r: &mut I,
+        w: &mut O,
+    ) -> Result<(u64, Option<HeaderMap>), Error> {
+        let mut copied = 0;
+        loop {
+            if !self.inner.peer_sending_body(R::SIDE) {
+                return Err(Error::NoBodyInProgress);
+            }
+            match self.inner.next_event(R::SIDE)? {
+                Some(Event::Data(data)) => {
+                    w.write_all(&data)?;
+                    copied += data.len() as u64;
                 }
-                Ok(None) => Ok(None),
-                Err(e) => {
-                    self.state = self.state.client_error();
-                    Err(e.into())
+                Some(Event::EndOfMessage(trailers)) => {
+                    return Ok((copied, trailers));
                 }
-            },
-            SendBody => {
-                let br = self.body_reader.as_mut().expect("reading body");
-                if !self.in_buf.is_empty() {
-                    br.next_event(&mut self.in_buf).map_err(Into::into)
-                } else if self.in_buf_closed {
-                    Ok(Some(br.eof()?))
-                } else {
-                    Ok(None)
+                Some(_) => return Err(Error::NoBodyInProgress),
+                None if self.inner.in_buf_closed => {
+                    return Err(
+                        io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                    );
+                }
+                None => {
+                    self.inner.read_from(r)?;
                 }
             }
-            Error => Err(self::Error::ClientErrorState),
-            Done | MustClose | Closed | MightSwitchProtocol
-            | SwitchedProtocol => Ok(None),
         }
     }
 
-    fn read_from<R: Read>(&mut self, r: &mut R) -> Result<usize, Error> {
-        if self.in_buf.remaining_mut() < self.max_event_size {
-            self.in_buf.reserve(self.max_event_size);
-        }
-        unsafe {
-            r.read(self.in_buf.bytes_mut())
-                .map_err(Into::into)
-                .and_then(|n| {
-                    if n == 0 {
-                        self.in_buf_closed = true;
-                    } else {
-                        if self.in_buf_closed {
-                            return Err(Error::DataFromClosedPeer);
-                        }
-                        self.in_buf.advance_mut(n);
-                    }
-                    Ok(n)
-                })
-        }
+    /// Gathers the rest of the body being received, up to the configured
+    /// `max_body_size`. `None` means more input is needed, with what was
+    /// read so far kept for the next call.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::NoBodyInProgress` if no body is being received,
+    /// with `Error::BodyTooLarge` past the limit, or as `next_event` would.
+    pub fn collect_body(
+        &mut self,
+    ) -> Result<Option<(Bytes, Option<HeaderMap>)>, Error> {
+        self.inner.collect_body(R::SIDE)
     }
 
-    fn write_event(&mut self, event: Event) -> Bytes {
-        event.into_buf(&mut self.out_buf)
+    /// # Errors
+    ///
+    /// Fails as `next_event` would on the rest of the body.
+    pub fn discard_remaining_body(&mut self) -> Result<Option<usize>, Error> {
+        self.inner.discard_remaining_body(R::SIDE)
     }
 
-    fn client_event(&mut self, event: &Event) -> Result<(), Error> {
-        use http::header::{EXPECT, UPGRADE};
+    // Whether the peer owes us more of a message, so the connection is
+    // waiting on input. A request head that is already fully buffered
+    // needs no more reading.
+    pub fn wants_read(&self) -> bool {
+        self.inner.wants_read(R::SIDE)
+    }
 
-        if let Event::Request(ref req) = *event {
-            if req.method == Method::CONNECT {
-                self.state = self.state.connect_proposal();
-            }
-            if req.headers.contains_key(UPGRADE) {
-                self.state = self.state.upgrade_proposal();
-            }
-        }
+    // Whether this side is in the middle of a message it must finish, or
+    // a request it must answer, or has a held body waiting to be taken.
+    pub fn wants_write(&self) -> bool {
+        self.inner.wants_write(R::SIDE)
+    }
 
-        self.state = self.state.client_event(event.to_state_event())?;
+    // `None` when nothing is expected from the peer, either because it is
+    // our turn to send or because the connection is finished.
+    pub fn timeout_phase(&self) -> Option<TimeoutPhase> {
+        self.inner.timeout_phase(R::SIDE)
+    }
 
-        match *event {
-            Event::Request(ref req) => {
-                if !req.can_keep_alive() {
-                    self.state = self.state.disable_keep_alive();
-                }
-                self.client_wants_continue = req
-                    .headers
-                    .get_all(EXPECT)
-                    .iter()
-                    .next_back()
-                    .map_or(false, |tok| {
-                        str::from_utf8(tok.as_bytes())
-                            .map(|s| {
-                                s.trim().eq_ignore_ascii_case("100-continue")
-                            })
-                            .unwrap_or(false)
-                    });
-            }
-            Event::Data(_) | Event::EndOfMessage(_) => {
-                self.client_wants_continue = false;
-            }
-            _ => {}
+    pub fn send_data(&mut self, data: Bytes) -> Result<Bytes, Error> {
+        self.inner.send_event(R::SIDE, &Event::Data(data))
+    }
+
+    // Like `send_data`, but split into pieces no larger than the
+    // configured `max_send_frame_size`, each framed only once the previous
+    // one has been taken, so a driver can wait for the socket in between.
+    pub fn send_data_frames(&mut self, data: Bytes) -> DataFrames<'_, R> {
+        let max = self.inner.config.max_send_frame_size;
+        DataFrames {
+            conn: self,
+            data,
+            max: max.unwrap_or(usize::MAX).max(1),
         }
-        Ok(())
     }
 
-    fn server_event(&mut self, event: &Event) -> Result<(), Error> {
-        let switch = match *event {
-            Event::InfoResponse(RespHead {
-                status: StatusCode::SWITCHING_PROTOCOLS,
-                ..
-            }) => Some(SwitchEvent::Upgrade),
-            Event::Response(RespHead { status, .. })
-                if self.state.pending_connect && status.is_success() =>
-            {
-                Some(SwitchEvent::Connect)
+    /// Holds trailers back until the end of the message, which sends them
+    /// along with any it is given.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::NotSendable` if this side is not sending a body.
+    pub fn send_trailers(
+        &mut self,
+        trailers: HeaderMap,
+    ) -> Result<Bytes, Error> {
+        if !self.inner.peer_sending_body(R::SIDE.peer()) {
+            return Err(Error::NotSendable);
+        }
+        match self.inner.held_trailers {
+            Some(ref mut held) => append_fields(held, &trailers),
+            None => self.inner.held_trailers = Some(trailers),
+        }
+        Ok(Bytes::new())
+    }
+
+    pub fn send_end_of_message(
+        &mut self,
+        headers: Option<HeaderMap>,
+    ) -> Result<Bytes, Error> {
+        let headers = match (self.inner.held_trailers.clone(), headers) {
+            (Some(mut held), Some(headers)) => {
+                append_fields(&mut held, &headers);
+                Some(held)
             }
-            _ => None,
+            (held, headers) => headers.or(held),
         };
+        let headers = self.inner.outgoing_trailers(R::SIDE, headers)?;
+        let out = self
+            .inner
+            .send_event(R::SIDE, &Event::EndOfMessage(headers))?;
+        self.inner.held_trailers = None;
+        Ok(out)
+    }
 
-        self.state =
-            self.state.server_event(event.to_state_event(), switch)?;
+    pub fn send_connection_closed(&mut self) -> Result<Bytes, Error> {
+        self.inner.send_event(R::SIDE, &Event::ConnectionClosed)
+    }
 
-        match *event {
-            Event::InfoResponse(_) => self.client_wants_continue = false,
-            Event::Response(ref resp) => {
-                if !resp.can_keep_alive() {
-                    self.state = self.state.disable_keep_alive();
-                }
-                self.client_wants_continue = false;
+    /// Sends any event this side may send, through the same method as
+    /// sending it directly would use.
+    ///
+    /// # Errors
+    ///
+    /// Fails as the method for sending that kind of event would.
+    pub fn send(&mut self, event: Event) -> Result<Bytes, Error> {
+        match event {
+            Event::Data(data) => self.send_data(data),
+            Event::Trailers(trailers) => self.send_trailers(trailers),
+            Event::EndOfMessage(trailers) => {
+                self.send_end_of_message(trailers)
             }
-            _ => {}
+            Event::ConnectionClosed => self.send_connection_closed(),
+            head => R::send_head(self, head),
         }
+    }
 
-        Ok(())
+    /// Like `send`, but only accepting events this side may send.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `send` would.
+    pub fn send_typed(&mut self, event: R::Sends) -> Result<Bytes, Error> {
+        self.send(event.into())
     }
 }
 
 #[derive(Debug)]
-pub enum Error {
-    ClientErrorState,
-    DataFromClosedPeer,
-    RequestHead(ReqHeadError),
-    HttpBody(BodyError),
-    IO(std::io::Error),
-    State(StateError),
+pub struct DataFrames<'a, R> {
+    conn: &'a mut HttpConn<R>,
+    data: Bytes,
+    max: usize,
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::ClientErrorState => write!(f, "Client in error state"),
-            Self::DataFromClosedPeer => {
-                write!(f, "peer closed then sent data??")
-            }
-            Self::RequestHead(e) => write!(
-                f,
-                "An error occurred when reading the request head: {}",
-                e
+impl<R: Role> Iterator for DataFrames<'_, R> {
+    type Item = Result<Bytes, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let n = self.data.len().min(self.max);
+        let res = self.conn.send_data(self.data.split_to(n));
+        if res.is_err() {
+            self.data.clear();
+        }
+        Some(res)
+    }
+}
+
+impl HttpConn<Client> {
+    #[cfg(any(test, feature = "testing"))]
+    #[must_use]
+    pub fn pair() -> crate::testing::Duplex {
+        crate::testing::Duplex::default()
+    }
+
+    /// # Errors
+    ///
+    /// Fails if no request can be sent in the current state, or if a
+    /// chunked request would go to an HTTP/1.0 server.
+    pub fn send_req(&mut self, req: ReqHead) -> Result<Bytes, Error> {
+        if self.inner.peer_http_version == Some(Version::HTTP_10)
+            && req.framing_method() == FramingMethod::Chunked
+        {
+            return Err(Error::ChunkedToHttp10);
+        }
+        self.inner.send_event(Side::Client, &Event::Request(req))
+    }
+
+    // Whether body data is being held back until the server answers an
+    // `Expect: 100-continue` request.
+    pub fn is_holding_body(&self) -> bool {
+        matches!(self.inner.held_body, Some(HeldBody::Holding(_)))
+    }
+
+    // Body data held back for `100 Continue` that may now be sent, because
+    // the server asked for it or `release_held_body` was called.
+    pub fn take_released_body(&mut self) -> Bytes {
+        self.inner.released_body.take().freeze()
+    }
+
+    // Stops waiting for `100 Continue`, as a client does once its own wait
+    // expires, and returns everything held so far. Later body data is sent
+    // as usual.
+    pub fn release_held_body(&mut self) -> Bytes {
+        self.inner.release_held_body();
+        self.take_released_body()
+    }
+
+    /// Sends a whole request while responses to earlier ones are still
+    /// pending. Each pipelined request's cycle begins in turn with
+    /// `start_next_cycle`, so responses are matched to requests in order.
+    /// Only HTTP/1.1 requests can be pipelined, not those that might switch
+    /// protocols or wait for `100 Continue`, and nothing can follow one
+    /// that closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::CannotPipeline` if the request cannot follow
+    /// those already sent, or as `send_request_with_body` would.
+    pub fn send_pipelined_request(
+        &mut self,
+        req: ReqHead,
+        body: Bytes,
+    ) -> Result<Bytes, Error> {
+        self.inner.send_pipelined(req, body)
+    }
+
+    // How many requests have been pipelined behind the current cycle's.
+    pub fn pipelined_requests(&self) -> usize {
+        self.inner.pipelined.len()
+    }
+
+    // `None` until a request has been sent in the current cycle.
+    pub fn retry_info(&self) -> Option<RetryInfo> {
+        use state::Client::{Idle, SendBody};
+
+        let inner = &self.inner;
+        let method = inner.request_method.as_ref()?;
+        let client = inner.state.states().0;
+        Some(RetryInfo {
+            idempotent: matches!(
+                *method,
+                Method::GET
+                    | Method::HEAD
+                    | Method::OPTIONS
+                    | Method::TRACE
+                    | Method::PUT
+                    | Method::DELETE
             ),
-            Self::HttpBody(e) => {
-                write!(f, "An error occurred in the http body: {}", e)
+            head_sent: client != Idle,
+            body_sent: client != Idle
+                && client != SendBody
+                && inner.held_body.is_none()
+                && inner.released_body.is_empty(),
+            response_started: inner.metrics.bytes_read
+                > inner.request_read_mark,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the request cannot be sent in the current state, or if its
+    /// body disagrees with a `Content-Length` it already carries.
+    pub fn send_request_with_body(
+        &mut self,
+        mut req: ReqHead,
+        body: Bytes,
+    ) -> Result<Bytes, Error> {
+        use http::header::CONTENT_LENGTH;
+
+        if (!body.is_empty() || req.headers.contains_key(CONTENT_LENGTH))
+            && !is_chunked(&req.headers)
+        {
+            BodyWriter::buffered(&mut req.headers, body.len())?;
+        }
+        let head = self.send_req(req)?;
+        let data = if body.is_empty() {
+            Bytes::new()
+        } else {
+            self.send_data(body)?
+        };
+        let end = self.send_end_of_message(None)?;
+        Ok(self.inner.concat(&[head, data, end]))
+    }
+}
+
+impl HttpConn<Server> {
+    pub fn send_info_resp(&mut self, resp: RespHead) -> Result<Bytes, Error> {
+        self.inner
+            .send_event(Side::Server, &Event::InfoResponse(resp))
+    }
+
+    /// A `103 Early Hints` response with the given `Link` values, which
+    /// lets the client start fetching what the final response will need.
+    /// Any number can be sent while that response is still being prepared.
+    /// HTTP/1.0 clients do not understand interim responses at all.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `Error::NotSendable` unless a response is due and not yet
+    /// started, or with `Error::InfoToHttp10` for an HTTP/1.0 client.
+    pub fn send_early_hints<I>(&mut self, links: I) -> Result<Bytes, Error>
+    where
+        I: IntoIterator<Item = HeaderValue>,
+    {
+        use http::header::LINK;
+
+        if self.inner.state.states().1 != state::Server::SendResponse {
+            return Err(Error::NotSendable);
+        }
+        if self.inner.peer_http_version < Some(Version::HTTP_11) {
+            return Err(Error::InfoToHttp10);
+        }
+        let mut headers = HeaderMap::new();
+        for link in links {
+            headers.append(LINK, link);
+        }
+        self.send_info_resp(RespHead {
+            status: InfoStatus::EarlyHints.status(),
+            version: Version::HTTP_11,
+            headers,
+            reason: Some(Bytes::from_static(b"Early Hints")),
+        })
+    }
+
+    /// A final response sent while the client is still waiting for a
+    /// `100 Continue` leaves it unknown whether the body will follow, so
+    /// the connection cannot be reused. Early responses to other requests
+    /// keep the connection alive once the remaining body has been read.
+    ///
+    /// A response with a body but no framing headers is sent chunked to
+    /// HTTP/1.1 clients and delimited by closing the connection otherwise.
+    /// HTTP/1.0 clients cannot decode chunked responses at all, so those are
+    /// refused unless the config allows falling back to closing instead.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no response can be sent in the current state, or with
+    /// `Error::ChunkedToHttp10` when refusing a chunked one as above.
+    pub fn send_resp(&mut self, mut resp: RespHead) -> Result<Bytes, Error> {
+        use http::header::{HeaderValue, CONNECTION, TRANSFER_ENCODING};
+
+        let close = HeaderValue::from_static("close");
+        if self.inner.client_wants_continue {
+            resp.headers.insert(CONNECTION, close.clone());
+        }
+        let method =
+            self.inner.request_method.as_ref().unwrap_or(&Method::GET);
+        if self.inner.peer_http_version == Some(Version::HTTP_10)
+            && resp.framing_method(method) == FramingMethod::Chunked
+        {
+            if !self.inner.config.downgrade_chunked_for_http10 {
+                return Err(Error::ChunkedToHttp10);
             }
-            Self::IO(e) => write!(f, "An IO error occurred: {}", e),
-            Self::State(e) => {
-                write!(f, "An error occurred in internal state: {}", e)
+            resp.headers.remove(TRANSFER_ENCODING);
+        }
+        if resp.framing_method(method) == FramingMethod::Http10 {
+            if self.inner.peer_http_version >= Some(Version::HTTP_11) {
+                resp.headers.append(
+                    TRANSFER_ENCODING,
+                    HeaderValue::from_static("chunked"),
+                );
+            } else {
+                resp.headers.insert(CONNECTION, close.clone());
             }
         }
+        let served = self.inner.metrics.cycles_reused + 1;
+        if self
+            .inner
+            .config
+            .max_requests_per_connection
+            .is_some_and(|max| served >= max)
+        {
+            self.inner.disable_keep_alive(CloseReason::RequestLimit);
+        }
+        if let Some(CloseReason::RequestLimit | CloseReason::Shutdown) =
+            self.inner.close_reason
+        {
+            resp.headers.insert(CONNECTION, close);
+        }
+        if self.inner.peer_http_version == Some(Version::HTTP_10)
+            && self.inner.state.keep_alive
+            && !has_token(&resp.headers, CONNECTION, "close")
+        {
+            resp.headers
+                .append(CONNECTION, HeaderValue::from_static("keep-alive"));
+        }
+        self.inner.send_event(Side::Server, &Event::Response(resp))
     }
-}
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Self::RequestHead(e) => Some(e),
-            Self::HttpBody(e) => Some(e),
-            Self::IO(e) => Some(e),
-            Self::State(e) => Some(e),
+    // Whether the request received in this cycle has a body to read. False
+    // until its head has been received.
+    pub fn request_has_body(&self) -> bool {
+        match self.inner.framing {
+            Some(FramingMethod::ContentLength(n)) => n > 0,
+            Some(FramingMethod::Chunked | FramingMethod::Http10) => true,
+            None => false,
+        }
+    }
+
+    // The body length the received request declares, or `None` if it is
+    // chunked and so only known once all of it has been read.
+    pub fn request_content_length(&self) -> Option<usize> {
+        match self.inner.framing {
+            Some(FramingMethod::ContentLength(n)) => Some(n),
             _ => None,
         }
     }
-}
 
-impl From<ReqHeadError> for Error {
-    fn from(e: ReqHeadError) -> Self {
-        Self::RequestHead(e)
+    // True when the head of a further pipelined request is already
+    // sitting in the input buffer, so `next_event` can make progress
+    // without another read. That holds at any point of the cycle, except
+    // while the buffer may still hold the current request's body.
+    pub fn has_buffered_request(&self) -> bool {
+        self.inner.state.states().0 != state::Client::SendBody
+            && twoway::find_bytes(&self.inner.in_buf, b"\r\n\r\n").is_some()
     }
-}
 
-impl From<BodyError> for Error {
-    fn from(e: BodyError) -> Self {
-        Self::HttpBody(e)
+    /// # Errors
+    ///
+    /// Fails if no response can be sent in the current state, or if `headers`
+    /// hold a `Content-Length` that disagrees with `body`.
+    pub fn respond(
+        &mut self,
+        status: StatusCode,
+        mut headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Bytes, Error> {
+        use http::header::TRANSFER_ENCODING;
+
+        headers.remove(TRANSFER_ENCODING);
+        if status != StatusCode::NO_CONTENT
+            && status != StatusCode::NOT_MODIFIED
+        {
+            BodyWriter::buffered(&mut headers, body.len())?;
+        }
+        // Answer in the request's version, so HTTP/1.0 clients are not
+        // sent a version they may not understand.
+        let version = match self.inner.peer_http_version {
+            Some(Version::HTTP_10) => Version::HTTP_10,
+            _ => Version::HTTP_11,
+        };
+        let head = self.send_resp(RespHead {
+            status,
+            version,
+            headers,
+            reason: None,
+        })?;
+        // The length still describes the body a GET would have had.
+        let data = if body.is_empty()
+            || self.inner.request_method == Some(Method::HEAD)
+        {
+            Bytes::new()
+        } else {
+            self.send_data(body)?
+        };
+        let end = self.send_end_of_message(None)?;
+        Ok(self.inner.concat(&[head, data, end]))
+    }
+
+    /// `now` is stamped into the `Date` header; the connection keeps no
+    /// clock of its own.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a response is already under way.
+    pub fn send_error_response(
+        &mut self,
+        status: StatusCode,
+        now: SystemTime,
+    ) -> Result<Bytes, Error> {
+        use http::header::{HeaderValue, CONNECTION, CONTENT_TYPE, DATE};
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            DATE,
+            HeaderValue::from_str(&format_http_date(now)).unwrap_or_else(
+                |_| unreachable!("formatted date is a valid header value"),
+            ),
+        );
+        match self.inner.state.states().0 {
+            state::Client::Error | state::Client::SendBody => {
+                headers.insert(CONNECTION, HeaderValue::from_static("close"));
+            }
+            _ => {}
+        }
+        let body = match status.canonical_reason() {
+            Some(_)
+                if status == StatusCode::NO_CONTENT
+                    || status == StatusCode::NOT_MODIFIED =>
+            {
+                Bytes::new()
+            }
+            Some(reason) => {
+                headers.insert(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                );
+                Bytes::from(format!("{} {}\n", status.as_str(), reason))
+            }
+            None => Bytes::new(),
+        };
+        self.respond(status, headers, body)
+    }
+
+    /// Answers a request that `next_event` failed on with the status the
+    /// error suggests, or 400 if it suggests none, and leaves the
+    /// connection to be closed.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `send_error_response` would.
+    pub fn send_error_response_for(
+        &mut self,
+        error: &Error,
+        now: SystemTime,
+    ) -> Result<Bytes, Error> {
+        self.send_error_response(
+            error.status_hint().unwrap_or(StatusCode::BAD_REQUEST),
+            now,
+        )
+    }
+
+    /// For when the client took too long with its request: answers 408
+    /// and leaves the connection to be closed, whatever of the request was
+    /// read. Returns nothing to write if a response is already under way.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the response cannot be sent in the current state.
+    pub fn send_timeout_response(
+        &mut self,
+        now: SystemTime,
+    ) -> Result<Bytes, Error> {
+        self.inner.peer_error(Side::Client);
+        match self.inner.state.states().1 {
+            state::Server::Idle | state::Server::SendResponse => {
+                self.send_error_response(StatusCode::REQUEST_TIMEOUT, now)
+            }
+            _ => Ok(Bytes::new()),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Fails if the end of the message cannot be sent, as
+    /// `send_end_of_message` would.
+    pub fn finish_and_reuse(
+        &mut self,
+        trailers: Option<HeaderMap>,
+    ) -> Result<(Bytes, bool), Error> {
+        let out = self.send_end_of_message(trailers)?;
+        let reusable = self.inner.state.states()
+            == (state::Client::Done, state::Server::Done);
+        if reusable {
+            self.inner.start_next_cycle()?;
+        }
+        Ok((out, reusable))
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(e: std::io::Error) -> Self {
-        Self::IO(e)
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone)]
+struct Inner {
+    state: State,
+    config: Config,
+    max_event_size: usize,
+    in_buf: BytesMut,
+    in_buf_closed: bool,
+    out_buf: BytesMut,
+    client_wants_continue: bool,
+    held_body: Option<HeldBody>,
+    released_body: BytesMut,
+    client_accepts_trailers: bool,
+    client_trailers: Vec<HeaderName>,
+    // Trailers sent ahead of the end of the message they go out with.
+    held_trailers: Option<HeaderMap>,
+    // Received trailers were reported on their own, and the end of the
+    // message they came with is still to be.
+    held_end_of_message: bool,
+    server_trailers: Vec<HeaderName>,
+    request_method: Option<Method>,
+    response_status: Option<StatusCode>,
+    // `bytes_read` when the request was sent, to tell whether any of the
+    // response has arrived since.
+    request_read_mark: u64,
+    // Requests sent in full behind the one of the current cycle, whose
+    // responses are still to come.
+    pipelined: VecDeque<PipelinedRequest>,
+    framing: Option<FramingMethod>,
+    body_reader: Option<BodyReader>,
+    data_encoding: Option<DataEncoding>,
+    body_writer: Option<BodyWriter>,
+    body_received: usize,
+    body_collected: BytesMut,
+    body_discarded: usize,
+    peer_http_version: Option<Version>,
+    peer_head_strict: bool,
+    warnings: Warnings,
+    // How far into everything received the end of `in_buf` is, counting
+    // bytes handed over with the buffers as well as those read or fed.
+    in_buf_end: u64,
+    wire_log: Option<WireLog>,
+    metrics: Metrics,
+    close_reason: Option<CloseReason>,
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (client, server) = self.state.states();
+        f.debug_struct("HttpConn")
+            .field("client", &client)
+            .field("server", &server)
+            .field("keep_alive", &self.state.keep_alive)
+            .field("pending_connect", &self.state.pending_connect)
+            .field("pending_upgrade", &self.state.pending_upgrade)
+            .field("in_buf_len", &self.in_buf.len())
+            .field("in_buf_closed", &self.in_buf_closed)
+            .field("out_buf_len", &self.out_buf.len())
+            .field("framing", &self.framing)
+            .finish_non_exhaustive()
     }
 }
 
-impl From<StateError> for Error {
-    fn from(e: StateError) -> Self {
-        Self::State(e)
+impl Inner {
+    fn from_bufs(
+        max_event_size: usize,
+        in_buf: BytesMut,
+        out_buf: BytesMut,
+    ) -> Self {
+        Self {
+            state: State::new(),
+            config: Config::default(),
+            max_event_size,
+            in_buf_end: in_buf.len() as u64,
+            in_buf,
+            in_buf_closed: false,
+            out_buf,
+            client_wants_continue: false,
+            held_body: None,
+            released_body: BytesMut::new(),
+            client_accepts_trailers: false,
+            client_trailers: Vec::new(),
+            held_trailers: None,
+            held_end_of_message: false,
+            server_trailers: Vec::new(),
+            request_method: None,
+            response_status: None,
+            request_read_mark: 0,
+            pipelined: VecDeque::new(),
+            framing: None,
+            body_reader: None,
+            data_encoding: None,
+            body_writer: None,
+            body_received: 0,
+            body_collected: BytesMut::new(),
+            body_discarded: 0,
+            peer_http_version: None,
+            peer_head_strict: true,
+            warnings: Warnings::default(),
+            wire_log: None,
+            metrics: Metrics::default(),
+            close_reason: None,
+        }
+    }
+
+    fn into_bufs(self) -> (BytesMut, BytesMut) {
+        (self.in_buf, self.out_buf)
+    }
+
+    fn set_config(&mut self, config: Config) {
+        reserve_total(&mut self.in_buf, config.in_buf_capacity);
+        reserve_total(&mut self.out_buf, config.out_buf_capacity);
+        self.config = config;
+    }
+
+    fn shrink_bufs(&mut self) {
+        let Some(threshold) = self.config.shrink_threshold else {
+            return;
+        };
+        if self.in_buf.capacity() > threshold {
+            let mut buf = BytesMut::with_capacity(
+                self.config.in_buf_capacity.max(self.in_buf.len()),
+            );
+            buf.extend_from_slice(&self.in_buf);
+            self.in_buf = buf;
+        }
+        if self.out_buf.capacity() > threshold {
+            let mut buf = BytesMut::with_capacity(
+                self.config.out_buf_capacity.max(self.out_buf.len()),
+            );
+            buf.extend_from_slice(&self.out_buf);
+            self.out_buf = buf;
+        }
+    }
+
+    fn next_event(&mut self, side: Side) -> Result<Option<Event>, Error> {
+        let offset = self.in_offset();
+        let len_before = self.in_buf.len();
+        let res = match side {
+            Side::Server => self.read_client_event(),
+            Side::Client => self.read_server_event(),
+        };
+        let consumed = len_before - self.in_buf.len();
+        if let Some(log) = self.wire_log.as_mut() {
+            log.consumed(offset, offset + consumed as u64);
+        }
+        match res {
+            Ok(Some(ref event)) => {
+                self.metrics.record(Direction::Inbound, event, consumed);
+            }
+            Ok(None)
+            | Err(Error::ClientErrorState | Error::ServerErrorState) => {}
+            Err(_) => self.metrics.protocol_errors += 1,
+        }
+        res
+    }
+
+    // XXX: this should be able to indicate that it will *never* return
+    //      an event again, because the connection has been hijacked via
+    //      UPGRADE or CONNECT
+    fn read_client_event(&mut self) -> Result<Option<Event>, Error> {
+        use state::Client::*;
+
+        match self.state.states().0 {
+            Idle => {
+                let head_offset = self.in_offset();
+                match ReqHead::from_buf_with(&mut self.in_buf, &self.config) {
+                    Ok(Some(_))
+                        if self.in_offset() - head_offset
+                            > self.max_event_size as u64 =>
+                    {
+                        self.state = self.state.client_error();
+                        Err(ReqHeadError::TooLarge.into())
+                    }
+                    Ok(Some((r, _)))
+                        if self.config.require_host
+                            && r.version == Version::HTTP_11
+                            && !r.headers.contains_key(HOST) =>
+                    {
+                        self.state = self.state.client_error();
+                        Err(ReqHeadError::MissingHost.into())
+                    }
+                    Ok(Some((r, anomalies))) => {
+                        self.peer_head_strict = anomalies.is_empty();
+                        self.warnings.record(head_offset, &anomalies);
+                        self.peer_http_version = Some(r.version);
+                        let framing = r.framing_method();
+                        let reader = BodyReader::from(framing)
+                            .with_transfer_codings(&r.headers, &self.config);
+                        let encoding =
+                            DataEncoding::for_body(&r.headers, &reader);
+                        let event = Event::Request(r);
+                        self.peer_event(Side::Client, &event)?;
+                        self.start_body(framing, reader, encoding);
+                        Ok(Some(event))
+                    }
+                    Ok(None) if self.in_buf.len() >= self.max_event_size => {
+                        self.state = self.state.client_error();
+                        Err(ReqHeadError::TooLarge.into())
+                    }
+                    Ok(None) if self.in_buf_closed => {
+                        self.peer_closed(Side::Client)
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => {
+                        self.state = self.state.client_error();
+                        Err(e.into())
+                    }
+                }
+            }
+            SendBody => self.read_body(Side::Client),
+            Error => Err(self::Error::ClientErrorState),
+            Done | MustClose
+                if self.in_buf_closed && self.in_buf.is_empty() =>
+            {
+                self.peer_closed(Side::Client)
+            }
+            Done | MustClose | Closed | MightSwitchProtocol
+            | SwitchedProtocol => Ok(None),
+        }
+    }
+
+    fn read_server_event(&mut self) -> Result<Option<Event>, Error> {
+        use state::Server::{
+            Closed, Done, Error, Idle, MustClose, SendBody, SendResponse,
+            SwitchedProtocol,
+        };
+
+        match self.state.states().1 {
+            Idle | SendResponse => {
+                let head_offset = self.in_offset();
+                match RespHead::from_buf_with(&mut self.in_buf, &self.config) {
+                    Ok(Some(_))
+                        if self.in_offset() - head_offset
+                            > self.max_event_size as u64 =>
+                    {
+                        self.state = self.state.server_error();
+                        Err(RespHeadError::TooLarge.into())
+                    }
+                    Ok(Some((r, anomalies))) => {
+                        self.peer_head_strict = anomalies.is_empty();
+                        self.warnings.record(head_offset, &anomalies);
+                        self.peer_http_version = Some(r.version);
+                        let framing = r.framing_method(
+                            self.request_method
+                                .as_ref()
+                                .unwrap_or(&Method::GET),
+                        );
+                        let reader = BodyReader::from(framing)
+                            .with_transfer_codings(&r.headers, &self.config);
+                        let encoding =
+                            DataEncoding::for_body(&r.headers, &reader);
+                        let event = Event::from(r);
+                        self.peer_event(Side::Server, &event)?;
+                        if let Event::Response(_) = event {
+                            self.start_body(framing, reader, encoding);
+                        }
+                        Ok(Some(event))
+                    }
+                    Ok(None) if self.in_buf.len() >= self.max_event_size => {
+                        self.state = self.state.server_error();
+                        Err(RespHeadError::TooLarge.into())
+                    }
+                    Ok(None) if self.in_buf_closed => {
+                        self.peer_closed(Side::Server)
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => {
+                        self.state = self.state.server_error();
+                        Err(e.into())
+                    }
+                }
+            }
+            SendBody => self.read_body(Side::Server),
+            Error => Err(self::Error::ServerErrorState),
+            Done | MustClose
+                if self.in_buf_closed && self.in_buf.is_empty() =>
+            {
+                self.peer_closed(Side::Server)
+            }
+            Done | MustClose | Closed | SwitchedProtocol => Ok(None),
+        }
+    }
+
+    fn start_body(
+        &mut self,
+        framing: FramingMethod,
+        reader: BodyReader,
+        encoding: DataEncoding,
+    ) {
+        self.framing = Some(framing);
+        self.body_reader = Some(reader);
+        self.data_encoding = Some(encoding);
+        self.body_received = 0;
+        self.body_discarded = 0;
+        self.body_collected = BytesMut::new();
+    }
+
+    fn read_body(&mut self, peer: Side) -> Result<Option<Event>, Error> {
+        let br = self.body_reader.as_mut().expect("reading body");
+        let res = match br.next_event(&mut self.in_buf, &self.config) {
+            Ok(Some(event)) => Ok(event),
+            Ok(None) if self.in_buf_closed => br.eof(),
+            Ok(None) => return Ok(None),
+            Err(e) => Err(e),
+        };
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                self.peer_error(peer);
+                return Err(e.into());
+            }
+        };
+        if let Event::Data(ref data) = event {
+            self.body_received += data.len();
+            if self
+                .config
+                .max_body_size
+                .is_some_and(|max| self.body_received > max)
+            {
+                self.peer_error(peer);
+                return Err(Error::BodyTooLarge);
+            }
+        }
+        self.peer_event(peer, &event)?;
+        Ok(Some(event))
+    }
+
+    fn discard_remaining_body(
+        &mut self,
+        side: Side,
+    ) -> Result<Option<usize>, Error> {
+        while self.peer_sending_body(side) {
+            match self.next_event(side)? {
+                Some(Event::Data(data)) => self.body_discarded += data.len(),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.body_discarded))
+    }
+
+    fn collect_body(
+        &mut self,
+        side: Side,
+    ) -> Result<Option<(Bytes, Option<HeaderMap>)>, Error> {
+        if !self.peer_sending_body(side) {
+            return Err(Error::NoBodyInProgress);
+        }
+        loop {
+            match self.next_event(side)? {
+                Some(Event::Data(data)) => {
+                    self.body_collected.extend_from_slice(&data);
+                }
+                Some(Event::EndOfMessage(trailers)) => {
+                    let body = self.body_collected.take().freeze();
+                    return Ok(Some((body, trailers)));
+                }
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn peer_sending_body(&self, side: Side) -> bool {
+        match side {
+            Side::Server => self.state.states().0 == state::Client::SendBody,
+            Side::Client => self.state.states().1 == state::Server::SendBody,
+        }
+    }
+
+    fn wants_read(&self, side: Side) -> bool {
+        if self.in_buf_closed {
+            return false;
+        }
+        match (side, self.state.states()) {
+            (Side::Server, (state::Client::Idle, _)) => {
+                twoway::find_bytes(&self.in_buf, b"\r\n\r\n").is_none()
+            }
+            (Side::Server, (state::Client::SendBody, _))
+            | (
+                Side::Client,
+                (_, state::Server::SendResponse | state::Server::SendBody),
+            ) => true,
+            _ => false,
+        }
+    }
+
+    fn timeout_phase(&self, side: Side) -> Option<TimeoutPhase> {
+        use state::{Client, Server};
+
+        if self.in_buf_closed {
+            return None;
+        }
+        match (side, self.state.states()) {
+            (Side::Server, (Client::Idle, _))
+            | (Side::Client, (Client::Idle, Server::Idle)) => {
+                if self.in_buf.is_empty() {
+                    Some(TimeoutPhase::Idle)
+                } else {
+                    Some(TimeoutPhase::ReadingHead)
+                }
+            }
+            (Side::Server, (Client::SendBody, _))
+            | (Side::Client, (_, Server::SendBody)) => {
+                Some(TimeoutPhase::ReadingBody)
+            }
+            (Side::Client, (_, Server::SendResponse))
+                if self.client_wants_continue =>
+            {
+                Some(TimeoutPhase::ContinueWait)
+            }
+            (Side::Client, (_, Server::SendResponse)) => {
+                Some(TimeoutPhase::ReadingHead)
+            }
+            _ => None,
+        }
+    }
+
+    fn wants_write(&self, side: Side) -> bool {
+        !self.released_body.is_empty()
+            || match (side, self.state.states()) {
+                (Side::Client, (client, _)) => {
+                    client == state::Client::SendBody
+                }
+                (Side::Server, (_, server)) => matches!(
+                    server,
+                    state::Server::SendResponse | state::Server::SendBody
+                ),
+            }
+    }
+
+    fn start_next_cycle(&mut self) -> Result<(), Error> {
+        let pipelined = self.pipelined.front().cloned();
+        self.state = match pipelined {
+            Some(_) => self.state.start_pipelined_cycle()?,
+            None => self.state.start_next_cycle()?,
+        };
+        self.pipelined.pop_front();
+        self.metrics.cycles_reused += 1;
+        self.shrink_bufs();
+        self.request_method = None;
+        self.response_status = None;
+        self.framing = None;
+        self.body_reader = None;
+        self.data_encoding = None;
+        self.body_writer = None;
+        self.client_wants_continue = false;
+        self.held_body = None;
+        self.released_body.clear();
+        self.client_accepts_trailers = false;
+        self.client_trailers.clear();
+        self.held_trailers = None;
+        self.held_end_of_message = false;
+        self.server_trailers.clear();
+        if let Some(req) = pipelined {
+            self.request_method = Some(req.method);
+            // Any of its response already buffered counts as received.
+            self.request_read_mark =
+                self.metrics.bytes_read - self.in_buf.len() as u64;
+            if req.closes {
+                self.disable_keep_alive(CloseReason::ConnectionClose(
+                    Direction::Outbound,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Writes a whole request behind the current one without touching the
+    // state, which catches up once its cycle starts.
+    fn send_pipelined(
+        &mut self,
+        mut req: ReqHead,
+        body: Bytes,
+    ) -> Result<Bytes, Error> {
+        use http::header::{CONNECTION, CONTENT_LENGTH, EXPECT, UPGRADE};
+
+        let can_pipeline = self.state.states().0 == state::Client::Done
+            && matches!(
+                self.state.states().1,
+                state::Server::SendResponse
+                    | state::Server::SendBody
+                    | state::Server::Done
+            )
+            && self.state.keep_alive
+            && self.peer_http_version != Some(Version::HTTP_10)
+            && req.version == Version::HTTP_11
+            && !self.pipelined.iter().any(|req| req.closes)
+            && req.method != Method::CONNECT
+            && !req.headers.contains_key(UPGRADE)
+            && !req.headers.contains_key(EXPECT);
+        if !can_pipeline {
+            return Err(Error::CannotPipeline);
+        }
+        if (!body.is_empty() || req.headers.contains_key(CONTENT_LENGTH))
+            && !is_chunked(&req.headers)
+        {
+            BodyWriter::buffered(&mut req.headers, body.len())?;
+        }
+        let mut writer = BodyWriter::from(req.framing_method());
+        let entry = PipelinedRequest {
+            method: req.method.clone(),
+            closes: has_token(&req.headers, CONNECTION, "close"),
+        };
+        let data = if body.is_empty() {
+            None
+        } else {
+            Some(writer.write_data(&mut self.out_buf, &body)?)
+        };
+        let end = writer.write_end(&mut self.out_buf, None)?;
+        let head = self.write_event(&Event::Request(req), None);
+        let data = match data {
+            Some(data) => self.write_event(&Event::Data(body), Some(data)),
+            None => Bytes::new(),
+        };
+        let end = self.write_event(&Event::EndOfMessage(None), Some(end));
+        self.pipelined.push_back(entry);
+        Ok(self.concat(&[head, data, end]))
+    }
+
+    fn read_from<R: Read>(&mut self, r: &mut R) -> Result<usize, Error> {
+        let growth = self.config.read_growth.unwrap_or(self.max_event_size);
+        if self.in_buf.remaining_mut() < growth {
+            self.in_buf.reserve(growth);
+        }
+        unsafe {
+            r.read(self.in_buf.bytes_mut())
+                .map_err(Into::into)
+                .and_then(|n| {
+                    if n == 0 {
+                        self.in_buf_closed = true;
+                    } else {
+                        if self.in_buf_closed {
+                            return Err(Error::DataFromClosedPeer);
+                        }
+                        self.in_buf.advance_mut(n);
+                        if let Some(log) = self.wire_log.as_mut() {
+                            log.received(
+                                &self.in_buf[self.in_buf.len() - n..],
+                            );
+                        }
+                        self.metrics.bytes_read += n as u64;
+                        self.in_buf_end += n as u64;
+                    }
+                    Ok(n)
+                })
+        }
+    }
+
+    // How far into the stream the unread input starts.
+    fn in_offset(&self) -> u64 {
+        self.in_buf_end - self.in_buf.len() as u64
+    }
+
+    fn take_read_buf(&mut self) -> BytesMut {
+        let growth = self.config.read_growth.unwrap_or(self.max_event_size);
+        if self.in_buf.remaining_mut() < growth {
+            self.in_buf.reserve(growth);
+        }
+        let len = self.in_buf.len();
+        let capacity = self.in_buf.capacity();
+        self.in_buf.resize(capacity, 0);
+        self.in_buf.split_off(len)
+    }
+
+    fn return_read_buf(
+        &mut self,
+        mut buf: BytesMut,
+        n: usize,
+    ) -> Result<(), Error> {
+        assert!(n <= buf.len(), "read past the end of the buffer");
+        if n == 0 {
+            self.in_buf_closed = true;
+            return Ok(());
+        }
+        if self.in_buf_closed {
+            return Err(Error::DataFromClosedPeer);
+        }
+        buf.truncate(n);
+        if let Some(log) = self.wire_log.as_mut() {
+            log.received(&buf);
+        }
+        // Free when nothing was fed since the buffer was taken, since it
+        // then still directly follows the input buffer.
+        self.in_buf.unsplit(buf);
+        self.metrics.bytes_read += n as u64;
+        self.in_buf_end += n as u64;
+        Ok(())
+    }
+
+    fn feed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.is_empty() {
+            self.in_buf_closed = true;
+        } else if self.in_buf_closed {
+            return Err(Error::DataFromClosedPeer);
+        } else {
+            self.in_buf.extend_from_slice(bytes);
+            if let Some(log) = self.wire_log.as_mut() {
+                log.received(bytes);
+            }
+            self.metrics.bytes_read += bytes.len() as u64;
+            self.in_buf_end += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    // `body` is the already-framed form of a data or end-of-message event.
+    fn write_event(&mut self, event: &Event, body: Option<Bytes>) -> Bytes {
+        let out = match body {
+            Some(body) => body,
+            None => event.write_to_buf(&mut self.out_buf),
+        };
+        self.metrics.bytes_written += out.len() as u64;
+        self.metrics.record(Direction::Outbound, event, out.len());
+        if let Some(log) = self.wire_log.as_mut() {
+            log.record(Direction::Outbound, out.clone());
+        }
+        out
+    }
+
+    fn concat(&mut self, parts: &[Bytes]) -> Bytes {
+        for part in parts {
+            self.out_buf.extend_from_slice(part);
+        }
+        self.out_buf.take().freeze()
+    }
+
+    // Servers may only send trailers to clients that asked for them with
+    // `TE: trailers`; what happens otherwise is up to the config.
+    fn outgoing_trailers(
+        &self,
+        side: Side,
+        trailers: Option<HeaderMap>,
+    ) -> Result<Option<HeaderMap>, Error> {
+        if side == Side::Client || self.client_accepts_trailers {
+            return Ok(trailers);
+        }
+        match (trailers, self.config.unrequested_trailers) {
+            (None, _) | (Some(_), TrailerPolicy::Strip) => Ok(None),
+            (Some(_), TrailerPolicy::Reject) => {
+                Err(Error::TrailersNotAccepted)
+            }
+            (trailers, TrailerPolicy::Send) => Ok(trailers),
+        }
+    }
+
+    fn send_event(
+        &mut self,
+        side: Side,
+        event: &Event,
+    ) -> Result<Bytes, Error> {
+        debug_assert!(side.sends(event));
+        let discarded;
+        let event = match (event, self.forbidden_body(side)) {
+            (Event::Data(data), Some(_)) if data.is_empty() => event,
+            (Event::Data(_), Some(Error::BodyForHead))
+                if self.config.discard_head_response_body =>
+            {
+                discarded = Event::Data(Bytes::new());
+                &discarded
+            }
+            (Event::Data(_), Some(e)) => return Err(e),
+            _ => event,
+        };
+        if let Event::InfoResponse(ref resp) | Event::Response(ref resp) =
+            *event
+        {
+            if !resp.has_valid_reason() {
+                return Err(Error::InvalidReason);
+            }
+        }
+        // Frame the body before touching the state, so a rejected write
+        // leaves the connection as it was.
+        let mut writer = self.body_writer.unwrap_or(BodyWriter::Http10);
+        let body = match *event {
+            Event::Data(ref data) => {
+                Some(writer.write_data(&mut self.out_buf, data)?)
+            }
+            Event::EndOfMessage(ref trailers) => {
+                Some(writer.write_end(&mut self.out_buf, trailers.as_ref())?)
+            }
+            _ => None,
+        };
+        self.event(side, event, Direction::Outbound)?;
+        self.body_writer = match *event {
+            Event::Request(ref req) => {
+                Some(BodyWriter::from(req.framing_method()))
+            }
+            Event::Response(ref resp) => {
+                Some(BodyWriter::from(resp.framing_method(
+                    self.request_method.as_ref().unwrap_or(&Method::GET),
+                )))
+            }
+            Event::Data(_) => Some(writer),
+            _ => self.body_writer,
+        };
+        let out = self.write_event(event, body);
+        match (event, self.held_body.as_mut()) {
+            (
+                Event::Data(_) | Event::EndOfMessage(_),
+                Some(HeldBody::Holding(held)),
+            ) => {
+                held.extend_from_slice(&out);
+                Ok(Bytes::new())
+            }
+            (
+                Event::Data(_) | Event::EndOfMessage(_),
+                Some(HeldBody::Discarding),
+            ) => Ok(Bytes::new()),
+            _ => Ok(out),
+        }
+    }
+
+    // Why the response being sent cannot have a body, if it cannot. A
+    // response to HEAD has the headers a GET would get, but no body.
+    fn forbidden_body(&self, side: Side) -> Option<Error> {
+        if side != Side::Server
+            || self.state.states().1 != state::Server::SendBody
+        {
+            return None;
+        }
+        match self.response_status? {
+            status @ (StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED) => {
+                Some(Error::BodyNotAllowed(status))
+            }
+            _ if self.request_method == Some(Method::HEAD) => {
+                Some(Error::BodyForHead)
+            }
+            _ => None,
+        }
+    }
+
+    fn release_held_body(&mut self) {
+        if let Some(HeldBody::Holding(held)) = self.held_body.take() {
+            self.released_body.unsplit(held);
+        }
+        self.client_wants_continue = false;
+    }
+
+    // Applies an event received from the peer. Events the state machine
+    // refuses put the peer into the error state, just as a parse failure
+    // would.
+    fn peer_event(&mut self, peer: Side, event: &Event) -> Result<(), Error> {
+        debug_assert!(peer.sends(event));
+        let res = self.event(peer, event, Direction::Inbound);
+        if res.is_err() {
+            self.peer_error(peer);
+        }
+        res
+    }
+
+    // The peer's EOF, which is clean between its messages but cuts short
+    // one it has started, or a response it still owes.
+    fn peer_closed(&mut self, peer: Side) -> Result<Option<Event>, Error> {
+        let between_messages = match peer {
+            Side::Client => matches!(
+                self.state.states().0,
+                state::Client::Idle
+                    | state::Client::Done
+                    | state::Client::MustClose
+            ),
+            Side::Server => matches!(
+                self.state.states().1,
+                state::Server::Idle
+                    | state::Server::Done
+                    | state::Server::MustClose
+            ),
+        };
+        if !between_messages || !self.in_buf.is_empty() {
+            self.peer_error(peer);
+            return Err(Error::ClosedPrematurely);
+        }
+        let event = Event::ConnectionClosed;
+        self.peer_event(peer, &event)?;
+        Ok(Some(event))
+    }
+
+    fn peer_error(&mut self, peer: Side) {
+        self.state = match peer {
+            Side::Client => self.state.client_error(),
+            Side::Server => self.state.server_error(),
+        };
+    }
+
+    fn event(
+        &mut self,
+        side: Side,
+        event: &Event,
+        direction: Direction,
+    ) -> Result<(), Error> {
+        if let Event::EndOfMessage(Some(ref trailers)) = *event {
+            self.check_trailers(side, trailers)?;
+        }
+        match side {
+            Side::Client => self.client_event(event, direction),
+            Side::Server => self.server_event(event, direction),
+        }
+    }
+
+    // HTTP/1.0 messages only persist under the keep-alive extension, and
+    // only if it is enabled.
+    fn head_close_reason(
+        &self,
+        version: Version,
+        headers: &HeaderMap,
+        direction: Direction,
+    ) -> Option<CloseReason> {
+        use http::header::CONNECTION;
+
+        if version < Version::HTTP_11
+            && !(self.config.http10_keep_alive
+                && has_token(headers, CONNECTION, "keep-alive"))
+        {
+            Some(CloseReason::Http10(direction))
+        } else if has_token(headers, CONNECTION, "close") {
+            Some(CloseReason::ConnectionClose(direction))
+        } else {
+            None
+        }
+    }
+
+    // The first reason found is kept, since later ones are usually just
+    // consequences of it.
+    fn disable_keep_alive(&mut self, reason: CloseReason) {
+        self.state = self.state.disable_keep_alive();
+        self.close_reason.get_or_insert(reason);
+    }
+
+    fn check_trailers(
+        &self,
+        side: Side,
+        trailers: &HeaderMap,
+    ) -> Result<(), Error> {
+        if !self.config.strict_trailers {
+            return Ok(());
+        }
+        let declared = match side {
+            Side::Client => &self.client_trailers,
+            Side::Server => &self.server_trailers,
+        };
+        match trailer_findings(declared, trailers).into_iter().next() {
+            Some(finding) => Err(Error::InvalidTrailer(finding)),
+            None => Ok(()),
+        }
+    }
+
+    fn client_event(
+        &mut self,
+        event: &Event,
+        direction: Direction,
+    ) -> Result<(), Error> {
+        use http::header::{EXPECT, TE, UPGRADE};
+
+        if let Event::Request(ref req) = *event {
+            if req.method == Method::CONNECT {
+                self.state = self.state.connect_proposal();
+            }
+            if req.headers.contains_key(UPGRADE) {
+                self.state = self.state.upgrade_proposal();
+            }
+        }
+
+        self.state = self.state.client_event(event.to_state_event())?;
+
+        match *event {
+            Event::Request(ref req) => {
+                self.request_method = Some(req.method.clone());
+                self.request_read_mark = self.metrics.bytes_read;
+                if let Some(reason) = self.head_close_reason(
+                    req.version,
+                    &req.headers,
+                    direction,
+                ) {
+                    self.disable_keep_alive(reason);
+                }
+                self.client_wants_continue =
+                    last_token_is(&req.headers, EXPECT, "100-continue");
+                if self.client_wants_continue
+                    && self.config.hold_body_for_continue
+                    && direction == Direction::Outbound
+                {
+                    self.held_body = Some(HeldBody::Holding(BytesMut::new()));
+                }
+                self.client_accepts_trailers =
+                    has_token(&req.headers, TE, "trailers");
+                if self.config.strict_trailers {
+                    self.client_trailers = declared_trailers(&req.headers);
+                }
+            }
+            Event::Data(_) | Event::EndOfMessage(_)
+                if self.held_body.is_none() =>
+            {
+                self.client_wants_continue = false;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn server_event(
+        &mut self,
+        event: &Event,
+        direction: Direction,
+    ) -> Result<(), Error> {
+        let switch = match *event {
+            Event::InfoResponse(RespHead {
+                status: StatusCode::SWITCHING_PROTOCOLS,
+                ..
+            }) => Some(SwitchEvent::Upgrade),
+            Event::Response(RespHead { status, .. })
+                if self.state.pending_connect && status.is_success() =>
+            {
+                Some(SwitchEvent::Connect)
+            }
+            _ => None,
+        };
+
+        self.state =
+            self.state.server_event(event.to_state_event(), switch)?;
+
+        match *event {
+            Event::InfoResponse(ref resp) => {
+                if resp.info_status() == Some(InfoStatus::Continue) {
+                    self.release_held_body();
+                }
+                if self.held_body.is_none() {
+                    self.client_wants_continue = false;
+                }
+            }
+            Event::Response(ref resp) => {
+                self.response_status = Some(resp.status);
+                if let Some(HeldBody::Holding(_)) = self.held_body {
+                    if resp.status.is_client_error()
+                        || resp.status.is_server_error()
+                    {
+                        self.held_body = Some(HeldBody::Discarding);
+                        self.disable_keep_alive(CloseReason::BodyWithheld);
+                    } else {
+                        self.release_held_body();
+                    }
+                }
+                let method =
+                    self.request_method.as_ref().unwrap_or(&Method::GET);
+                let close_delimited =
+                    resp.framing_method(method) == FramingMethod::Http10;
+                match self.head_close_reason(
+                    resp.version,
+                    &resp.headers,
+                    direction,
+                ) {
+                    Some(reason @ CloseReason::Http10(_)) => {
+                        self.disable_keep_alive(reason);
+                    }
+                    _ if close_delimited => {
+                        self.disable_keep_alive(CloseReason::CloseDelimited);
+                    }
+                    Some(reason) => self.disable_keep_alive(reason),
+                    None => {}
+                }
+                if self.config.strict_trailers {
+                    self.server_trailers = declared_trailers(&resp.headers);
+                }
+                self.client_wants_continue = false;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PipelinedRequest {
+    // Needed to frame its response.
+    method: Method,
+    closes: bool,
+}
+
+// What becomes of request body data sent while waiting for `100 Continue`.
+#[derive(Clone, Debug)]
+enum HeldBody {
+    Holding(BytesMut),
+    Discarding,
+}
+
+fn reserve_total(buf: &mut BytesMut, capacity: usize) {
+    if buf.capacity() < capacity {
+        let additional = capacity - buf.len();
+        buf.reserve(additional);
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ClientErrorState,
+    ServerErrorState,
+    DataFromClosedPeer,
+    BodyTooLarge,
+    TrailersNotAccepted,
+    InvalidTrailer(Finding),
+    ChunkedToHttp10,
+    InfoToHttp10,
+    BodyForHead,
+    BodyNotAllowed(StatusCode),
+    ClosedPrematurely,
+    NotSendable,
+    // A response's reason phrase holds CR, LF or another control byte.
+    InvalidReason,
+    NoBodyInProgress,
+    CannotPipeline,
+    RequestHead(ReqHeadError),
+    ResponseHead(RespHeadError),
+    HttpBody(BodyError),
+    IO(std::io::Error),
+    State(StateError),
+}
+
+impl Error {
+    // The status a server should answer with when reading a request
+    // failed this way, or `None` if the client is not to blame.
+    pub fn status_hint(&self) -> Option<StatusCode> {
+        match self {
+            Self::RequestHead(e) => Some(e.status_hint()),
+            Self::BodyTooLarge
+            | Self::HttpBody(BodyError::DecodedTooLarge) => {
+                Some(StatusCode::PAYLOAD_TOO_LARGE)
+            }
+            Self::HttpBody(BodyError::UnsupportedCoding(e)) => {
+                Some(e.status_hint())
+            }
+            Self::InvalidTrailer(_)
+            | Self::HttpBody(
+                BodyError::InvalidChunkSize
+                | BodyError::InvalidChunkEnd
+                | BodyError::ChunkSizeLineTooLong
+                | BodyError::ChunkExtensionsTooLong
+                | BodyError::Decoding(_)
+                | BodyError::HttpParse(_),
+            ) => Some(StatusCode::BAD_REQUEST),
+            _ => None,
+        }
+    }
+
+    // Whether the peer closed the connection before finishing its message,
+    // as opposed to the clean close reported as `Event::ConnectionClosed`.
+    // A response cut short this way must not be cached.
+    pub fn is_premature_close(&self) -> bool {
+        matches!(
+            self,
+            Self::ClosedPrematurely
+                | Self::HttpBody(BodyError::ConnectionClosedPrematurely)
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ClientErrorState => write!(f, "Client in error state"),
+            Self::ServerErrorState => write!(f, "Server in error state"),
+            Self::DataFromClosedPeer => {
+                write!(f, "peer closed then sent data??")
+            }
+            Self::BodyTooLarge => {
+                write!(f, "body exceeds the configured maximum size")
+            }
+            Self::TrailersNotAccepted => {
+                write!(f, "client did not send TE: trailers")
+            }
+            Self::InvalidTrailer(finding) => {
+                write!(f, "trailer field not allowed: {finding:?}")
+            }
+            Self::ChunkedToHttp10 => {
+                write!(
+                    f,
+                    "chunked encoding cannot be sent to an HTTP/1.0 peer"
+                )
+            }
+            Self::InfoToHttp10 => {
+                write!(
+                    f,
+                    "interim responses cannot be sent to an HTTP/1.0 peer"
+                )
+            }
+            Self::BodyForHead => {
+                write!(f, "a response to HEAD cannot have a body")
+            }
+            Self::BodyNotAllowed(status) => {
+                write!(f, "a {status} response cannot have a body")
+            }
+            Self::ClosedPrematurely => {
+                write!(
+                    f,
+                    "peer closed the connection partway through a message"
+                )
+            }
+            Self::NotSendable => {
+                write!(f, "this side of the connection cannot send that")
+            }
+            Self::InvalidReason => {
+                write!(f, "reason phrase contains control characters")
+            }
+            Self::NoBodyInProgress => {
+                write!(f, "no message body is being received")
+            }
+            Self::CannotPipeline => {
+                write!(f, "another request cannot be pipelined now")
+            }
+            Self::RequestHead(e) => write!(
+                f,
+                "An error occurred when reading the request head: {}",
+                e
+            ),
+            Self::ResponseHead(e) => write!(
+                f,
+                "An error occurred when reading the response head: {e}"
+            ),
+            Self::HttpBody(e) => {
+                write!(f, "An error occurred in the http body: {}", e)
+            }
+            Self::IO(e) => write!(f, "An IO error occurred: {}", e),
+            Self::State(e) => {
+                write!(f, "An error occurred in internal state: {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::RequestHead(e) => Some(e),
+            Self::ResponseHead(e) => Some(e),
+            Self::HttpBody(e) => Some(e),
+            Self::IO(e) => Some(e),
+            Self::State(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReqHeadError> for Error {
+    fn from(e: ReqHeadError) -> Self {
+        Self::RequestHead(e)
+    }
+}
+
+impl From<RespHeadError> for Error {
+    fn from(e: RespHeadError) -> Self {
+        Self::ResponseHead(e)
+    }
+}
+
+impl From<BodyError> for Error {
+    fn from(e: BodyError) -> Self {
+        Self::HttpBody(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<StateError> for Error {
+    fn from(e: StateError) -> Self {
+        Self::State(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Duplex;
+    use crate::warning::Anomaly;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use http::header::{
+        HeaderValue, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, EXPECT, HOST,
+        TRANSFER_ENCODING,
+    };
+
+    fn req(method: Method, headers: HeaderMap) -> ReqHead {
+        ReqHead {
+            method,
+            uri: "/a".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+        }
+    }
+
+    #[test]
+    fn send_request_with_body_sets_content_length() {
+        let mut conn = HttpConn::<Client>::new();
+        let out = conn
+            .send_request_with_body(
+                req(
+                    Method::POST,
+                    vec![(HOST, HeaderValue::from_static("example.com"))]
+                        .into_iter()
+                        .collect(),
+                ),
+                Bytes::from_static(b"hello"),
+            )
+            .expect("send request");
+        assert_eq!(
+            &b"POST /a HTTP/1.1\r\n\
+               host: example.com\r\n\
+               content-length: 5\r\n\r\n\
+               hello"[..],
+            &out[..]
+        );
+        assert_eq!(
+            (state::Client::Done, state::Server::SendResponse),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn send_request_with_body_keeps_content_length() {
+        let mut conn = HttpConn::<Client>::new();
+        let out = conn
+            .send_request_with_body(
+                req(
+                    Method::PUT,
+                    vec![(CONTENT_LENGTH, HeaderValue::from_static("2"))]
+                        .into_iter()
+                        .collect(),
+                ),
+                Bytes::from_static(b"hi"),
+            )
+            .expect("send request");
+        assert_eq!(
+            &b"PUT /a HTTP/1.1\r\ncontent-length: 2\r\n\r\nhi"[..],
+            &out[..]
+        );
+    }
+
+    #[test]
+    fn send_request_with_body_rejects_wrong_content_length() {
+        let mut conn = HttpConn::<Client>::new();
+        let res = conn.send_request_with_body(
+            req(
+                Method::PUT,
+                vec![(CONTENT_LENGTH, HeaderValue::from_static("3"))]
+                    .into_iter()
+                    .collect(),
+            ),
+            Bytes::from_static(b"hi"),
+        );
+        match res {
+            Err(Error::HttpBody(BodyError::ContentLengthMismatch)) => {}
+            r => panic!("expected length mismatch, got {:?}", r),
+        }
+        assert_eq!(
+            (state::Client::Idle, state::Server::Idle),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn send_request_with_empty_body() {
+        let mut conn = HttpConn::<Client>::new();
+        let out = conn
+            .send_request_with_body(
+                req(Method::GET, HeaderMap::new()),
+                Bytes::new(),
+            )
+            .expect("send request");
+        assert_eq!(&b"GET /a HTTP/1.1\r\n\r\n"[..], &out[..]);
+    }
+
+    fn server_after_request(req_text: &[u8]) -> HttpConn<Server> {
+        let mut conn = HttpConn::<Server>::from_bufs(
+            8192,
+            req_text.into(),
+            BytesMut::new(),
+        );
+        match conn.next_event().expect("read request") {
+            Some(Event::Request(_)) => {}
+            e => panic!("expected request, got {:?}", e),
+        }
+        match conn.next_event().expect("read end of message") {
+            Some(Event::EndOfMessage(None)) => {}
+            e => panic!("expected end of message, got {:?}", e),
+        }
+        conn
+    }
+
+    #[test]
+    fn respond_with_body() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        let out = conn
+            .respond(
+                StatusCode::OK,
+                HeaderMap::new(),
+                Bytes::from_static(b"hello"),
+            )
+            .expect("respond");
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello"[..],
+            &out[..]
+        );
+        assert_eq!(
+            (state::Client::Done, state::Server::Done),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn respond_rejects_wrong_content_length() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        let res = conn.respond(
+            StatusCode::OK,
+            vec![(CONTENT_LENGTH, HeaderValue::from_static("100"))]
+                .into_iter()
+                .collect(),
+            Bytes::from_static(b"hello"),
+        );
+        match res {
+            Err(Error::HttpBody(BodyError::ContentLengthMismatch)) => {}
+            r => panic!("expected length mismatch, got {:?}", r),
+        }
+        assert_eq!(
+            (state::Client::Done, state::Server::SendResponse),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn respond_no_content() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        let out = conn
+            .respond(StatusCode::NO_CONTENT, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert_eq!(&b"HTTP/1.1 204 No Content\r\n\r\n"[..], &out[..]);
+    }
+
+    #[test]
+    fn respond_in_the_request_version() {
+        let mut conn = server_after_request(b"GET / HTTP/1.0\r\n\r\n");
+        let out = conn
+            .respond(StatusCode::NO_CONTENT, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert_eq!(&b"HTTP/1.0 204 No Content\r\n\r\n"[..], &out[..]);
+    }
+
+    #[test]
+    fn send_error_response_after_parse_failure() {
+        use http::header::{CONNECTION, DATE};
+
+        let mut conn = HttpConn::<Server>::from_bufs(
+            8192,
+            b"GET / HTTP/1.1\r\nfoo : bar\r\n\r\n"[..].into(),
+            BytesMut::new(),
+        );
+        assert!(conn.next_event().is_err());
+        let out = conn
+            .send_error_response(StatusCode::BAD_REQUEST, SystemTime::now())
+            .expect("send error response");
+        let mut out_buf = BytesMut::from(&out[..]);
+        let resp = RespHead::from_buf(&mut out_buf)
+            .expect("parse response")
+            .expect("complete response");
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status);
+        assert!(resp.headers.contains_key(DATE));
+        assert_eq!("close", resp.headers[CONNECTION]);
+        assert_eq!("16", resp.headers[CONTENT_LENGTH]);
+        assert_eq!(&b"400 Bad Request\n"[..], &out_buf[..]);
+        assert_eq!(
+            (state::Client::Error, state::Server::MustClose),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn send_error_response_without_body() {
+        use http::header::DATE;
+
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        for &status in &[StatusCode::NO_CONTENT, StatusCode::NOT_MODIFIED] {
+            let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+            let out = conn.send_error_response(status, now).expect("respond");
+            let mut out_buf = BytesMut::from(&out[..]);
+            let resp = RespHead::from_buf(&mut out_buf)
+                .expect("parse response")
+                .expect("complete response");
+            assert_eq!(status, resp.status);
+            assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", resp.headers[DATE]);
+            assert!(!resp.headers.contains_key(CONTENT_TYPE));
+            assert!(out_buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn finish_and_reuse_keep_alive() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        conn.send_resp(RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: None,
+        })
+        .expect("send response");
+        let (out, reusable) =
+            conn.finish_and_reuse(None).expect("finish response");
+        assert_eq!(&b"0\r\n\r\n"[..], &out[..]);
+        assert!(reusable);
+        assert_eq!(
+            (state::Client::Idle, state::Server::Idle),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn unframed_response_to_http_10_closes() {
+        let mut conn = server_after_request(b"GET / HTTP/1.0\r\n\r\n");
+        let out = conn
+            .send_resp(RespHead {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+                reason: None,
+            })
+            .expect("send response");
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n"[..],
+            &out[..]
+        );
+        let out = conn.send_data(Bytes::from("hi")).expect("send data");
+        assert_eq!(&b"hi"[..], &out[..]);
+        let (_, reusable) = conn.finish_and_reuse(None).expect("finish");
+        assert!(!reusable);
+    }
+
+    fn chunked_resp() -> RespHead {
+        RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: vec![(
+                TRANSFER_ENCODING,
+                HeaderValue::from_static("chunked"),
+            )]
+            .into_iter()
+            .collect(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn chunked_to_http_10_is_refused() {
+        let mut conn = server_after_request(b"GET / HTTP/1.0\r\n\r\n");
+        let before = conn.inner.state.states();
+        match conn.send_resp(chunked_resp()) {
+            Err(Error::ChunkedToHttp10) => {}
+            r => panic!("expected refusal, got {:?}", r),
+        }
+        assert_eq!(before, conn.inner.state.states());
+    }
+
+    #[test]
+    fn chunked_to_http_10_downgrades() {
+        let mut conn = server_after_request(b"GET / HTTP/1.0\r\n\r\n")
+            .with_config(Config {
+                downgrade_chunked_for_http10: true,
+                ..Config::default()
+            });
+        let out = conn.send_resp(chunked_resp()).expect("send response");
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n"[..],
+            &out[..]
+        );
+        assert_eq!(
+            &b"hi"[..],
+            &conn.send_data(Bytes::from("hi")).expect("send data")[..]
+        );
+    }
+
+    #[test]
+    fn http_10_keep_alive() {
+        let req = b"GET / HTTP/1.0\r\nconnection: keep-alive\r\n\r\n";
+        let mut conn = server_after_request(req);
+        let out = conn
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert!(twoway::find_bytes(&out, b"keep-alive").is_none());
+        assert_eq!(
+            Some(CloseReason::Http10(Direction::Inbound)),
+            conn.close_reason()
+        );
+
+        let config = Config {
+            http10_keep_alive: true,
+            ..Config::default()
+        };
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        conn.feed(req).expect("feed");
+        while conn.next_event().expect("read request").is_some() {}
+        let out = conn
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert_eq!(
+            &b"HTTP/1.0 200 OK\r\ncontent-length: 0\r\n\
+               connection: keep-alive\r\n\r\n"[..],
+            &out[..]
+        );
+        assert_eq!(None, conn.close_reason());
+        conn.start_next_cycle().expect("reuse connection");
+    }
+
+    #[test]
+    fn no_body_in_response_to_head() {
+        let resp = || {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(5));
+            RespHead {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers,
+                reason: None,
+            }
+        };
+
+        let mut conn = server_after_request(b"HEAD / HTTP/1.1\r\n\r\n");
+        conn.send_resp(resp()).expect("send response");
+        match conn.send_data(Bytes::from("hello")) {
+            Err(Error::BodyForHead) => {}
+            r => panic!("expected refusal, got {:?}", r),
+        }
+        assert!(conn.send_data(Bytes::new()).expect("send data").is_empty());
+        conn.send_end_of_message(None).expect("end response");
+
+        let mut conn = server_after_request(b"HEAD / HTTP/1.1\r\n\r\n")
+            .with_config(Config {
+                discard_head_response_body: true,
+                ..Config::default()
+            });
+        conn.send_resp(resp()).expect("send response");
+        let out = conn.send_data(Bytes::from("hello")).expect("send data");
+        assert!(out.is_empty());
+        conn.send_end_of_message(None).expect("end response");
+        assert_eq!(
+            (state::Client::Done, state::Server::Done),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn no_body_for_204_or_304() {
+        for status in &[StatusCode::NO_CONTENT, StatusCode::NOT_MODIFIED] {
+            let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+            conn.send_resp(RespHead {
+                status: *status,
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+                reason: None,
+            })
+            .expect("send response");
+            match conn.send_data(Bytes::from("hello")) {
+                Err(Error::BodyNotAllowed(s)) => assert_eq!(*status, s),
+                r => panic!("expected refusal, got {:?}", r),
+            }
+            conn.send_end_of_message(None).expect("end response");
+            conn.start_next_cycle().expect("reuse connection");
+        }
+    }
+
+    #[test]
+    fn respond_to_head() {
+        let mut conn = server_after_request(b"HEAD / HTTP/1.1\r\n\r\n");
+        let out = conn
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::from("hello"))
+            .expect("respond");
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n"[..],
+            &out[..]
+        );
+        conn.start_next_cycle().expect("reuse connection");
+    }
+
+    #[test]
+    fn received_body_offset() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n",
+        )
+        .expect("feed");
+        conn.next_event().expect("read request");
+        assert_eq!(0, conn.received_body_offset());
+        conn.next_event().expect("read data");
+        assert_eq!(3, conn.received_body_offset());
+        conn.feed(b"2\r\nde\r\n0\r\n\r\n").expect("feed");
+        match conn.next_event().expect("read data") {
+            Some(Event::Data(data)) => assert_eq!(&b"de"[..], &data[..]),
+            e => panic!("expected data, got {:?}", e),
+        }
+        assert_eq!(5, conn.received_body_offset());
+        conn.next_event().expect("read end of message");
+        assert_eq!(5, conn.received_body_offset());
+
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        conn.start_next_cycle().expect("reuse connection");
+        conn.feed(b"GET / HTTP/1.1\r\n\r\n").expect("feed");
+        conn.next_event().expect("read request");
+        assert_eq!(0, conn.received_body_offset());
+    }
+
+    #[test]
+    fn clean_close() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        conn.feed(b"").expect("feed EOF");
+        assert_eq!(
+            Some(Event::ConnectionClosed),
+            conn.next_event().expect("read close")
+        );
+        assert_eq!(None, conn.next_event().expect("read after close"));
+        assert_eq!(state::Client::Closed, conn.inner.state.states().0);
+
+        let mut pair = HttpConn::pair();
+        pair.client_send(|client| {
+            client.send_request_with_body(
+                ReqHead {
+                    method: Method::GET,
+                    uri: "/".parse().unwrap(),
+                    version: Version::HTTP_11,
+                    headers: HeaderMap::new(),
+                },
+                Bytes::new(),
+            )
+        })
+        .expect("send request");
+        while pair.server.next_event().expect("read request").is_some() {}
+        pair.server_send(|server| {
+            server.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+        })
+        .expect("respond");
+        let client = &mut pair.client;
+        client.feed(b"").expect("feed EOF");
+        let mut events = Vec::new();
+        while let Some(event) = client.next_event().expect("read response") {
+            events.push(event);
+        }
+        assert_eq!(Some(&Event::ConnectionClosed), events.last());
+    }
+
+    #[test]
+    fn reject_invalid_reason() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        let mut resp = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: Some(Bytes::from_static(b"OK\r\nset-cookie: a=b")),
+        };
+        assert!(matches!(
+            conn.send_resp(resp.clone()),
+            Err(Error::InvalidReason)
+        ));
+        resp.reason = None;
+        conn.send_resp(resp).expect("send response");
+    }
+
+    #[test]
+    fn head_size_limit() {
+        let mut conn = HttpConn::<Client>::from_bufs(
+            64,
+            BytesMut::new(),
+            BytesMut::new(),
+        );
+        conn.send_request_with_body(
+            ReqHead {
+                method: Method::GET,
+                uri: "/".parse().unwrap(),
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+            },
+            Bytes::new(),
+        )
+        .expect("send request");
+        conn.feed(b"HTTP/1.1 200 OK\r\n").expect("feed");
+        assert_eq!(None, conn.next_event().expect("partial head"));
+        conn.feed(&[b'a'; 64]).expect("feed");
+        assert!(matches!(
+            conn.next_event(),
+            Err(Error::ResponseHead(RespHeadError::TooLarge))
+        ));
+    }
+
+    #[test]
+    fn premature_close() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HTTP/1.1\r\nhost").expect("feed");
+        conn.feed(b"").expect("feed EOF");
+        let e = conn.next_event().expect_err("close mid-head");
+        assert!(e.is_premature_close());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\nab")
+            .expect("feed");
+        conn.feed(b"").expect("feed EOF");
+        let e = loop {
+            match conn.next_event() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("expected a premature close"),
+                Err(e) => break e,
+            }
+        };
+        assert!(e.is_premature_close());
+
+        // A client whose request went unanswered.
+        let mut client = HttpConn::<Client>::new();
+        client
+            .send_request_with_body(
+                ReqHead {
+                    method: Method::GET,
+                    uri: "/".parse().unwrap(),
+                    version: Version::HTTP_11,
+                    headers: HeaderMap::new(),
+                },
+                Bytes::new(),
+            )
+            .expect("send request");
+        client.feed(b"").expect("feed EOF");
+        let e = client.next_event().expect_err("close before response");
+        assert!(e.is_premature_close());
+        assert!(!Error::NotSendable.is_premature_close());
+    }
+
+    #[test]
+    fn typed_events() {
+        let mut pair = HttpConn::pair();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(2));
+        let req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+        };
+        for event in [
+            ClientEvent::Request(req),
+            ClientEvent::Data(Bytes::from("hi")),
+            ClientEvent::EndOfMessage(None),
+        ] {
+            pair.client_send(|client| client.send_typed(event))
+                .expect("send");
+        }
+        let Duplex {
+            mut client,
+            mut server,
+        } = pair;
+        match server.next_typed_event().expect("read request") {
+            Some(ClientEvent::Request(req)) => assert_eq!("/", req.uri),
+            e => panic!("expected request, got {:?}", e),
+        }
+        assert_eq!(
+            Some(ClientEvent::Data(Bytes::from("hi"))),
+            server.next_typed_event().expect("read data")
+        );
+        assert_eq!(
+            Some(ClientEvent::EndOfMessage(None)),
+            server.next_typed_event().expect("read end of message")
+        );
+
+        let out = server
+            .send_typed(ServerEvent::Response(RespHead {
+                status: StatusCode::NO_CONTENT,
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+                reason: None,
+            }))
+            .expect("send response");
+        client.feed(&out).expect("feed");
+        match client.next_typed_event().expect("read response") {
+            Some(ServerEvent::Response(resp)) => {
+                assert_eq!(StatusCode::NO_CONTENT, resp.status);
+            }
+            e => panic!("expected response, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn early_hints() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        let out = conn
+            .send_early_hints(vec![
+                HeaderValue::from_static("</a.css>; rel=preload; as=style"),
+                HeaderValue::from_static("</b.js>; rel=preload; as=script"),
+            ])
+            .expect("send early hints");
+        assert_eq!(
+            &b"HTTP/1.1 103 Early Hints\r\n\
+               link: </a.css>; rel=preload; as=style\r\n\
+               link: </b.js>; rel=preload; as=script\r\n\r\n"[..],
+            &out[..]
+        );
+        conn.send_early_hints(None).expect("send more early hints");
+        assert_eq!(state::Server::SendResponse, conn.inner.state.states().1);
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        match conn.send_early_hints(None) {
+            Err(Error::NotSendable) => {}
+            r => panic!("expected refusal, got {:?}", r),
+        }
+
+        let mut conn = server_after_request(b"GET / HTTP/1.0\r\n\r\n");
+        match conn.send_early_hints(None) {
+            Err(Error::InfoToHttp10) => {}
+            r => panic!("expected refusal, got {:?}", r),
+        }
+        assert_eq!(state::Server::SendResponse, conn.inner.state.states().1);
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!conn.graceful_shutdown());
+        let out = conn
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\
+               connection: close\r\n\r\n"[..],
+            &out[..]
+        );
+        assert_eq!(Some(CloseReason::Shutdown), conn.close_reason());
+        assert!(conn.start_next_cycle().is_err());
+
+        let mut conn = HttpConn::<Server>::new();
+        assert!(conn.graceful_shutdown());
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HT").expect("feed");
+        assert!(!conn.graceful_shutdown());
+    }
+
+    #[test]
+    fn request_body_expectations() {
+        let mut conn = HttpConn::<Server>::new();
+        assert!(!conn.request_has_body());
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\n")
+            .expect("feed");
+        assert!(!conn.request_has_body());
+        conn.next_event().expect("read request");
+        assert!(conn.request_has_body());
+        assert_eq!(Some(5), conn.request_content_length());
+
+        let conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!conn.request_has_body());
+        assert_eq!(Some(0), conn.request_content_length());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n")
+            .expect("feed");
+        conn.next_event().expect("read request");
+        assert!(conn.request_has_body());
+        assert_eq!(None, conn.request_content_length());
+    }
+
+    #[test]
+    fn max_requests_per_connection() {
+        let mut conn = HttpConn::<Server>::new().with_config(Config {
+            max_requests_per_connection: Some(2),
+            ..Config::default()
+        });
+        conn.feed(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+            .expect("feed");
+        while conn.next_event().expect("read request").is_some() {}
+        let out = conn
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert!(twoway::find_bytes(&out, b"close").is_none());
+        assert_eq!(None, conn.close_reason());
+        conn.start_next_cycle().expect("reuse connection");
+
+        while conn.next_event().expect("read request").is_some() {}
+        let out = conn
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\
+               connection: close\r\n\r\n"[..],
+            &out[..]
+        );
+        assert_eq!(Some(CloseReason::RequestLimit), conn.close_reason());
+        assert!(conn.start_next_cycle().is_err());
+    }
+
+    #[test]
+    fn space_before_colon() {
+        let req = b"GET / HTTP/1.1\r\nhost : a\r\n\r\n";
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(req).expect("feed");
+        assert!(conn.next_event().is_err());
+
+        let mut conn = HttpConn::<Server>::new().with_config(Config {
+            allow_space_before_colon: true,
+            ..Config::default()
+        });
+        conn.feed(req).expect("feed");
+        match conn.next_event().expect("read request") {
+            Some(Event::Request(ref req)) if req.headers[HOST] == "a" => {}
+            e => panic!("expected request, got {:?}", e),
+        }
+        assert!(!conn.peer_head_was_strict());
+    }
+
+    #[test]
+    fn warnings_for_tolerated_anomalies() {
+        let mut conn = HttpConn::<Server>::new().with_config(Config {
+            allow_space_before_colon: true,
+            allow_extra_line_whitespace: true,
+            ..Config::default()
+        });
+        conn.feed(b"GET / HTTP/1.1\r\n\r\n").expect("feed");
+        while conn.next_event().expect("read request").is_some() {}
+        assert!(conn.take_warnings().is_empty());
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        conn.start_next_cycle().expect("reuse connection");
+
+        conn.feed(b"GET  / HTTP/1.1\r\nhost: a\r\nx : 1\r\ny\t: 2\r\n\r\n")
+            .expect("feed");
+        conn.next_event().expect("read request");
+        let warning = |anomaly, line| Warning {
+            anomaly,
+            head_offset: 18,
+            line,
+        };
+        assert_eq!(
+            vec![
+                warning(Anomaly::ExtraLineWhitespace, 1),
+                warning(Anomaly::SpaceBeforeColon, 3),
+                warning(Anomaly::SpaceBeforeColon, 4),
+            ],
+            conn.take_warnings()
+        );
+        assert!(conn.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn close_reasons() {
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(None, conn.close_reason());
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        assert_eq!(None, conn.close_reason());
+
+        let conn = server_after_request(b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(
+            Some(CloseReason::Http10(Direction::Inbound)),
+            conn.close_reason()
+        );
+
+        let mut conn = server_after_request(b"GET / HTTP/1.1\r\n\r\n");
+        let mut headers = HeaderMap::new();
+        headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        conn.respond(StatusCode::OK, headers, Bytes::new())
+            .expect("respond");
+        assert_eq!(
+            Some(CloseReason::ConnectionClose(Direction::Outbound)),
+            conn.close_reason()
+        );
+
+        let mut client = HttpConn::<Client>::new();
+        client
+            .send_req(req(Method::GET, HeaderMap::new()))
+            .expect("send request");
+        client.feed(b"HTTP/1.1 200 OK\r\n\r\n").expect("feed");
+        assert!(client.next_event().expect("read response").is_some());
+        assert_eq!(Some(CloseReason::CloseDelimited), client.close_reason());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HTTP/1.1\r\nbad header\r\n\r\n")
+            .expect("feed");
+        assert!(conn.next_event().is_err());
+        assert_eq!(Some(CloseReason::Error), conn.close_reason());
+    }
+
+    #[test]
+    fn finish_and_reuse_connection_close() {
+        let mut conn = server_after_request(
+            b"GET / HTTP/1.1\r\nconnection: close\r\n\r\n",
+        );
+        conn.send_resp(RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: None,
+        })
+        .expect("send response");
+        let (_, reusable) =
+            conn.finish_and_reuse(None).expect("finish response");
+        assert!(!reusable);
+        assert_eq!(
+            (state::Client::MustClose, state::Server::MustClose),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn current_framing() {
+        let mut conn = HttpConn::<Server>::from_bufs(
+            8192,
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n"[..]
+                .into(),
+            BytesMut::new(),
+        );
+        assert_eq!(None, conn.current_framing());
+        assert!(conn.next_event().expect("read request").is_some());
+        assert_eq!(Some(FramingMethod::Chunked), conn.current_framing());
+
+        let conn = server_after_request(
+            b"POST / HTTP/1.1\r\ncontent-length: 0\r\n\r\n",
+        );
+        assert_eq!(
+            Some(FramingMethod::ContentLength(0)),
+            conn.current_framing()
+        );
+    }
+
+    #[test]
+    fn debug_shows_state_without_buffer_contents() {
+        let conn = HttpConn::<Server>::from_bufs(
+            8192,
+            b"POST / HTTP/1.1\r\nsecret: hunter2\r\n"[..].into(),
+            BytesMut::new(),
+        );
+        let debug = format!("{conn:?}");
+        assert_eq!(
+            "HttpConn { client: Idle, server: Idle, keep_alive: true, \
+             pending_connect: false, pending_upgrade: false, \
+             in_buf_len: 34, in_buf_closed: false, out_buf_len: 0, \
+             framing: None, .. }",
+            debug
+        );
+    }
+
+    #[test]
+    fn wire_log_records_consumed_and_produced_bytes() {
+        let mut conn = HttpConn::<Server>::from_bufs(
+            8192,
+            b"POST / HTTP/1.1\r\ncontent-length: 2\r\n\r\nhi"[..].into(),
+            BytesMut::new(),
+        );
+        conn.enable_wire_log(16, SystemTime::now);
+        assert!(conn.next_event().expect("read request").is_some());
+        assert!(conn.next_event().expect("read data").is_some());
+        assert!(conn.next_event().expect("read end").is_some());
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+
+        let log = conn.take_wire_log();
+        assert_eq!(
+            vec![
+                (
+                    Direction::Inbound,
+                    &b"POST / HTTP/1.1\r\ncontent-length: 2\r\n\r\n"[..]
+                ),
+                (Direction::Inbound, &b"hi"[..]),
+                (
+                    Direction::Outbound,
+                    &b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"[..]
+                ),
+            ],
+            log.iter()
+                .map(|r| (r.direction, &r.bytes[..]))
+                .collect::<Vec<_>>()
+        );
+        assert!(conn.take_wire_log().is_empty());
+    }
+
+    #[test]
+    fn discard_remaining_body() {
+        let mut conn = HttpConn::<Server>::from_bufs(
+            8192,
+            b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n01234"[..].into(),
+            BytesMut::new(),
+        );
+        assert!(conn.next_event().expect("read request").is_some());
+        assert_eq!(None, conn.discard_remaining_body().expect("discard"));
+        conn.inner.in_buf.extend_from_slice(b"56789");
+        assert_eq!(Some(10), conn.discard_remaining_body().expect("discard"));
+        assert_eq!(state::Client::Done, conn.inner.state.states().0);
+    }
+
+    #[test]
+    fn discard_remaining_body_respects_max_body_size() {
+        let mut conn = HttpConn::<Server>::from_bufs(
+            8192,
+            b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n0123456789"[..]
+                .into(),
+            BytesMut::new(),
+        )
+        .with_config(Config {
+            max_body_size: Some(5),
+            ..Config::default()
+        });
+        assert!(conn.next_event().expect("read request").is_some());
+        match conn.discard_remaining_body() {
+            Err(Error::BodyTooLarge) => {}
+            r => panic!("expected body too large, got {:?}", r),
+        }
+    }
+
+    fn client_after_request(
+        method: Method,
+        resp_text: &[u8],
+    ) -> HttpConn<Client> {
+        let mut conn = HttpConn::<Client>::from_bufs(
+            8192,
+            resp_text.into(),
+            BytesMut::new(),
+        );
+        conn.send_req(req(method, HeaderMap::new()))
+            .expect("send request");
+        conn.send_end_of_message(None).expect("send end of message");
+        conn
+    }
+
+    #[test]
+    fn client_receives_response() {
+        let mut conn = client_after_request(
+            Method::GET,
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello",
+        );
+        match conn.next_event().expect("read info response") {
+            Some(Event::InfoResponse(ref resp))
+                if resp.status == StatusCode::CONTINUE => {}
+            e => panic!("expected info response, got {:?}", e),
+        }
+        match conn.next_event().expect("read response") {
+            Some(Event::Response(ref resp))
+                if resp.status == StatusCode::OK => {}
+            e => panic!("expected response, got {:?}", e),
+        }
+        assert_eq!(
+            Some(Event::data(&b"hello"[..])),
+            conn.next_event().expect("read data")
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().expect("read end of message")
+        );
+        assert_eq!(
+            (state::Client::Done, state::Server::Done),
+            conn.inner.state.states()
+        );
+        conn.start_next_cycle().expect("reuse connection");
+    }
+
+    #[test]
+    fn client_response_to_head_has_no_body() {
+        let mut conn = client_after_request(
+            Method::HEAD,
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n",
+        );
+        assert!(conn.next_event().expect("read response").is_some());
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().expect("read end of message")
+        );
+    }
+
+    #[test]
+    fn client_reads_until_close() {
+        let mut conn =
+            client_after_request(Method::GET, b"HTTP/1.1 200 OK\r\n\r\nhello");
+        assert!(conn.next_event().expect("read response").is_some());
+        assert_eq!(
+            Some(Event::data(&b"hello"[..])),
+            conn.next_event().expect("read data")
+        );
+        assert_eq!(None, conn.next_event().expect("need data"));
+        conn.read_from(&mut &b""[..]).expect("read eof");
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().expect("read end of message")
+        );
+        assert_eq!(
+            (state::Client::MustClose, state::Server::MustClose),
+            conn.inner.state.states()
+        );
+    }
+
+    #[test]
+    fn client_rejects_unproposed_switch() {
+        let mut conn = client_after_request(
+            Method::GET,
+            b"HTTP/1.1 101 Switching Protocols\r\n\r\n",
+        );
+        assert!(conn.next_event().is_err());
+        match conn.next_event() {
+            Err(Error::ServerErrorState) => {}
+            r => panic!("expected server error state, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn client_rejects_bad_response_head() {
+        let mut conn =
+            client_after_request(Method::GET, b"HTTP/1.1 abc OK\r\n\r\n");
+        match conn.next_event() {
+            Err(Error::ResponseHead(_)) => {}
+            r => panic!("expected response head error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn pair_exchange() {
+        let mut pair = HttpConn::pair();
+
+        pair.client_send(|client| {
+            client.send_request_with_body(
+                req(Method::POST, HeaderMap::new()),
+                Bytes::from_static(b"ping"),
+            )
+        })
+        .expect("send request");
+        let server = &mut pair.server;
+        assert!(server.next_event().expect("read request").is_some());
+        assert_eq!(
+            Some(Event::data(&b"ping"[..])),
+            server.next_event().expect("read data")
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            server.next_event().expect("read end of message")
+        );
+
+        pair.server_send(|server| {
+            server.respond(
+                StatusCode::OK,
+                HeaderMap::new(),
+                Bytes::from("pong"),
+            )
+        })
+        .expect("respond");
+        let client = &mut pair.client;
+        assert!(client.next_event().expect("read response").is_some());
+        assert_eq!(
+            Some(Event::data(&b"pong"[..])),
+            client.next_event().expect("read data")
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            client.next_event().expect("read end of message")
+        );
+        pair.client.start_next_cycle().expect("reuse client");
+        pair.server.start_next_cycle().expect("reuse server");
+    }
+
+    #[test]
+    fn feed_after_eof() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"").expect("feed eof");
+        match conn.feed(b"GET") {
+            Err(Error::DataFromClosedPeer) => {}
+            r => panic!("expected data from closed peer, got {:?}", r),
+        }
+    }
+
+    fn resp(status: StatusCode) -> RespHead {
+        RespHead {
+            status,
+            version: Version::HTTP_11,
+            headers: vec![(CONTENT_LENGTH, HeaderValue::from_static("0"))]
+                .into_iter()
+                .collect(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn early_response_then_body() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n01234")
+            .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert!(conn.next_event().expect("read data").is_some());
+
+        let out = conn
+            .send_resp(resp(StatusCode::PAYLOAD_TOO_LARGE))
+            .expect("send response");
+        assert!(twoway::find_bytes(&out, b"connection").is_none());
+        conn.send_end_of_message(None).expect("send end of message");
+        assert_eq!(
+            (state::Client::SendBody, state::Server::Done),
+            conn.inner.state.states()
+        );
+        assert!(conn.start_next_cycle().is_err());
+
+        conn.feed(b"56789").expect("feed");
+        assert_eq!(
+            Some(Event::data(&b"56789"[..])),
+            conn.next_event().expect("read data")
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().expect("read end of message")
+        );
+        conn.start_next_cycle().expect("reuse connection");
+    }
+
+    #[test]
+    fn early_response_to_expect_continue_closes() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(
+            b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\
+              expect: 100-continue\r\n\r\n",
+        )
+        .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+
+        let out = conn
+            .send_resp(resp(StatusCode::UNAUTHORIZED))
+            .expect("send response");
+        assert!(twoway::find_bytes(&out, b"connection: close").is_some());
+        conn.send_end_of_message(None).expect("send end of message");
+
+        // The client may still decide to send the body.
+        conn.feed(b"hello").expect("feed");
+        assert!(conn.next_event().expect("read data").is_some());
+        assert!(conn.next_event().expect("read end of message").is_some());
+        assert_eq!(
+            (state::Client::MustClose, state::Server::MustClose),
+            conn.inner.state.states()
+        );
+    }
+
+    fn respond_with_trailers(
+        config: Config,
+        te: &[u8],
+    ) -> Result<Bytes, Error> {
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        let mut req = b"GET / HTTP/1.1\r\n".to_vec();
+        req.extend_from_slice(te);
+        req.extend_from_slice(b"\r\n");
+        conn.feed(&req).expect("feed");
+        while conn.next_event().expect("read request").is_some() {}
+        let mut headers = HeaderMap::new();
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        conn.send_resp(RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        })
+        .expect("send response");
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+        conn.send_end_of_message(Some(trailers))
+    }
+
+    #[test]
+    fn trailers_sent_when_requested() {
+        let out =
+            respond_with_trailers(Config::default(), b"te: trailers\r\n");
+        assert_eq!(
+            &b"0\r\nx-checksum: abc\r\n\r\n"[..],
+            &out.expect("send")[..]
+        );
+    }
+
+    #[test]
+    fn unrequested_trailers_follow_policy() {
+        let config = |unrequested_trailers| Config {
+            unrequested_trailers,
+            ..Config::default()
+        };
+        let out = respond_with_trailers(config(TrailerPolicy::Strip), b"");
+        assert_eq!(&b"0\r\n\r\n"[..], &out.expect("send")[..]);
+        match respond_with_trailers(config(TrailerPolicy::Reject), b"") {
+            Err(Error::TrailersNotAccepted) => {}
+            r => panic!("expected rejection, got {:?}", r),
+        }
+        let out = respond_with_trailers(config(TrailerPolicy::Send), b"");
+        assert_eq!(
+            &b"0\r\nx-checksum: abc\r\n\r\n"[..],
+            &out.expect("send")[..]
+        );
+    }
+
+    #[test]
+    fn strict_trailers() {
+        let config = Config {
+            strict_trailers: true,
+            ..Config::default()
+        };
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        conn.feed(
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\
+              trailer: x-checksum\r\n\r\n0\r\nx-checksum: abc\r\n\r\n",
+        )
+        .expect("feed");
+        while conn.next_event().expect("declared trailer").is_some() {}
+
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        conn.feed(
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n\
+              0\r\nx-checksum: abc\r\n\r\n",
+        )
+        .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        match conn.next_event() {
+            Err(Error::InvalidTrailer(Finding::UndeclaredTrailer(_))) => {}
+            r => panic!("expected undeclared trailer, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn separate_trailers() {
+        let req = &b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n\
+                     0\r\nx-checksum: abc\r\n\r\n"[..];
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(req).expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert_eq!(
+            Some(Event::EndOfMessage(Some(trailers.clone()))),
+            conn.next_event().expect("read end of message")
+        );
+
+        let mut conn = HttpConn::<Server>::new().with_config(Config {
+            separate_trailers: true,
+            ..Config::default()
+        });
+        conn.feed(req).expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert_eq!(
+            Some(Event::Trailers(trailers)),
+            conn.next_event().expect("read trailers")
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().expect("read end of message")
+        );
+        assert_eq!(None, conn.next_event().expect("read after end"));
+    }
+
+    #[test]
+    fn send_trailers_ahead_of_end_of_message() {
+        let mut conn = HttpConn::<Client>::new();
+        let field = |value| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("x-checksum", HeaderValue::from_static(value));
+            trailers
+        };
+        match conn.send(Event::Trailers(field("abc"))) {
+            Err(Error::NotSendable) => {}
+            r => panic!("expected not sendable, got {:?}", r),
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        conn.send(Event::request(Method::POST, "/".parse().unwrap(), headers))
+            .expect("send request");
+        let out = conn.send(Event::Trailers(field("abc"))).expect("send");
+        assert!(out.is_empty());
+        let out = conn
+            .send_end_of_message(Some(field("def")))
+            .expect("send end of message");
+        assert_eq!(
+            &b"0\r\nx-checksum: abc\r\nx-checksum: def\r\n\r\n"[..],
+            &out[..]
+        );
+    }
+
+    #[test]
+    fn pipelined_requests_survive_next_cycle() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(
+            b"GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.1\r\n\r\nGET /3 HTTP/1.1",
+        )
+        .expect("feed");
+        assert!(conn.has_buffered_request());
+        for &(path, more) in &[("/1", true), ("/2", false)] {
+            match conn.next_event().expect("read request") {
+                Some(Event::Request(ref req)) if req.uri == path => {}
+                e => panic!("expected request for {}, got {:?}", path, e),
+            }
+            assert!(conn.next_event().expect("read end").is_some());
+            assert_eq!(more, conn.has_buffered_request());
+            conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+                .expect("respond");
+            conn.start_next_cycle().expect("reuse connection");
+        }
+        assert!(!conn.has_buffered_request());
+        assert_eq!(None, conn.next_event().expect("partial request"));
+        conn.feed(b"\r\n\r\n").expect("feed");
+        assert!(conn.has_buffered_request());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 4\r\n\r\n")
+            .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        conn.feed(b"\r\n\r\n").expect("feed");
+        assert!(!conn.has_buffered_request());
+    }
+
+    #[test]
+    fn metrics() {
+        let mut conn = HttpConn::<Server>::new();
+        let req = &b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n\
+                     5\r\nhello\r\n0\r\n\r\ngarbage\r\n\r\n"[..];
+        conn.read_from(&mut &req[..]).expect("read");
+        while conn.next_event().expect("read events").is_some() {}
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::from("hi"))
+            .expect("respond");
+        conn.start_next_cycle().expect("reuse connection");
+        assert!(conn.next_event().is_err());
+        assert!(conn.next_event().is_err());
+        assert_eq!(
+            &Metrics {
+                bytes_read: req.len() as u64,
+                bytes_written: 40,
+                header_bytes_received: 47,
+                header_bytes_sent: 38,
+                body_bytes_received: 5,
+                body_bytes_sent: 2,
+                messages_received: 1,
+                messages_sent: 1,
+                cycles_reused: 1,
+                protocol_errors: 1,
+            },
+            conn.metrics()
+        );
+    }
+
+    #[test]
+    fn buffer_capacity_config() {
+        let config = Config {
+            in_buf_capacity: 1024,
+            out_buf_capacity: 256,
+            read_growth: Some(64 * 1024),
+            shrink_threshold: Some(4096),
+            ..Config::default()
+        };
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        assert!(conn.inner.in_buf.capacity() >= 1024);
+        assert!(conn.inner.out_buf.capacity() >= 256);
+
+        conn.read_from(&mut &b"GET / HTTP/1.1\r\n\r\nGET /"[..])
+            .expect("read");
+        assert!(conn.inner.in_buf.capacity() >= 64 * 1024);
+        while conn.next_event().expect("read events").is_some() {}
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        conn.start_next_cycle().expect("reuse connection");
+        assert!(conn.inner.in_buf.capacity() < 4096);
+        assert_eq!(&b"GET /"[..], &conn.inner.in_buf[..]);
+    }
+
+    #[test]
+    fn into_parts_and_back() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n01234")
+            .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+
+        let parts = conn.into_parts();
+        assert_eq!(&b"01234"[..], &parts.in_buf[..]);
+        let mut conn = std::thread::spawn(move || {
+            let mut parts = parts;
+            parts.in_buf.extend_from_slice(b"56789");
+            HttpConn::from_parts(parts)
+        })
+        .join()
+        .expect("join");
+
+        assert_eq!(
+            Some(Event::data(&b"0123456789"[..])),
+            conn.next_event().expect("read data")
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().expect("read end of message")
+        );
+        assert_eq!(
+            Some(FramingMethod::ContentLength(10)),
+            conn.current_framing()
+        );
+    }
+
+    #[test]
+    fn clone_for_speculative_parsing() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HTTP/1.1\r\n\r\n").expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert!(conn.next_event().expect("read end").is_some());
+        conn.respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        conn.start_next_cycle().expect("reuse connection");
+        conn.feed(b"\x16\x03\x01 \r\n\r\n").expect("feed");
+
+        let mut attempt = conn.clone();
+        assert!(attempt.next_event().is_err());
+
+        // The original is untouched by the failed attempt.
+        assert_eq!(conn.inner.state.states().0, state::Client::Idle);
+        assert_eq!(&b"\x16\x03\x01 \r\n\r\n"[..], &conn.inner.in_buf[..]);
+        assert_eq!(1, conn.metrics().cycles_reused);
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HT").expect("feed");
+        assert_eq!(Sniffed::Http1, conn.sniff_buffered());
+        assert_eq!(&b"GET / HT"[..], conn.peek_buffered());
+        assert_eq!(None, conn.next_event().expect("partial request"));
+        conn.feed(b"TP/1.1\r\n\r\n").expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert_eq!(&b""[..], conn.peek_buffered());
+    }
+
+    #[test]
+    fn send_any_event() {
+        let Duplex {
+            mut client,
+            mut server,
+        } = HttpConn::pair();
+        let req = Event::request(
+            Method::POST,
+            "/".parse().unwrap(),
+            chunked_headers(),
+        );
+        match client.send(Event::Response(resp(StatusCode::OK))) {
+            Err(Error::NotSendable) => {}
+            r => panic!("expected not sendable, got {:?}", r),
+        }
+        let mut out = client.send(req).expect("send request").to_vec();
+        out.extend_from_slice(&client.send(Event::data(&b"hi"[..])).unwrap());
+        out.extend_from_slice(
+            &client.send(Event::EndOfMessage(None)).unwrap(),
+        );
+        assert!(out.ends_with(b"2\r\nhi\r\n0\r\n\r\n"));
+
+        server.feed(&out).expect("feed");
+        while server.next_event().expect("read request").is_some() {}
+        match server.send(Event::request(
+            Method::GET,
+            "/".parse().unwrap(),
+            HeaderMap::new(),
+        )) {
+            Err(Error::NotSendable) => {}
+            r => panic!("expected not sendable, got {:?}", r),
+        }
+        assert!(server.send(Event::Response(resp(StatusCode::OK))).is_ok());
+    }
+
+    fn chunked_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers
+    }
+
+    #[test]
+    fn readiness_hints() {
+        let Duplex {
+            mut client,
+            mut server,
+        } = HttpConn::pair();
+        assert!(!client.wants_read() && !client.wants_write());
+        assert!(server.wants_read() && !server.wants_write());
+
+        let req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: chunked_headers(),
+        };
+        let head = client.send_req(req).expect("send request");
+        assert!(client.wants_read() && client.wants_write());
+        server.feed(&head).expect("feed");
+        assert!(!server.wants_read());
+        assert!(server.next_event().expect("read request").is_some());
+        assert!(server.wants_read() && server.wants_write());
+
+        let end = client.send_end_of_message(None).expect("send end");
+        assert!(client.wants_read() && !client.wants_write());
+        server.feed(&end).expect("feed");
+        assert!(server.next_event().expect("read end").is_some());
+        assert!(!server.wants_read() && server.wants_write());
+
+        server
+            .send_resp(resp(StatusCode::OK))
+            .expect("send response");
+        server.send_end_of_message(None).expect("send end");
+        assert!(!server.wants_read() && !server.wants_write());
+
+        server.feed(b"").expect("feed eof");
+        server.start_next_cycle().expect("reuse connection");
+        assert!(!server.wants_read());
+    }
+
+    #[test]
+    fn owned_read_bufs() {
+        let mut conn = HttpConn::<Server>::new();
+        let msg = b"GET / HTTP/1.1\r\nhost: a\r\n\r\n";
+        for part in msg.chunks(10) {
+            let mut buf = conn.take_read_buf();
+            let ptr = buf.as_ptr() as usize;
+            buf[..part.len()].copy_from_slice(part);
+            conn.return_read_buf(buf, part.len())
+                .expect("return buffer");
+            let in_buf = conn.peek_buffered();
+            assert_eq!(
+                ptr,
+                in_buf.as_ptr() as usize + in_buf.len() - part.len()
+            );
+        }
+        assert_eq!(&msg[..], conn.peek_buffered());
+        assert_eq!(msg.len() as u64, conn.metrics().bytes_read);
+        assert!(conn.next_event().expect("read request").is_some());
+
+        let buf = conn.take_read_buf();
+        conn.return_read_buf(buf, 0).expect("return eof");
+        let mut buf = conn.take_read_buf();
+        buf[0] = b'G';
+        match conn.return_read_buf(buf, 1) {
+            Err(Error::DataFromClosedPeer) => {}
+            r => panic!("expected data from closed peer, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn send_data_in_frames() {
+        let config = Config {
+            max_send_frame_size: Some(4),
+            ..Config::default()
+        };
+        let mut client = HttpConn::<Client>::new().with_config(config);
+        let req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: chunked_headers(),
+        };
+        client.send_req(req).expect("send request");
+        let frames = client
+            .send_data_frames(Bytes::from(&b"0123456789"[..]))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("send data");
+        assert_eq!(
+            vec![
+                &b"4\r\n0123\r\n"[..],
+                &b"4\r\n4567\r\n"[..],
+                &b"2\r\n89\r\n"[..],
+            ],
+            frames
+        );
+        assert_eq!(0, client.send_data_frames(Bytes::new()).count());
+
+        let mut client = HttpConn::<Client>::new().with_config(config);
+        let mut req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+        };
+        req.headers
+            .insert(CONTENT_LENGTH, HeaderValue::from_static("6"));
+        client.send_req(req).expect("send request");
+        let results = client
+            .send_data_frames(Bytes::from(&b"0123456789"[..]))
+            .collect::<Vec<_>>();
+        assert_eq!(2, results.len());
+        assert!(results[0].is_ok() && results[1].is_err());
+    }
+
+    #[test]
+    fn timeout_phases() {
+        let Duplex {
+            mut client,
+            mut server,
+        } = HttpConn::pair();
+        assert_eq!(Some(TimeoutPhase::Idle), server.timeout_phase());
+        assert_eq!(Some(TimeoutPhase::Idle), client.timeout_phase());
+
+        let mut req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: chunked_headers(),
+        };
+        req.headers
+            .insert(EXPECT, HeaderValue::from_static("100-continue"));
+        let head = client.send_req(req).expect("send request");
+        assert_eq!(Some(TimeoutPhase::ContinueWait), client.timeout_phase());
+
+        server.feed(&head[..5]).expect("feed");
+        assert_eq!(Some(TimeoutPhase::ReadingHead), server.timeout_phase());
+        server.feed(&head[5..]).expect("feed");
+        assert!(server.next_event().expect("read request").is_some());
+        assert_eq!(Some(TimeoutPhase::ReadingBody), server.timeout_phase());
+
+        let data = client.send_data(Bytes::from("hi")).expect("send data");
+        assert_eq!(Some(TimeoutPhase::ReadingHead), client.timeout_phase());
+        let end = client.send_end_of_message(None).expect("send end");
+        server.feed(&data).expect("feed");
+        server.feed(&end).expect("feed");
+        while server.next_event().expect("read body").is_some() {}
+        assert_eq!(None, server.timeout_phase());
+
+        let resp = server.send_resp(resp(StatusCode::OK)).expect("respond");
+        client.feed(&resp).expect("feed");
+        assert!(client.next_event().expect("read response").is_some());
+        assert_eq!(Some(TimeoutPhase::ReadingBody), client.timeout_phase());
+        client.feed(b"").expect("feed eof");
+        assert_eq!(None, client.timeout_phase());
+    }
+
+    fn expect_continue_client() -> HttpConn<Client> {
+        let mut client = HttpConn::<Client>::new().with_config(Config {
+            hold_body_for_continue: true,
+            ..Config::default()
+        });
+        let mut req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: vec![(CONTENT_LENGTH, HeaderValue::from_static("5"))]
+                .into_iter()
+                .collect(),
+        };
+        req.headers
+            .insert(EXPECT, HeaderValue::from_static("100-continue"));
+        client.send_req(req).expect("send request");
+        assert!(client.is_holding_body());
+        let data = client.send_data(Bytes::from("hello")).expect("send data");
+        let end = client.send_end_of_message(None).expect("send end");
+        assert!(data.is_empty() && end.is_empty());
+        assert_eq!(Some(TimeoutPhase::ContinueWait), client.timeout_phase());
+        client
+    }
+
+    #[test]
+    fn client_holds_body_until_continue() {
+        let mut client = expect_continue_client();
+        assert!(client.take_released_body().is_empty());
+        client.feed(b"HTTP/1.1 100 Continue\r\n\r\n").expect("feed");
+        assert!(client.next_event().expect("read 100").is_some());
+        assert!(!client.is_holding_body() && client.wants_write());
+        assert_eq!(&b"hello"[..], &client.take_released_body()[..]);
+        assert_eq!(Some(TimeoutPhase::ReadingHead), client.timeout_phase());
+    }
+
+    #[test]
+    fn client_releases_body_on_timeout() {
+        let mut client = expect_continue_client();
+        assert_eq!(&b"hello"[..], &client.release_held_body()[..]);
+        assert!(!client.is_holding_body());
+        assert!(client.take_released_body().is_empty());
+    }
+
+    #[test]
+    fn client_discards_body_on_error_response() {
+        let mut client = expect_continue_client();
+        client
+            .feed(
+                b"HTTP/1.1 417 Expectation Failed\r\n\
+                  content-length: 0\r\n\r\n",
+            )
+            .expect("feed");
+        assert!(client.next_event().expect("read response").is_some());
+        assert!(!client.is_holding_body());
+        assert!(client.take_released_body().is_empty());
+        assert_eq!(Some(CloseReason::BodyWithheld), client.close_reason());
+    }
+
+    #[test]
+    fn client_discards_body_sent_after_error_response() {
+        let mut client = HttpConn::<Client>::new().with_config(Config {
+            hold_body_for_continue: true,
+            ..Config::default()
+        });
+        let mut req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: chunked_headers(),
+        };
+        req.headers
+            .insert(EXPECT, HeaderValue::from_static("100-continue"));
+        client.send_req(req).expect("send request");
+        client
+            .feed(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n")
+            .expect("feed");
+        assert!(client.next_event().expect("read response").is_some());
+        let data = client.send_data(Bytes::from("late")).expect("send data");
+        let end = client.send_end_of_message(None).expect("send end");
+        assert!(data.is_empty() && end.is_empty());
+        assert!(!client.wants_write());
+    }
+
+    #[test]
+    fn retry_info() {
+        let mut client = HttpConn::<Client>::new();
+        assert_eq!(None, client.retry_info());
+
+        let req = ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: chunked_headers(),
+        };
+        client.send_req(req).expect("send request");
+        let info = client.retry_info().unwrap();
+        assert!(info.head_sent && !info.body_sent && !info.idempotent);
+        assert!(!info.is_safe());
+
+        client.send_end_of_message(None).expect("send end");
+        assert!(client.retry_info().unwrap().body_sent);
+        client.feed(b"HTTP/1.1 5").expect("feed");
+        assert!(client.retry_info().unwrap().response_started);
+
+        let mut client = HttpConn::<Client>::new();
+        client
+            .send_request_with_body(
+                ReqHead {
+                    method: Method::PUT,
+                    uri: "/".parse().unwrap(),
+                    version: Version::HTTP_11,
+                    headers: HeaderMap::new(),
+                },
+                Bytes::from("x"),
+            )
+            .expect("send request");
+        let info = client.retry_info().unwrap();
+        assert!(info.idempotent && info.body_sent && info.is_safe());
+        client.feed(b"").expect("feed eof");
+        assert!(client.retry_info().unwrap().is_safe());
+    }
+
+    #[test]
+    fn copy_body_to_writer() {
+        let mut client = HttpConn::<Client>::new();
+        client
+            .send_request_with_body(
+                ReqHead {
+                    method: Method::GET,
+                    uri: "/".parse().unwrap(),
+                    version: Version::HTTP_11,
+                    headers: HeaderMap::new(),
+                },
+                Bytes::new(),
+            )
+            .expect("send request");
+        let mut out = Vec::new();
+        assert!(matches!(
+            client.copy_body(&mut &b""[..], &mut out),
+            Err(Error::NoBodyInProgress)
+        ));
+
+        client
+            .feed(
+                b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\n",
+            )
+            .expect("feed");
+        assert!(client.next_event().expect("read response").is_some());
+        let mut rest = &b"6\r\n world\r\n0\r\nx-sum: 1\r\n\r\n"[..];
+        let (copied, trailers) =
+            client.copy_body(&mut rest, &mut out).expect("copy body");
+        assert_eq!(11, copied);
+        assert_eq!(&b"hello world"[..], &out[..]);
+        assert_eq!("1", trailers.expect("trailers")["x-sum"]);
+    }
+
+    #[test]
+    fn copy_body_truncated() {
+        let mut client = HttpConn::<Client>::new();
+        client
+            .send_request_with_body(
+                ReqHead {
+                    method: Method::GET,
+                    uri: "/".parse().unwrap(),
+                    version: Version::HTTP_11,
+                    headers: HeaderMap::new(),
+                },
+                Bytes::new(),
+            )
+            .expect("send request");
+        client
+            .feed(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nabc")
+            .expect("feed");
+        assert!(client.next_event().expect("read response").is_some());
+        let mut out = Vec::new();
+        assert!(client.copy_body(&mut &b"de"[..], &mut out).is_err());
+        assert_eq!(&b"abcde"[..], &out[..]);
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let Duplex {
+            mut client,
+            mut server,
+        } = HttpConn::pair();
+        let get = |method: Method, path: &str| ReqHead {
+            method,
+            uri: path.parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+        };
+        let mut wire = client
+            .send_request_with_body(get(Method::GET, "/a"), Bytes::new())
+            .expect("send first request")
+            .to_vec();
+        for req in [get(Method::HEAD, "/b"), get(Method::POST, "/c")] {
+            let out = client
+                .send_pipelined_request(req, Bytes::from("xy"))
+                .expect("pipeline request");
+            wire.extend_from_slice(&out);
+        }
+        assert_eq!(2, client.pipelined_requests());
+
+        // The server answers each in turn; the HEAD response has a length
+        // but no body.
+        server.feed(&wire).expect("feed");
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            let req = match server.next_event().expect("read request") {
+                Some(Event::Request(req)) => req,
+                e => panic!("expected a request, got {:?}", e),
+            };
+            while let Some(Event::Data(_)) =
+                server.next_event().expect("read body")
+            {}
+            let mut resp = resp(StatusCode::OK);
+            resp.headers
+                .insert(CONTENT_LENGTH, HeaderValue::from_static("2"));
+            responses.extend_from_slice(&server.send_resp(resp).unwrap());
+            if req.method != Method::HEAD {
+                let body = format!("{}!", &req.uri.path()[1..]);
+                let data = server.send_data(Bytes::from(body)).unwrap();
+                responses.extend_from_slice(&data);
+            }
+            server.send_end_of_message(None).expect("send end");
+            server.start_next_cycle().expect("next cycle");
+        }
+
+        client.feed(&responses).expect("feed");
+        let mut bodies = Vec::new();
+        for _ in 0..3 {
+            assert!(client.next_event().expect("read response").is_some());
+            let mut body = Vec::new();
+            while let Some(Event::Data(data)) =
+                client.next_event().expect("read body")
+            {
+                body.extend_from_slice(&data);
+            }
+            bodies.push(body);
+            client.start_next_cycle().expect("next cycle");
+        }
+        assert_eq!(vec![b"a!".to_vec(), Vec::new(), b"c!".to_vec()], bodies);
+        assert_eq!(0, client.pipelined_requests());
+    }
+
+    #[test]
+    fn pipelining_refused() {
+        let mut client = HttpConn::<Client>::new();
+        let req = |headers: HeaderMap| ReqHead {
+            method: Method::GET,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+        };
+        assert!(matches!(
+            client.send_pipelined_request(req(HeaderMap::new()), Bytes::new()),
+            Err(Error::CannotPipeline)
+        ));
+        client
+            .send_req(req(chunked_headers()))
+            .expect("send request");
+        assert!(client
+            .send_pipelined_request(req(HeaderMap::new()), Bytes::new())
+            .is_err());
+        client.send_end_of_message(None).expect("send end");
+
+        let mut expect = HeaderMap::new();
+        expect.insert(EXPECT, HeaderValue::from_static("100-continue"));
+        assert!(client
+            .send_pipelined_request(req(expect), Bytes::new())
+            .is_err());
+        let mut http10 = req(HeaderMap::new());
+        http10.version = Version::HTTP_10;
+        assert!(matches!(
+            client.send_pipelined_request(http10, Bytes::new()),
+            Err(Error::CannotPipeline)
+        ));
+        let mut close = HeaderMap::new();
+        close.insert(CONNECTION, HeaderValue::from_static("close"));
+        client
+            .send_pipelined_request(req(close), Bytes::new())
+            .expect("pipeline closing request");
+        assert!(client
+            .send_pipelined_request(req(HeaderMap::new()), Bytes::new())
+            .is_err());
+    }
+
+    #[test]
+    fn collect_body() {
+        let mut conn = HttpConn::<Server>::new();
+        assert!(matches!(conn.collect_body(), Err(Error::NoBodyInProgress)));
+
+        conn.feed(
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n\
+              3\r\nabc\r\n",
+        )
+        .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert!(conn.collect_body().expect("collect body").is_none());
+        conn.feed(b"2\r\nde\r\n0\r\nx-sum: 5\r\n\r\n")
+            .expect("feed");
+        let (body, trailers) =
+            conn.collect_body().expect("collect body").unwrap();
+        assert_eq!(&b"abcde"[..], &body[..]);
+        assert_eq!("5", trailers.expect("trailers")["x-sum"]);
+        assert!(conn.collect_body().is_err());
+    }
+
+    #[test]
+    fn collect_body_respects_max_body_size() {
+        let mut conn = HttpConn::<Server>::new().with_config(Config {
+            max_body_size: Some(4),
+            ..Config::default()
+        });
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\nabcde")
+            .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert!(matches!(conn.collect_body(), Err(Error::BodyTooLarge)));
+    }
+
+    #[test]
+    fn error_responses_follow_status_hints() {
+        let cases: &[(&[u8], StatusCode)] = &[
+            (
+                b"GET / HTTP/1.1\r\nfoo : bar\r\n\r\n",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                b"GET / HTTP/1.7\r\n\r\n",
+                StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            ),
+            (
+                b"GET / HTTP/1.1\r\nx-long: 0123456789abcdef0123456789abcdef",
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ),
+            (
+                b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\nzz\r\n",
+                StatusCode::BAD_REQUEST,
+            ),
+        ];
+        for &(input, status) in cases {
+            let mut conn = HttpConn::<Server>::from_bufs(
+                48,
+                BytesMut::new(),
+                BytesMut::new(),
+            );
+            conn.feed(input).expect("feed");
+            let err = loop {
+                match conn.next_event() {
+                    Ok(Some(_)) => {}
+                    Ok(None) => panic!("expected an error for {:?}", input),
+                    Err(e) => break e,
+                }
+            };
+            assert_eq!(Some(status), err.status_hint());
+            let out = conn
+                .send_error_response_for(&err, SystemTime::now())
+                .expect("respond");
+            let resp = RespHead::from_buf(&mut BytesMut::from(&out[..]))
+                .expect("parse response")
+                .expect("complete response");
+            assert_eq!(status, resp.status);
+            assert_eq!("close", resp.headers[CONNECTION]);
+            assert!(matches!(
+                conn.inner.state.states(),
+                (state::Client::Error, state::Server::MustClose)
+            ));
+        }
+
+        let mut hdrs = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..60 {
+            hdrs.extend_from_slice(format!("x-{i}: a\r\n").as_bytes());
+        }
+        hdrs.extend_from_slice(b"\r\n");
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(&hdrs).expect("feed");
+        let err = conn.next_event().unwrap_err();
+        assert_eq!(
+            Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            err.status_hint()
+        );
+    }
+
+    #[test]
+    fn timeout_responses() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HT").expect("feed");
+        assert!(conn.next_event().expect("read request").is_none());
+        let out = conn
+            .send_timeout_response(SystemTime::now())
+            .expect("send 408");
+        assert!(out.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(twoway::find_bytes(&out, b"connection: close").is_some());
+        assert_eq!(
+            (state::Client::Error, state::Server::MustClose),
+            conn.inner.state.states()
+        );
+        assert!(conn.next_event().is_err());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\nabc")
+            .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        assert!(conn.next_event().expect("read data").is_some());
+        let out = conn
+            .send_timeout_response(SystemTime::now())
+            .expect("send 408");
+        assert!(out.starts_with(b"HTTP/1.1 408 Request Timeout\r\n"));
+        assert_eq!(Some(CloseReason::Error), conn.close_reason());
+
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n")
+            .expect("feed");
+        assert!(conn.next_event().expect("read request").is_some());
+        conn.send_resp(resp(StatusCode::OK)).expect("respond");
+        assert!(conn
+            .send_timeout_response(SystemTime::now())
+            .expect("no 408")
+            .is_empty());
+        assert_eq!(state::Client::Error, conn.inner.state.states().0);
     }
 }
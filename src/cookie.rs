@@ -0,0 +1,551 @@
+use std::fmt;
+#[cfg(any(test, feature = "cookie-jar"))]
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use http::header::{HeaderValue, COOKIE, SET_COOKIE};
+use http::{HeaderMap, Uri};
+
+use crate::conn::{Client, Error, HttpConn};
+use crate::event::Event;
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+use crate::util::parse_http_date;
+
+// One parsed `Set-Cookie` field. Attribute names are matched without
+// regard to case and unknown attributes are ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub expires: Option<std::time::SystemTime>,
+    pub max_age: Option<i64>,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<String>,
+}
+
+impl SetCookie {
+    // `None` if the field has no `name=value` pair to start with.
+    #[must_use]
+    pub fn parse(field: &str) -> Option<Self> {
+        let mut parts = field.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Self {
+            name: name.to_owned(),
+            value: value.trim().trim_matches('"').to_owned(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        };
+        for attr in parts {
+            let (attr, value) = match attr.split_once('=') {
+                Some((attr, value)) => (attr.trim(), value.trim()),
+                None => (attr.trim(), ""),
+            };
+            match attr.to_ascii_lowercase().as_str() {
+                "expires" => cookie.expires = parse_http_date(value),
+                "max-age" => cookie.max_age = value.parse().ok(),
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.');
+                    cookie.domain = Some(domain.to_ascii_lowercase());
+                }
+                "path" if value.starts_with('/') => {
+                    cookie.path = Some(value.to_owned());
+                }
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        Some(cookie)
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+// The cookies set by every `Set-Cookie` field, skipping malformed ones.
+pub fn parse_set_cookies(headers: &HeaderMap) -> Vec<SetCookie> {
+    headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(SetCookie::parse)
+        .collect()
+}
+
+// Somewhere to keep cookies between requests. `uri` is always the
+// absolute URI of the request concerned.
+pub trait CookieStore {
+    // Called with the cookies a response set.
+    fn set_cookies(&mut self, cookies: Vec<SetCookie>, uri: &Uri);
+
+    // The `Cookie` value to send with a request, if any.
+    fn cookie_header(&self, uri: &Uri) -> Option<HeaderValue>;
+}
+
+// Hands the cookies set by `resp` to the store.
+pub fn store_cookies<S: CookieStore + ?Sized>(
+    store: &mut S,
+    uri: &Uri,
+    resp: &RespHead,
+) {
+    let cookies = parse_set_cookies(&resp.headers);
+    if !cookies.is_empty() {
+        store.set_cookies(cookies, uri);
+    }
+}
+
+// Adds the store's cookies for `uri` to `req`, after any `Cookie` the
+// caller set itself.
+pub fn add_cookies<S: CookieStore + ?Sized>(
+    store: &S,
+    uri: &Uri,
+    req: &mut ReqHead,
+) {
+    let Some(stored) = store.cookie_header(uri) else {
+        return;
+    };
+    let value = match req.headers.get(COOKIE) {
+        Some(existing) => {
+            let mut joined = existing.as_bytes().to_vec();
+            joined.extend_from_slice(b"; ");
+            joined.extend_from_slice(stored.as_bytes());
+            HeaderValue::from_shared(Bytes::from(joined)).unwrap_or_else(
+                |_| unreachable!("joined cookie values are valid"),
+            )
+        }
+        None => stored,
+    };
+    req.headers.insert(COOKIE, value);
+}
+
+// Wraps a client connection so requests carry the store's cookies and
+// responses feed it. Requests go out in origin form, so the scheme and
+// authority they are sent to are given separately as `origin`.
+#[derive(Debug)]
+pub struct CookieConn<S> {
+    conn: HttpConn<Client>,
+    store: S,
+    origin: Uri,
+    request_uri: Option<Uri>,
+}
+
+impl<S: CookieStore> CookieConn<S> {
+    pub fn new(conn: HttpConn<Client>, store: S, origin: Uri) -> Self {
+        Self {
+            conn,
+            store,
+            origin,
+            request_uri: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &HttpConn<Client> {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut HttpConn<Client> {
+        &mut self.conn
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn into_parts(self) -> (HttpConn<Client>, S) {
+        (self.conn, self.store)
+    }
+
+    // For when a redirect moves the connection's requests elsewhere.
+    pub fn set_origin(&mut self, origin: Uri) {
+        self.origin = origin;
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::send_req` would.
+    pub fn send_req(&mut self, mut req: ReqHead) -> Result<Bytes, Error> {
+        let uri = self.absolute(&req.uri);
+        add_cookies(&self.store, &uri, &mut req);
+        let out = self.conn.send_req(req)?;
+        self.request_uri = Some(uri);
+        Ok(out)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::next_event` would.
+    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        let event = self.conn.next_event()?;
+        if let (Some(Event::Response(resp)), Some(uri)) =
+            (&event, &self.request_uri)
+        {
+            store_cookies(&mut self.store, uri, resp);
+        }
+        Ok(event)
+    }
+
+    fn absolute(&self, target: &Uri) -> Uri {
+        let path = target.path_and_query().map_or("/", |pq| pq.as_str());
+        let mut parts = self.origin.clone().into_parts();
+        parts.path_and_query = path.parse().ok();
+        Uri::from_parts(parts).unwrap_or_else(|_| self.origin.clone())
+    }
+}
+
+#[cfg(any(test, feature = "cookie-jar"))]
+#[derive(Clone, Debug, PartialEq)]
+struct StoredCookie {
+    name: String,
+    value: String,
+    domain: String,
+    // Sent only to exactly `domain`, since no `Domain` attribute widened
+    // it to subdomains.
+    host_only: bool,
+    path: String,
+    expires: Option<SystemTime>,
+    secure: bool,
+}
+
+// A plain in-memory cookie jar following the storage and matching rules
+// of RFC 6265. Cookies without an expiry live as long as the jar.
+//
+// The jar carries no public suffix list. It refuses a `Domain` of a single
+// label such as `com`, but a cookie for a multi-label public suffix such as
+// `co.uk` is accepted from any host under it; a store that needs to refuse
+// those must implement `CookieStore` itself.
+#[cfg(any(test, feature = "cookie-jar"))]
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    cookies: Vec<StoredCookie>,
+}
+
+#[cfg(any(test, feature = "cookie-jar"))]
+impl CookieJar {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    fn insert(&mut self, cookie: SetCookie, uri: &Uri, now: SystemTime) {
+        let Some(host) = uri.host().map(str::to_ascii_lowercase) else {
+            return;
+        };
+        // A cookie marked secure set over plain HTTP could be forged by
+        // anyone on the path, so it is ignored.
+        if cookie.secure && uri.scheme_str() != Some("https") {
+            return;
+        }
+        let (domain, host_only) = match cookie.domain {
+            // A single label would cover every site under it, so it can only
+            // stand for the host itself.
+            Some(domain) if !domain.contains('.') => {
+                if domain != host {
+                    return;
+                }
+                (host, true)
+            }
+            Some(domain) if domain_match(&host, &domain) => (domain, false),
+            Some(_) => return,
+            None => (host, true),
+        };
+        let expires = match cookie.max_age {
+            Some(age) if age <= 0 => Some(SystemTime::UNIX_EPOCH),
+            // Too far out to represent is as good as never.
+            Some(age) => {
+                now.checked_add(Duration::from_secs(age.unsigned_abs()))
+            }
+            None => cookie.expires,
+        };
+        let stored = StoredCookie {
+            name: cookie.name,
+            value: cookie.value,
+            domain,
+            host_only,
+            path: cookie.path.unwrap_or_else(|| default_path(uri.path())),
+            expires,
+            secure: cookie.secure,
+        };
+        self.cookies.retain(|c| {
+            (&c.name, &c.domain, &c.path)
+                != (&stored.name, &stored.domain, &stored.path)
+        });
+        if stored.expires.is_none_or(|expires| expires > now) {
+            self.cookies.push(stored);
+        }
+    }
+
+    fn matching(&self, uri: &Uri, now: SystemTime) -> Vec<&StoredCookie> {
+        let Some(host) = uri.host().map(str::to_ascii_lowercase) else {
+            return Vec::new();
+        };
+        let secure = uri.scheme_str() == Some("https");
+        let mut cookies = self
+            .cookies
+            .iter()
+            .filter(|c| {
+                if c.host_only {
+                    c.domain == host
+                } else {
+                    domain_match(&host, &c.domain)
+                }
+            })
+            .filter(|c| path_match(uri.path(), &c.path))
+            .filter(|c| secure || !c.secure)
+            .filter(|c| c.expires.is_none_or(|expires| expires > now))
+            .collect::<Vec<_>>();
+        // Longer paths first, as RFC 6265 asks; the sort is stable, so
+        // older cookies stay ahead of newer ones otherwise.
+        cookies.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        cookies
+    }
+}
+
+#[cfg(any(test, feature = "cookie-jar"))]
+impl CookieStore for CookieJar {
+    fn set_cookies(&mut self, cookies: Vec<SetCookie>, uri: &Uri) {
+        let now = SystemTime::now();
+        for cookie in cookies {
+            self.insert(cookie, uri, now);
+        }
+    }
+
+    fn cookie_header(&self, uri: &Uri) -> Option<HeaderValue> {
+        let cookies = self.matching(uri, SystemTime::now());
+        if cookies.is_empty() {
+            return None;
+        }
+        let joined = cookies
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&joined).ok()
+    }
+}
+
+#[cfg(any(test, feature = "cookie-jar"))]
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+#[cfg(any(test, feature = "cookie-jar"))]
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/')
+                || path[cookie_path.len()..].starts_with('/')))
+}
+
+// The directory of the request path, used when a cookie names no path.
+#[cfg(any(test, feature = "cookie-jar"))]
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(i) => path[..i].to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::{Method, StatusCode, Version};
+
+    use crate::testing::Duplex;
+
+    fn uri(s: &str) -> Uri {
+        s.parse().unwrap()
+    }
+
+    fn set(jar: &mut CookieJar, uri_str: &str, fields: &[&str]) {
+        let cookies = fields.iter().filter_map(|f| SetCookie::parse(f));
+        jar.set_cookies(cookies.collect(), &uri(uri_str));
+    }
+
+    fn header(jar: &CookieJar, uri_str: &str) -> Option<String> {
+        jar.cookie_header(&uri(uri_str))
+            .map(|v| v.to_str().unwrap().to_owned())
+    }
+
+    #[test]
+    fn parse_set_cookie() {
+        let cookie = SetCookie::parse(
+            "id=\"a3fWa\"; Expires=Wed, 21 Oct 2015 07:28:00 GMT; \
+             max-age=60; Domain=.Example.com; Path=/docs; Secure; \
+             HttpOnly; SameSite=Lax; Unknown",
+        )
+        .unwrap();
+        assert_eq!("id", cookie.name);
+        assert_eq!("a3fWa", cookie.value);
+        assert!(cookie.expires.is_some());
+        assert_eq!(Some(60), cookie.max_age);
+        assert_eq!(Some("example.com"), cookie.domain.as_deref());
+        assert_eq!(Some("/docs"), cookie.path.as_deref());
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(Some("Lax"), cookie.same_site.as_deref());
+
+        assert_eq!(None, SetCookie::parse("novalue"));
+        assert_eq!(None, SetCookie::parse("=value"));
+        assert_eq!(None, SetCookie::parse("a=b; Path=docs").unwrap().path);
+    }
+
+    #[test]
+    fn jar_matching() {
+        let mut jar = CookieJar::new();
+        set(
+            &mut jar,
+            "http://www.example.com/docs/page",
+            &[
+                "host=1",
+                "wide=2; Domain=example.com; Path=/",
+                "other=4; Domain=example.org",
+            ],
+        );
+        set(&mut jar, "https://www.example.com/", &["secure=3; Secure"]);
+        assert_eq!(3, jar.len());
+        assert_eq!(
+            Some("host=1; wide=2"),
+            header(&jar, "http://www.example.com/docs/x").as_deref()
+        );
+        assert_eq!(
+            Some("wide=2; secure=3"),
+            header(&jar, "https://www.example.com/").as_deref()
+        );
+        assert_eq!(
+            Some("wide=2"),
+            header(&jar, "http://api.example.com/docs").as_deref()
+        );
+        assert_eq!(None, header(&jar, "http://badexample.com/"));
+        assert_eq!(
+            Some("wide=2"),
+            header(&jar, "http://www.example.com/docsx").as_deref()
+        );
+    }
+
+    #[test]
+    fn jar_refuses_unsafe_cookies() {
+        let mut jar = CookieJar::new();
+        set(
+            &mut jar,
+            "http://www.example.com/",
+            &["tld=1; Domain=com", "secure=2; Secure"],
+        );
+        assert!(jar.is_empty());
+
+        set(
+            &mut jar,
+            "http://localhost/",
+            &["local=1; Domain=localhost"],
+        );
+        assert_eq!(
+            Some("local=1"),
+            header(&jar, "http://localhost/").as_deref()
+        );
+        assert_eq!(None, header(&jar, "http://a.localhost/"));
+    }
+
+    #[test]
+    fn jar_replaces_and_expires() {
+        let mut jar = CookieJar::new();
+        set(&mut jar, "http://a.com/", &["id=1", "gone=x"]);
+        set(
+            &mut jar,
+            "http://a.com/",
+            &[
+                "id=2",
+                "gone=; Max-Age=0",
+                "old=1; Expires=Thu, 01 Jan 1970 00:00:01 GMT",
+            ],
+        );
+        assert_eq!(Some("id=2"), header(&jar, "http://a.com/").as_deref());
+        assert_eq!(1, jar.len());
+    }
+
+    fn get(path: &str) -> ReqHead {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::HOST, HeaderValue::from_static("a.com"));
+        ReqHead {
+            method: Method::GET,
+            uri: uri(path),
+            version: Version::HTTP_11,
+            headers,
+        }
+    }
+
+    #[test]
+    fn cookie_conn_round_trip() {
+        let Duplex { client, mut server } = HttpConn::pair();
+        let mut conn =
+            CookieConn::new(client, CookieJar::new(), uri("http://a.com"));
+
+        let mut wire = conn.send_req(get("/login")).unwrap().to_vec();
+        wire.extend_from_slice(
+            &conn.get_mut().send_end_of_message(None).unwrap(),
+        );
+        server.feed(&wire).unwrap();
+        server.next_event().unwrap();
+        server.next_event().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, HeaderValue::from_static("sid=abc"));
+        headers.insert(
+            http::header::CONTENT_LENGTH,
+            HeaderValue::from_static("0"),
+        );
+        let resp = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        };
+        let wire = server.send_resp(resp).unwrap();
+        conn.get_mut().feed(&wire).unwrap();
+        conn.next_event().unwrap();
+        conn.next_event().unwrap();
+        assert_eq!(1, conn.store().len());
+
+        let mut next = get("/x");
+        next.headers
+            .insert(COOKIE, HeaderValue::from_static("lang=en"));
+        add_cookies(conn.store(), &uri("http://a.com/x"), &mut next);
+        assert_eq!("lang=en; sid=abc", next.headers[COOKIE]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::future::poll_fn;
+use std::io;
+use std::task::{Context, Poll};
+
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+const READ_SIZE: usize = 8 * 1024;
+
+// What a driver needs from a stream, adapted from each runtime's own I/O
+// traits.
+pub(crate) trait Io {
+    fn poll_read(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>>;
+
+    fn poll_write(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>>;
+
+    // Shuts down the write side of the stream.
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<io::Result<()>>;
+}
+
+// The runtime-independent part of a driver: reading whenever the
+// connection needs more input and writing out everything sent in full.
+#[derive(Debug)]
+pub(crate) struct Core<R, T> {
+    conn: HttpConn<R>,
+    io: T,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Role, T: Io> Core<R, T> {
+    pub(crate) fn new(conn: HttpConn<R>, io: T) -> Self {
+        Self {
+            conn,
+            io,
+            buf: vec![0; READ_SIZE],
+            eof: false,
+        }
+    }
+
+    pub(crate) fn conn(&self) -> &HttpConn<R> {
+        &self.conn
+    }
+
+    pub(crate) fn conn_mut(&mut self) -> &mut HttpConn<R> {
+        &mut self.conn
+    }
+
+    pub(crate) fn into_parts(self) -> (HttpConn<R>, T) {
+        (self.conn, self.io)
+    }
+
+    pub(crate) async fn recv_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            match self.conn.next_event()? {
+                Some(Event::ConnectionClosed) => return Ok(None),
+                Some(event) => return Ok(Some(event)),
+                None => {}
+            }
+            if self.eof {
+                if self.conn.peek_buffered().is_empty() {
+                    return Ok(None);
+                }
+                return Err(
+                    io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                );
+            }
+            let (io, buf) = (&mut self.io, &mut self.buf);
+            let n = poll_fn(|cx| io.poll_read(cx, buf)).await?;
+            self.eof = n == 0;
+            self.conn.feed(&self.buf[..n])?;
+        }
+    }
+
+    pub(crate) async fn send(&mut self, event: Event) -> Result<(), Error> {
+        let close = event == Event::ConnectionClosed;
+        let out = self.conn.send(event)?;
+        self.write_all(&out).await?;
+        if close {
+            poll_fn(|cx| self.io.poll_close(cx)).await?;
+        } else {
+            poll_fn(|cx| self.io.poll_flush(cx)).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn write_all(
+        &mut self,
+        mut out: &[u8],
+    ) -> io::Result<()> {
+        while !out.is_empty() {
+            match poll_fn(|cx| self.io.poll_write(cx, out)).await? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => out = &out[n..],
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::futures::io::{AsyncRead, AsyncWrite};
+
+use super::core::{Core, Io};
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+// The same driver as `driver::tokio`, over any `futures` stream, so it
+// works with async-std, smol and other runtimes.
+#[derive(Debug)]
+pub struct Driver<R, T> {
+    core: Core<R, Futures<T>>,
+}
+
+#[derive(Debug)]
+struct Futures<T>(T);
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Io for Futures<T> {
+    fn poll_read(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+
+    fn poll_write(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+impl<R: Role, T: AsyncRead + AsyncWrite + Unpin> Driver<R, T> {
+    pub fn new(io: T) -> Self {
+        Self::with_conn(HttpConn::new(), io)
+    }
+
+    pub fn with_conn(conn: HttpConn<R>, io: T) -> Self {
+        Self {
+            core: Core::new(conn, Futures(io)),
+        }
+    }
+
+    pub fn conn(&self) -> &HttpConn<R> {
+        self.core.conn()
+    }
+
+    pub fn conn_mut(&mut self) -> &mut HttpConn<R> {
+        self.core.conn_mut()
+    }
+
+    pub fn into_parts(self) -> (HttpConn<R>, T) {
+        let (conn, io) = self.core.into_parts();
+        (conn, io.0)
+    }
+
+    /// The next event from the peer. `None` means the peer closed the
+    /// connection between messages; closing partway through one is an
+    /// error.
+    ///
+    /// # Errors
+    ///
+    /// Fails if reading from the stream fails, or as `HttpConn::next_event`
+    /// would.
+    pub async fn recv_event(&mut self) -> Result<Option<Event>, Error> {
+        self.core.recv_event().await
+    }
+
+    /// Sends the event and flushes it to the peer. Sending
+    /// `ConnectionClosed` also shuts down the write side of the stream.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the event cannot be sent in the current state, or if
+    /// writing to the stream fails.
+    pub async fn send(&mut self, event: Event) -> Result<(), Error> {
+        self.core.send(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::futures::executor::block_on;
+    use ::futures::io::Cursor;
+    use http::header::{HeaderValue, CONNECTION};
+    use http::{HeaderMap, StatusCode, Version};
+
+    use crate::conn::Server;
+    use crate::resp::RespHead;
+
+    // Reads from a fixed input and collects everything written.
+    struct Io {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        closed: bool,
+    }
+
+    impl AsyncRead for Io {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Io {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _: &mut Context,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            mut self: Pin<&mut Self>,
+            _: &mut Context,
+        ) -> Poll<io::Result<()>> {
+            self.closed = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn server(input: &[u8]) -> Driver<Server, Io> {
+        Driver::new(Io {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+            closed: false,
+        })
+    }
+
+    #[test]
+    fn serve_one_request() {
+        let mut server = server(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n");
+        block_on(async {
+            assert!(matches!(
+                server.recv_event().await.unwrap(),
+                Some(Event::Request(_))
+            ));
+            assert_eq!(
+                Some(Event::EndOfMessage(None)),
+                server.recv_event().await.unwrap()
+            );
+            assert_eq!(None, server.recv_event().await.unwrap());
+            let mut resp = RespHead {
+                status: StatusCode::NO_CONTENT,
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+                reason: None,
+            };
+            resp.headers
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+            server.send(Event::Response(resp)).await.unwrap();
+            server.send(Event::EndOfMessage(None)).await.unwrap();
+            server.send(Event::ConnectionClosed).await.unwrap();
+        });
+        let (_, io) = server.into_parts();
+        assert!(io.output.starts_with(b"HTTP/1.1 204 No Content\r\n"));
+        assert!(io.closed);
+    }
+
+    #[test]
+    fn eof_mid_head_is_an_error() {
+        let mut server = server(b"GET / HTTP/1.1\r\nho");
+        match block_on(server.recv_event()) {
+            Err(e) => assert!(e.is_premature_close(), "{:?}", e),
+            r => panic!("expected a premature close, got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use ::mio::Interest;
+
+use crate::conn::{HttpConn, Role};
+
+// The readiness the connection's socket should be registered for.
+// `unflushed` says whether the driver still holds bytes it could not write
+// yet. Only those need the socket to be writable: `wants_write` just means
+// the application owes more of a message, and registering for writability
+// meanwhile would wake the loop for nothing. `None` means nothing is
+// expected in either direction, so the socket can be deregistered.
+pub fn interest<R: Role>(
+    conn: &HttpConn<R>,
+    unflushed: bool,
+) -> Option<Interest> {
+    match (conn.wants_read(), unflushed) {
+        (true, true) => Some(Interest::READABLE | Interest::WRITABLE),
+        (true, false) => Some(Interest::READABLE),
+        (false, true) => Some(Interest::WRITABLE),
+        (false, false) => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reregistration {
+    Unchanged,
+    Register(Interest),
+    Deregister,
+}
+
+// Remembers the interest a socket is registered with. Call `update` after
+// every batch of events, sends and writes; it says what, if anything, to
+// do with the registry.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InterestTracker {
+    registered: Option<Interest>,
+}
+
+impl InterestTracker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn registered(&self) -> Option<Interest> {
+        self.registered
+    }
+
+    pub fn update<R: Role>(
+        &mut self,
+        conn: &HttpConn<R>,
+        unflushed: bool,
+    ) -> Reregistration {
+        let wanted = interest(conn, unflushed);
+        if wanted == self.registered {
+            return Reregistration::Unchanged;
+        }
+        self.registered = wanted;
+        match wanted {
+            Some(interest) => Reregistration::Register(interest),
+            None => Reregistration::Deregister,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::{HeaderMap, Method};
+
+    use crate::event::Event;
+    use crate::testing::Duplex;
+
+    #[test]
+    fn tracks_request_cycle() {
+        let Duplex {
+            mut client,
+            mut server,
+        } = HttpConn::pair();
+        let mut tracker = InterestTracker::new();
+        assert_eq!(Reregistration::Unchanged, tracker.update(&client, false));
+        assert_eq!(
+            Reregistration::Register(Interest::READABLE),
+            InterestTracker::new().update(&server, false)
+        );
+
+        let mut head = client
+            .send(Event::request(
+                Method::GET,
+                "/".parse().unwrap(),
+                HeaderMap::new(),
+            ))
+            .unwrap();
+        assert_eq!(
+            Reregistration::Register(Interest::READABLE | Interest::WRITABLE),
+            tracker.update(&client, true)
+        );
+        head.extend_from_slice(
+            &client.send(Event::EndOfMessage(None)).unwrap(),
+        );
+        assert_eq!(
+            Reregistration::Register(Interest::READABLE),
+            tracker.update(&client, false)
+        );
+        assert_eq!(Reregistration::Unchanged, tracker.update(&client, false));
+
+        // The server owes a response, but has written nothing yet.
+        server.feed(&head).unwrap();
+        while server.next_event().unwrap().is_some() {}
+        assert!(server.wants_write());
+        assert_eq!(None, interest(&server, false));
+        assert_eq!(Some(Interest::WRITABLE), interest(&server, true));
+
+        client.feed(b"").unwrap();
+        assert_eq!(Reregistration::Deregister, tracker.update(&client, false));
+        assert_eq!(None, tracker.registered());
+    }
+}
@@ -0,0 +1,8 @@
+#[cfg(any(feature = "futures", feature = "tokio"))]
+mod core;
+#[cfg(feature = "futures")]
+pub mod futures;
+#[cfg(feature = "mio")]
+pub mod mio;
+#[cfg(feature = "tokio")]
+pub mod tokio;
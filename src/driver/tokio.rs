@@ -0,0 +1,212 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use ::tokio::net::TcpStream;
+
+use super::core::{Core, Io};
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+// Owns a stream and the connection speaking over it, reading whenever the
+// connection needs more input and writing out everything sent in full.
+//
+// `recv_event` and `send` both need the driver mutably, so they cannot run
+// at the same time. `recv_event` is cancel safe, though: nothing read is
+// lost if it is dropped while waiting, so a task can `select!` over it and
+// whatever produces its outgoing events, and send once it has the driver
+// back. Fully concurrent reading and writing needs the stream and the
+// connection split instead, with `HttpConn::split` and each half driven
+// by its own task.
+#[derive(Debug)]
+pub struct Driver<R, T = TcpStream> {
+    core: Core<R, Tokio<T>>,
+}
+
+#[derive(Debug)]
+struct Tokio<T>(T);
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Io for Tokio<T> {
+    fn poll_read(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        Pin::new(&mut self.0)
+            .poll_read(cx, &mut buf)
+            .map_ok(|()| buf.filled().len())
+    }
+
+    fn poll_write(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl<R: Role, T: AsyncRead + AsyncWrite + Unpin> Driver<R, T> {
+    pub fn new(io: T) -> Self {
+        Self::with_conn(HttpConn::new(), io)
+    }
+
+    pub fn with_conn(conn: HttpConn<R>, io: T) -> Self {
+        Self {
+            core: Core::new(conn, Tokio(io)),
+        }
+    }
+
+    pub fn conn(&self) -> &HttpConn<R> {
+        self.core.conn()
+    }
+
+    pub fn conn_mut(&mut self) -> &mut HttpConn<R> {
+        self.core.conn_mut()
+    }
+
+    pub fn into_parts(self) -> (HttpConn<R>, T) {
+        let (conn, io) = self.core.into_parts();
+        (conn, io.0)
+    }
+
+    /// The next event from the peer. `None` means the peer closed the
+    /// connection between messages; closing partway through one is an
+    /// error.
+    ///
+    /// # Errors
+    ///
+    /// Fails if reading from the stream fails, or as `HttpConn::next_event`
+    /// would.
+    pub async fn recv_event(&mut self) -> Result<Option<Event>, Error> {
+        self.core.recv_event().await
+    }
+
+    /// Sends the event and flushes it to the peer. Sending
+    /// `ConnectionClosed` also shuts down the write side of the stream.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the event cannot be sent in the current state, or if
+    /// writing to the stream fails.
+    pub async fn send(&mut self, event: Event) -> Result<(), Error> {
+        self.core.send(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+    use bytes::Bytes;
+    use http::{HeaderMap, Method, StatusCode};
+
+    use crate::conn::{Client, Server};
+
+    fn pair() -> (Driver<Client, DuplexStream>, Driver<Server, DuplexStream>) {
+        let (a, b) = duplex(64);
+        (Driver::new(a), Driver::new(b))
+    }
+
+    #[::tokio::test]
+    async fn request_response_cycle() {
+        let (mut client, mut server) = pair();
+        let server = ::tokio::spawn(async move {
+            let mut events = Vec::new();
+            while let Some(event) = server.recv_event().await.unwrap() {
+                let end = matches!(event, Event::EndOfMessage(_));
+                events.push(event);
+                if end {
+                    break;
+                }
+            }
+            let out = server
+                .conn_mut()
+                .respond(StatusCode::OK, HeaderMap::new(), Bytes::from("hi"))
+                .unwrap();
+            server.core.write_all(&out).await.unwrap();
+            server.send(Event::ConnectionClosed).await.unwrap();
+            events
+        });
+
+        client
+            .send(Event::request(
+                Method::GET,
+                "/".parse().unwrap(),
+                HeaderMap::new(),
+            ))
+            .await
+            .unwrap();
+        client.send(Event::EndOfMessage(None)).await.unwrap();
+        assert!(matches!(
+            client.recv_event().await.unwrap(),
+            Some(Event::Response(_))
+        ));
+        assert_eq!(
+            Some(Event::data(&b"hi"[..])),
+            client.recv_event().await.unwrap()
+        );
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            client.recv_event().await.unwrap()
+        );
+        assert_eq!(None, client.recv_event().await.unwrap());
+        assert_eq!(2, server.await.unwrap().len());
+    }
+
+    #[::tokio::test]
+    async fn recv_event_is_cancel_safe() {
+        let (mut client, mut server) = pair();
+        let req = client
+            .conn_mut()
+            .send(Event::request(
+                Method::GET,
+                "/".parse().unwrap(),
+                HeaderMap::new(),
+            ))
+            .unwrap();
+        client.core.write_all(&req[..5]).await.unwrap();
+        ::tokio::select! {
+            r = server.recv_event() => panic!("unexpected {:?}", r),
+            () = ::tokio::task::yield_now() => {}
+        }
+        client.core.write_all(&req[5..]).await.unwrap();
+        assert!(matches!(
+            server.recv_event().await.unwrap(),
+            Some(Event::Request(_))
+        ));
+    }
+
+    #[::tokio::test]
+    async fn eof_mid_message_is_an_error() {
+        let (mut client, server) = pair();
+        client
+            .send(Event::request(
+                Method::GET,
+                "/".parse().unwrap(),
+                HeaderMap::new(),
+            ))
+            .await
+            .unwrap();
+        let (_, mut io) = server.into_parts();
+        io.write_all(b"HTTP/1.1 200 OK\r\ncontent-len")
+            .await
+            .unwrap();
+        drop(io);
+        match client.recv_event().await {
+            Err(e) => assert!(e.is_premature_close(), "{:?}", e),
+            r => panic!("expected a premature close, got {:?}", r),
+        }
+    }
+}
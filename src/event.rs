@@ -1,22 +1,96 @@
+use std::convert::TryFrom;
+use std::fmt;
+
 use bytes::{Bytes, BytesMut};
-use http::HeaderMap;
+use http::{HeaderMap, Method, Uri, Version};
 
 use crate::req::ReqHead;
-use crate::resp::RespHead;
+use crate::resp::{InfoStatus, RespHead, StatusClass};
 use crate::state::StateEvent;
+use crate::util::fields_len;
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Event {
     Request(ReqHead),
     InfoResponse(RespHead),
     Response(RespHead),
     Data(Bytes),
+    Trailers(HeaderMap),
     EndOfMessage(Option<HeaderMap>),
     ConnectionClosed,
 }
 
 impl Event {
+    pub fn request(method: Method, uri: Uri, headers: HeaderMap) -> Self {
+        Self::Request(ReqHead {
+            method,
+            uri,
+            version: Version::HTTP_11,
+            headers,
+        })
+    }
+
+    pub fn data<B: Into<Bytes>>(data: B) -> Self {
+        Self::Data(data.into())
+    }
+
+    pub fn as_request(&self) -> Option<&ReqHead> {
+        match *self {
+            Self::Request(ref req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub fn as_response(&self) -> Option<&RespHead> {
+        match *self {
+            Self::InfoResponse(ref resp) | Self::Response(ref resp) => {
+                Some(resp)
+            }
+            _ => None,
+        }
+    }
+
+    // How an interim response should be handled; `None` for any other
+    // event.
+    pub fn info_status(&self) -> Option<InfoStatus> {
+        match *self {
+            Self::InfoResponse(ref resp) => resp.info_status(),
+            _ => None,
+        }
+    }
+
+    pub fn as_data(&self) -> Option<&Bytes> {
+        match *self {
+            Self::Data(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // How many bytes sending the event writes. Heads are measured exactly
+    // as given, though sending one may add framing or `Connection`
+    // fields. Body events are measured as if chunked, which is the most
+    // their framing can add.
+    pub fn size_hint(&self) -> usize {
+        match self {
+            Self::Request(req) => req.written_len(),
+            Self::InfoResponse(resp) | Self::Response(resp) => {
+                resp.written_len()
+            }
+            Self::Data(data) if data.is_empty() => 0,
+            Self::Data(data) => {
+                let digits =
+                    (usize::BITS - data.len().leading_zeros()).div_ceil(4);
+                digits as usize + data.len() + 4
+            }
+            Self::Trailers(trailers) => fields_len(trailers),
+            Self::EndOfMessage(trailers) => {
+                "0\r\n\r\n".len() + trailers.as_ref().map_or(0, fields_len)
+            }
+            Self::ConnectionClosed => 0,
+        }
+    }
+
     pub(crate) fn to_state_event(&self) -> StateEvent {
         use self::StateEvent::*;
 
@@ -24,20 +98,21 @@ impl Event {
             Self::Request(_) => Request,
             Self::InfoResponse(_) => InfoResponse,
             Self::Response(_) => Response,
-            Self::Data(_) => Data,
+            // Trailers only ever go out with the end of the message.
+            Self::Data(_) | Self::Trailers(_) => Data,
             Self::EndOfMessage(_) => EndOfMessage,
             Self::ConnectionClosed => ConnectionClosed,
         }
     }
 
-    pub(crate) fn into_buf(self, buf: &mut BytesMut) -> Bytes {
+    pub(crate) fn write_to_buf(&self, buf: &mut BytesMut) -> Bytes {
         use self::Event::*;
 
         match self {
             Request(req) => req.write_to_buf(buf),
             InfoResponse(resp) | Response(resp) => resp.write_to_buf(buf),
-            Data(b) => b,
-            EndOfMessage(Some(hdrs)) => {
+            Data(b) => b.clone(),
+            Trailers(hdrs) | EndOfMessage(Some(hdrs)) => {
                 let mut n = 0;
                 for (name, value) in hdrs.iter() {
                     buf.extend_from_slice(name.as_str().as_bytes());
@@ -55,3 +130,295 @@ impl Event {
         }
     }
 }
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Request(req) => write!(f, "Request({req})"),
+            Self::InfoResponse(resp) => write!(f, "InfoResponse({resp})"),
+            Self::Response(resp) => write!(f, "Response({resp})"),
+            Self::Data(data) => write!(f, "Data({} bytes)", data.len()),
+            Self::Trailers(trailers) => {
+                write!(f, "Trailers({} fields)", trailers.len())
+            }
+            Self::EndOfMessage(Some(trailers)) => {
+                write!(f, "EndOfMessage({} trailers)", trailers.len())
+            }
+            Self::EndOfMessage(None) => write!(f, "EndOfMessage"),
+            Self::ConnectionClosed => write!(f, "ConnectionClosed"),
+        }
+    }
+}
+
+impl From<ReqHead> for Event {
+    fn from(req: ReqHead) -> Self {
+        Self::Request(req)
+    }
+}
+
+impl From<RespHead> for Event {
+    fn from(resp: RespHead) -> Self {
+        if resp.class() == StatusClass::Informational {
+            Self::InfoResponse(resp)
+        } else {
+            Self::Response(resp)
+        }
+    }
+}
+
+// The events a client sends and a server receives. `HttpConn::send_typed`
+// takes only these on a client, so sending a response from one does not
+// compile.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientEvent {
+    Request(ReqHead),
+    Data(Bytes),
+    Trailers(HeaderMap),
+    EndOfMessage(Option<HeaderMap>),
+    ConnectionClosed,
+}
+
+// The events a server sends and a client receives.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerEvent {
+    InfoResponse(RespHead),
+    Response(RespHead),
+    Data(Bytes),
+    Trailers(HeaderMap),
+    EndOfMessage(Option<HeaderMap>),
+    ConnectionClosed,
+}
+
+impl From<ClientEvent> for Event {
+    fn from(event: ClientEvent) -> Self {
+        match event {
+            ClientEvent::Request(req) => Self::Request(req),
+            ClientEvent::Data(data) => Self::Data(data),
+            ClientEvent::Trailers(trailers) => Self::Trailers(trailers),
+            ClientEvent::EndOfMessage(trailers) => {
+                Self::EndOfMessage(trailers)
+            }
+            ClientEvent::ConnectionClosed => Self::ConnectionClosed,
+        }
+    }
+}
+
+impl From<ServerEvent> for Event {
+    fn from(event: ServerEvent) -> Self {
+        match event {
+            ServerEvent::InfoResponse(resp) => Self::InfoResponse(resp),
+            ServerEvent::Response(resp) => Self::Response(resp),
+            ServerEvent::Data(data) => Self::Data(data),
+            ServerEvent::Trailers(trailers) => Self::Trailers(trailers),
+            ServerEvent::EndOfMessage(trailers) => {
+                Self::EndOfMessage(trailers)
+            }
+            ServerEvent::ConnectionClosed => Self::ConnectionClosed,
+        }
+    }
+}
+
+// Fails with the event itself if only a server sends it.
+impl TryFrom<Event> for ClientEvent {
+    type Error = Event;
+
+    fn try_from(event: Event) -> Result<Self, Event> {
+        match event {
+            Event::Request(req) => Ok(Self::Request(req)),
+            Event::Data(data) => Ok(Self::Data(data)),
+            Event::Trailers(trailers) => Ok(Self::Trailers(trailers)),
+            Event::EndOfMessage(trailers) => Ok(Self::EndOfMessage(trailers)),
+            Event::ConnectionClosed => Ok(Self::ConnectionClosed),
+            event @ (Event::InfoResponse(_) | Event::Response(_)) => {
+                Err(event)
+            }
+        }
+    }
+}
+
+// Fails with the event itself if only a client sends it.
+impl TryFrom<Event> for ServerEvent {
+    type Error = Event;
+
+    fn try_from(event: Event) -> Result<Self, Event> {
+        match event {
+            Event::InfoResponse(resp) => Ok(Self::InfoResponse(resp)),
+            Event::Response(resp) => Ok(Self::Response(resp)),
+            Event::Data(data) => Ok(Self::Data(data)),
+            Event::Trailers(trailers) => Ok(Self::Trailers(trailers)),
+            Event::EndOfMessage(trailers) => Ok(Self::EndOfMessage(trailers)),
+            Event::ConnectionClosed => Ok(Self::ConnectionClosed),
+            event @ Event::Request(_) => Err(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::body::{BodyWriter, FramingMethod};
+
+    use http::StatusCode;
+
+    #[test]
+    fn request_constructor() {
+        let event = Event::request(
+            Method::GET,
+            "/a".parse().unwrap(),
+            HeaderMap::new(),
+        );
+        let req = event.as_request().expect("request event");
+        assert_eq!(Method::GET, req.method);
+        assert_eq!(Version::HTTP_11, req.version);
+        assert!(event.as_data().is_none());
+        assert!(event.info_status().is_none());
+    }
+
+    #[test]
+    fn data_constructor() {
+        let event = Event::data(&b"hello"[..]);
+        assert_eq!(Some(&Bytes::from_static(b"hello")), event.as_data());
+        assert!(event.as_request().is_none());
+    }
+
+    #[test]
+    fn from_resp_head() {
+        let info = Event::from(RespHead {
+            status: StatusCode::CONTINUE,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: None,
+        });
+        match info {
+            Event::InfoResponse(_) => {}
+            e => panic!("expected info response, got {:?}", e),
+        }
+
+        let resp = Event::from(RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: None,
+        });
+        assert_eq!(
+            StatusCode::OK,
+            resp.as_response().expect("response event").status
+        );
+    }
+
+    #[test]
+    fn role_typed_events() {
+        let event = Event::from(ClientEvent::Data(Bytes::from("a")));
+        assert_eq!(Event::data("a"), event);
+        assert_eq!(
+            Ok(ServerEvent::Data(Bytes::from("a"))),
+            ServerEvent::try_from(event)
+        );
+        let req = Event::request(
+            Method::GET,
+            "/a".parse().unwrap(),
+            HeaderMap::new(),
+        );
+        let req = ServerEvent::try_from(req).unwrap_err();
+        assert!(matches!(
+            ClientEvent::try_from(req),
+            Ok(ClientEvent::Request(_))
+        ));
+        let resp = Event::from(ServerEvent::Response(RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: None,
+        }));
+        assert!(ClientEvent::try_from(resp).is_err());
+    }
+
+    #[test]
+    fn size_hint() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "example.com".parse().unwrap());
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-sum", "3".parse().unwrap());
+        let connect = ReqHead {
+            method: Method::CONNECT,
+            uri: "example.com:443".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: headers.clone(),
+        };
+        for event in &[
+            Event::request(Method::GET, "/a?b=c".parse().unwrap(), headers),
+            Event::Request(connect),
+            Event::from(RespHead {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers: trailers.clone(),
+                reason: None,
+            }),
+            Event::from(RespHead {
+                status: StatusCode::from_u16(299).unwrap(),
+                version: Version::HTTP_11,
+                headers: HeaderMap::new(),
+                reason: None,
+            }),
+            Event::Trailers(trailers.clone()),
+            Event::EndOfMessage(None),
+            Event::EndOfMessage(Some(trailers)),
+        ] {
+            let mut buf = BytesMut::new();
+            let written = match event {
+                Event::EndOfMessage(trailers) => {
+                    let mut w = BodyWriter::from(FramingMethod::Chunked);
+                    w.write_end(&mut buf, trailers.as_ref()).unwrap()
+                }
+                _ => event.write_to_buf(&mut buf),
+            };
+            assert_eq!(written.len(), event.size_hint(), "{event}");
+        }
+        for len in &[1, 15, 16, 4096, 70000] {
+            let data = Bytes::from(vec![b'a'; *len]);
+            let mut w = BodyWriter::from(FramingMethod::Chunked);
+            let chunk = w.write_data(&mut BytesMut::new(), &data).unwrap();
+            assert_eq!(chunk.len(), Event::Data(data).size_hint());
+        }
+        assert_eq!(0, Event::data("").size_hint());
+        assert_eq!(0, Event::ConnectionClosed.size_hint());
+    }
+
+    #[test]
+    fn clone_to_log_and_forward() {
+        let event = Event::request(
+            Method::GET,
+            "/a".parse().unwrap(),
+            HeaderMap::new(),
+        );
+        let logged = event.clone();
+        assert_eq!(logged, event);
+        let forwarded = match event {
+            Event::Request(req) => req,
+            e => panic!("expected request, got {:?}", e),
+        };
+        assert_eq!(logged.as_request(), Some(&forwarded));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            "Request(GET /a HTTP/1.1 (0 headers))",
+            Event::request(
+                Method::GET,
+                "/a".parse().unwrap(),
+                HeaderMap::new()
+            )
+            .to_string()
+        );
+        assert_eq!("Data(1024 bytes)", Event::data(vec![0; 1024]).to_string());
+        assert_eq!(
+            "Trailers(0 fields)",
+            Event::Trailers(HeaderMap::new()).to_string()
+        );
+        assert_eq!("EndOfMessage", Event::EndOfMessage(None).to_string());
+    }
+}
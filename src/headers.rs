@@ -0,0 +1,390 @@
+use std::fmt;
+
+use bytes::BytesMut;
+use http::header::{AsHeaderName, HeaderValue, SET_COOKIE, UPGRADE};
+use http::HeaderMap;
+
+use crate::util::{is_token, trim};
+
+// Splits a list-typed field value into its elements. Whitespace around
+// elements is dropped, as are empty elements, and commas inside quoted
+// strings do not separate elements.
+pub fn split_list(value: &HeaderValue) -> Vec<&[u8]> {
+    let bytes = value.as_bytes();
+    let mut elements = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, &b) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if in_quotes && b == b'\\' {
+            escaped = true;
+        } else if b == b'"' {
+            in_quotes = !in_quotes;
+        } else if b == b',' && !in_quotes {
+            push_element(&mut elements, &bytes[start..i]);
+            start = i + 1;
+        }
+    }
+    push_element(&mut elements, &bytes[start..]);
+    elements
+}
+
+fn push_element<'a>(elements: &mut Vec<&'a [u8]>, element: &'a [u8]) {
+    let element = trim(element);
+    if !element.is_empty() {
+        elements.push(element);
+    }
+}
+
+// The elements of every field with the given name, in order.
+pub fn split_all<K: AsHeaderName>(headers: &HeaderMap, name: K) -> Vec<&[u8]> {
+    headers.get_all(name).iter().flat_map(split_list).collect()
+}
+
+// Merges repeated fields into one comma-separated field. `Set-Cookie` is
+// left alone since its values may themselves contain commas.
+#[must_use]
+pub fn combine(headers: &HeaderMap) -> HeaderMap {
+    let mut combined = HeaderMap::with_capacity(headers.keys_len());
+    for name in headers.keys() {
+        if *name == SET_COOKIE {
+            for value in headers.get_all(name) {
+                combined.append(name.clone(), value.clone());
+            }
+            continue;
+        }
+        let mut values = headers.get_all(name).iter();
+        let first = values
+            .next()
+            .unwrap_or_else(|| unreachable!("key has at least one value"));
+        let mut merged = BytesMut::from(first.as_bytes());
+        for value in values {
+            merged.extend_from_slice(b", ");
+            merged.extend_from_slice(value.as_bytes());
+        }
+        let merged =
+            HeaderValue::from_shared(merged.freeze()).unwrap_or_else(|_| {
+                unreachable!("joined header values are valid")
+            });
+        combined.insert(name.clone(), merged);
+    }
+    combined
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaType {
+    pub type_: String,
+    pub subtype: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    // Parameter names are case-insensitive; values are returned as sent,
+    // with any quoting removed.
+    #[must_use]
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[must_use]
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.type_, self.subtype)
+    }
+}
+
+// Parses a `Content-Type`-style value. The type, subtype and parameter
+// names are lowercased since they are all case-insensitive.
+pub fn parse_media_type(value: &HeaderValue) -> Option<MediaType> {
+    let mut rest = value.as_bytes();
+    let type_ = take_token(&mut rest)?;
+    rest = rest.strip_prefix(b"/")?;
+    let subtype = take_token(&mut rest)?;
+    let mut params = Vec::new();
+    loop {
+        rest = trim(rest);
+        if rest.is_empty() {
+            break;
+        }
+        rest = trim(rest.strip_prefix(b";")?);
+        if rest.is_empty() || rest[0] == b';' {
+            continue;
+        }
+        let name = take_token(&mut rest)?;
+        rest = rest.strip_prefix(b"=")?;
+        let value = if rest.first() == Some(&b'"') {
+            take_quoted(&mut rest)?
+        } else {
+            String::from_utf8(take_token(&mut rest)?.to_vec()).ok()?
+        };
+        params.push((lowercase(name), value));
+    }
+    Some(MediaType {
+        type_: lowercase(type_),
+        subtype: lowercase(subtype),
+        params,
+    })
+}
+
+pub(crate) fn take_token<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = bytes.iter().take_while(|&&b| is_token(b)).count();
+    if len == 0 {
+        return None;
+    }
+    let (token, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(token)
+}
+
+pub(crate) fn take_quoted(bytes: &mut &[u8]) -> Option<String> {
+    let mut value = Vec::new();
+    let mut iter = bytes.iter().enumerate().skip(1);
+    while let Some((i, &b)) = iter.next() {
+        match b {
+            b'"' => {
+                *bytes = &bytes[i + 1..];
+                return String::from_utf8(value).ok();
+            }
+            b'\\' => value.push(*iter.next()?.1),
+            _ => value.push(b),
+        }
+    }
+    None
+}
+
+pub(crate) fn lowercase(token: &[u8]) -> String {
+    // Tokens are always ASCII.
+    String::from_utf8_lossy(token).to_ascii_lowercase()
+}
+
+// One entry of an `Upgrade` header, such as `websocket` or `HTTP/2.0`.
+// The name and version are always tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Protocol {
+    name: String,
+    version: Option<String>,
+}
+
+impl Protocol {
+    // `None` unless the name and any version are tokens.
+    pub fn new(name: &str, version: Option<&str>) -> Option<Self> {
+        let valid = |s: &str| !s.is_empty() && s.bytes().all(is_token);
+        if !valid(name) || !version.into_iter().all(valid) {
+            return None;
+        }
+        Some(Self {
+            name: name.to_owned(),
+            version: version.map(str::to_owned),
+        })
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.version {
+            Some(ref version) => write!(f, "{}/{}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+// The protocols offered in every `Upgrade` field, in order of preference.
+// Malformed entries are skipped.
+pub fn parse_upgrade(headers: &HeaderMap) -> Vec<Protocol> {
+    split_all(headers, UPGRADE)
+        .into_iter()
+        .filter_map(|element| {
+            let mut rest = element;
+            let name = take_token(&mut rest)?;
+            let version = match rest.strip_prefix(b"/") {
+                Some(mut after) => {
+                    let version = take_token(&mut after)?;
+                    rest = after;
+                    Some(version)
+                }
+                None => None,
+            };
+            if !rest.is_empty() {
+                return None;
+            }
+            // Tokens are always ASCII.
+            Some(Protocol {
+                name: String::from_utf8_lossy(name).into_owned(),
+                version: version
+                    .map(|v| String::from_utf8_lossy(v).into_owned()),
+            })
+        })
+        .collect()
+}
+
+/// Builds an `Upgrade` value, e.g. for the 101 response that accepts one of
+/// the client's offers.
+///
+/// # Panics
+///
+/// Panics if a name or version holds bytes not allowed in a header value,
+/// which those parsed by `parse_upgrade` never do.
+pub fn upgrade_value(protocols: &[Protocol]) -> HeaderValue {
+    let joined = protocols
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    // `Protocol` only ever holds tokens.
+    HeaderValue::from_str(&joined).expect("tokens are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::{ACCEPT, CACHE_CONTROL};
+
+    #[test]
+    fn split_simple() {
+        let value = HeaderValue::from_static(" gzip ,deflate,, br ,");
+        assert_eq!(
+            vec![&b"gzip"[..], &b"deflate"[..], &b"br"[..]],
+            split_list(&value)
+        );
+    }
+
+    #[test]
+    fn split_respects_quotes() {
+        let value = HeaderValue::from_static(
+            "text/html; q=\"0,5\", foo=\"a\\\",b\", text/plain",
+        );
+        assert_eq!(
+            vec![
+                &b"text/html; q=\"0,5\""[..],
+                &b"foo=\"a\\\",b\""[..],
+                &b"text/plain"[..],
+            ],
+            split_list(&value)
+        );
+    }
+
+    #[test]
+    fn split_empty() {
+        assert!(split_list(&HeaderValue::from_static("")).is_empty());
+        assert!(split_list(&HeaderValue::from_static(" , ,")).is_empty());
+    }
+
+    #[test]
+    fn split_all_repeated_fields() {
+        let mut headers = HeaderMap::new();
+        headers.append(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        headers.append(
+            CACHE_CONTROL,
+            HeaderValue::from_static("no-store, max-age=0"),
+        );
+        assert_eq!(
+            vec![&b"no-cache"[..], &b"no-store"[..], &b"max-age=0"[..]],
+            split_all(&headers, CACHE_CONTROL)
+        );
+    }
+
+    #[test]
+    fn combine_keeps_set_cookie_separate() {
+        let mut headers = HeaderMap::new();
+        headers.append(ACCEPT, HeaderValue::from_static("text/html"));
+        headers.append(ACCEPT, HeaderValue::from_static("text/plain"));
+        headers.append(
+            SET_COOKIE,
+            HeaderValue::from_static("a=1; Expires=Wed, 21 Oct 2015"),
+        );
+        headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+
+        let combined = combine(&headers);
+        assert_eq!(
+            vec!["text/html, text/plain"],
+            combined
+                .get_all(ACCEPT)
+                .iter()
+                .map(|v| v.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, combined.get_all(SET_COOKIE).iter().count());
+    }
+
+    #[test]
+    fn media_type_with_params() {
+        let mt = parse_media_type(&HeaderValue::from_static(
+            "Multipart/Form-Data; Boundary=\"a b;\\\"c\" ; charset=UTF-8",
+        ))
+        .unwrap();
+        assert_eq!("multipart/form-data", mt.essence());
+        assert_eq!(Some("a b;\"c"), mt.param("boundary"));
+        assert_eq!(Some("UTF-8"), mt.param("CHARSET"));
+        assert_eq!(None, mt.param("q"));
+    }
+
+    #[test]
+    fn media_type_empty_params() {
+        let mt = parse_media_type(&HeaderValue::from_static("text/plain;;"))
+            .unwrap();
+        assert_eq!("text", mt.type_);
+        assert_eq!("plain", mt.subtype);
+        assert!(mt.params.is_empty());
+    }
+
+    #[test]
+    fn media_type_rejects_malformed() {
+        for value in &[
+            "",
+            "text",
+            "text/",
+            "/plain",
+            "text/plain charset=utf-8",
+            "text/plain; charset",
+            "text/plain; charset=",
+            "text/plain; charset=\"utf-8",
+            "text/plain; charset = utf-8",
+        ] {
+            let value = HeaderValue::from_static(value);
+            assert_eq!(None, parse_media_type(&value), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn upgrade_protocols() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            UPGRADE,
+            HeaderValue::from_static("HTTP/2.0, SHTTP/1.3, bad/, IRC/6.9"),
+        );
+        headers.append(UPGRADE, HeaderValue::from_static("websocket"));
+        let protocols = parse_upgrade(&headers);
+        assert_eq!(
+            vec![
+                Protocol::new("HTTP", Some("2.0")).unwrap(),
+                Protocol::new("SHTTP", Some("1.3")).unwrap(),
+                Protocol::new("IRC", Some("6.9")).unwrap(),
+                Protocol::new("websocket", None).unwrap(),
+            ],
+            protocols
+        );
+        assert_eq!(
+            "HTTP/2.0, websocket",
+            upgrade_value(&[protocols[0].clone(), protocols[3].clone()])
+        );
+        assert_eq!(Some("2.0"), protocols[0].version());
+        assert_eq!(None, Protocol::new("h2c\r\nx: y", None));
+        assert_eq!(None, Protocol::new("", None));
+        assert_eq!(None, Protocol::new("HTTP", Some("2.0, evil")));
+    }
+}
@@ -6,21 +6,94 @@
 // endings there are in HTTP.
 #![allow(clippy::write_with_newline)]
 
+#[macro_use]
+mod macros;
+
+#[cfg(any(test, feature = "access-log"))]
+pub mod access_log;
+pub mod auth;
 mod body;
+pub mod bridge;
+pub mod coding;
+mod config;
 mod conn;
+pub mod cookie;
+#[cfg(any(feature = "futures", feature = "mio", feature = "tokio"))]
+pub mod driver;
 mod event;
+pub mod headers;
+mod metrics;
+pub mod proxy;
+mod reader;
+pub mod redirect;
 mod req;
 mod resp;
+pub mod security;
+mod sniff;
+mod split;
 mod state;
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timing;
 mod util;
+mod warning;
+mod wire;
+
+pub use body::FramingMethod;
+pub use config::{Config, TrailerPolicy};
+pub use conn::{
+    Client, CloseReason, ConnState, DataFrames, HttpConn, Parts, RetryInfo,
+    Role, Server,
+};
+pub use event::{ClientEvent, Event, ServerEvent};
+pub use metrics::Metrics;
+pub use reader::EventReader;
+pub use req::{ReqHead, TargetForm};
+pub use resp::{InfoStatus, RespHead, StatusClass};
+pub use sniff::{sniff, Sniffed};
+pub use split::{RecvHalf, ReuniteError, SendHalf};
+#[cfg(feature = "futures")]
+pub use stream::{BodySink, BodyStream};
+pub use util::{
+    format_http_date, has_token, last_token_is, parse_http_date, token_eq,
+};
+pub use warning::{Anomaly, Warning};
+pub use wire::{Direction, WireRecord};
 
-pub use conn::{Client, HttpConn, Server};
-pub use event::Event;
-pub use req::ReqHead;
-pub use resp::RespHead;
+#[doc(hidden)]
+pub use macros::__private;
 
 pub mod error {
+    pub use crate::body::BodyError;
     pub use crate::conn::Error;
+    pub use crate::req::ReqHeadError;
+    pub use crate::resp::RespHeadError;
 
     pub type Result<T> = std::result::Result<T, Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use static_assertions::assert_impl_all;
+
+    use super::*;
+
+    assert_impl_all!(HttpConn<Client>: Send, Sync, Clone);
+    assert_impl_all!(HttpConn<Server>: Send, Sync, Clone);
+    assert_impl_all!(Parts<Client>: Send, Sync);
+    assert_impl_all!(Parts<Server>: Send, Sync);
+    assert_impl_all!(RecvHalf<Server>: Send, Sync);
+    assert_impl_all!(SendHalf<Server>: Send, Sync);
+    assert_impl_all!(Config: Send, Sync);
+    assert_impl_all!(Metrics: Send, Sync);
+    assert_impl_all!(Event: Send, Sync);
+    assert_impl_all!(ReqHead: Send, Sync);
+    assert_impl_all!(RespHead: Send, Sync);
+    assert_impl_all!(WireRecord: Send, Sync);
+    assert_impl_all!(error::Error: Send, Sync);
+    assert_impl_all!(error::BodyError: Send, Sync);
+    assert_impl_all!(error::ReqHeadError: Send, Sync);
+    assert_impl_all!(error::RespHeadError: Send, Sync);
+}
@@ -0,0 +1,141 @@
+#[macro_export]
+macro_rules! headers {
+    ($($name:tt => $value:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut map = $crate::__private::HeaderMap::new();
+        $(
+            map.append(
+                $crate::__header_name!($name),
+                $crate::__private::header_value($value),
+            );
+        )*
+        map
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __header_name {
+    ($name:literal) => {{
+        const _: () = assert!(
+            $crate::__private::is_valid_header_name($name),
+            "header names must be lowercase tokens",
+        );
+        $crate::__private::HeaderName::from_static($name)
+    }};
+    ($name:expr) => {
+        $crate::__private::header_name($name)
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use http::header::{HeaderName, HeaderValue};
+    pub use http::HeaderMap;
+
+    use http::HttpTryFrom;
+
+    #[must_use]
+    pub fn header_name<T>(name: T) -> HeaderName
+    where
+        HeaderName: HttpTryFrom<T>,
+    {
+        HeaderName::try_from(name)
+            .unwrap_or_else(|_| panic!("invalid header name"))
+    }
+
+    #[must_use]
+    pub fn header_value<T>(value: T) -> HeaderValue
+    where
+        HeaderValue: HttpTryFrom<T>,
+    {
+        HeaderValue::try_from(value)
+            .unwrap_or_else(|_| panic!("invalid header value"))
+    }
+
+    #[must_use]
+    pub const fn is_valid_header_name(name: &str) -> bool {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() {
+            return false;
+        }
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'!'
+                | b'#'
+                | b'$'
+                | b'%'
+                | b'&'
+                | b'\''
+                | b'*'
+                | b'+'
+                | b'-'
+                | b'.'
+                | b'^'
+                | b'_'
+                | b'`'
+                | b'|'
+                | b'~' => {}
+                _ => return false,
+            }
+            i += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{HeaderValue, CONNECTION, HOST};
+    use http::HeaderMap;
+
+    #[test]
+    fn empty() {
+        assert_eq!(HeaderMap::new(), headers! {});
+    }
+
+    #[test]
+    fn static_and_constant_names() {
+        let expected: HeaderMap = vec![
+            (HOST, HeaderValue::from_static("example.com")),
+            (CONNECTION, HeaderValue::from_static("close")),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            expected,
+            headers! {
+                "host" => "example.com",
+                CONNECTION => "close",
+            }
+        );
+    }
+
+    #[test]
+    fn repeated_names_append() {
+        let map = headers! {
+            "x-foo" => "a",
+            "x-foo" => String::from("b"),
+        };
+        assert_eq!(
+            vec!["a", "b"],
+            map.get_all("x-foo")
+                .iter()
+                .map(|v| v.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn name_validation() {
+        use super::__private::is_valid_header_name;
+
+        assert!(is_valid_header_name("content-type"));
+        assert!(!is_valid_header_name("Content-Type"));
+        assert!(!is_valid_header_name("bad name"));
+        assert!(!is_valid_header_name(""));
+    }
+}
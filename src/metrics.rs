@@ -0,0 +1,86 @@
+use crate::event::Event;
+use crate::wire::Direction;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Metrics {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub header_bytes_received: u64,
+    pub header_bytes_sent: u64,
+    pub body_bytes_received: u64,
+    pub body_bytes_sent: u64,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub cycles_reused: u64,
+    pub protocol_errors: u64,
+}
+
+impl Metrics {
+    // `wire_len` is the number of bytes the event occupied on the wire,
+    // which for data includes any chunk framing around it.
+    pub(crate) fn record(
+        &mut self,
+        direction: Direction,
+        event: &Event,
+        wire_len: usize,
+    ) {
+        let wire_len = wire_len as u64;
+        let (header_bytes, body_bytes, messages) = match direction {
+            Direction::Inbound => (
+                &mut self.header_bytes_received,
+                &mut self.body_bytes_received,
+                &mut self.messages_received,
+            ),
+            Direction::Outbound => (
+                &mut self.header_bytes_sent,
+                &mut self.body_bytes_sent,
+                &mut self.messages_sent,
+            ),
+        };
+        match event {
+            Event::Request(_)
+            | Event::InfoResponse(_)
+            | Event::Response(_) => {
+                *header_bytes += wire_len;
+            }
+            Event::Data(data) => *body_bytes += data.len() as u64,
+            Event::EndOfMessage(_) => *messages += 1,
+            // Counted with the end of the message they are written with.
+            Event::Trailers(_) | Event::ConnectionClosed => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::{HeaderMap, Method};
+
+    #[test]
+    fn record() {
+        let mut m = Metrics::default();
+        m.record(
+            Direction::Inbound,
+            &Event::request(
+                Method::GET,
+                "/".parse().unwrap(),
+                HeaderMap::new(),
+            ),
+            18,
+        );
+        m.record(Direction::Inbound, &Event::data(&b"hello"[..]), 10);
+        m.record(Direction::Inbound, &Event::EndOfMessage(None), 5);
+        m.record(Direction::Outbound, &Event::data(&b"hi"[..]), 2);
+        assert_eq!(
+            Metrics {
+                header_bytes_received: 18,
+                body_bytes_received: 5,
+                messages_received: 1,
+                body_bytes_sent: 2,
+                ..Metrics::default()
+            },
+            m
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::{error, fmt, mem};
+
+use bytes::{Bytes, BytesMut};
+use http::header::{HeaderValue, HOST};
+use http::uri::Authority;
+use http::{HeaderMap, Method, Uri, Version};
+
+use crate::conn::{Client, Error, HttpConn};
+use crate::event::Event;
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+
+#[derive(Debug)]
+pub enum ProxyError {
+    Http(Error),
+    NotConnect,
+    // The proxy answered with something other than 2xx. Its body, if
+    // any, can still be read from the connection.
+    Refused(Box<RespHead>),
+    Closed,
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "{e}"),
+            Self::NotConnect => write!(f, "tunnel request is not a CONNECT"),
+            Self::Refused(resp) => {
+                write!(f, "proxy refused the tunnel with {}", resp.status)
+            }
+            Self::Closed => {
+                write!(f, "proxy closed the connection before responding")
+            }
+        }
+    }
+}
+
+impl error::Error for ProxyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ProxyError {
+    fn from(e: Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+// A `CONNECT host:port` request, to which credentials such as
+// `Proxy-Authorization` can be added before it is sent.
+pub fn connect_request(authority: &Authority) -> ReqHead {
+    let value =
+        HeaderValue::from_str(authority.as_str()).unwrap_or_else(|_| {
+            unreachable!("an authority is a valid header value")
+        });
+    let mut headers = HeaderMap::new();
+    headers.insert(HOST, value);
+    ReqHead {
+        method: Method::CONNECT,
+        uri: Uri::from_parts({
+            let mut parts = http::uri::Parts::default();
+            parts.authority = Some(authority.clone());
+            parts
+        })
+        .unwrap_or_else(|_| unreachable!("authority-form URI")),
+        version: Version::HTTP_11,
+        headers,
+    }
+}
+
+// The proxy accepted: everything from here on is tunnel traffic, such as
+// a TLS handshake with the origin. `leftover` is what the proxy already
+// sent past its response and belongs to the tunnel.
+#[derive(Debug)]
+pub struct Tunnel {
+    pub response: RespHead,
+    pub leftover: Bytes,
+}
+
+// Drives a client connection through a CONNECT exchange with a proxy.
+// Interim responses are skipped; the first final response decides
+// whether the tunnel is up.
+#[derive(Debug)]
+pub struct ProxyConnect {
+    conn: HttpConn<Client>,
+}
+
+impl ProxyConnect {
+    pub fn new(conn: HttpConn<Client>) -> Self {
+        Self { conn }
+    }
+
+    pub fn get_ref(&self) -> &HttpConn<Client> {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut HttpConn<Client> {
+        &mut self.conn
+    }
+
+    pub fn into_inner(self) -> HttpConn<Client> {
+        self.conn
+    }
+
+    /// The bytes to write to the proxy. `req` is usually built with
+    /// `connect_request`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `req` is not a CONNECT request or cannot be sent.
+    pub fn start(&mut self, req: ReqHead) -> Result<Bytes, ProxyError> {
+        if req.method != Method::CONNECT {
+            return Err(ProxyError::NotConnect);
+        }
+        Ok(self.conn.send_request_with_body(req, Bytes::new())?)
+    }
+
+    /// Feeds bytes read from the proxy, with an empty slice for EOF.
+    /// `None` means more input is needed.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the proxy refuses the tunnel, closes the connection before
+    /// answering, or breaks the protocol.
+    pub fn feed(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<Option<Tunnel>, ProxyError> {
+        self.conn.feed(bytes)?;
+        loop {
+            let event = match self.conn.next_event() {
+                Err(e) if e.is_premature_close() => {
+                    return Err(ProxyError::Closed)
+                }
+                res => res?,
+            };
+            match event {
+                Some(Event::InfoResponse(_)) => {}
+                Some(Event::Response(resp)) if resp.status.is_success() => {
+                    let conn = mem::take(&mut self.conn);
+                    let (in_buf, _) = conn.into_bufs();
+                    return Ok(Some(Tunnel {
+                        response: resp,
+                        leftover: BytesMut::freeze(in_buf),
+                    }));
+                }
+                Some(Event::Response(resp)) => {
+                    return Err(ProxyError::Refused(Box::new(resp)));
+                }
+                Some(_) | None => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::PROXY_AUTHORIZATION;
+
+    use crate::auth;
+
+    fn authority() -> Authority {
+        "example.com:443".parse().unwrap()
+    }
+
+    #[test]
+    fn request_shape() {
+        let mut req = connect_request(&authority());
+        req.headers
+            .insert(PROXY_AUTHORIZATION, auth::basic("user", "pass"));
+        let mut connect = ProxyConnect::new(HttpConn::new());
+        assert_eq!(
+            &b"CONNECT example.com:443 HTTP/1.1\r\n\
+               host: example.com:443\r\n\
+               proxy-authorization: Basic dXNlcjpwYXNz\r\n\r\n"[..],
+            &connect.start(req).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn tunnel_established() {
+        let mut connect = ProxyConnect::new(HttpConn::new());
+        connect.start(connect_request(&authority())).unwrap();
+        assert!(connect
+            .feed(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .unwrap()
+            .is_none());
+        assert!(connect.feed(b"HTTP/1.1 200 Connection").unwrap().is_none());
+        let tunnel = connect
+            .feed(b" established\r\n\r\n\x16\x03\x01")
+            .unwrap()
+            .unwrap();
+        assert_eq!(200, tunnel.response.status);
+        assert_eq!(&b"\x16\x03\x01"[..], &tunnel.leftover[..]);
+    }
+
+    #[test]
+    fn tunnel_refused() {
+        let mut connect = ProxyConnect::new(HttpConn::new());
+        connect.start(connect_request(&authority())).unwrap();
+        match connect.feed(
+            b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+              proxy-authenticate: Basic realm=\"proxy\"\r\n\
+              content-length: 2\r\n\r\nno",
+        ) {
+            Err(ProxyError::Refused(resp)) => assert_eq!(407, resp.status),
+            r => panic!("expected a refusal, got {:?}", r),
+        }
+        match connect.get_mut().next_event() {
+            Ok(Some(Event::Data(data))) => assert_eq!(&b"no"[..], &data[..]),
+            r => panic!("expected the refusal body, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn proxy_closes() {
+        let mut connect = ProxyConnect::new(HttpConn::new());
+        connect.start(connect_request(&authority())).unwrap();
+        assert!(matches!(connect.feed(b""), Err(ProxyError::Closed)));
+    }
+
+    #[test]
+    fn start_rejects_other_methods() {
+        let mut req = connect_request(&authority());
+        req.method = Method::GET;
+        assert!(ProxyConnect::new(HttpConn::new()).start(req).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::Read;
+
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+// Iterates over the events the peer sends, reading from `io` whenever the
+// connection needs more input. Iteration pauses, returning `None`, while
+// it is this side's turn to send; it can carry on once that is done. It
+// ends for good when the connection closes or after an error.
+#[derive(Debug)]
+pub struct EventReader<R, T> {
+    conn: HttpConn<R>,
+    io: T,
+    done: bool,
+}
+
+impl<R: Role, T: Read> EventReader<R, T> {
+    pub fn new(conn: HttpConn<R>, io: T) -> Self {
+        Self {
+            conn,
+            io,
+            done: false,
+        }
+    }
+
+    pub fn conn(&self) -> &HttpConn<R> {
+        &self.conn
+    }
+
+    pub fn conn_mut(&mut self) -> &mut HttpConn<R> {
+        &mut self.conn
+    }
+
+    pub fn into_parts(self) -> (HttpConn<R>, T) {
+        (self.conn, self.io)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            match self.conn.next_event()? {
+                Some(Event::ConnectionClosed) => {
+                    self.done = true;
+                    return Ok(None);
+                }
+                Some(event) => return Ok(Some(event)),
+                None if self.conn.wants_read() => {
+                    self.conn.read_from(&mut self.io)?;
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R: Role, T: Read> Iterator for EventReader<R, T> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_event() {
+            Ok(event) => event.map(Ok),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use http::{HeaderMap, StatusCode};
+
+    use crate::conn::Server;
+
+    #[test]
+    fn reads_events_until_close() {
+        let io: &[u8] =
+            b"POST / HTTP/1.1\r\nhost: a\r\ncontent-length: 2\r\n\r\nhi";
+        let mut reader = EventReader::new(HttpConn::<Server>::new(), io);
+        let events = reader
+            .by_ref()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("read request");
+        assert_eq!(3, events.len());
+        assert!(matches!(events[0], Event::Request(_)));
+        assert_eq!(Event::Data(Bytes::from("hi")), events[1]);
+        assert_eq!(Event::EndOfMessage(None), events[2]);
+        assert!(!reader.is_done());
+
+        reader
+            .conn_mut()
+            .respond(StatusCode::OK, HeaderMap::new(), Bytes::new())
+            .expect("respond");
+        reader
+            .conn_mut()
+            .start_next_cycle()
+            .expect("reuse connection");
+        assert!(reader.next().is_none());
+        assert!(reader.is_done());
+    }
+
+    #[test]
+    fn stops_after_an_error() {
+        let io: &[u8] =
+            b"POST / HTTP/1.1\r\nhost: a\r\ncontent-length: 5\r\n\r\nhi";
+        let mut reader = EventReader::new(HttpConn::<Server>::new(), io);
+        assert!(matches!(reader.next(), Some(Ok(Event::Request(_)))));
+        assert!(matches!(reader.next(), Some(Ok(Event::Data(_)))));
+        match reader.next() {
+            Some(Err(e)) => assert!(e.is_premature_close()),
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,458 @@
+use std::fmt;
+
+use http::header::{
+    HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LANGUAGE,
+    CONTENT_LENGTH, CONTENT_LOCATION, CONTENT_TYPE, COOKIE, EXPECT, HOST,
+    LOCATION, PROXY_AUTHORIZATION, TRANSFER_ENCODING,
+};
+use http::{Method, StatusCode, Uri};
+
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+// Fields describing a request body, dropped when a redirect turns the
+// request into a GET.
+const BODY_HEADERS: &[http::header::HeaderName] = &[
+    CONTENT_ENCODING,
+    CONTENT_LANGUAGE,
+    CONTENT_LENGTH,
+    CONTENT_LOCATION,
+    CONTENT_TYPE,
+    EXPECT,
+    TRANSFER_ENCODING,
+];
+
+// Credentials that must not follow a redirect to another origin.
+const CREDENTIAL_HEADERS: &[http::header::HeaderName] =
+    &[AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+#[derive(Debug)]
+pub enum RedirectError {
+    MissingLocation,
+    InvalidLocation,
+    UnsupportedScheme,
+    TooManyRedirects,
+}
+
+impl fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingLocation => {
+                write!(f, "redirect response has no Location")
+            }
+            Self::InvalidLocation => {
+                write!(f, "redirect Location is not a valid URI reference")
+            }
+            Self::UnsupportedScheme => {
+                write!(f, "redirect Location is not an http or https URI")
+            }
+            Self::TooManyRedirects => write!(f, "too many redirects"),
+        }
+    }
+}
+
+impl std::error::Error for RedirectError {}
+
+// The request to send after a redirect.
+#[derive(Debug)]
+pub struct Redirect {
+    pub request: ReqHead,
+    // Whether the original body should be sent again. It is dropped only
+    // when the request is turned into a GET.
+    pub keeps_body: bool,
+}
+
+// Follows the redirects of one logical request, keeping track of where
+// it currently points and how many hops have been taken.
+#[derive(Clone, Debug)]
+pub struct Redirects {
+    uri: Uri,
+    max: usize,
+    followed: usize,
+}
+
+impl Redirects {
+    /// # Panics
+    ///
+    /// Panics if `uri` is not absolute, since relative locations could
+    /// not be resolved against it.
+    pub fn new(uri: Uri) -> Self {
+        assert!(
+            uri.scheme_part().is_some() && uri.authority_part().is_some(),
+            "redirects need an absolute starting URI"
+        );
+        Self {
+            uri,
+            max: DEFAULT_MAX_REDIRECTS,
+            followed: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn with_max(mut self, max: usize) -> Self {
+        self.max = max;
+        self
+    }
+
+    // Where the request currently points.
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    pub fn followed(&self) -> usize {
+        self.followed
+    }
+
+    /// Works out the request to send after `resp`, given the request that
+    /// produced it. `Ok(None)` means `resp` is not a redirect to follow,
+    /// which covers 300 Multiple Choices and 304 Not Modified.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the redirect has no usable `Location`, points to an
+    /// unsupported scheme, or goes past the limit on redirects.
+    pub fn next_request(
+        &mut self,
+        req: &ReqHead,
+        resp: &RespHead,
+    ) -> Result<Option<Redirect>, RedirectError> {
+        // A 303 turns anything but a GET or HEAD into a GET, and so, as
+        // browsers have always done, does a 301 or 302 a POST. Only a
+        // rewritten request loses its body.
+        let to_get = match resp.status {
+            StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT => false,
+            StatusCode::SEE_OTHER => {
+                req.method != Method::GET && req.method != Method::HEAD
+            }
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => {
+                req.method == Method::POST
+            }
+            _ => return Ok(None),
+        };
+        if self.followed >= self.max {
+            return Err(RedirectError::TooManyRedirects);
+        }
+        let location = resp
+            .headers
+            .get(LOCATION)
+            .ok_or(RedirectError::MissingLocation)?
+            .to_str()
+            .map_err(|_| RedirectError::InvalidLocation)?;
+        let target = resolve(&self.uri, location)?;
+
+        let mut request = ReqHead {
+            method: req.method.clone(),
+            uri: target
+                .path_and_query()
+                .map_or("/", |pq| pq.as_str())
+                .parse()
+                .map_err(|_| RedirectError::InvalidLocation)?,
+            version: req.version,
+            headers: req.headers.clone(),
+        };
+        if to_get {
+            request.method = Method::GET;
+            for name in BODY_HEADERS {
+                request.headers.remove(name);
+            }
+        }
+        if !same_origin(&self.uri, &target) {
+            for name in CREDENTIAL_HEADERS {
+                request.headers.remove(name);
+            }
+        }
+        let authority = target
+            .authority_part()
+            .ok_or(RedirectError::InvalidLocation)?
+            .as_str();
+        request.headers.insert(
+            HOST,
+            HeaderValue::from_str(authority)
+                .map_err(|_| RedirectError::InvalidLocation)?,
+        );
+
+        self.uri = target;
+        self.followed += 1;
+        Ok(Some(Redirect {
+            request,
+            keeps_body: !to_get,
+        }))
+    }
+}
+
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    a.scheme_str() == b.scheme_str()
+        && a.host().map(str::to_ascii_lowercase)
+            == b.host().map(str::to_ascii_lowercase)
+        && effective_port(a) == effective_port(b)
+}
+
+fn effective_port(uri: &Uri) -> Option<u16> {
+    uri.port_u16().or(match uri.scheme_str() {
+        Some("http") => Some(80),
+        Some("https") => Some(443),
+        _ => None,
+    })
+}
+
+// Resolves a `Location` value against the current URI, following RFC 3986
+// section 5.2. Any fragment is dropped, since it never goes on the wire.
+fn resolve(base: &Uri, reference: &str) -> Result<Uri, RedirectError> {
+    let reference = reference.split('#').next().unwrap_or_default().trim();
+    let scheme = base.scheme_str().unwrap_or("http");
+    let authority = base.authority_part().map_or("", |a| a.as_str());
+    let resolved = if has_scheme(reference) {
+        reference.to_owned()
+    } else if reference.starts_with("//") {
+        format!("{scheme}:{reference}")
+    } else {
+        let (path, query) = match reference.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (reference, None),
+        };
+        let path = if path.is_empty() {
+            base.path().to_owned()
+        } else if path.starts_with('/') {
+            remove_dot_segments(path)
+        } else {
+            let base_path = base.path();
+            let dir = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+            remove_dot_segments(&format!(
+                "/{}{}",
+                dir.trim_start_matches('/'),
+                path
+            ))
+        };
+        let query = match query {
+            Some(query) => Some(query),
+            None if reference.is_empty() => base.query(),
+            None => None,
+        };
+        match query {
+            Some(query) => {
+                format!("{scheme}://{authority}{path}?{query}")
+            }
+            None => format!("{scheme}://{authority}{path}"),
+        }
+    };
+    let uri = resolved
+        .parse::<Uri>()
+        .map_err(|_| RedirectError::InvalidLocation)?;
+    match uri.scheme_str() {
+        Some(s) if s.eq_ignore_ascii_case("http") => {}
+        Some(s) if s.eq_ignore_ascii_case("https") => {}
+        _ => return Err(RedirectError::UnsupportedScheme),
+    }
+    if uri.authority_part().is_none() {
+        return Err(RedirectError::InvalidLocation);
+    }
+    Ok(uri)
+}
+
+fn has_scheme(reference: &str) -> bool {
+    match reference.find(':') {
+        Some(end) => {
+            let scheme = &reference[..end];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')
+                })
+        }
+        None => false,
+    }
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    for (i, &segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match segment {
+            "." => {
+                if last {
+                    out.push("");
+                }
+            }
+            ".." => {
+                out.pop();
+                if last {
+                    out.push("");
+                }
+            }
+            segment => out.push(segment),
+        }
+    }
+    format!("/{}", out.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::{HeaderMap, Version};
+
+    fn req(method: Method, headers: HeaderMap) -> ReqHead {
+        ReqHead {
+            method,
+            uri: "/a/b?x=1".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers,
+        }
+    }
+
+    fn resp(status: u16, location: &'static str) -> RespHead {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, HeaderValue::from_static(location));
+        RespHead {
+            status: StatusCode::from_u16(status).unwrap(),
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        }
+    }
+
+    fn resolved(reference: &str) -> String {
+        let base: Uri = "http://a/b/c/d;p?q".parse().unwrap();
+        resolve(&base, reference).unwrap().to_string()
+    }
+
+    #[test]
+    fn resolve_references() {
+        // Examples from RFC 3986 section 5.4.
+        for &(reference, expected) in &[
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q"),
+            ("g#s", "http://a/b/c/g"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("https://b:8443/x", "https://b:8443/x"),
+        ] {
+            assert_eq!(expected, resolved(reference), "{reference:?}");
+        }
+        let base: Uri = "http://a/".parse().unwrap();
+        match resolve(&base, "ftp://a/file") {
+            Err(RedirectError::UnsupportedScheme) => {}
+            r => panic!("expected unsupported scheme, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn method_rewrites() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("5"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        let post = req(Method::POST, headers);
+        for &(status, ref method, keeps_body) in &[
+            (301, Method::GET, false),
+            (302, Method::GET, false),
+            (303, Method::GET, false),
+            (307, Method::POST, true),
+            (308, Method::POST, true),
+        ] {
+            let mut redirects =
+                Redirects::new("http://a/a/b?x=1".parse().unwrap());
+            let redirect = redirects
+                .next_request(&post, &resp(status, "/c"))
+                .unwrap()
+                .unwrap();
+            assert_eq!(*method, redirect.request.method, "{status}");
+            assert_eq!(keeps_body, redirect.keeps_body);
+            assert_eq!(
+                keeps_body,
+                redirect.request.headers.contains_key(CONTENT_LENGTH)
+            );
+            assert_eq!("/c", redirect.request.uri);
+        }
+
+        let mut redirects = Redirects::new("http://a/".parse().unwrap());
+        let head = req(Method::HEAD, HeaderMap::new());
+        let redirect = redirects
+            .next_request(&head, &resp(303, "/c"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Method::HEAD, redirect.request.method);
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("5"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        let put = req(Method::PUT, headers);
+        let redirect = redirects
+            .next_request(&put, &resp(302, "/c"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Method::PUT, redirect.request.method);
+        assert!(redirect.keeps_body);
+        assert_eq!("5", redirect.request.headers[CONTENT_LENGTH]);
+        assert_eq!("text/plain", redirect.request.headers[CONTENT_TYPE]);
+    }
+
+    #[test]
+    fn scrubs_credentials_across_origins() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static("a"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic eA=="));
+        headers.insert(COOKIE, HeaderValue::from_static("id=1"));
+        let get = req(Method::GET, headers);
+
+        let mut redirects = Redirects::new("http://a/a/b".parse().unwrap());
+        let same = redirects
+            .next_request(&get, &resp(302, "http://A:80/c"))
+            .unwrap()
+            .unwrap();
+        assert!(same.request.headers.contains_key(AUTHORIZATION));
+        assert_eq!("A:80", same.request.headers[HOST]);
+
+        let other = redirects
+            .next_request(&same.request, &resp(301, "https://b/d?e"))
+            .unwrap()
+            .unwrap();
+        assert!(!other.request.headers.contains_key(AUTHORIZATION));
+        assert!(!other.request.headers.contains_key(COOKIE));
+        assert_eq!("b", other.request.headers[HOST]);
+        assert_eq!("/d?e", other.request.uri);
+        assert_eq!("https://b/d?e", redirects.uri().to_string());
+        assert_eq!(2, redirects.followed());
+    }
+
+    #[test]
+    fn limits_and_non_redirects() {
+        let get = req(Method::GET, HeaderMap::new());
+        let mut redirects =
+            Redirects::new("http://a/".parse().unwrap()).with_max(1);
+        assert!(redirects
+            .next_request(&get, &resp(304, "/c"))
+            .unwrap()
+            .is_none());
+        assert!(redirects
+            .next_request(&get, &resp(300, "/c"))
+            .unwrap()
+            .is_none());
+        redirects.next_request(&get, &resp(302, "/c")).unwrap();
+        match redirects.next_request(&get, &resp(302, "/d")) {
+            Err(RedirectError::TooManyRedirects) => {}
+            r => panic!("expected too many redirects, got {:?}", r),
+        }
+
+        let mut missing = resp(302, "/c");
+        missing.headers.remove(LOCATION);
+        match Redirects::new("http://a/".parse().unwrap())
+            .next_request(&get, &missing)
+        {
+            Err(RedirectError::MissingLocation) => {}
+            r => panic!("expected missing location, got {:?}", r),
+        }
+    }
+}
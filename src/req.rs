@@ -1,15 +1,33 @@
 use std::fmt;
 
 use bytes::{Bytes, BytesMut};
-use http::header::{HeaderName, HeaderValue};
-use http::{HeaderMap, Method, Uri, Version};
+use http::header::{HeaderName, HeaderValue, HOST};
+use http::uri::Authority;
+use http::{HeaderMap, Method, StatusCode, Uri, Version};
 use httparse::{Request, EMPTY_HEADER};
 use twoway::find_bytes;
 
 use crate::body::FramingMethod;
-use crate::util::{can_keep_alive, is_chunked, maybe_content_length};
+use crate::coding::{content_codings, is_compressed, ContentCoding};
+use crate::config::Config;
+use crate::util::{
+    can_keep_alive, collapse_line_delimiters, fields_len, has_bare_lf,
+    is_chunked, maybe_content_length, strip_space_before_colon,
+};
+use crate::warning::{Anomalies, Anomaly};
+
+// The forms a request target can take, from RFC 7230 section 5.3.
+// Absolute-form is what forward proxies receive; authority-form is only
+// used by CONNECT, and asterisk-form only by server-wide OPTIONS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TargetForm {
+    Origin,
+    Absolute,
+    Authority,
+    Asterisk,
+}
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ReqHead {
     pub method: Method,
     pub uri: Uri,
@@ -19,10 +37,41 @@ pub struct ReqHead {
 
 impl ReqHead {
     pub(crate) fn from_buf(buf: &mut BytesMut) -> ReqHeadResult<Option<Self>> {
-        let buf = match find_bytes(buf, &b"\r\n\r\n"[..]) {
-            Some(n) => buf.split_to(n + 4).freeze(),
+        Ok(
+            Self::from_buf_with(buf, &Config::default())?
+                .map(|(head, _)| head),
+        )
+    }
+
+    // Also reports what the enabled leniencies let through, with the
+    // line each was on.
+    pub(crate) fn from_buf_with(
+        buf: &mut BytesMut,
+        config: &Config,
+    ) -> ReqHeadResult<Option<(Self, Anomalies)>> {
+        let end = find_bytes(buf, &b"\r\n\r\n"[..]).map(|n| n + 4);
+        if has_bare_lf(&buf[..end.unwrap_or(buf.len())]) {
+            return Err(httparse::Error::NewLine.into());
+        }
+        let mut buf = match end {
+            Some(end) => buf.split_to(end).freeze(),
             None => return Ok(None),
         };
+        let mut anomalies = Vec::new();
+        if config.allow_extra_line_whitespace {
+            if let Some(fixed) = collapse_line_delimiters(&buf) {
+                buf = Bytes::from(fixed);
+                anomalies.push((Anomaly::ExtraLineWhitespace, 1));
+            }
+        }
+        if config.allow_space_before_colon {
+            if let Some((fixed, lines)) = strip_space_before_colon(&buf) {
+                buf = Bytes::from(fixed);
+                anomalies.extend(
+                    lines.into_iter().map(|n| (Anomaly::SpaceBeforeColon, n)),
+                );
+            }
+        }
         let mut hdrs = [EMPTY_HEADER; 50];
         let mut pr = Request::new(&mut hdrs);
         let s = pr.parse(&buf)?;
@@ -36,10 +85,10 @@ impl ReqHead {
         let path_end = path_start + path.len();
         let uri = Uri::from_shared(buf.slice(path_start, path_end))?;
 
-        let version = if pr.version.unwrap() == 1 {
-            Version::HTTP_11
-        } else {
-            Version::HTTP_10
+        let version = match pr.version {
+            Some(0) => Version::HTTP_10,
+            Some(1) => Version::HTTP_11,
+            _ => return Err(ReqHeadError::UnsupportedVersion),
         };
 
         let mut headers = HeaderMap::with_capacity(pr.headers.len());
@@ -56,12 +105,67 @@ impl ReqHead {
             headers.append(name, value);
         }
 
-        Ok(Some(Self {
+        let mut head = Self {
             method,
             uri,
             version,
             headers,
-        }))
+        };
+        head.check_target()?;
+        Ok(Some((head, anomalies)))
+    }
+
+    pub fn target_form(&self) -> TargetForm {
+        if self.uri.scheme_part().is_some() {
+            TargetForm::Absolute
+        } else if self.uri.authority_part().is_some() {
+            TargetForm::Authority
+        } else if self.uri.path() == "*" {
+            TargetForm::Asterisk
+        } else {
+            TargetForm::Origin
+        }
+    }
+
+    // The host the request is for: from the target when it names one,
+    // otherwise from the `Host` header.
+    pub fn authority(&self) -> Option<Authority> {
+        match self.uri.authority_part() {
+            Some(authority) => Some(authority.clone()),
+            None => Authority::from_shared(
+                self.headers.get(HOST)?.as_bytes().into(),
+            )
+            .ok(),
+        }
+    }
+
+    // Each form is only valid with the methods it exists for. A received
+    // absolute-form target overrides the `Host` header, as RFC 7230
+    // section 5.4 requires, so the two cannot disagree.
+    fn check_target(&mut self) -> ReqHeadResult<()> {
+        let connect = self.method == Method::CONNECT;
+        let valid = match self.target_form() {
+            TargetForm::Origin | TargetForm::Absolute => !connect,
+            TargetForm::Authority => {
+                connect
+                    && self
+                        .uri
+                        .authority_part()
+                        .and_then(Authority::port_part)
+                        .is_some()
+            }
+            TargetForm::Asterisk => self.method == Method::OPTIONS,
+        };
+        if !valid {
+            return Err(ReqHeadError::InvalidTarget);
+        }
+        if self.target_form() == TargetForm::Absolute {
+            let authority = self.uri.authority_part().unwrap().as_str();
+            let value = HeaderValue::from_str(authority)
+                .map_err(|_| ReqHeadError::InvalidTarget)?;
+            self.headers.insert(HOST, value);
+        }
+        Ok(())
     }
 
     pub(crate) fn write_to_buf(&self, buf: &mut BytesMut) -> Bytes {
@@ -70,13 +174,22 @@ impl ReqHead {
         n += self.method.as_str().len();
         buf.extend_from_slice(b" ");
         n += 1;
-        buf.extend_from_slice(self.uri.path().as_bytes());
-        n += self.uri.path().len();
-        if let Some(qs) = self.uri.query() {
-            buf.extend_from_slice(b"?");
-            n += 1;
-            buf.extend_from_slice(qs.as_bytes());
-            n += qs.len();
+        match self.uri.authority_part() {
+            // CONNECT names the host and port to tunnel to instead.
+            Some(authority) if self.method == Method::CONNECT => {
+                buf.extend_from_slice(authority.as_str().as_bytes());
+                n += authority.as_str().len();
+            }
+            _ => {
+                buf.extend_from_slice(self.uri.path().as_bytes());
+                n += self.uri.path().len();
+                if let Some(qs) = self.uri.query() {
+                    buf.extend_from_slice(b"?");
+                    n += 1;
+                    buf.extend_from_slice(qs.as_bytes());
+                    n += qs.len();
+                }
+            }
         }
         buf.extend_from_slice(b" ");
         n += 1;
@@ -103,10 +216,39 @@ impl ReqHead {
         buf.split_to(n).freeze()
     }
 
+    // What `write_to_buf` writes, to the byte.
+    pub(crate) fn written_len(&self) -> usize {
+        let target = match self.uri.authority_part() {
+            Some(authority) if self.method == Method::CONNECT => {
+                authority.as_str().len()
+            }
+            _ => {
+                self.uri.path().len()
+                    + self.uri.query().map_or(0, |qs| qs.len() + 1)
+            }
+        };
+        self.method.as_str().len()
+            + target
+            + " HTTP/1.1\r\n".len()
+            + 1
+            + fields_len(&self.headers)
+            + 2
+    }
+
     pub(crate) fn can_keep_alive(&self) -> bool {
         can_keep_alive(self.version, &self.headers)
     }
 
+    // The codings in `Content-Encoding`, in the order they were applied,
+    // or `None` if any is unknown.
+    pub fn content_codings(&self) -> Option<Vec<ContentCoding>> {
+        content_codings(&self.headers)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        is_compressed(&self.headers)
+    }
+
     pub(crate) fn framing_method(&self) -> FramingMethod {
         if is_chunked(&self.headers) {
             FramingMethod::Chunked
@@ -118,12 +260,42 @@ impl ReqHead {
     }
 }
 
+impl fmt::Display for ReqHead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:?} ({} header{})",
+            self.method,
+            self.uri,
+            self.version,
+            self.headers.len(),
+            if self.headers.len() == 1 { "" } else { "s" },
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use http::header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
 
+    #[test]
+    fn parse_rejects_unsupported_versions() {
+        for req_text in &[
+            &b"GET / HTTP/1.7\r\n\r\n"[..],
+            &b"GET / HTTP/2.0\r\n\r\n"[..],
+        ] {
+            match ReqHead::from_buf(&mut (*req_text).into()) {
+                Err(e @ ReqHeadError::UnsupportedVersion) => assert_eq!(
+                    StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+                    e.status_hint()
+                ),
+                r => panic!("expected unsupported version, got {:?}", r),
+            }
+        }
+    }
+
     #[test]
     fn parse_simple_request() {
         let req_text = &b"GET /a HTTP/1.1\r\n\
@@ -199,6 +371,44 @@ mod tests {
         assert!(ReqHead::from_buf(&mut req_text.into()).is_err());
     }
 
+    #[test]
+    fn parse_space_before_colon_when_allowed() {
+        let req_text = &b"HEAD /foo HTTP/1.1\r\n\
+                       foo : line\r\n\r\n"[..];
+        let config = Config {
+            allow_space_before_colon: true,
+            ..Config::default()
+        };
+        let (req, anomalies) =
+            ReqHead::from_buf_with(&mut req_text.into(), &config)
+                .expect("parsed request")
+                .expect("complete request");
+        assert_eq!("line", req.headers["foo"]);
+        assert_eq!(vec![(Anomaly::SpaceBeforeColon, 2)], anomalies);
+
+        // Never for the fields that frame the body.
+        let req_text = &b"POST /foo HTTP/1.1\r\n\
+                       content-length : 5\r\n\r\nhello"[..];
+        let res = ReqHead::from_buf_with(&mut req_text.into(), &config);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn parse_extra_request_line_whitespace_when_allowed() {
+        let req_text = &b"GET  /a \tHTTP/1.1\r\n\r\n"[..];
+        assert!(ReqHead::from_buf(&mut req_text.into()).is_err());
+        let config = Config {
+            allow_extra_line_whitespace: true,
+            ..Config::default()
+        };
+        let (req, anomalies) =
+            ReqHead::from_buf_with(&mut req_text.into(), &config)
+                .expect("parsed request")
+                .expect("complete request");
+        assert_eq!("/a", req.uri);
+        assert_eq!(vec![(Anomaly::ExtraLineWhitespace, 1)], anomalies);
+    }
+
     #[test]
     fn parse_reject_ht_before_colon() {
         let req_text = &b"HEAD /foo HTTP/1.1\r\n\
@@ -213,6 +423,71 @@ mod tests {
         assert!(ReqHead::from_buf(&mut req_text.into()).is_err());
     }
 
+    fn parse(req_text: &[u8]) -> ReqHeadResult<ReqHead> {
+        Ok(ReqHead::from_buf(&mut req_text.into())?.unwrap())
+    }
+
+    #[test]
+    fn target_forms() {
+        for (req_text, form) in &[
+            (&b"GET /a?b HTTP/1.1\r\n\r\n"[..], TargetForm::Origin),
+            (
+                &b"GET http://example.com/a HTTP/1.1\r\n\r\n"[..],
+                TargetForm::Absolute,
+            ),
+            (
+                &b"CONNECT example.com:443 HTTP/1.1\r\n\r\n"[..],
+                TargetForm::Authority,
+            ),
+            (&b"OPTIONS * HTTP/1.1\r\n\r\n"[..], TargetForm::Asterisk),
+        ] {
+            assert_eq!(*form, parse(req_text).unwrap().target_form());
+        }
+    }
+
+    #[test]
+    fn targets_must_suit_the_method() {
+        for req_text in &[
+            &b"CONNECT / HTTP/1.1\r\n\r\n"[..],
+            &b"CONNECT http://example.com/ HTTP/1.1\r\n\r\n"[..],
+            &b"CONNECT example.com HTTP/1.1\r\n\r\n"[..],
+            &b"GET example.com:80 HTTP/1.1\r\n\r\n"[..],
+            &b"GET * HTTP/1.1\r\n\r\n"[..],
+        ] {
+            match parse(req_text) {
+                Err(e @ ReqHeadError::InvalidTarget) => {
+                    assert_eq!(StatusCode::BAD_REQUEST, e.status_hint());
+                }
+                r => panic!("expected an invalid target, got {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn absolute_form_overrides_host() {
+        let req = parse(
+            b"GET http://example.com:8080/a?b HTTP/1.1\r\n\
+              host: elsewhere.net\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(Some("http"), req.uri.scheme_str());
+        assert_eq!("/a?b", req.uri.path_and_query().unwrap());
+        assert_eq!("example.com:8080", req.headers[HOST]);
+        assert_eq!(1, req.headers.get_all(HOST).iter().count());
+        assert_eq!("example.com:8080", req.authority().unwrap());
+    }
+
+    #[test]
+    fn authority_from_host() {
+        let req =
+            parse(b"GET / HTTP/1.1\r\nhost: example.com\r\n\r\n").unwrap();
+        assert_eq!("example.com", req.authority().unwrap());
+        assert!(parse(b"GET / HTTP/1.0\r\n\r\n")
+            .unwrap()
+            .authority()
+            .is_none());
+    }
+
     #[test]
     fn write_simple_req() {
         let out_buf: Bytes = b"GET /a HTTP/1.1\r\n\
@@ -236,6 +511,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn display() {
+        let req = ReqHead {
+            method: Method::GET,
+            uri: "/a?b=c".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: vec![
+                (HOST, HeaderValue::from_static("example.com")),
+                (CONNECTION, HeaderValue::from_static("close")),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        assert_eq!("GET /a?b=c HTTP/1.1 (2 headers)", req.to_string());
+    }
+
     #[test]
     fn framing_method_no_headers() {
         assert_eq!(
@@ -294,6 +585,27 @@ pub enum ReqHeadError {
     Parse(httparse::Error),
     InvalidMethod(http::method::InvalidMethod),
     InvalidUriBytes(http::uri::InvalidUriBytes),
+    UnsupportedVersion,
+    TooLarge,
+    // The target's form does not suit the method, such as an origin-form
+    // CONNECT or an asterisk-form GET.
+    InvalidTarget,
+    // An HTTP/1.1 request without the `Host` header it must carry.
+    MissingHost,
+}
+
+impl ReqHeadError {
+    // The status a server should answer with, if it answers at all.
+    #[must_use]
+    pub fn status_hint(&self) -> StatusCode {
+        match self {
+            Self::UnsupportedVersion => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            Self::TooLarge | Self::Parse(httparse::Error::TooManyHeaders) => {
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
 pub type ReqHeadResult<T> = std::result::Result<T, ReqHeadError>;
@@ -310,6 +622,14 @@ impl fmt::Display for ReqHeadError {
             Self::InvalidUriBytes(e) => {
                 write!(f, "Invalid URI bytes were provided: {}", e)
             }
+            Self::UnsupportedVersion => write!(f, "Unsupported HTTP version"),
+            Self::TooLarge => {
+                write!(f, "request head exceeds the maximum event size")
+            }
+            Self::InvalidTarget => {
+                write!(f, "request target does not suit the method")
+            }
+            Self::MissingHost => write!(f, "HTTP/1.1 request without Host"),
         }
     }
 }
@@ -320,13 +640,20 @@ impl std::error::Error for ReqHeadError {
             Self::Parse(e) => Some(e),
             Self::InvalidMethod(e) => Some(e),
             Self::InvalidUriBytes(e) => Some(e),
+            Self::UnsupportedVersion
+            | Self::TooLarge
+            | Self::InvalidTarget
+            | Self::MissingHost => None,
         }
     }
 }
 
 impl From<httparse::Error> for ReqHeadError {
     fn from(e: httparse::Error) -> Self {
-        Self::Parse(e)
+        match e {
+            httparse::Error::Version => Self::UnsupportedVersion,
+            e => Self::Parse(e),
+        }
     }
 }
 
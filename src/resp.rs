@@ -7,32 +7,93 @@ use httparse::{Response, EMPTY_HEADER};
 use twoway::find_bytes;
 
 use crate::body::FramingMethod;
-use crate::util::{can_keep_alive, is_chunked, maybe_content_length};
+use crate::coding::{content_codings, is_compressed, ContentCoding};
+use crate::config::Config;
+use crate::util::{
+    can_keep_alive, collapse_line_delimiters, fields_len, has_bare_lf,
+    is_chunked, maybe_content_length, strip_space_before_colon,
+};
+use crate::warning::{Anomalies, Anomaly};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RespHead {
     pub status: StatusCode,
     pub version: Version,
     pub headers: HeaderMap,
+    // `None` means the canonical reason phrase for the status, if there is
+    // one. An empty reason gives a status line with no reason at all.
+    pub reason: Option<Bytes>,
 }
 
 impl RespHead {
-    fn from_buf(buf: &mut BytesMut) -> Result<Option<Self>, RespHeadError> {
-        let buf = match find_bytes(buf, &b"\r\n\r\n"[..]) {
-            Some(n) => buf.split_to(n + 4).freeze(),
+    pub(crate) fn from_buf(
+        buf: &mut BytesMut,
+    ) -> Result<Option<Self>, RespHeadError> {
+        Ok(
+            Self::from_buf_with(buf, &Config::default())?
+                .map(|(head, _)| head),
+        )
+    }
+
+    // See `ReqHead::from_buf_with`.
+    pub(crate) fn from_buf_with(
+        buf: &mut BytesMut,
+        config: &Config,
+    ) -> Result<Option<(Self, Anomalies)>, RespHeadError> {
+        let end = find_bytes(buf, &b"\r\n\r\n"[..]).map(|n| n + 4);
+        if has_bare_lf(&buf[..end.unwrap_or(buf.len())]) {
+            return Err(httparse::Error::NewLine.into());
+        }
+        let mut buf = match end {
+            Some(end) => buf.split_to(end).freeze(),
             None => return Ok(None),
         };
+        let mut anomalies = Vec::new();
+        if config.allow_icy_status_line && buf.starts_with(b"ICY ") {
+            let mut fixed = BytesMut::with_capacity(buf.len() + 5);
+            fixed.extend_from_slice(b"HTTP/1.0");
+            fixed.extend_from_slice(&buf[3..]);
+            buf = fixed.freeze();
+            anomalies.push((Anomaly::IcyStatusLine, 1));
+        }
+        if config.allow_extra_line_whitespace {
+            if let Some(fixed) = collapse_line_delimiters(&buf) {
+                buf = Bytes::from(fixed);
+                anomalies.push((Anomaly::ExtraLineWhitespace, 1));
+            }
+        }
+        if config.allow_space_before_colon {
+            if let Some((fixed, lines)) = strip_space_before_colon(&buf) {
+                buf = Bytes::from(fixed);
+                anomalies.extend(
+                    lines.into_iter().map(|n| (Anomaly::SpaceBeforeColon, n)),
+                );
+            }
+        }
         let mut hdrs = [EMPTY_HEADER; 50];
         let mut pr = Response::new(&mut hdrs);
         let s = pr.parse(&buf)?;
         debug_assert!(s.is_complete());
 
-        let status = StatusCode::from_u16(pr.code.unwrap())?;
+        let code = pr.code.unwrap();
+        let status = StatusCode::from_u16(code).map_err(|e| {
+            if code >= 600 {
+                RespHeadError::UnknownStatusClass(code)
+            } else {
+                e.into()
+            }
+        })?;
+        let reason = pr.reason.unwrap_or("").trim_end();
+        let reason = match status.canonical_reason() {
+            Some(canonical) if canonical == reason => None,
+            None if reason.is_empty() => None,
+            _ => Some(Bytes::from(reason)),
+        };
 
-        let version = if pr.version.unwrap() == 1 {
-            Version::HTTP_11
-        } else {
-            Version::HTTP_10
+        let version = match pr.version {
+            Some(0) => Version::HTTP_10,
+            Some(1) => Version::HTTP_11,
+            _ => return Err(RespHeadError::UnsupportedVersion),
         };
 
         let buf_start = buf.as_ref().as_ptr() as usize;
@@ -51,29 +112,39 @@ impl RespHead {
             headers.append(name, value);
         }
 
-        Ok(Some(Self {
-            status,
-            version,
-            headers,
-        }))
+        Ok(Some((
+            Self {
+                status,
+                version,
+                headers,
+                reason,
+            },
+            anomalies,
+        )))
     }
 
     pub(crate) fn write_to_buf(&self, buf: &mut BytesMut) -> Bytes {
         let mut n = 0;
         if self.version == Version::HTTP_11 {
             buf.extend_from_slice(b"HTTP/1.1");
-            n += 8;
+        } else if self.version == Version::HTTP_10 {
+            buf.extend_from_slice(b"HTTP/1.0");
         } else {
             unreachable!();
         }
+        n += 8;
         buf.extend_from_slice(b" ");
         n += 1;
         buf.extend_from_slice(self.status.as_str().as_bytes());
         n += self.status.as_str().len();
-        if let Some(reason) = self.status.canonical_reason() {
+        let reason = match self.reason {
+            Some(ref reason) => reason.as_ref(),
+            None => self.status.canonical_reason().unwrap_or("").as_bytes(),
+        };
+        if !reason.is_empty() {
             buf.extend_from_slice(b" ");
             n += 1;
-            buf.extend_from_slice(reason.as_bytes());
+            buf.extend_from_slice(reason);
             n += reason.len();
         }
         buf.extend_from_slice(b"\r\n");
@@ -93,15 +164,58 @@ impl RespHead {
         buf.split_to(n).freeze()
     }
 
+    // Whether the reason phrase can go on the wire as is. Tabs, spaces,
+    // visible ASCII and obs-text are allowed, but never CR, LF or other
+    // control bytes, which would let the phrase forge header lines.
+    pub(crate) fn has_valid_reason(&self) -> bool {
+        self.reason
+            .iter()
+            .flatten()
+            .all(|b| b == b'\t' || b == b' ' || b > 0x20 && b != 0x7f)
+    }
+
+    // What `write_to_buf` writes, to the byte.
+    pub(crate) fn written_len(&self) -> usize {
+        let reason = match self.reason {
+            Some(ref reason) => reason.len(),
+            None => self.status.canonical_reason().map_or(0, str::len),
+        };
+        "HTTP/1.1 000".len()
+            + if reason == 0 { 0 } else { reason + 1 }
+            + 2
+            + fields_len(&self.headers)
+            + 2
+    }
+
     pub(crate) fn can_keep_alive(&self) -> bool {
         can_keep_alive(self.version, &self.headers)
     }
 
-    fn framing_method(&self, method: &Method) -> FramingMethod {
-        if self.status == StatusCode::NO_CONTENT
-            || self.status == StatusCode::NOT_MODIFIED
+    // The codings in `Content-Encoding`, in the order they were applied,
+    // or `None` if any is unknown.
+    pub fn content_codings(&self) -> Option<Vec<ContentCoding>> {
+        content_codings(&self.headers)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        is_compressed(&self.headers)
+    }
+
+    pub fn class(&self) -> StatusClass {
+        StatusClass::of(self.status)
+    }
+
+    // `None` for a final response.
+    pub fn info_status(&self) -> Option<InfoStatus> {
+        InfoStatus::of(self.status)
+    }
+
+    pub(crate) fn framing_method(&self, method: &Method) -> FramingMethod {
+        let class = self.class();
+        if class == StatusClass::Informational
+            || matches!(self.status.as_u16(), 204 | 304)
             || method == Method::HEAD
-            || (method == Method::CONNECT && self.status.is_success())
+            || (method == Method::CONNECT && class == StatusClass::Success)
         {
             FramingMethod::ContentLength(0)
         } else if is_chunked(&self.headers) {
@@ -113,10 +227,119 @@ impl RespHead {
     }
 }
 
+// Classification goes by numeric range alone, so unregistered codes such
+// as 299 or 599 are framed like any other code in their class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatusClass {
+    Informational,
+    Success,
+    Redirection,
+    ClientError,
+    ServerError,
+}
+
+impl StatusClass {
+    #[must_use]
+    pub fn of(status: StatusCode) -> Self {
+        // `StatusCode` only holds 100 through 599.
+        match status.as_u16() {
+            100..=199 => Self::Informational,
+            200..=299 => Self::Success,
+            300..=399 => Self::Redirection,
+            400..=499 => Self::ClientError,
+            _ => Self::ServerError,
+        }
+    }
+}
+
+// The interim responses a client may need to act on, with any others
+// kept as their status code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InfoStatus {
+    Continue,
+    SwitchingProtocols,
+    Processing,
+    EarlyHints,
+    OtherInformational(StatusCode),
+}
+
+impl InfoStatus {
+    // `None` unless the status is informational.
+    #[must_use]
+    pub fn of(status: StatusCode) -> Option<Self> {
+        Some(match status.as_u16() {
+            100 => Self::Continue,
+            101 => Self::SwitchingProtocols,
+            102 => Self::Processing,
+            103 => Self::EarlyHints,
+            104..=199 => Self::OtherInformational(status),
+            _ => return None,
+        })
+    }
+
+    #[must_use]
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::Continue => StatusCode::CONTINUE,
+            Self::SwitchingProtocols => StatusCode::SWITCHING_PROTOCOLS,
+            Self::Processing => StatusCode::PROCESSING,
+            Self::EarlyHints => StatusCode::from_u16(103)
+                .unwrap_or_else(|_| unreachable!("valid status")),
+            Self::OtherInformational(status) => status,
+        }
+    }
+
+    // A client holding back its body for `Expect: 100-continue` can send
+    // it now.
+    #[must_use]
+    pub fn is_continue(self) -> bool {
+        self == Self::Continue
+    }
+
+    // The connection stops speaking HTTP/1.1 after this response.
+    #[must_use]
+    pub fn is_switching_protocols(self) -> bool {
+        self == Self::SwitchingProtocols
+    }
+
+    // Only advice about the final response still to come, which a client
+    // is free to ignore.
+    #[must_use]
+    pub fn is_hint(self) -> bool {
+        matches!(self, Self::Processing | Self::EarlyHints)
+    }
+}
+
+impl fmt::Display for RespHead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {}", self.version, self.status.as_str())?;
+        match self.reason {
+            Some(ref reason) if !reason.is_empty() => {
+                write!(f, " {}", String::from_utf8_lossy(reason))?;
+            }
+            Some(_) => {}
+            None => {
+                if let Some(reason) = self.status.canonical_reason() {
+                    write!(f, " {reason}")?;
+                }
+            }
+        }
+        write!(
+            f,
+            " ({} header{})",
+            self.headers.len(),
+            if self.headers.len() == 1 { "" } else { "s" },
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum RespHeadError {
     HttpParse(httparse::Error),
     InvalidStatusCode(http::status::InvalidStatusCode),
+    UnknownStatusClass(u16),
+    UnsupportedVersion,
+    TooLarge,
 }
 
 impl fmt::Display for RespHeadError {
@@ -128,6 +351,13 @@ impl fmt::Display for RespHeadError {
             Self::InvalidStatusCode(e) => {
                 write!(f, "An invalid status code was provided: {}", e)
             }
+            Self::UnknownStatusClass(code) => {
+                write!(f, "Status code {code} is outside the known classes")
+            }
+            Self::UnsupportedVersion => write!(f, "Unsupported HTTP version"),
+            Self::TooLarge => {
+                write!(f, "response head exceeds the maximum event size")
+            }
         }
     }
 }
@@ -137,13 +367,19 @@ impl std::error::Error for RespHeadError {
         match self {
             Self::HttpParse(e) => Some(e),
             Self::InvalidStatusCode(e) => Some(e),
+            Self::UnknownStatusClass(_)
+            | Self::UnsupportedVersion
+            | Self::TooLarge => None,
         }
     }
 }
 
 impl From<httparse::Error> for RespHeadError {
     fn from(e: httparse::Error) -> Self {
-        Self::HttpParse(e)
+        match e {
+            httparse::Error::Version => Self::UnsupportedVersion,
+            e => Self::HttpParse(e),
+        }
     }
 }
 
@@ -159,6 +395,135 @@ mod tests {
 
     use http::header::CONNECTION;
 
+    #[test]
+    fn parse_rejects_unsupported_versions() {
+        match RespHead::from_buf(&mut (&b"HTTP/1.7 200 OK\r\n\r\n"[..]).into())
+        {
+            Err(RespHeadError::UnsupportedVersion) => {}
+            r => panic!("expected unsupported version, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_extra_status_line_whitespace_when_allowed() {
+        let config = Config {
+            allow_extra_line_whitespace: true,
+            ..Config::default()
+        };
+        let (resp, anomalies) = RespHead::from_buf_with(
+            &mut (&b"HTTP/1.1 \t200  Not  Bad\r\n\r\n"[..]).into(),
+            &config,
+        )
+        .expect("parsed response")
+        .expect("complete response");
+        assert_eq!(StatusCode::OK, resp.status);
+        assert_eq!(vec![(Anomaly::ExtraLineWhitespace, 1)], anomalies);
+    }
+
+    #[test]
+    fn parse_icy_status_line_when_allowed() {
+        let resp_text = &b"ICY 200 OK\r\nicy-name: Radio\r\n\r\n"[..];
+        assert!(RespHead::from_buf(&mut resp_text.into()).is_err());
+
+        let config = Config {
+            allow_icy_status_line: true,
+            ..Config::default()
+        };
+        let (resp, anomalies) =
+            RespHead::from_buf_with(&mut resp_text.into(), &config)
+                .expect("parsed response")
+                .expect("complete response");
+        assert_eq!(Version::HTTP_10, resp.version);
+        assert_eq!(StatusCode::OK, resp.status);
+        assert_eq!("Radio", resp.headers["icy-name"]);
+        assert_eq!(vec![(Anomaly::IcyStatusLine, 1)], anomalies);
+        assert_eq!(FramingMethod::Http10, resp.framing_method(&Method::GET));
+    }
+
+    #[test]
+    fn non_standard_status_codes() {
+        let resp_text =
+            &b"HTTP/1.1 299 Vendor\r\ncontent-length: 3\r\n\r\n"[..];
+        let resp = RespHead::from_buf(&mut resp_text.into())
+            .expect("parsed response")
+            .expect("complete response");
+        assert_eq!(StatusClass::Success, resp.class());
+        assert_eq!(
+            FramingMethod::ContentLength(3),
+            resp.framing_method(&Method::GET)
+        );
+        let status = |code| StatusCode::from_u16(code).unwrap();
+        assert_eq!(StatusClass::ServerError, StatusClass::of(status(599)));
+        assert_eq!(StatusClass::Informational, StatusClass::of(status(199)));
+
+        match RespHead::from_buf(&mut (&b"HTTP/1.1 999 X\r\n\r\n"[..]).into())
+        {
+            Err(RespHeadError::UnknownStatusClass(999)) => {}
+            r => panic!("expected unknown status class, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn info_status() {
+        let status = |code| StatusCode::from_u16(code).unwrap();
+        for (code, info) in &[
+            (100, InfoStatus::Continue),
+            (101, InfoStatus::SwitchingProtocols),
+            (102, InfoStatus::Processing),
+            (103, InfoStatus::EarlyHints),
+            (199, InfoStatus::OtherInformational(status(199))),
+        ] {
+            assert_eq!(Some(*info), InfoStatus::of(status(*code)));
+            assert_eq!(status(*code), info.status());
+        }
+        assert_eq!(None, InfoStatus::of(StatusCode::OK));
+
+        assert!(InfoStatus::Continue.is_continue());
+        assert!(InfoStatus::SwitchingProtocols.is_switching_protocols());
+        assert!(InfoStatus::EarlyHints.is_hint());
+        assert!(!InfoStatus::OtherInformational(status(150)).is_hint());
+
+        let resp = RespHead::from_buf(
+            &mut (&b"HTTP/1.1 103 Early Hints\r\n\r\n"[..]).into(),
+        )
+        .expect("parsed response")
+        .expect("complete response");
+        assert_eq!(Some(InfoStatus::EarlyHints), resp.info_status());
+    }
+
+    #[test]
+    fn reason_validation() {
+        let resp = |reason: &'static [u8]| RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: Some(Bytes::from_static(reason)),
+        };
+        assert!(resp(b"All\tgood \xe2\x9c\x93").has_valid_reason());
+        assert!(resp(b"").has_valid_reason());
+        for bad in &[&b"OK\r\nx: y"[..], b"OK\n", b"O\0K", b"OK\x7f"] {
+            assert!(!resp(bad).has_valid_reason());
+        }
+    }
+
+    #[test]
+    fn reasons_round_trip() {
+        for resp_text in &[
+            &b"HTTP/1.1 200\r\n\r\n"[..],
+            &b"HTTP/1.1 200 Fine\r\n\r\n"[..],
+            &b"HTTP/1.1 200 OK\r\n\r\n"[..],
+            &b"HTTP/1.1 599\r\n\r\n"[..],
+        ] {
+            let resp = RespHead::from_buf(&mut (*resp_text).into())
+                .expect("parsed response")
+                .expect("complete response");
+            assert_eq!(
+                *resp_text,
+                &resp.write_to_buf(&mut BytesMut::new())[..]
+            );
+        }
+    }
+
     #[test]
     fn parse_simple_response() {
         let resp_text = &b"HTTP/1.1 200 OK \r\n\
@@ -170,6 +535,7 @@ mod tests {
                 headers: vec![(CONNECTION, HeaderValue::from_static("close"))]
                     .into_iter()
                     .collect(),
+                reason: None,
             },
             RespHead::from_buf(&mut resp_text.into())
                 .expect("parsed request")
@@ -185,6 +551,7 @@ mod tests {
                 status: StatusCode::OK,
                 version: Version::HTTP_11,
                 headers: HeaderMap::new(),
+                reason: None,
             },
             RespHead::from_buf(&mut resp_text.into())
                 .expect("parsed request")
@@ -207,6 +574,7 @@ mod tests {
                 )]
                 .into_iter()
                 .collect(),
+                reason: None,
             },
             RespHead::from_buf(&mut resp_text.into())
                 .expect("parsed request")
@@ -229,6 +597,7 @@ mod tests {
                 )]
                 .into_iter()
                 .collect(),
+                reason: None,
             },
             RespHead::from_buf(&mut resp_text.into())
                 .expect("parsed request")
@@ -251,10 +620,24 @@ mod tests {
                 )]
                 .into_iter()
                 .collect(),
+                reason: None,
             },
             RespHead::from_buf(&mut resp_text.into())
                 .expect("parsed request")
                 .expect("complete request")
         );
     }
+
+    #[test]
+    fn display() {
+        let resp = RespHead {
+            status: StatusCode::NOT_FOUND,
+            version: Version::HTTP_11,
+            headers: vec![(CONNECTION, HeaderValue::from_static("close"))]
+                .into_iter()
+                .collect(),
+            reason: None,
+        };
+        assert_eq!("HTTP/1.1 404 Not Found (1 header)", resp.to_string());
+    }
 }
@@ -0,0 +1,332 @@
+use std::str;
+
+use http::header::{
+    HeaderName, AUTHORIZATION, CACHE_CONTROL, CONTENT_ENCODING,
+    CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, EXPECT, HOST, MAX_FORWARDS,
+    PRAGMA, RANGE, SET_COOKIE, TE, TRAILER, TRANSFER_ENCODING,
+};
+use http::{HeaderMap, Version};
+
+use crate::headers::split_all;
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+
+pub trait Head {
+    fn version(&self) -> Version;
+    fn headers(&self) -> &HeaderMap;
+}
+
+impl Head for ReqHead {
+    fn version(&self) -> Version {
+        self.version
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+impl Head for RespHead {
+    fn version(&self) -> Version {
+        self.version
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Finding {
+    ContentLengthWithTransferEncoding,
+    DuplicateContentLength { conflicting: bool },
+    InvalidContentLength,
+    TransferEncodingOnHttp10,
+    ObsFold(HeaderName),
+    UnusualWhitespace(HeaderName),
+    UndeclaredTrailer(HeaderName),
+    ForbiddenTrailer(HeaderName),
+}
+
+// Folding and stray whitespace do not survive parsing, so those findings
+// come from `audit_raw_head` instead.
+pub fn audit_head<H: Head>(head: &H) -> Vec<Finding> {
+    let headers = head.headers();
+    let mut findings = Vec::new();
+
+    let has_te = headers.contains_key(TRANSFER_ENCODING);
+    if has_te && headers.contains_key(CONTENT_LENGTH) {
+        findings.push(Finding::ContentLengthWithTransferEncoding);
+    }
+    if has_te && head.version() < Version::HTTP_11 {
+        findings.push(Finding::TransferEncodingOnHttp10);
+    }
+
+    let mut lengths: Vec<u64> = Vec::new();
+    for value in headers.get_all(CONTENT_LENGTH) {
+        let parsed = str::from_utf8(value.as_bytes()).ok().and_then(|s| {
+            s.split(',')
+                .map(|tok| tok.trim().parse::<u64>().ok())
+                .collect::<Option<Vec<_>>>()
+        });
+        match parsed {
+            Some(ls) if !ls.is_empty() => lengths.extend(ls),
+            _ => findings.push(Finding::InvalidContentLength),
+        }
+    }
+    if lengths.len() > 1 {
+        findings.push(Finding::DuplicateContentLength {
+            conflicting: lengths.iter().any(|&l| l != lengths[0]),
+        });
+    }
+
+    findings
+}
+
+// Checks the field lines of a head as received, up to the empty line that
+// ends it, for folded lines and for whitespace before a field's colon or
+// after its value, which lenient parsing would let through or smooth over.
+pub fn audit_raw_head(head: &[u8]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut name = None;
+    for line in head.split(|&b| b == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let finding = if is_ws(line[0]) {
+            name.clone().map(Finding::ObsFold)
+        } else {
+            let colon = line.iter().position(|&b| b == b':');
+            let (raw_name, value) = match colon {
+                Some(colon) => (&line[..colon], &line[colon + 1..]),
+                None => (line, &[][..]),
+            };
+            let trimmed = raw_name
+                .iter()
+                .rposition(|&b| !is_ws(b))
+                .map_or(&[][..], |end| &raw_name[..=end]);
+            name = HeaderName::from_bytes(trimmed).ok();
+            if trimmed.len() < raw_name.len()
+                || value.last().is_some_and(|&b| is_ws(b))
+                || value.iter().any(|&b| b == b'\r' || b == 0x0b || b == 0x0c)
+            {
+                name.clone().map(Finding::UnusualWhitespace)
+            } else {
+                None
+            }
+        };
+        if let Some(finding) = finding {
+            if findings.last() != Some(&finding) {
+                findings.push(finding);
+            }
+        }
+    }
+    findings
+}
+
+// Fields that affect framing, routing, authentication or how the content
+// is interpreted, none of which a recipient can act on after the body.
+const FORBIDDEN_TRAILERS: &[HeaderName] = &[
+    AUTHORIZATION,
+    CACHE_CONTROL,
+    CONTENT_ENCODING,
+    CONTENT_LENGTH,
+    CONTENT_RANGE,
+    CONTENT_TYPE,
+    EXPECT,
+    HOST,
+    MAX_FORWARDS,
+    PRAGMA,
+    RANGE,
+    SET_COOKIE,
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+];
+
+// Checks trailer fields against the names `head` announced in its
+// `Trailer` header.
+pub fn audit_trailers<H: Head>(
+    head: &H,
+    trailers: &HeaderMap,
+) -> Vec<Finding> {
+    trailer_findings(&declared_trailers(head.headers()), trailers)
+}
+
+pub(crate) fn declared_trailers(headers: &HeaderMap) -> Vec<HeaderName> {
+    split_all(headers, TRAILER)
+        .into_iter()
+        .filter_map(|name| HeaderName::from_bytes(name).ok())
+        .collect()
+}
+
+pub(crate) fn trailer_findings(
+    declared: &[HeaderName],
+    trailers: &HeaderMap,
+) -> Vec<Finding> {
+    trailers
+        .keys()
+        .filter_map(|name| {
+            if FORBIDDEN_TRAILERS.contains(name) {
+                Some(Finding::ForbiddenTrailer(name.clone()))
+            } else if !declared.contains(name) {
+                Some(Finding::UndeclaredTrailer(name.clone()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn is_ws(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::{HeaderValue, HOST};
+    use http::{Method, StatusCode};
+
+    fn req(version: Version, headers: HeaderMap) -> ReqHead {
+        ReqHead {
+            method: Method::POST,
+            uri: "/".parse().unwrap(),
+            version,
+            headers,
+        }
+    }
+
+    #[test]
+    fn clean_request_has_no_findings() {
+        let head = req(
+            Version::HTTP_11,
+            vec![
+                (HOST, HeaderValue::from_static("example.com")),
+                (CONTENT_LENGTH, HeaderValue::from_static("10")),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert!(audit_head(&head).is_empty());
+    }
+
+    #[test]
+    fn content_length_and_transfer_encoding() {
+        let head = req(
+            Version::HTTP_11,
+            vec![
+                (CONTENT_LENGTH, HeaderValue::from_static("10")),
+                (TRANSFER_ENCODING, HeaderValue::from_static("chunked")),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(
+            vec![Finding::ContentLengthWithTransferEncoding],
+            audit_head(&head)
+        );
+    }
+
+    #[test]
+    fn transfer_encoding_on_http_10() {
+        let head = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_10,
+            headers: vec![(
+                TRANSFER_ENCODING,
+                HeaderValue::from_static("chunked"),
+            )]
+            .into_iter()
+            .collect(),
+            reason: None,
+        };
+        assert_eq!(vec![Finding::TransferEncodingOnHttp10], audit_head(&head));
+    }
+
+    #[test]
+    fn duplicate_content_length() {
+        let mut headers = HeaderMap::new();
+        headers.append(CONTENT_LENGTH, HeaderValue::from_static("10"));
+        headers.append(CONTENT_LENGTH, HeaderValue::from_static("10"));
+        assert_eq!(
+            vec![Finding::DuplicateContentLength { conflicting: false }],
+            audit_head(&req(Version::HTTP_11, headers))
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append(CONTENT_LENGTH, HeaderValue::from_static("10, 20"));
+        assert_eq!(
+            vec![Finding::DuplicateContentLength { conflicting: true }],
+            audit_head(&req(Version::HTTP_11, headers))
+        );
+    }
+
+    #[test]
+    fn invalid_content_length() {
+        let head = req(
+            Version::HTTP_11,
+            vec![(CONTENT_LENGTH, HeaderValue::from_static("-1"))]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(vec![Finding::InvalidContentLength], audit_head(&head));
+    }
+
+    #[test]
+    fn unusual_whitespace() {
+        assert_eq!(
+            vec![
+                Finding::UnusualWhitespace(HOST),
+                Finding::UnusualWhitespace(HeaderName::from_static("x-a")),
+                Finding::UnusualWhitespace(CONTENT_TYPE),
+            ],
+            audit_raw_head(
+                b"GET / HTTP/1.1\r\nhost : a\r\nx-a: b\t\r\n\
+                  content-type: a\x0bb\r\nx-b: c\r\n\r\nx-c : d\r\n"
+            )
+        );
+        assert!(
+            audit_raw_head(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n").is_empty()
+        );
+    }
+
+    #[test]
+    fn obs_fold() {
+        assert_eq!(
+            vec![
+                Finding::ObsFold(HeaderName::from_static("x-a")),
+                Finding::ObsFold(HOST)
+            ],
+            audit_raw_head(
+                b"GET / HTTP/1.1\r\nx-a: b\r\n c\r\n\td\r\n\
+                  host: a\r\n e\r\n\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn trailers() {
+        let head = req(
+            Version::HTTP_11,
+            vec![(TRAILER, HeaderValue::from_static("X-Checksum, Expires"))]
+                .into_iter()
+                .collect(),
+        );
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+        assert!(audit_trailers(&head, &trailers).is_empty());
+
+        trailers.insert("x-other", HeaderValue::from_static("1"));
+        trailers.insert(CONTENT_LENGTH, HeaderValue::from_static("1"));
+        assert_eq!(
+            vec![
+                Finding::UndeclaredTrailer(HeaderName::from_static("x-other")),
+                Finding::ForbiddenTrailer(CONTENT_LENGTH),
+            ],
+            audit_trailers(&head, &trailers)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::util::is_token;
+
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Longest method name that is still taken as a sign of HTTP/1.
+const MAX_METHOD_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sniffed {
+    Http1,
+    Http2Preface,
+    Tls,
+    Unknown,
+    NeedMoreData,
+}
+
+#[must_use]
+pub fn sniff(buf: &[u8]) -> Sniffed {
+    if buf.is_empty() {
+        return Sniffed::NeedMoreData;
+    }
+    if buf.starts_with(H2_PREFACE) {
+        return Sniffed::Http2Preface;
+    }
+    if H2_PREFACE.starts_with(buf) {
+        return Sniffed::NeedMoreData;
+    }
+    // A TLS record header: handshake content type, then a 3.x version.
+    if buf[0] == 0x16 {
+        return match buf.get(1) {
+            None => Sniffed::NeedMoreData,
+            Some(3) => Sniffed::Tls,
+            Some(_) => Sniffed::Unknown,
+        };
+    }
+    let method_len = buf.iter().take_while(|&&b| is_token(b)).count();
+    match buf.get(method_len) {
+        _ if method_len > MAX_METHOD_LEN => Sniffed::Unknown,
+        Some(b' ') if method_len > 0 => Sniffed::Http1,
+        None => Sniffed::NeedMoreData,
+        Some(_) => Sniffed::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http1() {
+        assert_eq!(Sniffed::Http1, sniff(b"GET / HTTP/1.1\r\n"));
+        assert_eq!(Sniffed::Http1, sniff(b"M-SEARCH * HTTP/1.1\r\n"));
+        assert_eq!(Sniffed::NeedMoreData, sniff(b"POS"));
+    }
+
+    #[test]
+    fn http2_preface() {
+        assert_eq!(Sniffed::Http2Preface, sniff(H2_PREFACE));
+        assert_eq!(Sniffed::NeedMoreData, sniff(b"PRI * HTTP/2"));
+        assert_eq!(Sniffed::Http1, sniff(b"PRI /x HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn tls() {
+        assert_eq!(Sniffed::Tls, sniff(b"\x16\x03\x01\x02\x00"));
+        assert_eq!(Sniffed::NeedMoreData, sniff(b"\x16"));
+        assert_eq!(Sniffed::Unknown, sniff(b"\x16\x01"));
+    }
+
+    #[test]
+    fn unknown() {
+        assert_eq!(Sniffed::NeedMoreData, sniff(b""));
+        assert_eq!(Sniffed::Unknown, sniff(b"\x00\x01"));
+        assert_eq!(Sniffed::Unknown, sniff(b" GET"));
+        assert_eq!(Sniffed::Unknown, sniff(&[b'A'; 40]));
+    }
+}
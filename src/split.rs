@@ -0,0 +1,213 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use http::HeaderMap;
+
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+// The two halves of a split connection share it behind a lock that is only
+// held for the duration of each call, so one task can receive events while
+// another sends.
+pub struct RecvHalf<R> {
+    conn: Arc<Mutex<HttpConn<R>>>,
+}
+
+pub struct SendHalf<R> {
+    conn: Arc<Mutex<HttpConn<R>>>,
+}
+
+// Returned by `reunite` along with the halves, which did not come from the
+// same connection.
+pub struct ReuniteError<R>(pub RecvHalf<R>, pub SendHalf<R>);
+
+impl<R> HttpConn<R> {
+    pub fn split(self) -> (RecvHalf<R>, SendHalf<R>) {
+        let conn = Arc::new(Mutex::new(self));
+        (
+            RecvHalf {
+                conn: Arc::clone(&conn),
+            },
+            SendHalf { conn },
+        )
+    }
+
+    /// # Errors
+    ///
+    /// Fails with both halves given back if they are not from the same
+    /// connection.
+    pub fn reunite(
+        recv: RecvHalf<R>,
+        send: SendHalf<R>,
+    ) -> Result<Self, ReuniteError<R>> {
+        if !Arc::ptr_eq(&recv.conn, &send.conn) {
+            return Err(ReuniteError(recv, send));
+        }
+        drop(send);
+        let conn = Arc::try_unwrap(recv.conn)
+            .unwrap_or_else(|_| unreachable!("both halves were given"));
+        Ok(conn.into_inner().unwrap_or_else(|e| {
+            let mut conn = e.into_inner();
+            conn.fail();
+            conn
+        }))
+    }
+}
+
+// A closure given to `with` may have panicked partway through changing the
+// connection, so after that every call fails with an error state instead
+// of going on with whatever it left behind.
+fn lock<R>(conn: &Mutex<HttpConn<R>>) -> MutexGuard<'_, HttpConn<R>> {
+    conn.lock().unwrap_or_else(|e| {
+        let mut conn = e.into_inner();
+        conn.fail();
+        conn
+    })
+}
+
+impl<R: Role> RecvHalf<R> {
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::feed` would.
+    pub fn feed(&self, bytes: &[u8]) -> Result<(), Error> {
+        lock(&self.conn).feed(bytes)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::next_event` would.
+    pub fn next_event(&self) -> Result<Option<Event>, Error> {
+        lock(&self.conn).next_event()
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::discard_remaining_body` would.
+    pub fn discard_remaining_body(&self) -> Result<Option<usize>, Error> {
+        lock(&self.conn).discard_remaining_body()
+    }
+
+    // Runs `f` with the whole connection locked.
+    pub fn with<T>(&self, f: impl FnOnce(&mut HttpConn<R>) -> T) -> T {
+        f(&mut lock(&self.conn))
+    }
+}
+
+impl<R: Role> SendHalf<R> {
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::send` would.
+    pub fn send(&self, event: Event) -> Result<Bytes, Error> {
+        lock(&self.conn).send(event)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::send_data` would.
+    pub fn send_data(&self, data: Bytes) -> Result<Bytes, Error> {
+        lock(&self.conn).send_data(data)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::send_end_of_message` would.
+    pub fn send_end_of_message(
+        &self,
+        headers: Option<HeaderMap>,
+    ) -> Result<Bytes, Error> {
+        lock(&self.conn).send_end_of_message(headers)
+    }
+
+    // Runs `f` with the whole connection locked.
+    pub fn with<T>(&self, f: impl FnOnce(&mut HttpConn<R>) -> T) -> T {
+        f(&mut lock(&self.conn))
+    }
+}
+
+impl<R> fmt::Debug for RecvHalf<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RecvHalf").finish_non_exhaustive()
+    }
+}
+
+impl<R> fmt::Debug for SendHalf<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendHalf").finish_non_exhaustive()
+    }
+}
+
+impl<R> fmt::Debug for ReuniteError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ReuniteError")
+            .field(&self.0)
+            .field(&self.1)
+            .finish()
+    }
+}
+
+impl<R> fmt::Display for ReuniteError<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "halves do not belong to the same connection")
+    }
+}
+
+impl<R> std::error::Error for ReuniteError<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::thread;
+
+    use http::StatusCode;
+
+    use crate::conn::Server;
+
+    #[test]
+    fn receive_and_send_concurrently() {
+        let (recv, send) = HttpConn::<Server>::new().split();
+        let (tx, rx) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            recv.feed(b"GET / HTTP/1.1\r\nhost: a\r\n\r\n").unwrap();
+            while let Some(event) = recv.next_event().unwrap() {
+                tx.send(event).unwrap();
+            }
+            recv
+        });
+        let writer = thread::spawn(move || {
+            assert!(matches!(rx.recv().unwrap(), Event::Request(_)));
+            let out = send.with(|conn| {
+                conn.respond(StatusCode::OK, HeaderMap::new(), "hi".into())
+            });
+            assert!(out.unwrap().ends_with(b"\r\n\r\nhi"));
+            send
+        });
+        let recv = reader.join().unwrap();
+        let send = writer.join().unwrap();
+
+        let (other, _) = HttpConn::<Server>::new().split();
+        let ReuniteError(_, send) =
+            HttpConn::reunite(other, send).unwrap_err();
+        let mut conn = HttpConn::reunite(recv, send).unwrap();
+        conn.start_next_cycle().expect("reuse connection");
+    }
+
+    #[test]
+    fn panic_in_with_fails_the_connection() {
+        let (recv, send) = HttpConn::<Server>::new().split();
+        let res = thread::spawn(move || {
+            send.with(|conn| {
+                conn.feed(b"GET / HTTP/1.1\r\n").unwrap();
+                panic!("handler failed");
+            });
+        })
+        .join();
+        assert!(res.is_err());
+        match recv.next_event() {
+            Err(Error::ClientErrorState) => {}
+            r => panic!("expected an error state, got {:?}", r),
+        }
+    }
+}
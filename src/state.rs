@@ -90,9 +90,9 @@ impl Server {
 pub(crate) struct State {
     client: Client,
     server: Server,
-    keep_alive: bool,
+    pub(crate) keep_alive: bool,
     pub(crate) pending_connect: bool,
-    pending_upgrade: bool,
+    pub(crate) pending_upgrade: bool,
 }
 
 impl State {
@@ -209,6 +209,14 @@ impl State {
         })
     }
 
+    // Starts a cycle whose request was already sent in full, pipelined
+    // behind the previous one.
+    pub fn start_pipelined_cycle(self) -> StateResult<Self> {
+        self.start_next_cycle()?
+            .client_event(StateEvent::Request)?
+            .client_event(StateEvent::EndOfMessage)
+    }
+
     fn state_transitions(mut self) -> Self {
         loop {
             let start_states = self.states();
@@ -711,4 +719,63 @@ mod tests {
 
         assert!(cs.start_next_cycle().is_err());
     }
+
+    fn apply(cs: State, op: u8) -> State {
+        let event = match op % 6 {
+            0 => Request,
+            1 => InfoResponse,
+            2 => Response,
+            3 => Data,
+            4 => EndOfMessage,
+            _ => ConnectionClosed,
+        };
+        let result = match op / 6 {
+            0 => cs.client_event(event),
+            1 => cs.server_event(event, None),
+            2 => cs.server_event(event, Some(Connect)),
+            3 => cs.server_event(event, Some(Upgrade)),
+            4 => Ok(cs.connect_proposal()),
+            5 => Ok(cs.upgrade_proposal()),
+            6 => Ok(cs.disable_keep_alive()),
+            7 => cs.start_next_cycle(),
+            8 => Ok(cs.client_error()),
+            _ => Ok(cs.server_error()),
+        };
+        result.unwrap_or(cs)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn states_stay_consistent(
+            ops in proptest::collection::vec(0u8..60, 0..64),
+        ) {
+            let mut cs = State::new();
+            for op in ops {
+                cs = apply(cs, op);
+                let (client, server) = cs.states();
+                if !cs.keep_alive {
+                    proptest::prop_assert_ne!(Client::Done, client);
+                    proptest::prop_assert_ne!(Server::Done, server);
+                }
+                if client == Client::MightSwitchProtocol {
+                    proptest::prop_assert!(cs.any_pending());
+                }
+                // The server can still be put into the error state after
+                // the switch; it never leaves it any other way.
+                if client == Client::SwitchedProtocol {
+                    proptest::prop_assert!(matches!(
+                        server,
+                        Server::SwitchedProtocol | Server::Error
+                    ));
+                }
+                if client == Client::Done && server == Server::Done {
+                    let next = cs.start_next_cycle().unwrap();
+                    proptest::prop_assert_eq!(
+                        (Client::Idle, Server::Idle),
+                        next.states()
+                    );
+                }
+            }
+        }
+    }
 }
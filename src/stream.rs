@@ -0,0 +1,339 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::Stream;
+use http::HeaderMap;
+
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+const READ_SIZE: usize = 8 * 1024;
+
+// The body of the message whose head was just received, read from `io` as
+// needed. The stream ends at the end of the message, after which any
+// trailers can be taken. It also ends, with no trailers, if the connection
+// produces an event other than body data, such as the head of the next
+// message or `ConnectionClosed`; that event is kept for `take_event`.
+#[derive(Debug)]
+pub struct BodyStream<'a, R, T> {
+    conn: &'a mut HttpConn<R>,
+    io: &'a mut T,
+    buf: Vec<u8>,
+    trailers: Option<HeaderMap>,
+    event: Option<Event>,
+    eof: bool,
+    done: bool,
+}
+
+impl<'a, R: Role, T: AsyncRead + Unpin> BodyStream<'a, R, T> {
+    pub fn new(conn: &'a mut HttpConn<R>, io: &'a mut T) -> Self {
+        Self {
+            conn,
+            io,
+            buf: vec![0; READ_SIZE],
+            trailers: None,
+            event: None,
+            eof: false,
+            done: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    pub fn into_trailers(self) -> Option<HeaderMap> {
+        self.trailers
+    }
+
+    // The event that ended the stream, if it was not part of the body.
+    pub fn take_event(&mut self) -> Option<Event> {
+        self.event.take()
+    }
+}
+
+impl<R: Role, T: AsyncRead + Unpin> Stream for BodyStream<'_, R, T> {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.done {
+            match this.conn.next_event() {
+                Ok(Some(Event::Data(data))) => {
+                    return Poll::Ready(Some(Ok(data)))
+                }
+                Ok(Some(Event::Trailers(trailers))) => {
+                    this.trailers = Some(trailers);
+                }
+                Ok(Some(Event::EndOfMessage(trailers))) => {
+                    this.trailers = trailers.or_else(|| this.trailers.take());
+                    this.done = true;
+                }
+                Ok(Some(event)) => {
+                    this.event = Some(event);
+                    this.done = true;
+                }
+                Ok(None) if this.eof => this.done = true,
+                Ok(None) => {
+                    let n = match Pin::new(&mut *this.io)
+                        .poll_read(cx, &mut this.buf)
+                    {
+                        Poll::Ready(Ok(n)) => n,
+                        Poll::Ready(Err(e)) => {
+                            this.done = true;
+                            return Poll::Ready(Some(Err(e.into())));
+                        }
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    this.eof = n == 0;
+                    if let Err(e) = this.conn.feed(&this.buf[..n]) {
+                        this.done = true;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+// Sends everything written to it as body data of the outgoing message,
+// framed to suit the head that was sent, and writes the framed bytes to
+// `io`. Closing the sink ends the message, with any trailers given to
+// `with_trailers`, and flushes `io` but leaves it open for the next
+// message.
+#[derive(Debug)]
+pub struct BodySink<'a, R, T> {
+    conn: &'a mut HttpConn<R>,
+    io: &'a mut T,
+    pending: Bytes,
+    trailers: Option<HeaderMap>,
+    ended: bool,
+}
+
+impl<'a, R: Role, T: AsyncWrite + Unpin> BodySink<'a, R, T> {
+    pub fn new(conn: &'a mut HttpConn<R>, io: &'a mut T) -> Self {
+        Self {
+            conn,
+            io,
+            pending: Bytes::new(),
+            trailers: None,
+            ended: false,
+        }
+    }
+
+    #[must_use]
+    pub fn with_trailers(mut self, trailers: HeaderMap) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            match Pin::new(&mut *self.io).poll_write(cx, &self.pending) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()))
+                }
+                Poll::Ready(Ok(n)) => self.pending.advance(n),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: Role, T: AsyncWrite + Unpin> AsyncWrite for BodySink<'_, R, T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.ended {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other.map_ok(|()| 0),
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        this.pending = this
+            .conn
+            .send_data(Bytes::from(buf))
+            .map_err(into_io_error)?;
+        // The data now belongs to the message; getting it onto the wire
+        // can wait for the next write or flush.
+        let _ = this.poll_pending(cx);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut *this.io).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if !this.ended {
+            match this.poll_pending(cx) {
+                Poll::Ready(Ok(())) => {}
+                other => return other,
+            }
+            let trailers = this.trailers.take();
+            this.pending = this
+                .conn
+                .send_end_of_message(trailers)
+                .map_err(into_io_error)?;
+            this.ended = true;
+        }
+        self.poll_flush(cx)
+    }
+}
+
+fn into_io_error(e: Error) -> io::Error {
+    match e {
+        Error::IO(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+    use futures::io::AsyncWriteExt;
+    use futures::io::Cursor;
+    use futures::stream::TryStreamExt;
+    use http::header::CONTENT_LENGTH;
+
+    use http::{HeaderValue, StatusCode, Version};
+
+    use crate::conn::Server;
+    use crate::resp::RespHead;
+
+    fn server_after_head(io: &mut Cursor<&[u8]>) -> HttpConn<Server> {
+        let mut conn = HttpConn::<Server>::new();
+        let mut stream = BodyStream::new(&mut conn, io);
+        // The head is not body data, so it ends the stream and is handed
+        // back.
+        assert_eq!(None, block_on(stream.try_next()).unwrap());
+        assert!(stream.trailers().is_none());
+        match stream.take_event() {
+            Some(Event::Request(req)) => assert_eq!("/", req.uri),
+            e => panic!("expected request, got {:?}", e),
+        }
+        conn
+    }
+
+    #[test]
+    fn chunked_body_with_trailers() {
+        let mut io = Cursor::new(
+            &b"POST / HTTP/1.1\r\nhost: example.com\r\n\
+               transfer-encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n6\r\n world\r\n0\r\nx-sum: 1\r\n\r\n"[..],
+        );
+        let mut conn = server_after_head(&mut io);
+        let mut stream = BodyStream::new(&mut conn, &mut io);
+        let body: Vec<Bytes> =
+            block_on((&mut stream).try_collect()).expect("body read");
+        assert_eq!(vec![&b"hello"[..], &b" world"[..]], body);
+        assert!(stream.is_done());
+        assert_eq!(None, stream.take_event());
+        assert_eq!("1", stream.into_trailers().unwrap()["x-sum"]);
+    }
+
+    #[test]
+    fn truncated_body_is_an_error() {
+        let mut io = Cursor::new(
+            &b"POST / HTTP/1.1\r\nhost: example.com\r\n\
+               content-length: 10\r\n\r\nhello"[..],
+        );
+        let mut conn = server_after_head(&mut io);
+        let mut stream = BodyStream::new(&mut conn, &mut io);
+        assert_eq!(
+            Bytes::from(&b"hello"[..]),
+            block_on(stream.try_next()).unwrap().unwrap()
+        );
+        assert!(block_on(stream.try_next()).is_err());
+        assert_eq!(None, block_on(stream.try_next()).unwrap());
+    }
+
+    fn server_ready_to_respond() -> HttpConn<Server> {
+        let mut conn = HttpConn::<Server>::new();
+        conn.feed(b"GET / HTTP/1.1\r\nhost: example.com\r\n\r\n")
+            .expect("feed request");
+        while conn.next_event().expect("request read").is_some() {}
+        conn
+    }
+
+    fn resp(headers: HeaderMap) -> RespHead {
+        RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn sink_chunks_body_and_ends_message() {
+        let mut conn = server_ready_to_respond();
+        let mut io = Cursor::new(Vec::new());
+        let head = conn.send_resp(resp(HeaderMap::new())).unwrap();
+        io.get_mut().extend_from_slice(&head);
+        io.set_position(head.len() as u64);
+        block_on(async {
+            let mut sink = BodySink::new(&mut conn, &mut io);
+            sink.write_all(b"hello").await.unwrap();
+            sink.write_all(b" world").await.unwrap();
+            sink.close().await.unwrap();
+            assert!(sink.write_all(b"!").await.is_err());
+        });
+        assert!(io.into_inner().ends_with(
+            b"transfer-encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn sink_enforces_content_length() {
+        let mut conn = server_ready_to_respond();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("3"));
+        conn.send_resp(resp(headers)).unwrap();
+        let mut io = Cursor::new(Vec::new());
+        block_on(async {
+            let mut sink = BodySink::new(&mut conn, &mut io);
+            sink.write_all(b"ab").await.unwrap();
+            assert!(sink.close().await.is_err());
+        });
+        assert_eq!(b"ab", &io.into_inner()[..]);
+    }
+}
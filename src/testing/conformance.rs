@@ -0,0 +1,703 @@
+// Cases ported from the Python h11 test suite (test_io.py and
+// test_connection.py), from the server's point of view. Cases where this
+// crate deliberately or knowingly differs from h11 are kept but ignored,
+// with the difference named in the ignore reason.
+
+use std::time::SystemTime;
+
+use bytes::{Bytes, BytesMut};
+use http::header::{HeaderName, HeaderValue};
+use http::{HeaderMap, Method, StatusCode, Version};
+
+use crate::config::Config;
+use crate::conn::{Error, HttpConn, Server};
+use crate::event::Event;
+use crate::req::ReqHeadError;
+use crate::resp::RespHead;
+use crate::testing::transcript::Transcript;
+use crate::testing::{parse_req_head, parse_resp_head};
+
+fn hdrs(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    pairs
+        .iter()
+        .map(|&(name, value)| {
+            (
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            )
+        })
+        .collect()
+}
+
+fn resp(status: u16, headers: &[(&'static str, &'static str)]) -> RespHead {
+    RespHead {
+        status: StatusCode::from_u16(status).unwrap(),
+        version: Version::HTTP_11,
+        headers: hdrs(headers),
+        reason: None,
+    }
+}
+
+fn server(bytes: &[u8]) -> HttpConn<Server> {
+    let mut conn = HttpConn::<Server>::new();
+    let mut bytes = bytes;
+    while !bytes.is_empty() {
+        conn.read_from(&mut bytes).unwrap();
+    }
+    conn
+}
+
+// Drains every event currently available, merging adjacent data events
+// so that the result does not depend on how the reader splits the body.
+fn events(conn: &mut HttpConn<Server>) -> Result<Vec<Event>, Error> {
+    let mut out = Vec::new();
+    while let Some(event) = conn.next_event()? {
+        match (out.last_mut(), event) {
+            (Some(Event::Data(prev)), Event::Data(data)) => {
+                let mut joined = BytesMut::from(&prev[..]);
+                joined.extend_from_slice(&data);
+                *prev = joined.freeze();
+            }
+            (_, event) => out.push(event),
+        }
+    }
+    Ok(out)
+}
+
+fn req_head_ok(bytes: &[u8]) -> bool {
+    matches!(parse_req_head(&mut BytesMut::from(bytes)), Ok(Some(_)))
+}
+
+fn resp_head_ok(bytes: &[u8]) -> bool {
+    matches!(parse_resp_head(&mut BytesMut::from(bytes)), Ok(Some(_)))
+}
+
+mod lowlevel {
+    use super::*;
+
+    #[test]
+    fn simple_request() {
+        let req = parse_req_head(&mut BytesMut::from(
+            &b"GET /a HTTP/1.1\r\nHost: foo\r\nConnection: close\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(Method::GET, req.method);
+        assert_eq!("/a", req.uri);
+        assert_eq!(Version::HTTP_11, req.version);
+        assert_eq!(
+            hdrs(&[("host", "foo"), ("connection", "close")]),
+            req.headers
+        );
+    }
+
+    #[test]
+    fn simple_responses() {
+        let resp = parse_resp_head(&mut BytesMut::from(
+            &b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(StatusCode::OK, resp.status);
+        assert_eq!(hdrs(&[("connection", "close")]), resp.headers);
+
+        let resp = parse_resp_head(&mut BytesMut::from(
+            &b"HTTP/1.1 101 Upgrade\r\nUpgrade: websocket\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(StatusCode::SWITCHING_PROTOCOLS, resp.status);
+    }
+
+    #[test]
+    fn http10_messages() {
+        let req = parse_req_head(&mut BytesMut::from(
+            &b"HEAD /foo HTTP/1.0\r\nSome: header\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(Method::HEAD, req.method);
+        assert_eq!(Version::HTTP_10, req.version);
+        assert_eq!(hdrs(&[("some", "header")]), req.headers);
+
+        let resp = parse_resp_head(&mut BytesMut::from(
+            &b"HTTP/1.0 200 OK\r\nSome: header\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(Version::HTTP_10, resp.version);
+    }
+
+    #[test]
+    fn reasonless_status_line() {
+        assert!(resp_head_ok(b"HTTP/1.1 200\r\nSome: header\r\n\r\n"));
+    }
+
+    #[test]
+    fn header_value_whitespace_is_stripped() {
+        let req = parse_req_head(&mut BytesMut::from(
+            &b"GET / HTTP/1.1\r\nfoo: \t \t bar \t \t \r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(hdrs(&[("foo", "bar")]), req.headers);
+    }
+
+    #[test]
+    fn empty_header_value() {
+        let req = parse_req_head(&mut BytesMut::from(
+            &b"GET / HTTP/1.1\r\nfoo:\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(hdrs(&[("foo", "")]), req.headers);
+    }
+
+    #[test]
+    fn rejects_whitespace_before_colon() {
+        assert!(!req_head_ok(b"GET /foo HTTP/1.1\r\nfoo : line\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_header_without_colon() {
+        assert!(!req_head_ok(b"GET /foo HTTP/1.1\r\nfoo\r\n\r\n"));
+        assert!(!resp_head_ok(b"HTTP/1.1 200 OK\r\nfoo\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_leading_whitespace_on_first_header() {
+        assert!(!req_head_ok(b"GET /foo HTTP/1.1\r\n foo: line\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_bad_versions() {
+        assert!(!req_head_ok(b"HEAD /foo HTTP/1.x\r\n\r\n"));
+        assert!(!req_head_ok(b"HEAD /foo HTTP/11\r\n\r\n"));
+        assert!(!resp_head_ok(b"HTTP/1.x 200 OK\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_bad_status_codes() {
+        assert!(!resp_head_ok(b"HTTP/1.1 2000 OK\r\n\r\n"));
+        assert!(!resp_head_ok(b"HTTP/1.1 20 OK\r\n\r\n"));
+        assert!(!resp_head_ok(b"HTTP/1.1 abc OK\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_missing_target() {
+        assert!(!req_head_ok(b"GET HTTP/1.1\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_nul_in_header_value() {
+        assert!(!req_head_ok(b"GET / HTTP/1.1\r\nfoo: a\x00b\r\n\r\n"));
+    }
+
+    #[test]
+    #[ignore = "obsolete line folding is rejected rather than unfolded"]
+    fn obsolete_line_folding() {
+        let req = parse_req_head(&mut BytesMut::from(
+            &b"HEAD /foo HTTP/1.1\r\nHost: example.com\r\n\
+               Some: multi-line\r\n header\r\n\tnonsense\r\n\
+               \x20   \t   \t\tlines\r\n\r\n"[..],
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            "multi-line header nonsense lines",
+            req.headers["some"].to_str().unwrap()
+        );
+    }
+
+    #[test]
+    #[ignore = "leading blank lines before a request line are rejected"]
+    fn leading_blank_lines() {
+        assert!(req_head_ok(b"\r\n\r\nGET / HTTP/1.1\r\n\r\n"));
+    }
+}
+
+mod body {
+    use super::*;
+
+    fn body_events(head: &[u8], body: &[u8]) -> Result<Vec<Event>, Error> {
+        let mut conn = server(head);
+        let mut out = events(&mut conn)?;
+        if !body.is_empty() {
+            conn.read_from(&mut &body[..]).unwrap();
+        }
+        out.extend(events(&mut conn)?);
+        Ok(out.split_off(1))
+    }
+
+    fn chunked(body: &[u8]) -> Result<Vec<Event>, Error> {
+        body_events(
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n",
+            body,
+        )
+    }
+
+    #[test]
+    fn content_length() {
+        assert_eq!(
+            vec![Event::data(&b"0123456789"[..]), Event::EndOfMessage(None)],
+            body_events(
+                b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n",
+                b"0123456789",
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn zero_content_length() {
+        assert_eq!(
+            vec![Event::EndOfMessage(None)],
+            body_events(b"POST / HTTP/1.1\r\ncontent-length: 0\r\n\r\n", b"")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn content_length_stops_at_message_end() {
+        let events = body_events(
+            b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\n",
+            b"01234GET / HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![Event::data(&b"01234"[..]), Event::EndOfMessage(None)],
+            events
+        );
+    }
+
+    #[test]
+    fn chunked_basic() {
+        assert_eq!(
+            vec![
+                Event::data(&b"012340123456789abcdef"[..]),
+                Event::EndOfMessage(None),
+            ],
+            chunked(b"5\r\n01234\r\n10\r\n0123456789abcdef\r\n0\r\n\r\n")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn chunked_extensions() {
+        assert_eq!(
+            vec![Event::data(&b"01234"[..]), Event::EndOfMessage(None)],
+            chunked(b"5; hello=there\r\n01234\r\n0\r\n\r\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn chunked_uppercase_hex() {
+        assert_eq!(
+            vec![
+                Event::data(&b"0123456789ABCDEF"[..]),
+                Event::EndOfMessage(None),
+            ],
+            chunked(b"A\r\n0123456789\r\n6\r\nABCDEF\r\n0\r\n\r\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn chunked_trailers() {
+        assert_eq!(
+            vec![
+                Event::data(&b"01234"[..]),
+                Event::EndOfMessage(Some(hdrs(&[("some", "header")]))),
+            ],
+            chunked(b"5\r\n01234\r\n0\r\nSome: header\r\n\r\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn chunked_rejects_bad_size() {
+        assert!(chunked(b"x\r\n").is_err());
+        assert!(chunked(b"-1\r\n").is_err());
+    }
+
+    #[test]
+    fn chunked_rejects_missing_crlf_after_data() {
+        assert!(chunked(b"5\r\n01234XX0\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn chunked_whitespace_after_size() {
+        assert_eq!(
+            vec![Event::data(&b"01234"[..]), Event::EndOfMessage(None)],
+            chunked(b"5      \r\n01234\r\n0\r\n\r\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn eof_in_content_length_body() {
+        let mut conn =
+            server(b"POST / HTTP/1.1\r\ncontent-length: 10\r\n\r\n01234");
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        conn.read_from(&mut &b""[..]).unwrap();
+        assert!(conn.next_event().is_err());
+    }
+
+    #[test]
+    fn eof_in_chunked_body() {
+        let mut conn = server(
+            b"POST / HTTP/1.1\r\ntransfer-encoding: chunked\r\n\r\n5\r\n012",
+        );
+        events(&mut conn).unwrap();
+        conn.read_from(&mut &b""[..]).unwrap();
+        assert!(conn.next_event().is_err());
+    }
+}
+
+mod connection {
+    use super::*;
+
+    #[test]
+    fn basics_and_content_length() {
+        let mut conn = HttpConn::<Server>::new();
+        Transcript::new()
+            .recv(
+                &b"GET / HTTP/1.1\r\nHost: example.com\r\n\
+                   Content-Length: 10\r\n\r\n"[..],
+            )
+            .expect(Event::request(
+                Method::GET,
+                "/".parse().unwrap(),
+                hdrs(&[("host", "example.com"), ("content-length", "10")]),
+            ))
+            .expect_need_data()
+            .recv(&b"12345"[..])
+            .expect(Event::data(&b"12345"[..]))
+            .recv(&b"67890"[..])
+            .expect(Event::data(&b"67890"[..]))
+            .expect(Event::EndOfMessage(None))
+            .expect_need_data()
+            .send(
+                Event::Response(resp(200, &[("content-length", "11")])),
+                &b"HTTP/1.1 200 OK\r\ncontent-length: 11\r\n\r\n"[..],
+            )
+            .send(Event::data(&b"hello world"[..]), &b"hello world"[..])
+            .send(Event::EndOfMessage(None), Bytes::new())
+            .run_server(&mut conn);
+        conn.start_next_cycle().unwrap();
+    }
+
+    #[test]
+    fn chunked_request() {
+        let mut conn = HttpConn::<Server>::new();
+        Transcript::new()
+            .recv(
+                &b"POST / HTTP/1.1\r\nHost: example.com\r\n\
+                   Transfer-Encoding: chunked\r\n\r\n"[..],
+            )
+            .expect(Event::request(
+                Method::POST,
+                "/".parse().unwrap(),
+                hdrs(&[
+                    ("host", "example.com"),
+                    ("transfer-encoding", "chunked"),
+                ]),
+            ))
+            .recv(&b"5\r\n12345\r\n"[..])
+            .expect(Event::data(&b"12345"[..]))
+            .recv(&b"0\r\n\r\n"[..])
+            .expect(Event::EndOfMessage(None))
+            .run_server(&mut conn);
+    }
+
+    #[test]
+    fn chunked_response() {
+        let mut conn = server(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        events(&mut conn).unwrap();
+        Transcript::new()
+            .send(
+                Event::Response(resp(
+                    200,
+                    &[("transfer-encoding", "chunked")],
+                )),
+                &b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n"[..],
+            )
+            .send(Event::data(&b"1234567890"[..]), &b"a\r\n1234567890\r\n"[..])
+            .send(Event::EndOfMessage(None), &b"0\r\n\r\n"[..])
+            .run_server(&mut conn);
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        let mut conn = server(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n",
+        );
+        let mut out = Vec::new();
+        while let Some(event) = conn.next_event().unwrap() {
+            out.push(event);
+        }
+        assert_eq!(Event::data(&b"hello"[..]), out[1]);
+        conn.read_from(&mut &b"5\r\nhel"[..]).unwrap();
+        assert_eq!(Some(Event::data(&b"hel"[..])), conn.next_event().unwrap());
+        conn.read_from(&mut &b"l"[..]).unwrap();
+        assert_eq!(Some(Event::data(&b"l"[..])), conn.next_event().unwrap());
+        conn.read_from(&mut &b"o\r\n0\r\n\r\n"[..]).unwrap();
+        assert_eq!(Some(Event::data(&b"o"[..])), conn.next_event().unwrap());
+        assert_eq!(
+            Some(Event::EndOfMessage(None)),
+            conn.next_event().unwrap()
+        );
+    }
+
+    #[test]
+    fn pipelining() {
+        let mut conn = server(
+            b"GET /1 HTTP/1.1\r\nHost: a.com\r\nContent-Length: 5\r\n\r\n\
+              12345\
+              GET /2 HTTP/1.1\r\nHost: a.com\r\nContent-Length: 5\r\n\r\n\
+              67890\
+              GET /3 HTTP/1.1\r\nHost: a.com\r\n\r\n",
+        );
+        for (path, body) in &[("/1", Some("12345")), ("/2", Some("67890"))] {
+            let events = events(&mut conn).unwrap();
+            assert_eq!(3, events.len());
+            assert_eq!(*path, events[0].as_request().unwrap().uri);
+            assert_eq!(
+                body.map(|b| Bytes::from(b.as_bytes())),
+                events[1].as_data().cloned()
+            );
+            // Nothing more is produced until this cycle is finished.
+            assert_eq!(None, conn.next_event().unwrap());
+            conn.send_resp(resp(200, &[("content-length", "0")]))
+                .unwrap();
+            conn.send_end_of_message(None).unwrap();
+            conn.start_next_cycle().unwrap();
+        }
+        let events = events(&mut conn).unwrap();
+        assert_eq!("/3", events[0].as_request().unwrap().uri);
+        assert_eq!(Event::EndOfMessage(None), events[1]);
+    }
+
+    #[test]
+    fn reuse_simple() {
+        let mut conn =
+            server(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        assert!(conn.start_next_cycle().is_err());
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        conn.start_next_cycle().unwrap();
+        assert_eq!(2, events(&mut conn).unwrap().len());
+    }
+
+    #[test]
+    fn client_connection_close() {
+        let mut conn = server(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        assert!(conn.start_next_cycle().is_err());
+        conn.send_connection_closed().unwrap();
+    }
+
+    #[test]
+    fn server_connection_close() {
+        let mut conn = server(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        conn.send_resp(resp(
+            200,
+            &[("content-length", "0"), ("connection", "close")],
+        ))
+        .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        assert!(conn.start_next_cycle().is_err());
+    }
+
+    #[test]
+    fn http10_client_is_not_kept_alive() {
+        let mut conn = server(b"GET / HTTP/1.0\r\n\r\n");
+        let events = events(&mut conn).unwrap();
+        assert_eq!(Version::HTTP_10, events[0].as_request().unwrap().version);
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        assert!(conn.start_next_cycle().is_err());
+    }
+
+    #[test]
+    fn http10_keep_alive_is_not_honoured() {
+        let mut conn =
+            server(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        events(&mut conn).unwrap();
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        assert!(conn.start_next_cycle().is_err());
+    }
+
+    #[test]
+    fn connect_switches_protocol() {
+        let mut conn = server(
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: foo\r\n\
+              Content-Length: 1\r\n\r\n1tunnel",
+        );
+        assert_eq!(3, events(&mut conn).unwrap().len());
+        conn.send_resp(resp(200, &[])).unwrap();
+        assert_eq!(None, conn.next_event().unwrap());
+        let (in_buf, _) = conn.into_bufs();
+        assert_eq!(&b"tunnel"[..], &in_buf[..]);
+    }
+
+    #[test]
+    fn connect_denied() {
+        let mut conn = server(
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: foo\r\n\r\n\
+              GET / HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        conn.send_resp(resp(404, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        conn.start_next_cycle().unwrap();
+        assert_eq!(2, events(&mut conn).unwrap().len());
+    }
+
+    #[test]
+    fn upgrade_switches_protocol() {
+        let mut conn = server(
+            b"GET / HTTP/1.1\r\nHost: foo\r\nUpgrade: websocket\r\n\
+              Connection: upgrade\r\n\r\nframes",
+        );
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        conn.send_info_resp(resp(101, &[("upgrade", "websocket")]))
+            .unwrap();
+        assert_eq!(None, conn.next_event().unwrap());
+        let (in_buf, _) = conn.into_bufs();
+        assert_eq!(&b"frames"[..], &in_buf[..]);
+    }
+
+    #[test]
+    fn upgrade_denied() {
+        let mut conn = server(
+            b"GET / HTTP/1.1\r\nHost: foo\r\nUpgrade: websocket\r\n\r\n",
+        );
+        assert_eq!(2, events(&mut conn).unwrap().len());
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        conn.start_next_cycle().unwrap();
+    }
+
+    #[test]
+    fn switch_without_proposal_is_rejected() {
+        let mut conn = server(b"GET / HTTP/1.1\r\nHost: foo\r\n\r\n");
+        events(&mut conn).unwrap();
+        assert!(conn
+            .send_info_resp(resp(101, &[("upgrade", "websocket")]))
+            .is_err());
+    }
+
+    #[test]
+    fn informational_responses() {
+        let mut conn = server(
+            b"GET / HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\n\r\n",
+        );
+        events(&mut conn).unwrap();
+        assert_eq!(
+            &b"HTTP/1.1 100 Continue\r\n\r\n"[..],
+            &conn.send_info_resp(resp(100, &[])).unwrap()[..]
+        );
+        conn.send_info_resp(resp(103, &[])).unwrap();
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        assert!(conn.send_info_resp(resp(100, &[])).is_err());
+    }
+
+    #[test]
+    fn response_after_end_of_message_is_rejected() {
+        let mut conn = server(b"GET / HTTP/1.1\r\n\r\n");
+        events(&mut conn).unwrap();
+        conn.send_resp(resp(200, &[("content-length", "0")]))
+            .unwrap();
+        conn.send_end_of_message(None).unwrap();
+        assert!(conn
+            .send_resp(resp(200, &[("content-length", "0")]))
+            .is_err());
+    }
+
+    #[test]
+    fn errors_are_sticky() {
+        let mut conn = server(b"gibberish\r\n\r\n");
+        assert!(conn.next_event().is_err());
+        assert!(conn.next_event().is_err());
+        // An error response can still be sent to a client that broke
+        // the protocol.
+        conn.send_error_response(StatusCode::BAD_REQUEST, SystemTime::now())
+            .unwrap();
+    }
+
+    #[test]
+    fn early_detection_of_bad_method() {
+        let mut conn = server(b"\x00 / HTTP/1.1\r\n\r\n");
+        assert!(conn.next_event().is_err());
+    }
+
+    #[test]
+    fn data_after_close_is_rejected() {
+        let mut conn = server(b"GET / HTTP/1.1\r\n\r\n");
+        conn.read_from(&mut &b""[..]).unwrap();
+        assert!(conn.read_from(&mut &b"more"[..]).is_err());
+    }
+
+    #[test]
+    fn idle_receive_eof() {
+        let mut conn = HttpConn::<Server>::new();
+        conn.read_from(&mut &b""[..]).unwrap();
+        assert_eq!(Some(Event::ConnectionClosed), conn.next_event().unwrap());
+    }
+
+    #[test]
+    fn max_incomplete_event_size() {
+        let mut conn = HttpConn::<Server>::new();
+        let mut line = Vec::from(&b"GET / HTTP/1.1\r\n"[..]);
+        line.resize(line.len() + 32 * 1024, b'a');
+        conn.read_from(&mut &line[..]).unwrap();
+        assert!(matches!(
+            conn.next_event(),
+            Err(Error::RequestHead(ReqHeadError::TooLarge))
+        ));
+    }
+
+    #[test]
+    fn automatic_chunked_framing() {
+        let mut conn = server(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        events(&mut conn).unwrap();
+        assert_eq!(
+            &b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n"[..],
+            &conn.send_resp(resp(200, &[])).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn missing_host_is_rejected() {
+        let config = Config {
+            require_host: true,
+            ..Config::default()
+        };
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        conn.feed(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(matches!(
+            conn.next_event(),
+            Err(Error::RequestHead(ReqHeadError::MissingHost))
+        ));
+    }
+
+    #[test]
+    fn too_much_data_is_rejected() {
+        let mut conn = server(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        events(&mut conn).unwrap();
+        conn.send_resp(resp(200, &[("content-length", "2")]))
+            .unwrap();
+        assert!(conn.send_data(Bytes::from_static(b"xxx")).is_err());
+    }
+}
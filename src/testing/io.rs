@@ -0,0 +1,297 @@
+use std::io::{self, Read, Write};
+
+#[derive(Debug)]
+pub struct ChunkedReader<R> {
+    inner: R,
+    chunk: usize,
+}
+
+impl<R: Read> ChunkedReader<R> {
+    /// # Panics
+    ///
+    /// Panics if `chunk` is zero, since such a reader could never make
+    /// progress.
+    pub fn new(inner: R, chunk: usize) -> Self {
+        assert!(chunk > 0, "chunk size must be non-zero");
+        Self { inner, chunk }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.chunk);
+        self.inner.read(&mut buf[..n])
+    }
+}
+
+#[derive(Debug)]
+pub struct ShortWriteWriter<W> {
+    inner: W,
+    max: usize,
+}
+
+impl<W: Write> ShortWriteWriter<W> {
+    /// # Panics
+    ///
+    /// Panics if `max` is zero, since a writer accepting nothing would
+    /// look like a closed sink.
+    pub fn new(inner: W, max: usize) -> Self {
+        assert!(max > 0, "maximum write size must be non-zero");
+        Self { inner, max }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ShortWriteWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.max);
+        self.inner.write(&buf[..n])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug)]
+pub struct EofAfter<R> {
+    inner: R,
+    remaining: usize,
+}
+
+impl<R: Read> EofAfter<R> {
+    pub fn new(inner: R, n: usize) -> Self {
+        Self {
+            inner,
+            remaining: n,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for EofAfter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.remaining);
+        if n == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..n])?;
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Fault {
+    OneByte,
+    SplitAtCrlf,
+    SplitInsideHeadEnd,
+    EarlyEof(usize),
+}
+
+// Reads the whole inner reader up front so that split points can be
+// chosen by looking at the data, then hands it out one segment at a time.
+#[derive(Debug)]
+pub struct FaultReader<R> {
+    inner: Option<R>,
+    fault: Fault,
+    data: Vec<u8>,
+    cuts: Vec<usize>,
+    pos: usize,
+}
+
+impl<R: Read> FaultReader<R> {
+    pub fn new(inner: R, fault: Fault) -> Self {
+        Self {
+            inner: Some(inner),
+            fault,
+            data: Vec::new(),
+            cuts: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn load(&mut self) -> io::Result<()> {
+        if let Some(mut inner) = self.inner.take() {
+            inner.read_to_end(&mut self.data)?;
+            if let Fault::EarlyEof(n) = self.fault {
+                self.data.truncate(n);
+            }
+            self.cuts = cut_points(&self.data, self.fault);
+        }
+        Ok(())
+    }
+}
+
+fn cut_points(data: &[u8], fault: Fault) -> Vec<usize> {
+    let mut cuts = Vec::new();
+    for i in 1..data.len() {
+        let cut = match fault {
+            Fault::OneByte => true,
+            Fault::SplitAtCrlf => {
+                data[i - 1] == b'\r' && data[i] == b'\n'
+                    || data[i - 1] == b'\n'
+            }
+            Fault::SplitInsideHeadEnd => (1..4)
+                .any(|k| i >= k && data[i - k..].starts_with(b"\r\n\r\n")),
+            Fault::EarlyEof(_) => false,
+        };
+        if cut {
+            cuts.push(i);
+        }
+    }
+    cuts
+}
+
+impl<R: Read> Read for FaultReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.load()?;
+        let end = self
+            .cuts
+            .iter()
+            .copied()
+            .find(|&cut| cut > self.pos)
+            .unwrap_or(self.data.len());
+        let n = buf.len().min(end - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::conn::{HttpConn, Server};
+    use crate::event::Event;
+
+    use bytes::{Bytes, BytesMut};
+    use http::header::HeaderValue;
+
+    const REQ: &[u8] = b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\nhello";
+
+    #[test]
+    fn chunked_reader() {
+        let mut conn = HttpConn::<Server>::new();
+        let mut r = ChunkedReader::new(REQ, 3);
+        let mut reads = 0;
+        while conn.read_from(&mut r).unwrap() > 0 {
+            reads += 1;
+        }
+        assert_eq!(15, reads);
+        assert!(conn.next_event().unwrap().unwrap().as_request().is_some());
+        assert_eq!(
+            Some(Event::data(&b"hello"[..])),
+            conn.next_event().unwrap()
+        );
+    }
+
+    #[test]
+    fn short_write_writer() {
+        let mut w = ShortWriteWriter::new(Vec::new(), 4);
+        assert_eq!(4, w.write(b"hello world").unwrap());
+        w.write_all(b"o world").unwrap();
+        assert_eq!(&b"hello world"[..], &w.into_inner()[..]);
+    }
+
+    #[test]
+    fn eof_after() {
+        let mut conn = HttpConn::<Server>::new();
+        let mut r = EofAfter::new(REQ, REQ.len() - 2);
+        while conn.read_from(&mut r).unwrap() > 0 {}
+        assert!(conn.next_event().unwrap().is_some());
+        assert_eq!(Some(Event::data(&b"hel"[..])), conn.next_event().unwrap());
+        assert!(conn.next_event().is_err());
+    }
+
+    fn decode<R: Read>(mut r: R) -> Result<Vec<Event>, crate::conn::Error> {
+        let mut conn = HttpConn::<Server>::new();
+        let mut out = Vec::new();
+        loop {
+            let n = conn.read_from(&mut r)?;
+            while let Some(event) = conn.next_event()? {
+                match (out.last_mut(), event) {
+                    (Some(Event::Data(prev)), Event::Data(data)) => {
+                        let mut joined = BytesMut::from(&prev[..]);
+                        joined.extend_from_slice(&data);
+                        *prev = joined.freeze();
+                    }
+                    (_, event) => out.push(event),
+                }
+            }
+            if n == 0 {
+                return Ok(out);
+            }
+        }
+    }
+
+    const CHUNKED: &[u8] = b"POST / HTTP/1.1\r\n\
+        transfer-encoding: chunked\r\n\r\n\
+        5\r\nhello\r\n\
+        7;ext=1\r\n, world\r\n\
+        0\r\nx-checksum: abc\r\nx-other: def\r\n\r\n";
+
+    #[test]
+    fn cut_points() {
+        let data = b"a\r\nb\r\n\r\nc";
+        assert_eq!(
+            vec![2, 3, 5, 6, 7, 8],
+            super::cut_points(data, Fault::SplitAtCrlf)
+        );
+        assert_eq!(
+            vec![5, 6, 7],
+            super::cut_points(data, Fault::SplitInsideHeadEnd)
+        );
+    }
+
+    #[test]
+    fn faults_do_not_change_decoding() {
+        let expected = decode(CHUNKED).unwrap();
+        assert_eq!(Some(&Event::data(&b"hello, world"[..])), expected.get(1));
+        match expected.get(2) {
+            Some(Event::EndOfMessage(Some(trailers))) => {
+                assert_eq!(
+                    Some(&HeaderValue::from_static("abc")),
+                    trailers.get("x-checksum")
+                );
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        for &fault in &[
+            Fault::OneByte,
+            Fault::SplitAtCrlf,
+            Fault::SplitInsideHeadEnd,
+        ] {
+            let actual = decode(FaultReader::new(CHUNKED, fault)).unwrap();
+            assert_eq!(expected, actual, "{fault:?}");
+        }
+    }
+
+    #[test]
+    fn early_eof_is_an_error_at_every_point() {
+        let head_len = twoway::find_bytes(CHUNKED, b"\r\n\r\n").unwrap() + 4;
+        for n in head_len..CHUNKED.len() {
+            let r = FaultReader::new(CHUNKED, Fault::EarlyEof(n));
+            assert!(decode(r).is_err(), "truncated at {}", n);
+        }
+    }
+
+    #[test]
+    fn early_eof_truncates() {
+        let mut r = FaultReader::new(&b"hello"[..], Fault::EarlyEof(3));
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(Bytes::from_static(b"hel"), Bytes::from(out));
+    }
+}
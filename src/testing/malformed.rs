@@ -0,0 +1,196 @@
+fn request(headers: &str, body: &str) -> Vec<u8> {
+    format!("POST / HTTP/1.1\r\nhost: example.com\r\n{headers}\r\n{body}")
+        .into_bytes()
+}
+
+#[must_use]
+pub fn content_length_with_transfer_encoding() -> Vec<Vec<u8>> {
+    vec![
+        request(
+            "content-length: 5\r\ntransfer-encoding: chunked\r\n",
+            "0\r\n\r\n",
+        ),
+        request(
+            "transfer-encoding: chunked\r\ncontent-length: 5\r\n",
+            "0\r\n\r\n",
+        ),
+        request(
+            "content-length: 0\r\ntransfer-encoding: gzip, chunked\r\n",
+            "0\r\n\r\n",
+        ),
+    ]
+}
+
+#[must_use]
+pub fn conflicting_content_lengths() -> Vec<Vec<u8>> {
+    vec![
+        request("content-length: 5\r\ncontent-length: 6\r\n", "hello!"),
+        request("content-length: 5, 6\r\n", "hello!"),
+        request("content-length: -5\r\n", "hello"),
+        request("content-length: 0x5\r\n", "hello"),
+    ]
+}
+
+#[must_use]
+pub fn folded_headers() -> Vec<Vec<u8>> {
+    vec![
+        request("x-folded: one\r\n two\r\n", ""),
+        request("x-folded: one\r\n\ttwo\r\n", ""),
+        request("x-folded:\r\n one\r\n", ""),
+    ]
+}
+
+#[must_use]
+pub fn bad_chunk_sizes() -> Vec<Vec<u8>> {
+    let te = "transfer-encoding: chunked\r\n";
+    vec![
+        request(te, "x\r\n"),
+        request(te, "-1\r\n"),
+        request(te, "5 5\r\nhello\r\n0\r\n\r\n"),
+        request(te, "0x5\r\nhello\r\n0\r\n\r\n"),
+        request(te, "fffffffffffffffffffff\r\n"),
+    ]
+}
+
+#[must_use]
+pub fn oversized_head(size: usize) -> Vec<u8> {
+    let mut head = Vec::from(&b"GET / HTTP/1.1\r\nx-padding: "[..]);
+    let padding = size.saturating_sub(head.len() + 4);
+    head.resize(head.len() + padding, b'a');
+    head.extend_from_slice(b"\r\n\r\n");
+    head
+}
+
+#[must_use]
+pub fn oversized_chunk_size_line(size: usize) -> Vec<u8> {
+    let mut body = Vec::from(&b"5;x-ext="[..]);
+    body.resize(size.saturating_sub(2).max(body.len()), b'a');
+    body.extend_from_slice(b"\r\nhello\r\n0\r\n\r\n");
+    let mut req = request("transfer-encoding: chunked\r\n", "");
+    req.extend_from_slice(&body);
+    req
+}
+
+#[must_use]
+pub fn bare_lf() -> Vec<Vec<u8>> {
+    vec![
+        b"GET / HTTP/1.1\nhost: example.com\n\n".to_vec(),
+        b"GET / HTTP/1.1\r\nhost: example.com\n\r\n".to_vec(),
+        b"GET / HTTP/1.1\r\nhost: example.com\r\n\n".to_vec(),
+        // Cut at the later CRLF CRLF, this would hide the second request.
+        b"GET / HTTP/1.1\nhost: a\n\nGET /b HTTP/1.1\r\nhost: a\r\n\r\n"
+            .to_vec(),
+        request("transfer-encoding: chunked\r\n", "5\nhello\n0\n\n"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BytesMut;
+    use http::header::HeaderName;
+
+    use crate::config::Config;
+    use crate::conn::{Error, HttpConn, Server};
+    use crate::event::Event;
+    use crate::req::ReqHeadError;
+    use crate::security::{audit_head, audit_raw_head, Finding};
+    use crate::testing::parse_req_head;
+
+    fn events(
+        conn: &mut HttpConn<Server>,
+        mut bytes: &[u8],
+    ) -> Result<Vec<Event>, Error> {
+        while !bytes.is_empty() {
+            conn.read_from(&mut bytes)?;
+        }
+        let mut out = Vec::new();
+        while let Some(event) = conn.next_event()? {
+            out.push(event);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_flagged() {
+        for msg in content_length_with_transfer_encoding() {
+            let req =
+                parse_req_head(&mut BytesMut::from(msg)).unwrap().unwrap();
+            assert!(audit_head(&req)
+                .contains(&Finding::ContentLengthWithTransferEncoding));
+        }
+    }
+
+    #[test]
+    fn conflicting_content_lengths_are_flagged() {
+        for msg in conflicting_content_lengths() {
+            let req =
+                parse_req_head(&mut BytesMut::from(msg)).unwrap().unwrap();
+            assert!(!audit_head(&req).is_empty());
+        }
+    }
+
+    #[test]
+    fn folded_headers_are_rejected() {
+        for msg in folded_headers() {
+            assert!(parse_req_head(&mut BytesMut::from(msg)).is_err());
+        }
+    }
+
+    #[test]
+    fn folded_headers_are_flagged() {
+        for msg in folded_headers() {
+            assert_eq!(
+                vec![Finding::ObsFold(HeaderName::from_static("x-folded"))],
+                audit_raw_head(&msg)
+            );
+        }
+    }
+
+    #[test]
+    fn bad_chunk_sizes_are_rejected() {
+        for msg in bad_chunk_sizes() {
+            let mut conn = HttpConn::<Server>::new();
+            assert!(events(&mut conn, &msg).is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_head() {
+        assert_eq!(1000, super::oversized_head(1000).len());
+        let mut conn = HttpConn::<Server>::new();
+        match events(&mut conn, &super::oversized_head(16 * 1024)) {
+            Err(Error::RequestHead(ReqHeadError::TooLarge)) => {}
+            r => panic!("expected a too large head, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn oversized_chunk_size_line_hits_limit() {
+        let config = Config {
+            max_chunk_size_line: 64,
+            ..Config::default()
+        };
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        let msg = oversized_chunk_size_line(65);
+        assert!(events(&mut conn, &msg).is_err());
+
+        let mut conn = HttpConn::<Server>::new().with_config(config);
+        let msg = oversized_chunk_size_line(64);
+        assert_eq!(3, events(&mut conn, &msg).unwrap().len());
+    }
+
+    #[test]
+    fn bare_lf_heads_are_rejected() {
+        for msg in &bare_lf()[..4] {
+            let mut conn = HttpConn::<Server>::new();
+            match events(&mut conn, msg) {
+                Err(Error::RequestHead(ReqHeadError::Parse(
+                    httparse::Error::NewLine,
+                ))) => {}
+                r => panic!("expected a bare LF error, got {:?}", r),
+            }
+        }
+    }
+}
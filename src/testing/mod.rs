@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod conformance;
+pub mod io;
+pub mod malformed;
+#[cfg(any(test, feature = "pcap"))]
+pub mod pcap;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategies;
+pub mod transcript;
+
+use std::fmt::Write;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::conn::{Client, Error, HttpConn, Server};
+use crate::req::{ReqHead, ReqHeadError};
+use crate::resp::{RespHead, RespHeadError};
+
+// A client and a server connection wired back to back, so handlers can be
+// tested without sockets. Whatever one side sends is fed to the other.
+#[derive(Clone, Debug, Default)]
+pub struct Duplex {
+    pub client: HttpConn<Client>,
+    pub server: HttpConn<Server>,
+}
+
+impl Duplex {
+    /// Runs `send` on the client and feeds the bytes it returns to the
+    /// server.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the error from `send`, or from the server if it
+    /// refuses the bytes.
+    pub fn client_send<F>(&mut self, send: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut HttpConn<Client>) -> Result<Bytes, Error>,
+    {
+        let bytes = send(&mut self.client)?;
+        self.server.feed(&bytes)
+    }
+
+    /// The same as [`Duplex::client_send`] in the other direction.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the error from `send`, or from the client if it
+    /// refuses the bytes.
+    pub fn server_send<F>(&mut self, send: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut HttpConn<Server>) -> Result<Bytes, Error>,
+    {
+        let bytes = send(&mut self.server)?;
+        self.client.feed(&bytes)
+    }
+}
+
+/// # Errors
+///
+/// Fails if the buffer does not start with a valid request head.
+pub fn parse_req_head(
+    buf: &mut BytesMut,
+) -> Result<Option<ReqHead>, ReqHeadError> {
+    ReqHead::from_buf(buf)
+}
+
+/// # Errors
+///
+/// Fails if the buffer does not start with a valid response head.
+pub fn parse_resp_head(
+    buf: &mut BytesMut,
+) -> Result<Option<RespHead>, RespHeadError> {
+    RespHead::from_buf(buf)
+}
+
+pub(crate) fn escape(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len());
+    for &b in bytes {
+        for c in std::ascii::escape_default(b) {
+            s.push(c as char);
+        }
+    }
+    s
+}
+
+pub(crate) fn describe_mismatch(
+    step: usize,
+    what: &str,
+    expected: &str,
+    actual: &str,
+) -> String {
+    let mut msg = String::new();
+    let _ = writeln!(msg, "transcript step {step}: {what} mismatch");
+    let _ = writeln!(msg, "  expected: {expected}");
+    let _ = write!(msg, "    actual: {actual}");
+    msg
+}
@@ -0,0 +1,482 @@
+use std::fmt;
+
+use http::Method;
+
+use crate::conn::{self, Client, HttpConn, Server};
+use crate::event::Event;
+use crate::resp::InfoStatus;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TcpStreams {
+    pub client: Vec<u8>,
+    pub server: Vec<u8>,
+}
+
+type Endpoint = (Vec<u8>, u16);
+
+struct Segment {
+    src: Endpoint,
+    dst: Endpoint,
+    seq: u32,
+    syn: bool,
+    ack: bool,
+    payload: Vec<u8>,
+}
+
+/// # Errors
+///
+/// Fails if the capture cannot be read, holds no TCP connection, or is
+/// missing part of either stream.
+pub fn extract_streams(pcap: &[u8]) -> Result<TcpStreams, PcapError> {
+    let segments = read_segments(pcap)?;
+
+    // The connection is identified by the first SYN, falling back to the
+    // first segment carrying data for captures that started mid-stream.
+    let (client, server) = segments
+        .iter()
+        .find(|s| s.syn && !s.ack)
+        .or_else(|| segments.iter().find(|s| !s.payload.is_empty()))
+        .map(|s| (s.src.clone(), s.dst.clone()))
+        .ok_or(PcapError::NoTcpConnection)?;
+
+    let client_stream = reassemble(
+        segments
+            .iter()
+            .filter(|s| s.src == client && s.dst == server),
+    )?;
+    let server_stream = reassemble(
+        segments
+            .iter()
+            .filter(|s| s.src == server && s.dst == client),
+    )?;
+    Ok(TcpStreams {
+        client: client_stream,
+        server: server_stream,
+    })
+}
+
+fn reassemble<'a, I>(segments: I) -> Result<Vec<u8>, PcapError>
+where
+    I: Iterator<Item = &'a Segment>,
+{
+    let mut base = None;
+    let mut pieces = Vec::new();
+    for seg in segments {
+        if seg.syn {
+            base = Some(seg.seq.wrapping_add(1));
+        } else if !seg.payload.is_empty() {
+            let start = *base.get_or_insert(seg.seq);
+            pieces.push((seg.seq.wrapping_sub(start), &seg.payload));
+        }
+    }
+    pieces.sort_by_key(|&(offset, _)| offset);
+
+    let mut stream = Vec::new();
+    for (offset, payload) in pieces {
+        let offset = offset as usize;
+        if offset > stream.len() {
+            return Err(PcapError::MissingSegment(stream.len()));
+        }
+        // Retransmissions and overlapping segments only contribute the
+        // bytes we have not seen yet.
+        let skip = stream.len() - offset;
+        if skip < payload.len() {
+            stream.extend_from_slice(&payload[skip..]);
+        }
+    }
+    Ok(stream)
+}
+
+fn read_segments(pcap: &[u8]) -> Result<Vec<Segment>, PcapError> {
+    if pcap.len() < 24 {
+        return Err(PcapError::Truncated);
+    }
+    let big_endian = match pcap[..4] {
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => true,
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => false,
+        _ => return Err(PcapError::BadMagic),
+    };
+    let read_u32 = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    };
+    let linktype = read_u32(&pcap[20..24]);
+
+    let mut segments = Vec::new();
+    let mut rest = &pcap[24..];
+    while !rest.is_empty() {
+        if rest.len() < 16 {
+            return Err(PcapError::Truncated);
+        }
+        let incl_len = read_u32(&rest[8..12]) as usize;
+        let record =
+            rest.get(16..16 + incl_len).ok_or(PcapError::Truncated)?;
+        rest = &rest[16 + incl_len..];
+
+        let packet = match linktype {
+            LINKTYPE_NULL => record.get(4..),
+            LINKTYPE_ETHERNET => strip_ethernet(record),
+            LINKTYPE_RAW => Some(record),
+            LINKTYPE_LINUX_SLL => record.get(16..),
+            other => return Err(PcapError::UnsupportedLinkType(other)),
+        };
+        if let Some(seg) = packet.map_or(Ok(None), parse_ip)? {
+            segments.push(seg);
+        }
+    }
+    Ok(segments)
+}
+
+fn strip_ethernet(frame: &[u8]) -> Option<&[u8]> {
+    let mut offset = 12;
+    // Skip any 802.1Q VLAN tags.
+    while frame.get(offset..offset + 2)? == [0x81, 0x00] {
+        offset += 4;
+    }
+    frame.get(offset + 2..)
+}
+
+fn parse_ip(packet: &[u8]) -> Result<Option<Segment>, PcapError> {
+    let header = |len| packet.get(..len).ok_or(PcapError::Truncated);
+    match packet.first().map(|b| b >> 4) {
+        Some(4) => {
+            let header = header(20)?;
+            if header[9] != 6 {
+                return Ok(None);
+            }
+            let ihl = usize::from(header[0] & 0x0f) * 4;
+            let total =
+                usize::from(u16::from_be_bytes([header[2], header[3]]));
+            let tcp = packet.get(ihl..total).ok_or(PcapError::Truncated)?;
+            let (src, dst) =
+                (header[12..16].to_vec(), header[16..20].to_vec());
+            parse_tcp(src, dst, tcp).map(Some)
+        }
+        Some(6) => {
+            let header = header(40)?;
+            if header[6] != 6 {
+                return Ok(None);
+            }
+            let payload_len =
+                usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let tcp = packet
+                .get(40..40 + payload_len)
+                .ok_or(PcapError::Truncated)?;
+            let (src, dst) = (header[8..24].to_vec(), header[24..40].to_vec());
+            parse_tcp(src, dst, tcp).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parse_tcp(
+    src: Vec<u8>,
+    dst: Vec<u8>,
+    tcp: &[u8],
+) -> Result<Segment, PcapError> {
+    if tcp.len() < 20 {
+        return Err(PcapError::Truncated);
+    }
+    let src_port = u16::from_be_bytes([tcp[0], tcp[1]]);
+    let dst_port = u16::from_be_bytes([tcp[2], tcp[3]]);
+    let seq = u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]);
+    let data_offset = usize::from(tcp[12] >> 4) * 4;
+    let flags = tcp[13];
+    let payload = tcp.get(data_offset..).ok_or(PcapError::Truncated)?;
+    Ok(Segment {
+        src: (src, src_port),
+        dst: (dst, dst_port),
+        seq,
+        syn: flags & 0x02 != 0,
+        ack: flags & 0x10 != 0,
+        payload: payload.to_vec(),
+    })
+}
+
+/// Replays a capture through a client and a server connection. Each
+/// decodes what its peer sent, and every event it decodes is sent on by
+/// the connection playing that peer, so the two state machines check that
+/// the two directions agree with each other.
+///
+/// # Errors
+///
+/// Fails if either direction does not decode cleanly, or ends in the middle
+/// of a message.
+pub fn replay(streams: &TcpStreams) -> Result<usize, ReplayError> {
+    let mut server = HttpConn::<Server>::new();
+    server.feed(&streams.client).map_err(ReplayError::Client)?;
+    server.feed(b"").map_err(ReplayError::Client)?;
+    let mut client = HttpConn::<Client>::new();
+    client.feed(&streams.server).map_err(ReplayError::Client)?;
+    client.feed(b"").map_err(ReplayError::Client)?;
+    let mut exchanges = 0;
+
+    loop {
+        let mut method = None;
+        loop {
+            let event = match server.next_event() {
+                Ok(Some(Event::ConnectionClosed) | None)
+                    if method.is_none() =>
+                {
+                    return Ok(exchanges)
+                }
+                Ok(Some(event)) => event,
+                Ok(None) => return Err(ReplayError::ClientIncomplete),
+                Err(e) => return Err(ReplayError::Client(e)),
+            };
+            if let Event::Request(ref req) = event {
+                method = Some(req.method.clone());
+            }
+            let end = matches!(event, Event::EndOfMessage(_));
+            client.send(event).map_err(ReplayError::Client)?;
+            if end {
+                break;
+            }
+        }
+        let method = method.unwrap_or(Method::GET);
+
+        loop {
+            let event = match client.next_event() {
+                Ok(Some(Event::ConnectionClosed) | None) => {
+                    return Err(ReplayError::ServerIncomplete)
+                }
+                Ok(Some(event)) => event,
+                Err(e) => return Err(ReplayError::Server(e.to_string())),
+            };
+            let switched = match event {
+                Event::InfoResponse(ref resp) => {
+                    resp.info_status() == Some(InfoStatus::SwitchingProtocols)
+                }
+                Event::Response(ref resp) => {
+                    method == Method::CONNECT && resp.status.is_success()
+                }
+                _ => false,
+            };
+            let end = matches!(event, Event::EndOfMessage(_));
+            server.send(event).map_err(ReplayError::Client)?;
+            if switched {
+                return Ok(exchanges + 1);
+            }
+            if end {
+                break;
+            }
+        }
+        exchanges += 1;
+        if server.start_next_cycle().is_err() {
+            return Ok(exchanges);
+        }
+        client.start_next_cycle().map_err(ReplayError::Client)?;
+    }
+}
+
+/// # Panics
+///
+/// Panics if the capture cannot be read or if either direction of the
+/// connection fails to decode.
+pub fn assert_replays_cleanly(pcap: &[u8]) {
+    let streams = extract_streams(pcap)
+        .unwrap_or_else(|e| panic!("could not read capture: {}", e));
+    if let Err(e) = replay(&streams) {
+        panic!("replay failed: {}", e);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PcapError {
+    Truncated,
+    BadMagic,
+    UnsupportedLinkType(u32),
+    NoTcpConnection,
+    MissingSegment(usize),
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "capture is truncated"),
+            Self::BadMagic => write!(f, "not a pcap capture"),
+            Self::UnsupportedLinkType(t) => {
+                write!(f, "unsupported link type {t}")
+            }
+            Self::NoTcpConnection => write!(f, "no TCP connection found"),
+            Self::MissingSegment(offset) => {
+                write!(f, "stream has a gap at offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PcapError {}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Client(conn::Error),
+    ClientIncomplete,
+    Server(String),
+    ServerIncomplete,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Client(e) => write!(f, "protocol error: {e}"),
+            Self::ClientIncomplete => {
+                write!(f, "client stream ended mid-message")
+            }
+            Self::Server(e) => write!(f, "server stream error: {e}"),
+            Self::ServerIncomplete => {
+                write!(f, "server stream ended mid-message")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: [u8; 4] = [10, 0, 0, 1];
+    const SERVER: [u8; 4] = [10, 0, 0, 2];
+
+    fn packet(
+        src: [u8; 4],
+        dst: [u8; 4],
+        seq: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let (src_port, dst_port) = if src == CLIENT {
+            (40000u16, 80u16)
+        } else {
+            (80, 40000)
+        };
+        let mut tcp = Vec::new();
+        tcp.extend_from_slice(&src_port.to_be_bytes());
+        tcp.extend_from_slice(&dst_port.to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&[0; 4]);
+        tcp.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(data);
+
+        let mut ip = vec![0x45, 0];
+        ip.extend_from_slice(&(20 + tcp.len() as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0, 0, 64, 6, 0, 0]);
+        ip.extend_from_slice(&src);
+        ip.extend_from_slice(&dst);
+        ip.extend_from_slice(&tcp);
+
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&ip);
+        frame
+    }
+
+    fn capture(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend_from_slice(&[0; 8]);
+        pcap.extend_from_slice(&65535u32.to_le_bytes());
+        pcap.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for frame in frames {
+            pcap.extend_from_slice(&[0; 8]);
+            pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            pcap.extend_from_slice(frame);
+        }
+        pcap
+    }
+
+    #[test]
+    fn extracts_and_reorders_streams() {
+        let pcap = capture(&[
+            packet(CLIENT, SERVER, 99, 0x02, b""),
+            packet(SERVER, CLIENT, 499, 0x12, b""),
+            packet(CLIENT, SERVER, 106, 0x18, b"HTTP/1.1\r\n\r\n"),
+            packet(CLIENT, SERVER, 100, 0x18, b"GET / "),
+            // A retransmission overlapping data we already have.
+            packet(CLIENT, SERVER, 100, 0x18, b"GET / H"),
+            packet(
+                SERVER,
+                CLIENT,
+                500,
+                0x18,
+                b"HTTP/1.1 204 No Content\r\n\r\n",
+            ),
+        ]);
+        assert_eq!(
+            TcpStreams {
+                client: b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+                server: b"HTTP/1.1 204 No Content\r\n\r\n".to_vec(),
+            },
+            extract_streams(&pcap).expect("extract streams")
+        );
+    }
+
+    #[test]
+    fn detects_gaps() {
+        let pcap = capture(&[
+            packet(CLIENT, SERVER, 99, 0x02, b""),
+            packet(CLIENT, SERVER, 110, 0x18, b"HTTP/1.1\r\n\r\n"),
+        ]);
+        assert_eq!(Err(PcapError::MissingSegment(0)), extract_streams(&pcap));
+    }
+
+    #[test]
+    fn rejects_truncated_tcp_headers() {
+        let mut frame = packet(CLIENT, SERVER, 99, 0x02, b"");
+        frame.truncate(14 + 20 + 8);
+        frame[16..18].copy_from_slice(&28u16.to_be_bytes());
+        let pcap = capture(&[frame]);
+        assert_eq!(Err(PcapError::Truncated), extract_streams(&pcap));
+    }
+
+    #[test]
+    fn replays_keep_alive_exchanges() {
+        let pcap = capture(&[
+            packet(CLIENT, SERVER, 99, 0x02, b""),
+            packet(
+                CLIENT,
+                SERVER,
+                100,
+                0x18,
+                b"POST /a HTTP/1.1\r\ncontent-length: 2\r\n\r\nhi\
+                  GET /b HTTP/1.1\r\nconnection: close\r\n\r\n",
+            ),
+            packet(
+                SERVER,
+                CLIENT,
+                500,
+                0x18,
+                b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+                  2\r\nok\r\n0\r\n\r\n\
+                  HTTP/1.1 200 OK\r\n\r\nread until close",
+            ),
+        ]);
+        assert_replays_cleanly(&pcap);
+        let streams = extract_streams(&pcap).expect("extract streams");
+        assert_eq!(2, replay(&streams).expect("replay"));
+    }
+
+    #[test]
+    #[should_panic(expected = "replay failed")]
+    fn replay_reports_bad_responses() {
+        let pcap = capture(&[packet(
+            CLIENT,
+            SERVER,
+            100,
+            0x18,
+            b"GET / HTTP/1.1\r\n\r\n",
+        )]);
+        assert_replays_cleanly(&pcap);
+    }
+}
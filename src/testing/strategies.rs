@@ -0,0 +1,211 @@
+// The generators only ever unwrap values that are valid by construction.
+#![allow(clippy::missing_panics_doc)]
+
+use bytes::{Bytes, BytesMut};
+use http::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use http::{HeaderMap, Method, StatusCode, Uri, Version};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::event::Event;
+use crate::req::ReqHead;
+use crate::resp::RespHead;
+
+pub fn method() -> impl Strategy<Value = Method> {
+    prop_oneof![
+        Just(Method::GET),
+        Just(Method::HEAD),
+        Just(Method::POST),
+        Just(Method::PUT),
+        Just(Method::DELETE),
+        Just(Method::OPTIONS),
+        Just(Method::PATCH),
+        "[A-Z]{1,10}".prop_map(|m| Method::from_bytes(m.as_bytes()).unwrap()),
+    ]
+}
+
+pub fn uri() -> impl Strategy<Value = Uri> {
+    (
+        "(/[a-z0-9._~-]{0,8}){1,4}",
+        proptest::option::of("[a-z0-9=&]{0,16}"),
+    )
+        .prop_map(|(path, query)| {
+            let uri = match query {
+                Some(q) => format!("{path}?{q}"),
+                None => path,
+            };
+            uri.parse().unwrap()
+        })
+}
+
+pub fn header_name() -> impl Strategy<Value = HeaderName> {
+    "x-[a-z0-9-]{1,16}"
+        .prop_map(|n| HeaderName::from_bytes(n.as_bytes()).unwrap())
+}
+
+// Leading and trailing whitespace is not significant in header values
+// and is stripped by the parser, so it is never generated.
+pub fn header_value() -> impl Strategy<Value = HeaderValue> {
+    "[!-~]([ !-~]{0,30}[!-~])?"
+        .prop_map(|v| HeaderValue::from_str(&v).unwrap())
+}
+
+pub fn headers() -> impl Strategy<Value = HeaderMap> {
+    vec((header_name(), header_value()), 0..8).prop_map(|hdrs| {
+        let mut map = HeaderMap::new();
+        for (name, value) in hdrs {
+            map.append(name, value);
+        }
+        map
+    })
+}
+
+pub fn req_head() -> impl Strategy<Value = ReqHead> {
+    (method(), uri(), headers()).prop_map(|(method, uri, headers)| ReqHead {
+        method,
+        uri,
+        version: Version::HTTP_11,
+        headers,
+    })
+}
+
+pub fn status() -> impl Strategy<Value = StatusCode> {
+    (100u16..600).prop_map(|s| StatusCode::from_u16(s).unwrap())
+}
+
+pub fn resp_head() -> impl Strategy<Value = RespHead> {
+    (status(), headers()).prop_map(|(status, headers)| RespHead {
+        status,
+        version: Version::HTTP_11,
+        headers,
+        reason: None,
+    })
+}
+
+pub fn invalid_req_head() -> impl Strategy<Value = Vec<u8>> {
+    let line =
+        (method(), uri()).prop_map(|(method, uri)| format!("{method} {uri} "));
+    (line, 0..5usize).prop_map(|(line, kind)| {
+        let head = match kind {
+            0 => format!("{line}HTTP/1.1\r\nfoo : bar\r\n\r\n"),
+            1 => format!("{line}HTTP/2.0\r\n\r\n"),
+            2 => format!("{line}HTTP/1.1\r\nfoo: bar\r\n folded\r\n\r\n"),
+            3 => format!("{line}HTTP/1.1\r\n: empty\r\n\r\n"),
+            _ => format!("{line}HTTP/1.1\r\nfoo: a\0b\r\n\r\n"),
+        };
+        head.into_bytes()
+    })
+}
+
+pub fn body() -> impl Strategy<Value = Vec<Bytes>> {
+    vec(vec(any::<u8>(), 1..64).prop_map(Bytes::from), 0..4)
+}
+
+// A complete, content-length delimited message sent by a client.
+pub fn client_message() -> impl Strategy<Value = Vec<Event>> {
+    (req_head(), body()).prop_map(|(mut req, body)| {
+        let len: usize = body.iter().map(Bytes::len).sum();
+        req.headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+        let mut events = vec![Event::Request(req)];
+        events.extend(body.into_iter().map(Event::Data));
+        events.push(Event::EndOfMessage(None));
+        events
+    })
+}
+
+// Splits `bytes` at arbitrary points, preserving their order.
+pub fn split(bytes: Vec<u8>) -> impl Strategy<Value = Vec<BytesMut>> {
+    let len = bytes.len();
+    vec(0..=len, 0..8).prop_map(move |mut points| {
+        points.push(0);
+        points.push(len);
+        points.sort_unstable();
+        points.dedup();
+        points
+            .windows(2)
+            .map(|w| BytesMut::from(&bytes[w[0]..w[1]]))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::conn::{Client, HttpConn, Server};
+
+    fn serialize_client(events: Vec<Event>) -> Vec<u8> {
+        let mut conn = HttpConn::<Client>::new();
+        let mut out = Vec::new();
+        for event in events {
+            let b = match event {
+                Event::Request(req) => conn.send_req(req),
+                Event::Data(data) => conn.send_data(data),
+                Event::EndOfMessage(t) => conn.send_end_of_message(t),
+                e => panic!("not a client event: {:?}", e),
+            };
+            out.extend_from_slice(&b.expect("valid client event"));
+        }
+        out
+    }
+
+    proptest! {
+        #[test]
+        fn req_head_roundtrip(req in req_head()) {
+            let mut buf = BytesMut::new();
+            let mut wire = BytesMut::from(&req.write_to_buf(&mut buf)[..]);
+            let parsed = ReqHead::from_buf(&mut wire)
+                .expect("parse request")
+                .expect("complete request");
+            prop_assert_eq!(req, parsed);
+            prop_assert!(wire.is_empty());
+        }
+
+        #[test]
+        fn resp_head_roundtrip(resp in resp_head()) {
+            let mut buf = BytesMut::new();
+            let mut wire = BytesMut::from(&resp.write_to_buf(&mut buf)[..]);
+            let parsed = RespHead::from_buf(&mut wire)
+                .expect("parse response")
+                .expect("complete response");
+            prop_assert_eq!(resp, parsed);
+        }
+
+        #[test]
+        fn invalid_heads_rejected(head in invalid_req_head()) {
+            prop_assert!(ReqHead::from_buf(&mut head[..].into()).is_err());
+        }
+
+        #[test]
+        fn split_points_do_not_change_decoding(
+            (wire, parts) in client_message().prop_flat_map(|events| {
+                let wire = serialize_client(events);
+                (Just(wire.clone()), split(wire))
+            })
+        ) {
+            let whole = decode(vec![BytesMut::from(&wire[..])]);
+            prop_assert_eq!(whole, decode(parts));
+        }
+    }
+
+    // Decodes a client stream delivered in `parts`, merging adjacent
+    // Data events so that results can be compared across split points.
+    fn decode(parts: Vec<BytesMut>) -> Vec<Event> {
+        let mut conn = HttpConn::<Server>::new();
+        let mut events: Vec<Event> = Vec::new();
+        for part in parts {
+            conn.read_from(&mut &part[..]).expect("read");
+            while let Some(event) = conn.next_event().expect("decode") {
+                match (events.last_mut(), event) {
+                    (Some(Event::Data(prev)), Event::Data(data)) => {
+                        let mut merged = BytesMut::from(&prev[..]);
+                        merged.extend_from_slice(&data);
+                        *prev = merged.freeze();
+                    }
+                    (_, event) => events.push(event),
+                }
+            }
+        }
+        events
+    }
+}
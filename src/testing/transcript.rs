@@ -0,0 +1,255 @@
+use bytes::Bytes;
+
+use crate::conn::{Client, Error, HttpConn, Role, Server};
+use crate::event::Event;
+use crate::testing::{describe_mismatch, escape};
+
+#[derive(Debug)]
+enum Step {
+    Recv(Bytes),
+    RecvEof,
+    Expect(Option<Event>),
+    Send(Event, Bytes),
+}
+
+#[derive(Debug, Default)]
+pub struct Transcript {
+    steps: Vec<Step>,
+}
+
+impl Transcript {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn recv<B: Into<Bytes>>(mut self, bytes: B) -> Self {
+        self.steps.push(Step::Recv(bytes.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn recv_eof(mut self) -> Self {
+        self.steps.push(Step::RecvEof);
+        self
+    }
+
+    #[must_use]
+    pub fn expect(mut self, event: Event) -> Self {
+        self.steps.push(Step::Expect(Some(event)));
+        self
+    }
+
+    #[must_use]
+    pub fn expect_need_data(mut self) -> Self {
+        self.steps.push(Step::Expect(None));
+        self
+    }
+
+    #[must_use]
+    pub fn send<B: Into<Bytes>>(mut self, event: Event, wire: B) -> Self {
+        self.steps.push(Step::Send(event, wire.into()));
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics at the first step where the connection does not behave as the
+    /// transcript says, or if the transcript has the server send a request.
+    pub fn run_server(self, conn: &mut HttpConn<Server>) {
+        self.run(conn, |conn, i, event| match event {
+            Event::InfoResponse(resp) => conn.send_info_resp(resp),
+            Event::Response(resp) => conn.send_resp(resp),
+            _ => panic!("transcript step {}: servers cannot send requests", i),
+        });
+    }
+
+    /// # Panics
+    ///
+    /// Panics at the first step where the connection does not behave as the
+    /// transcript says, or if the transcript has the client send a
+    /// response.
+    pub fn run_client(self, conn: &mut HttpConn<Client>) {
+        self.run(conn, |conn, i, event| match event {
+            Event::Request(req) => conn.send_req(req),
+            _ => {
+                panic!("transcript step {}: clients cannot send responses", i)
+            }
+        });
+    }
+
+    fn run<R, F>(self, conn: &mut HttpConn<R>, send_head: F)
+    where
+        R: Role,
+        F: Fn(&mut HttpConn<R>, usize, Event) -> Result<Bytes, Error>,
+    {
+        for (i, step) in self.steps.into_iter().enumerate() {
+            match step {
+                Step::Recv(bytes) => feed(conn, i, &bytes),
+                Step::RecvEof => feed(conn, i, &[]),
+                Step::Expect(expected) => {
+                    let actual = conn.next_event().unwrap_or_else(|e| {
+                        panic!(
+                            "{}",
+                            describe_mismatch(
+                                i,
+                                "event",
+                                &format!("{expected:?}"),
+                                &format!("error: {e}"),
+                            )
+                        )
+                    });
+                    check_event(i, expected.as_ref(), actual.as_ref());
+                }
+                Step::Send(event, wire) => {
+                    let out = match event {
+                        Event::Data(data) => conn.send_data(data),
+                        Event::Trailers(trailers) => {
+                            conn.send_trailers(trailers)
+                        }
+                        Event::EndOfMessage(trailers) => {
+                            conn.send_end_of_message(trailers)
+                        }
+                        Event::ConnectionClosed => {
+                            conn.send_connection_closed()
+                        }
+                        event => send_head(conn, i, event),
+                    };
+                    check_wire(i, &wire, out);
+                }
+            }
+        }
+    }
+}
+
+fn feed<R>(conn: &mut HttpConn<R>, step: usize, mut bytes: &[u8]) {
+    loop {
+        match conn.read_from(&mut bytes) {
+            Ok(_) if bytes.is_empty() => return,
+            Ok(_) => {}
+            Err(e) => panic!("transcript step {}: read failed: {}", step, e),
+        }
+    }
+}
+
+fn check_event(step: usize, expected: Option<&Event>, actual: Option<&Event>) {
+    assert!(
+        expected == actual,
+        "{}",
+        describe_mismatch(
+            step,
+            "event",
+            &format!("{expected:?}"),
+            &format!("{actual:?}"),
+        )
+    );
+}
+
+fn check_wire(step: usize, expected: &[u8], actual: Result<Bytes, Error>) {
+    match actual {
+        Ok(ref out) if &out[..] == expected => {}
+        Ok(out) => panic!(
+            "{}",
+            describe_mismatch(step, "wire", &escape(expected), &escape(&out))
+        ),
+        Err(e) => panic!(
+            "{}",
+            describe_mismatch(
+                step,
+                "wire",
+                &escape(expected),
+                &format!("error: {e}"),
+            )
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::header::{HeaderValue, CONTENT_LENGTH, HOST};
+    use http::{HeaderMap, Method, StatusCode, Version};
+
+    use crate::req::ReqHead;
+    use crate::resp::RespHead;
+
+    #[test]
+    fn server_exchange() {
+        let mut conn = HttpConn::<Server>::new();
+        Transcript::new()
+            .recv(&b"POST /a HTTP/1.1\r\nhost: example.com\r\n"[..])
+            .expect_need_data()
+            .recv(&b"content-length: 5\r\n\r\nhel"[..])
+            .expect(Event::Request(ReqHead {
+                method: Method::POST,
+                uri: "/a".parse().unwrap(),
+                version: Version::HTTP_11,
+                headers: vec![
+                    (HOST, HeaderValue::from_static("example.com")),
+                    (CONTENT_LENGTH, HeaderValue::from_static("5")),
+                ]
+                .into_iter()
+                .collect(),
+            }))
+            .expect(Event::data(&b"hel"[..]))
+            .expect_need_data()
+            .recv(&b"lo"[..])
+            .expect(Event::data(&b"lo"[..]))
+            .expect(Event::EndOfMessage(None))
+            .send(
+                Event::Response(RespHead {
+                    status: StatusCode::OK,
+                    version: Version::HTTP_11,
+                    headers: vec![(
+                        CONTENT_LENGTH,
+                        HeaderValue::from_static("0"),
+                    )]
+                    .into_iter()
+                    .collect(),
+                    reason: None,
+                }),
+                &b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"[..],
+            )
+            .send(Event::EndOfMessage(None), Bytes::new())
+            .run_server(&mut conn);
+    }
+
+    #[test]
+    fn client_exchange() {
+        let mut conn = HttpConn::<Client>::new();
+        Transcript::new()
+            .send(
+                Event::request(
+                    Method::GET,
+                    "/".parse().unwrap(),
+                    HeaderMap::new(),
+                ),
+                &b"GET / HTTP/1.1\r\n\r\n"[..],
+            )
+            .send(Event::EndOfMessage(None), Bytes::new())
+            .recv(&b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nhi"[..])
+            .expect(Event::Response(RespHead {
+                status: StatusCode::OK,
+                version: Version::HTTP_11,
+                headers: vec![(CONTENT_LENGTH, HeaderValue::from_static("2"))]
+                    .into_iter()
+                    .collect(),
+                reason: None,
+            }))
+            .expect(Event::data(&b"hi"[..]))
+            .expect(Event::EndOfMessage(None))
+            .run_client(&mut conn);
+    }
+
+    #[test]
+    #[should_panic(expected = "transcript step 1: event mismatch")]
+    fn reports_event_mismatch() {
+        let mut conn = HttpConn::<Server>::new();
+        Transcript::new()
+            .recv(&b"GET / HTTP/1.1\r\n\r\n"[..])
+            .expect(Event::data(&b"nope"[..]))
+            .run_server(&mut conn);
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::io::Read;
+
+use bytes::Bytes;
+
+use crate::conn::{Error, HttpConn, Role};
+use crate::event::Event;
+
+// Where timestamps come from. Any `Fn() -> T` is a clock, so a driver can
+// pass `Instant::now`, or its runtime's cached time.
+pub trait Clock {
+    type Instant: Copy;
+
+    fn now(&self) -> Self::Instant;
+}
+
+impl<T: Copy, F: Fn() -> T> Clock for F {
+    type Instant = T;
+
+    fn now(&self) -> T {
+        self()
+    }
+}
+
+// When each stage of one message happened. For messages sent, the head
+// is written at once, so it starts and completes at the same moment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MessageTimings<T> {
+    pub head_started: Option<T>,
+    pub head_complete: Option<T>,
+    pub body_started: Option<T>,
+    pub complete: Option<T>,
+}
+
+impl<T> Default for MessageTimings<T> {
+    fn default() -> Self {
+        Self {
+            head_started: None,
+            head_complete: None,
+            body_started: None,
+            complete: None,
+        }
+    }
+}
+
+// Wraps a connection to stamp the messages of the current cycle with the
+// clock's time as bytes and events pass through. Input only counts once
+// it reaches the connection, so `feed` as soon as bytes are read.
+pub struct TimedConn<R, C: Clock> {
+    conn: HttpConn<R>,
+    clock: C,
+    received: MessageTimings<C::Instant>,
+    sent: MessageTimings<C::Instant>,
+}
+
+impl<R: Role, C: Clock> TimedConn<R, C> {
+    pub fn new(conn: HttpConn<R>, clock: C) -> Self {
+        let mut timed = Self {
+            conn,
+            clock,
+            received: MessageTimings::default(),
+            sent: MessageTimings::default(),
+        };
+        timed.input_arrived();
+        timed
+    }
+
+    pub fn get_ref(&self) -> &HttpConn<R> {
+        &self.conn
+    }
+
+    pub fn get_mut(&mut self) -> &mut HttpConn<R> {
+        &mut self.conn
+    }
+
+    pub fn into_inner(self) -> HttpConn<R> {
+        self.conn
+    }
+
+    // The message being received from the peer in this cycle.
+    pub fn received(&self) -> &MessageTimings<C::Instant> {
+        &self.received
+    }
+
+    // The message being sent to the peer in this cycle.
+    pub fn sent(&self) -> &MessageTimings<C::Instant> {
+        &self.sent
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::feed` would.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.conn.feed(bytes)?;
+        self.input_arrived();
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::read_from` would.
+    pub fn read_from<I: Read>(&mut self, r: &mut I) -> Result<usize, Error> {
+        let n = self.conn.read_from(r)?;
+        self.input_arrived();
+        Ok(n)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::next_event` would.
+    pub fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        let event = self.conn.next_event()?;
+        let (clock, received) = (&self.clock, &mut self.received);
+        let stamp = match event {
+            Some(Event::Request(_) | Event::Response(_)) => {
+                &mut received.head_complete
+            }
+            Some(Event::Data(ref data)) if !data.is_empty() => {
+                &mut received.body_started
+            }
+            Some(Event::EndOfMessage(_)) => &mut received.complete,
+            _ => return Ok(event),
+        };
+        stamp.get_or_insert_with(|| clock.now());
+        Ok(event)
+    }
+
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::send` would.
+    pub fn send(&mut self, event: Event) -> Result<Bytes, Error> {
+        let head = matches!(event, Event::Request(_) | Event::Response(_));
+        let body = matches!(event, Event::Data(ref data) if !data.is_empty());
+        let end = matches!(event, Event::EndOfMessage(_));
+        let out = self.conn.send(event)?;
+        let now = self.clock.now();
+        if head {
+            self.sent.head_started.get_or_insert(now);
+            self.sent.head_complete.get_or_insert(now);
+        } else if body {
+            self.sent.body_started.get_or_insert(now);
+        } else if end {
+            self.sent.complete.get_or_insert(now);
+        }
+        Ok(out)
+    }
+
+    /// Also clears the timings, ready for the next cycle's messages.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `HttpConn::start_next_cycle` would.
+    pub fn start_next_cycle(&mut self) -> Result<(), Error> {
+        self.conn.start_next_cycle()?;
+        self.received = MessageTimings::default();
+        self.sent = MessageTimings::default();
+        self.input_arrived();
+        Ok(())
+    }
+
+    // The first buffered byte of a cycle is the first byte of the
+    // received head, even if it arrived with the previous message.
+    fn input_arrived(&mut self) {
+        if self.received.head_started.is_none()
+            && !self.conn.peek_buffered().is_empty()
+        {
+            self.received.head_started = Some(self.clock.now());
+        }
+    }
+}
+
+impl<R, C: Clock> fmt::Debug for TimedConn<R, C>
+where
+    C::Instant: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimedConn")
+            .field("conn", &self.conn)
+            .field("received", &self.received)
+            .field("sent", &self.sent)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use http::header::{HeaderValue, CONTENT_LENGTH};
+    use http::{HeaderMap, Method, StatusCode, Version};
+
+    use crate::conn::{Client, Server};
+    use crate::req::ReqHead;
+    use crate::resp::RespHead;
+
+    fn ticks(tick: &Cell<u32>) -> impl Fn() -> u32 + '_ {
+        move || {
+            tick.set(tick.get() + 1);
+            tick.get()
+        }
+    }
+
+    #[test]
+    fn received_timings() {
+        let tick = Cell::new(0);
+        let mut server =
+            TimedConn::new(HttpConn::<Server>::new(), ticks(&tick));
+        server.feed(b"POST / HTTP/1.1\r\n").unwrap();
+        assert!(server.next_event().unwrap().is_none());
+        server.feed(b"content-length: 4\r\n\r\nab").unwrap();
+        assert!(server.next_event().unwrap().is_some());
+        assert!(server.next_event().unwrap().is_some());
+        server.feed(b"cd").unwrap();
+        assert!(server.next_event().unwrap().is_some());
+        assert!(server.next_event().unwrap().is_some());
+        assert_eq!(
+            MessageTimings {
+                head_started: Some(1),
+                head_complete: Some(2),
+                body_started: Some(3),
+                complete: Some(4),
+            },
+            *server.received()
+        );
+
+        let mut resp = RespHead {
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            reason: None,
+        };
+        resp.headers.insert(CONTENT_LENGTH, HeaderValue::from(2));
+        server.send(Event::Response(resp)).unwrap();
+        server.send(Event::Data(Bytes::from("ok"))).unwrap();
+        server.send(Event::EndOfMessage(None)).unwrap();
+        assert_eq!(
+            MessageTimings {
+                head_started: Some(5),
+                head_complete: Some(5),
+                body_started: Some(6),
+                complete: Some(7),
+            },
+            *server.sent()
+        );
+
+        server.start_next_cycle().unwrap();
+        assert_eq!(MessageTimings::default(), *server.received());
+        assert_eq!(MessageTimings::default(), *server.sent());
+    }
+
+    #[test]
+    fn response_to_bodiless_request() {
+        let tick = Cell::new(0);
+        let mut client =
+            TimedConn::new(HttpConn::<Client>::new(), ticks(&tick));
+        let req = ReqHead {
+            method: Method::GET,
+            uri: "/".parse().unwrap(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+        };
+        client.send(Event::Request(req)).unwrap();
+        client.send(Event::EndOfMessage(None)).unwrap();
+        client.feed(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        while let Some(event) = client.next_event().unwrap() {
+            if let Event::EndOfMessage(_) = event {
+                break;
+            }
+        }
+        assert_eq!(
+            MessageTimings {
+                head_started: Some(1),
+                head_complete: Some(1),
+                body_started: None,
+                complete: Some(2),
+            },
+            *client.sent()
+        );
+        assert_eq!(
+            MessageTimings {
+                head_started: Some(3),
+                head_complete: Some(4),
+                body_started: None,
+                complete: Some(5),
+            },
+            *client.received()
+        );
+    }
+}
@@ -1,38 +1,175 @@
-use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use http::header::AsHeaderName;
 use http::{HeaderMap, Version};
 
-pub fn can_keep_alive(version: Version, headers: &HeaderMap) -> bool {
+use crate::headers::split_list;
+
+// Compares a single header token against `expected`, ignoring ASCII case
+// and any surrounding whitespace.
+#[must_use]
+pub fn token_eq(token: &[u8], expected: &str) -> bool {
+    trim(token).eq_ignore_ascii_case(expected.as_bytes())
+}
+
+// Whether any element of any field with the given name is `token`, e.g.
+// `Connection: keep-alive, Upgrade` has the token "upgrade".
+pub fn has_token<K: AsHeaderName>(
+    headers: &HeaderMap,
+    name: K,
+    token: &str,
+) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .flat_map(split_list)
+        .any(|tok| token_eq(tok, token))
+}
+
+// Whether the final element of the final field with the given name is
+// `token`, which is how `Transfer-Encoding: gzip, chunked` is read.
+pub fn last_token_is<K: AsHeaderName>(
+    headers: &HeaderMap,
+    name: K,
+    token: &str,
+) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .next_back()
+        .and_then(|v| split_list(v).pop())
+        .is_some_and(|tok| token_eq(tok, token))
+}
+
+pub(crate) fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = bytes {
+        bytes = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = bytes {
+        bytes = rest;
+    }
+    bytes
+}
+
+pub(crate) fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// Collapses runs of spaces and tabs between the three parts of the
+// request or status line into single spaces. Whitespace inside a reason
+// phrase is left alone. Returns `None` when there is nothing to fix.
+pub(crate) fn collapse_line_delimiters(head: &[u8]) -> Option<Vec<u8>> {
+    let line_end = head
+        .iter()
+        .position(|&b| b == b'\r' || b == b'\n')
+        .unwrap_or(head.len());
+    let mut fixed = Vec::with_capacity(head.len());
+    let mut changed = false;
+    let mut delimiters = 0;
+    let mut i = 0;
+    while i < line_end {
+        let run = head[i..line_end]
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        if run == 0 || i == 0 || delimiters == 2 {
+            fixed.push(head[i]);
+            i += 1;
+            continue;
+        }
+        delimiters += 1;
+        if run > 1 || head[i] == b'\t' {
+            changed = true;
+        }
+        fixed.push(b' ');
+        i += run;
+    }
+    if !changed {
+        return None;
+    }
+    fixed.extend_from_slice(&head[line_end..]);
+    Some(fixed)
+}
+
+// Removes whitespace between header names and their colon, as in
+// `foo : bar`, and gives the numbers of the lines fixed, counting from 1
+// at the start line. Returns `None` when there is nothing to fix. Lines
+// whose name contains whitespace elsewhere are left for the parser to
+// reject, as are `Transfer-Encoding` and `Content-Length`, since peers
+// that read those differently can be made to disagree on framing.
+pub(crate) fn strip_space_before_colon(
+    head: &[u8],
+) -> Option<(Vec<u8>, Vec<usize>)> {
+    let mut fixed = Vec::with_capacity(head.len());
+    let mut changed = Vec::new();
+    let mut lines = head.split(|&b| b == b'\n');
+    fixed.extend_from_slice(lines.next().unwrap_or_default());
+    for (n, line) in (2..).zip(lines) {
+        fixed.push(b'\n');
+        let name_end = line.iter().position(|&b| b == b':');
+        let name = name_end.map(|n| &line[..n]);
+        match name.map(trim) {
+            Some(trimmed)
+                if trimmed.len() < name.map_or(0, <[u8]>::len)
+                    && !trimmed.is_empty()
+                    && line[0] != b' '
+                    && line[0] != b'\t'
+                    && trimmed.iter().all(|&b| is_token(b))
+                    && !token_eq(trimmed, "transfer-encoding")
+                    && !token_eq(trimmed, "content-length") =>
+            {
+                fixed.extend_from_slice(trimmed);
+                fixed.extend_from_slice(&line[name_end.unwrap_or(0)..]);
+                changed.push(n);
+            }
+            _ => fixed.extend_from_slice(line),
+        }
+    }
+    if changed.is_empty() {
+        None
+    } else {
+        Some((fixed, changed))
+    }
+}
+
+// Whether some line in `head` ends in LF alone. httparse would accept
+// such a line and stop at the first empty one, so a head has to be
+// refused as a whole rather than cut short at a later CRLF CRLF.
+pub(crate) fn has_bare_lf(head: &[u8]) -> bool {
+    head.iter()
+        .enumerate()
+        .any(|(i, &b)| b == b'\n' && (i == 0 || head[i - 1] != b'\r'))
+}
+
+pub(crate) fn can_keep_alive(version: Version, headers: &HeaderMap) -> bool {
     use http::header::CONNECTION;
 
-    !(version < Version::HTTP_11
-        || headers.get_all(CONNECTION).into_iter().any(|val| {
-            str::from_utf8(val.as_bytes())
-                .map(|s| {
-                    s.split(',')
-                        .any(|tok| tok.trim().eq_ignore_ascii_case("close"))
-                })
-                .unwrap_or(false)
-        }))
+    version >= Version::HTTP_11 && !has_token(headers, CONNECTION, "close")
 }
 
-pub fn is_chunked(headers: &HeaderMap) -> bool {
+pub(crate) fn is_chunked(headers: &HeaderMap) -> bool {
     use http::header::TRANSFER_ENCODING;
 
+    last_token_is(headers, TRANSFER_ENCODING, "chunked")
+}
+
+// The length of the fields as written, each as `name: value\r\n`.
+pub(crate) fn fields_len(headers: &HeaderMap) -> usize {
     headers
-        .get_all(TRANSFER_ENCODING)
         .iter()
-        .next_back()
-        .and_then(|v| str::from_utf8(v.as_bytes()).ok())
-        .and_then(|s| {
-            s.rsplit(',')
-                .next()
-                .map(|tok| tok.trim().eq_ignore_ascii_case("chunked"))
-        })
-        .unwrap_or(false)
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum()
+}
+
+// Adds every value in `more`, keeping those already there under the same
+// names.
+pub(crate) fn append_fields(headers: &mut HeaderMap, more: &HeaderMap) {
+    for (name, value) in more {
+        headers.append(name.clone(), value.clone());
+    }
 }
 
-pub fn maybe_content_length(headers: &HeaderMap) -> Option<usize> {
+pub(crate) fn maybe_content_length(headers: &HeaderMap) -> Option<usize> {
     use http::header::CONTENT_LENGTH;
 
     headers
@@ -40,12 +177,128 @@ pub fn maybe_content_length(headers: &HeaderMap) -> Option<usize> {
         .and_then(|tok| tok.to_str().ok().and_then(|s| s.parse().ok()))
 }
 
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+#[must_use]
+pub fn format_http_date(time: SystemTime) -> String {
+    let (days, secs_of_day) = days_and_secs(time);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAY_NAMES[(days % 7) as usize],
+        day,
+        MONTH_NAMES[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+    )
+}
+
+// Accepts the preferred IMF-fixdate format as well as the obsolete RFC 850
+// and asctime formats that recipients are still required to understand.
+// Two-digit RFC 850 years below 70 are taken to be in the 2000s.
+#[must_use]
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match parts[..] {
+        [wday, day, month, year, time, "GMT"] if wday.ends_with(',') => {
+            (day, month, year.parse().ok()?, time)
+        }
+        [wday, dmy, time, "GMT"] if wday.ends_with(',') => {
+            let mut dmy = dmy.split('-');
+            let (day, month, year) = (dmy.next()?, dmy.next()?, dmy.next()?);
+            if year.len() != 2 || dmy.next().is_some() {
+                return None;
+            }
+            let year: u64 = year.parse().ok()?;
+            (day, month, year + if year < 70 { 2000 } else { 1900 }, time)
+        }
+        [_, month, day, time, year] => (day, month, year.parse().ok()?, time),
+        _ => return None,
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTH_NAMES.iter().position(|&m| m == month)? as u64 + 1;
+    let mut hms = time.split(':').map(|n| n.parse::<u64>().ok());
+    let (h, m, sec) = (hms.next()??, hms.next()??, hms.next()??);
+    if hms.next().is_some()
+        || !(1970..=9999).contains(&year)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || h > 23
+        || m > 59
+        || sec > 60
+    {
+        return None;
+    }
+    let secs =
+        days_from_civil(year, month, day) * 86400 + h * 3600 + m * 60 + sec;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 => days_from_civil(year, 3, 1) - days_from_civil(year, 2, 1),
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(any(test, feature = "access-log"))]
+pub(crate) fn format_clf_date(time: SystemTime) -> String {
+    let (days, secs_of_day) = days_and_secs(time);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTH_NAMES[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+    )
+}
+
+fn days_and_secs(time: SystemTime) -> (u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    (secs / 86400, secs % 86400)
+}
+
+// Converts days since the Unix epoch into a (year, month, day) triple
+// using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use http::header::{
-        HeaderValue, CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING,
+        HeaderValue, CONNECTION, CONTENT_LENGTH, HOST, TE, TRANSFER_ENCODING,
     };
 
     #[test]
@@ -76,6 +329,73 @@ mod tests {
         assert!(!can_keep_alive(Version::HTTP_10, &HeaderMap::new()));
     }
 
+    #[test]
+    fn token_eq_ignores_case_and_whitespace() {
+        assert!(token_eq(b" Upgrade\t", "upgrade"));
+        assert!(!token_eq(b"upgrades", "upgrade"));
+    }
+
+    #[test]
+    fn has_token_scans_every_field() {
+        let mut headers = HeaderMap::new();
+        headers.append(CONNECTION, HeaderValue::from_static("keep-alive"));
+        headers.append(CONNECTION, HeaderValue::from_static("x, UPGRADE"));
+        assert!(has_token(&headers, CONNECTION, "upgrade"));
+        assert!(!has_token(&headers, CONNECTION, "close"));
+        assert!(!has_token(&headers, TE, "trailers"));
+    }
+
+    #[test]
+    fn last_token_is_only_checks_final_element() {
+        let mut headers = HeaderMap::new();
+        headers.append(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.append(TRANSFER_ENCODING, HeaderValue::from_static("gzip"));
+        assert!(!last_token_is(&headers, TRANSFER_ENCODING, "chunked"));
+        assert!(last_token_is(&headers, TRANSFER_ENCODING, "gzip"));
+        assert!(!last_token_is(&headers, TE, "gzip"));
+    }
+
+    #[test]
+    fn collapse_line_delimiters_only_touches_delimiters() {
+        assert_eq!(
+            Some(b"GET /a HTTP/1.1\r\nx:  y\r\n\r\n".to_vec()),
+            collapse_line_delimiters(b"GET  /a\t HTTP/1.1\r\nx:  y\r\n\r\n")
+        );
+        assert_eq!(
+            Some(b"HTTP/1.1 200 Not  Bad\r\n".to_vec()),
+            collapse_line_delimiters(b"HTTP/1.1  200 Not  Bad\r\n")
+        );
+        assert_eq!(None, collapse_line_delimiters(b"GET / HTTP/1.1\r\n"));
+        assert_eq!(None, collapse_line_delimiters(b" GET / HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn strip_space_before_colon_fixes_names() {
+        assert_eq!(
+            Some((
+                b"GET / HTTP/1.1\r\nfoo: a\r\nbar:b\r\n\r\n".to_vec(),
+                vec![2, 3]
+            )),
+            strip_space_before_colon(
+                b"GET / HTTP/1.1\r\nfoo \t: a\r\nbar :b\r\n\r\n"
+            )
+        );
+        assert_eq!(
+            None,
+            strip_space_before_colon(b"GET / HTTP/1.1\r\nfoo: a\r\n\r\n")
+        );
+        assert_eq!(
+            None,
+            strip_space_before_colon(b"GET / HTTP/1.1\r\nf o : a\r\n\r\n")
+        );
+        for framing in &[
+            &b"GET / HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\ncontent-length\t: 5\r\n\r\n",
+        ] {
+            assert_eq!(None, strip_space_before_colon(framing));
+        }
+    }
+
     #[test]
     fn is_chunked_with_header() {
         assert!(is_chunked(
@@ -106,4 +426,57 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn format_imf_fixdate() {
+        assert_eq!(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            format_http_date(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+        assert_eq!(
+            "Thu, 29 Feb 2024 00:00:01 GMT",
+            format_http_date(UNIX_EPOCH + Duration::from_secs(1_709_164_801))
+        );
+    }
+
+    #[test]
+    fn parse_all_three_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(expected, parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!(
+            expected,
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT")
+        );
+        assert_eq!(expected, parse_http_date("Sun Nov  6 08:49:37 1994"));
+    }
+
+    #[test]
+    fn parse_round_trips_format() {
+        for &secs in &[0, 951_782_400, 1_709_164_801, 4_102_444_799] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(Some(time), parse_http_date(&format_http_date(time)));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_malformed_dates() {
+        for date in &[
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+            "Sat, 31 Feb 2024 08:49:37 GMT",
+            "Mon, 29 Feb 2100 08:49:37 GMT",
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 18446744073709551615",
+        ] {
+            assert_eq!(None, parse_http_date(date), "{date}");
+        }
+        assert!(parse_http_date("Thu, 29 Feb 2024 08:49:37 GMT").is_some());
+        assert!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+    }
 }
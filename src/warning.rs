@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+
+// Something non-conforming in a received head that one of the lenient
+// parsing options let through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Anomaly {
+    // Runs of spaces or tabs between the parts of the start line.
+    ExtraLineWhitespace,
+    // Whitespace between a field name and its colon.
+    SpaceBeforeColon,
+    // A Shoutcast `ICY` status line.
+    IcyStatusLine,
+}
+
+// Where an anomaly was found: `line` counts from 1 at the start line of
+// the head that begins `head_offset` bytes into everything received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Warning {
+    pub anomaly: Anomaly,
+    pub head_offset: u64,
+    pub line: usize,
+}
+
+// Anomalies found while parsing a head, each with its line number.
+pub(crate) type Anomalies = Vec<(Anomaly, usize)>;
+
+// Only the most recent warnings are kept, in case they are never taken.
+const MAX_WARNINGS: usize = 64;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Warnings(VecDeque<Warning>);
+
+impl Warnings {
+    pub fn record(
+        &mut self,
+        head_offset: u64,
+        anomalies: &[(Anomaly, usize)],
+    ) {
+        for &(anomaly, line) in anomalies {
+            if self.0.len() == MAX_WARNINGS {
+                self.0.pop_front();
+            }
+            self.0.push_back(Warning {
+                anomaly,
+                head_offset,
+                line,
+            });
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<Warning> {
+        self.0.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest() {
+        let mut warnings = Warnings::default();
+        for offset in 0..100 {
+            warnings.record(offset, &[(Anomaly::SpaceBeforeColon, 2)]);
+        }
+        let taken = warnings.take();
+        assert_eq!(MAX_WARNINGS, taken.len());
+        assert_eq!(36, taken[0].head_offset);
+        assert_eq!(99, taken[MAX_WARNINGS - 1].head_offset);
+        assert!(warnings.take().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use bytes::{Bytes, BytesMut};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Clone, Debug)]
+pub struct WireRecord {
+    pub direction: Direction,
+    pub time: SystemTime,
+    pub bytes: Bytes,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct WireLog {
+    records: VecDeque<WireRecord>,
+    capacity: usize,
+    clock: fn() -> SystemTime,
+    // Input received but not yet consumed by an event, starting at
+    // `unread_offset` in the stream. Copying it on arrival means each byte
+    // is copied once, however many events it stays buffered for.
+    unread: BytesMut,
+    unread_offset: u64,
+}
+
+impl WireLog {
+    pub fn new(
+        capacity: usize,
+        clock: fn() -> SystemTime,
+        unread_offset: u64,
+    ) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+            clock,
+            unread: BytesMut::new(),
+            unread_offset,
+        }
+    }
+
+    pub fn received(&mut self, bytes: &[u8]) {
+        if self.capacity > 0 {
+            self.unread.extend_from_slice(bytes);
+        }
+    }
+
+    // Records the input between the two stream offsets as consumed by one
+    // event. Input consumed other than by events is skipped.
+    pub fn consumed(&mut self, start: u64, end: u64) {
+        let skip = (start.saturating_sub(self.unread_offset) as usize)
+            .min(self.unread.len());
+        self.unread.advance(skip);
+        let len = ((end - start) as usize).min(self.unread.len());
+        let bytes = self.unread.split_to(len).freeze();
+        self.unread_offset = end;
+        self.record(Direction::Inbound, bytes);
+    }
+
+    pub fn record(&mut self, direction: Direction, bytes: Bytes) {
+        if self.capacity == 0 || bytes.is_empty() {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(WireRecord {
+            direction,
+            time: (self.clock)(),
+            bytes,
+        });
+    }
+
+    pub fn take(&mut self) -> Vec<WireRecord> {
+        self.records.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_oldest_when_full() {
+        let mut log = WireLog::new(2, SystemTime::now, 0);
+        log.record(Direction::Inbound, Bytes::from_static(b"a"));
+        log.record(Direction::Outbound, Bytes::from_static(b"b"));
+        log.record(Direction::Inbound, Bytes::from_static(b"c"));
+        let records = log.take();
+        assert_eq!(
+            vec![&b"b"[..], &b"c"[..]],
+            records.iter().map(|r| &r.bytes[..]).collect::<Vec<_>>()
+        );
+        assert!(log.take().is_empty());
+    }
+
+    #[test]
+    fn skips_empty_writes() {
+        let mut log = WireLog::new(2, SystemTime::now, 0);
+        log.record(Direction::Outbound, Bytes::new());
+        assert!(log.take().is_empty());
+    }
+
+    #[test]
+    fn records_consumed_input() {
+        let mut log = WireLog::new(4, || SystemTime::UNIX_EPOCH, 10);
+        log.received(b"abcdef");
+        log.consumed(10, 12);
+        // Two bytes were consumed without an event.
+        log.consumed(14, 16);
+        log.received(b"gh");
+        log.consumed(16, 18);
+        assert_eq!(
+            vec![&b"ab"[..], &b"ef"[..], &b"gh"[..]],
+            log.take().iter().map(|r| &r.bytes[..]).collect::<Vec<_>>()
+        );
+    }
+}